@@ -1,4 +1,5 @@
 use crate::network::Network;
+use crate::script::{self, Opcode, Script};
 use bech32::{self, FromBase32, ToBase32, Variant};
 use secp256k1::PublicKey;
 
@@ -8,11 +9,14 @@ use secp256k1::PublicKey;
 
 // Convert public key to legacy P2PKH address
 pub fn pubkey_to_address(pubkey: &PublicKey, network: Network) -> String {
-    let hash160 = crate::crypto::hash160(&pubkey.serialize());
+    pubkey_hash_to_address(&crate::crypto::hash160(&pubkey.serialize()), network)
+}
 
+// Convert hash160 of a (compressed or uncompressed) public key to P2PKH address
+pub fn pubkey_hash_to_address(hash160: &[u8], network: Network) -> String {
     let mut payload = Vec::with_capacity(25);
     payload.push(network.p2pkh_prefix());
-    payload.extend(&hash160);
+    payload.extend(hash160);
 
     let checksum = crate::crypto::checksum(&payload);
     payload.extend(&checksum);
@@ -23,13 +27,56 @@ pub fn pubkey_to_address(pubkey: &PublicKey, network: Network) -> String {
 // Convert public key to Bech32 P2WPKH address
 pub fn pubkey_to_bech32(pubkey: &PublicKey, hrp: &str) -> String {
     let hash160 = crate::crypto::hash160(&pubkey.serialize());
+    segwit_v0_address(&hash160, hrp)
+}
+
+// Convert redeem script to Base58 P2SH address
+pub fn script_to_p2sh_address(script: &[u8], network: Network) -> String {
+    let hash160 = crate::crypto::hash160(script);
+
+    let mut payload = Vec::with_capacity(25);
+    payload.push(network.p2sh_prefix());
+    payload.extend(&hash160);
+
+    let checksum = crate::crypto::checksum(&payload);
+    payload.extend(&checksum);
+
+    bs58::encode(payload).into_string()
+}
+
+// Convert public key to P2SH-wrapped P2WPKH address
+pub fn pubkey_to_p2sh_p2wpkh(pubkey: &PublicKey, network: Network) -> String {
+    let hash160 = crate::crypto::hash160(&pubkey.serialize());
+    script_to_p2sh_address(&p2wpkh_script(&hash160), network)
+}
 
-    // witness version 0 + program
-    let mut data = Vec::with_capacity(1 + hash160.len());
-    data.push(bech32::u5::try_from_u8(0).expect("valid witness version"));
-    data.extend(hash160.to_base32());
+// Convert public key to Bech32m P2TR (key path only) address
+pub fn pubkey_to_p2tr(pubkey: &PublicKey, hrp: &str) -> String {
+    let (output_key, _) = crate::keys::taproot_output_key(&pubkey.x_only_public_key().0, None);
+    segwit_address(1, &output_key.serialize(), hrp)
+}
+
+// Convert witness script to Bech32 P2WSH address
+pub fn script_to_p2wsh_address(script: &[u8], hrp: &str) -> String {
+    segwit_v0_address(&crate::crypto::sha256(script), hrp)
+}
 
-    bech32::encode(hrp, data, Variant::Bech32).expect("bech32 encode")
+fn segwit_v0_address(program: &[u8], hrp: &str) -> String {
+    segwit_address(0, program, hrp)
+}
+
+// Bech32 (v0) / Bech32m (v1+, BIP350) segwit address
+pub fn segwit_address(version: u8, program: &[u8], hrp: &str) -> String {
+    let mut data = Vec::with_capacity(1 + program.len());
+    data.push(bech32::u5::try_from_u8(version).expect("valid witness version"));
+    data.extend(program.to_base32());
+
+    let variant = if version == 0 {
+        Variant::Bech32
+    } else {
+        Variant::Bech32m
+    };
+    bech32::encode(hrp, data, variant).expect("bech32 encode")
 }
 
 // --------------------------------------------------
@@ -63,10 +110,10 @@ pub fn validate_address(addr: &str, network: Network) -> bool {
 // Script conversion
 // --------------------------------------------------
 
-// Convert address (P2PKH / P2SH / Bech32 v0) to scriptPubKey
+// Convert address (P2PKH / P2SH / Bech32 v0 / Bech32m v1) to scriptPubKey
 pub fn address_to_scriptpubkey(addr: &str, network: Network) -> Vec<u8> {
     if addr.starts_with(network.bech32_hrp()) {
-        return p2wpkh_script_from_bech32(addr);
+        return segwit_script_from_bech32(addr);
     }
 
     let decoded = match bs58::decode(addr).into_vec() {
@@ -90,32 +137,78 @@ pub fn address_to_scriptpubkey(addr: &str, network: Network) -> Vec<u8> {
     }
 }
 
+// scriptPubKey of an address valid on `network`: a checksummed Base58
+// address with the network's prefix, or a Bech32/Bech32m address with its
+// HRP. None for anything else, which must never become an empty script.
+pub fn checked_scriptpubkey(addr: &str, network: Network) -> Option<Vec<u8>> {
+    let segwit = bech32::decode(addr).is_ok_and(|(hrp, _, _)| hrp == network.bech32_hrp());
+    if !segwit && !validate_address(addr, network) {
+        return None;
+    }
+    Some(address_to_scriptpubkey(addr, network)).filter(|script| !script.is_empty())
+}
+
 // --------------------------------------------------
 // Script builders
 // --------------------------------------------------
 
 // P2PKH script (USED by tx builder)
 pub fn p2pkh_script(hash160: &[u8]) -> Vec<u8> {
-    let mut script = Vec::with_capacity(25);
-    script.extend([
-        0x76, // OP_DUP
-        0xa9, // OP_HASH160
-        0x14, // push 20 bytes
-    ]);
-    script.extend(hash160);
-    script.extend([
-        0x88, // OP_EQUALVERIFY
-        0xac, // OP_CHECKSIG
-    ]);
-    script
+    Script::builder()
+        .push_opcode(Opcode::OP_DUP)
+        .push_opcode(Opcode::OP_HASH160)
+        .push_slice(hash160)
+        .push_opcode(Opcode::OP_EQUALVERIFY)
+        .push_opcode(Opcode::OP_CHECKSIG)
+        .into_bytes()
 }
 
 pub fn p2sh_script(hash160: &[u8]) -> Vec<u8> {
-    let mut script = Vec::with_capacity(23);
-    script.push(0xa9); // OP_HASH160
-    script.push(0x14);
-    script.extend(hash160);
-    script.push(0x87); // OP_EQUAL
+    Script::builder()
+        .push_opcode(Opcode::OP_HASH160)
+        .push_slice(hash160)
+        .push_opcode(Opcode::OP_EQUAL)
+        .into_bytes()
+}
+
+// P2WPKH script: OP_0 <hash160(pubkey)>
+pub fn p2wpkh_script(hash160: &[u8]) -> Vec<u8> {
+    Script::builder()
+        .push_opcode(Opcode::OP_0)
+        .push_slice(hash160)
+        .into_bytes()
+}
+
+// P2WSH script: OP_0 <sha256(witness script)>
+pub fn p2wsh_script(script_hash: &[u8]) -> Vec<u8> {
+    Script::builder()
+        .push_opcode(Opcode::OP_0)
+        .push_slice(script_hash)
+        .into_bytes()
+}
+
+// P2TR script: OP_1 <tweaked x-only output key>
+pub fn p2tr_script(output_key: &[u8]) -> Vec<u8> {
+    Script::builder()
+        .push_opcode(Opcode::OP_1)
+        .push_slice(output_key)
+        .into_bytes()
+}
+
+// Largest payload relayed in a standard OP_RETURN output (-datacarriersize 83)
+pub const MAX_OP_RETURN_DATA: usize = 80;
+
+// OP_RETURN data carrier script (provably unspendable, zero-value output)
+pub fn op_return_script(data: &[u8]) -> Vec<u8> {
+    assert!(
+        data.len() <= MAX_OP_RETURN_DATA,
+        "OP_RETURN data exceeds standard size"
+    );
+
+    let mut script = vec![Opcode::OP_RETURN as u8];
+    if !data.is_empty() {
+        script::push_data(&mut script, data);
+    }
     script
 }
 
@@ -123,19 +216,25 @@ pub fn p2sh_script(hash160: &[u8]) -> Vec<u8> {
 // Bech32 decoding
 // --------------------------------------------------
 
-// Decode Bech32 P2WPKH address into scriptPubKey
-fn p2wpkh_script_from_bech32(addr: &str) -> Vec<u8> {
+// Decode Bech32 (v0: P2WPKH / P2WSH) or Bech32m (v1+: P2TR) address into
+// scriptPubKey
+fn segwit_script_from_bech32(addr: &str) -> Vec<u8> {
     let (_hrp, data, variant) = match bech32::decode(addr) {
         Ok(v) => v,
         Err(_) => return Vec::new(),
     };
 
-    if variant != Variant::Bech32 || data.is_empty() {
+    if data.is_empty() {
         return Vec::new();
     }
 
     let version = data[0].to_u8();
-    if version != 0 {
+    let expected_variant = if version == 0 {
+        Variant::Bech32
+    } else {
+        Variant::Bech32m
+    };
+    if variant != expected_variant || version > 16 {
         return Vec::new();
     }
 
@@ -144,13 +243,17 @@ fn p2wpkh_script_from_bech32(addr: &str) -> Vec<u8> {
         Err(_) => return Vec::new(),
     };
 
-    if program.len() != 20 {
+    let valid_length = match version {
+        0 => program.len() == 20 || program.len() == 32,
+        _ => (2..=40).contains(&program.len()),
+    };
+    if !valid_length {
         return Vec::new();
     }
 
-    let mut script = Vec::with_capacity(22);
-    script.push(0x00); // OP_0
-    script.push(0x14); // push 20 bytes
+    let mut script = Vec::with_capacity(2 + program.len());
+    script.push(if version == 0 { 0x00 } else { 0x50 + version }); // OP_0 / OP_n
+    script.push(program.len() as u8);
     script.extend(program);
     script
 }
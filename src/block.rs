@@ -0,0 +1,324 @@
+use crate::{coinbase, crypto, tx::Transaction, utils};
+
+pub const HEADER_SIZE: usize = 80;
+
+// Display (big-endian) hex of an internal-order hash
+pub(crate) fn display_hex(hash: &[u8]) -> String {
+    let mut hash = hash.to_vec();
+    hash.reverse();
+    utils::bytes_to_hex(&hash)
+}
+
+// Internal-order bytes of a display hex hash
+pub(crate) fn internal_bytes(hex: &str) -> Vec<u8> {
+    let mut hash = utils::hex_to_bytes(hex);
+    hash.reverse();
+    hash
+}
+
+// --------------------
+// Block header
+// --------------------
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub version: u32,
+    pub prev_blockhash: String, // display hex, as txids
+    pub merkle_root: String,
+    pub time: u32,
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+impl BlockHeader {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend(utils::u32_le(self.version));
+        header.extend(internal_bytes(&self.prev_blockhash));
+        header.extend(internal_bytes(&self.merkle_root));
+        header.extend(utils::u32_le(self.time));
+        header.extend(utils::u32_le(self.bits));
+        header.extend(utils::u32_le(self.nonce));
+        header
+    }
+
+    pub fn parse(data: &[u8]) -> Option<BlockHeader> {
+        let mut pos = 0;
+        let header = Self::parse_from(data, &mut pos)?;
+        (pos == data.len()).then_some(header)
+    }
+
+    pub fn parse_from(data: &[u8], pos: &mut usize) -> Option<BlockHeader> {
+        Some(BlockHeader {
+            version: utils::read_u32_le(data, pos)?,
+            prev_blockhash: display_hex(utils::read_bytes(data, pos, 32)?),
+            merkle_root: display_hex(utils::read_bytes(data, pos, 32)?),
+            time: utils::read_u32_le(data, pos)?,
+            bits: utils::read_u32_le(data, pos)?,
+            nonce: utils::read_u32_le(data, pos)?,
+        })
+    }
+
+    // Double SHA256 of the header, display hex
+    pub fn block_hash(&self) -> String {
+        display_hex(&crypto::double_sha256(&self.serialize()))
+    }
+
+    // Target encoded by `bits` as a 256-bit big-endian number
+    pub fn target(&self) -> Option<[u8; 32]> {
+        bits_to_target(self.bits)
+    }
+
+    // Whether the block hash is at or below the target
+    pub fn check_pow(&self) -> bool {
+        let Some(target) = self.target() else {
+            return false;
+        };
+        let hash = utils::hex_to_bytes(&self.block_hash());
+        hash.as_slice() <= target.as_slice()
+    }
+}
+
+// Decode a compact target: 3-byte mantissa times 256^(exponent - 3). None
+// for negative, zero or overflowing encodings.
+pub fn bits_to_target(bits: u32) -> Option<[u8; 32]> {
+    let exponent = (bits >> 24) as usize;
+    let mantissa = bits & 0x007f_ffff;
+    if bits & 0x0080_0000 != 0 || mantissa == 0 {
+        return None;
+    }
+
+    let mut target = [0u8; 32];
+    for (i, byte) in mantissa.to_be_bytes()[1..].iter().enumerate() {
+        // Byte i of the mantissa lands at 256^(exponent - 1 - i)
+        let Some(power) = (exponent + 2).checked_sub(i + 3) else {
+            continue; // shifted out below 256^0
+        };
+        match 31usize.checked_sub(power) {
+            Some(index) => target[index] = *byte,
+            None if *byte != 0 => return None,
+            None => {}
+        }
+    }
+    (target != [0; 32]).then_some(target)
+}
+
+// Compact encoding of a big-endian target, rounding down to three
+// significant bytes (Core's GetCompact)
+pub fn target_to_bits(target: &[u8; 32]) -> u32 {
+    let Some(first) = target.iter().position(|b| *b != 0) else {
+        return 0;
+    };
+    let mut size = 32 - first;
+    // The three most significant bytes, zero-padded on the right
+    let mut mantissa = [0u8; 4];
+    for (i, byte) in target[first..].iter().take(3).enumerate() {
+        mantissa[i + 1] = *byte;
+    }
+    let mut compact = u32::from_be_bytes(mantissa);
+    // The mantissa's top bit is a sign bit; shift it into the exponent
+    if compact & 0x0080_0000 != 0 {
+        compact >>= 8;
+        size += 1;
+    }
+    compact | (size as u32) << 24
+}
+
+// --------------------
+// Block
+// --------------------
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub txdata: Vec<Transaction>,
+}
+
+impl Block {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut block = self.header.serialize();
+        block.extend(utils::varint(self.txdata.len()));
+        for tx in &self.txdata {
+            block.extend(tx.serialize());
+        }
+        block
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Block> {
+        let mut pos = 0;
+        let header = BlockHeader::parse_from(data, &mut pos)?;
+
+        let count = utils::read_varint(data, &mut pos)?;
+        let mut txdata = Vec::with_capacity(count.min(data.len()));
+        for _ in 0..count {
+            txdata.push(Transaction::parse_from(data, &mut pos)?);
+        }
+        (pos == data.len()).then_some(Block { header, txdata })
+    }
+
+    pub fn weight(&self) -> usize {
+        let header_and_count = HEADER_SIZE + utils::varint(self.txdata.len()).len();
+        header_and_count * 4 + self.txdata.iter().map(Transaction::weight).sum::<usize>()
+    }
+
+    // Merkle root of the txids, display hex
+    pub fn compute_merkle_root(&self) -> Option<String> {
+        let txids: Vec<Vec<u8>> = self
+            .txdata
+            .iter()
+            .map(|tx| internal_bytes(&tx.txid()))
+            .collect();
+        (!txids.is_empty()).then(|| display_hex(&crypto::merkle_root(&txids)))
+    }
+
+    pub fn check_merkle_root(&self) -> bool {
+        self.compute_merkle_root().as_ref() == Some(&self.header.merkle_root)
+    }
+
+    // Witness merkle root (internal order), the coinbase wtxid counting as zero
+    pub fn witness_root(&self) -> Option<Vec<u8>> {
+        let (_, rest) = self.txdata.split_first()?;
+        let wtxids: Vec<String> = rest.iter().map(Transaction::wtxid).collect();
+        Some(coinbase::witness_merkle_root(&wtxids))
+    }
+
+    // BIP-141: when any transaction has witness data, the last coinbase
+    // output starting with the commitment header must commit to the witness
+    // root and the coinbase's 32-byte witness reserved value
+    pub fn check_witness_commitment(&self) -> bool {
+        let Some(cb) = self.txdata.first() else {
+            return false;
+        };
+
+        let commitment = cb
+            .outputs
+            .iter()
+            .rev()
+            .find_map(|o| match o.script_pubkey.as_slice() {
+                [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed, commitment @ ..] if commitment.len() >= 32 => {
+                    Some(&commitment[..32])
+                }
+                _ => None,
+            });
+
+        let Some(commitment) = commitment else {
+            return !self.txdata.iter().any(Transaction::has_witness);
+        };
+        let reserved = match cb.inputs.first().map(|i| i.witness.as_slice()) {
+            Some([reserved]) if reserved.len() == 32 => reserved,
+            _ => return false,
+        };
+        let root = self.witness_root().expect("block has a coinbase");
+        coinbase::witness_commitment(&root, reserved) == commitment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        address,
+        tx::{TxIn, TxOut},
+    };
+
+    const GENESIS: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+    #[test]
+    fn test_genesis_block() {
+        let raw = utils::hex_to_bytes(GENESIS);
+        let block = Block::parse(&raw).unwrap();
+        assert_eq!(block.serialize(), raw);
+
+        assert_eq!(
+            block.header.block_hash(),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        assert_eq!(
+            block.header.merkle_root,
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+        );
+        assert!(block.check_merkle_root());
+        assert!(block.check_witness_commitment());
+        assert!(block.header.check_pow());
+
+        // Same header, nonce changed: the hash no longer meets the target
+        let mut header = block.header.clone();
+        header.nonce += 1;
+        assert!(!header.check_pow());
+    }
+
+    #[test]
+    fn test_compact_targets() {
+        let target = bits_to_target(0x1d00ffff).unwrap();
+        assert_eq!(hex::encode(&target[..6]), "00000000ffff");
+        assert!(target[6..].iter().all(|b| *b == 0));
+
+        assert_eq!(
+            bits_to_target(0x03123456).unwrap()[29..],
+            [0x12, 0x34, 0x56]
+        );
+        assert_eq!(bits_to_target(0x02123456).unwrap()[30..], [0x12, 0x34]);
+        assert_eq!(bits_to_target(0x207fffff).unwrap()[..3], [0x7f, 0xff, 0xff]);
+        assert_eq!(bits_to_target(0x04923456), None); // negative
+        assert_eq!(bits_to_target(0x23123456), None); // overflow
+        assert_eq!(bits_to_target(0x01003456), None); // zero
+
+        for bits in [0x1d00ffff, 0x1c05a3f4, 0x03123456, 0x02123400, 0x207fffff] {
+            assert_eq!(target_to_bits(&bits_to_target(bits).unwrap()), bits);
+        }
+        // A mantissa with the sign bit set moves up a byte
+        let mut target = [0u8; 32];
+        target[29] = 0x80;
+        assert_eq!(target_to_bits(&target), 0x04008000);
+    }
+
+    #[test]
+    fn test_segwit_block_commitment() {
+        let spend = Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                txid: "11".repeat(32),
+                vout: 0,
+                script_sig: Vec::new(),
+                sequence: 0xffffffff,
+                witness: vec![vec![1; 71], vec![2; 33]],
+            }],
+            outputs: vec![TxOut {
+                value: 1_000,
+                script_pubkey: address::p2wpkh_script(&[3; 20]),
+            }],
+            locktime: 0,
+        };
+
+        let root = coinbase::witness_merkle_root(&[spend.wtxid()]);
+        let commitment = coinbase::witness_commitment(&root, &[0; 32]);
+        let payout = [TxOut {
+            value: 50_000,
+            script_pubkey: address::p2wpkh_script(&[4; 20]),
+        }];
+        let cb = coinbase::build_coinbase(101, 4, b"", &payout, Some(&commitment)).tx;
+
+        let mut block = Block {
+            header: BlockHeader {
+                version: 0x2000_0000,
+                prev_blockhash: "00".repeat(32),
+                merkle_root: String::new(),
+                time: 1_700_000_000,
+                bits: 0x207fffff,
+                nonce: 0,
+            },
+            txdata: vec![cb, spend],
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        while !block.header.check_pow() {
+            block.header.nonce += 1;
+        }
+
+        let parsed = Block::parse(&block.serialize()).unwrap();
+        assert_eq!(parsed, block);
+        assert!(parsed.check_merkle_root() && parsed.check_witness_commitment());
+        assert!(parsed.weight() < block.serialize().len() * 4);
+
+        // Tampering with a witness breaks the commitment but not the txid root
+        block.txdata[1].inputs[0].witness[0][0] = 9;
+        assert!(block.check_merkle_root() && !block.check_witness_commitment());
+    }
+}
@@ -0,0 +1,511 @@
+use crate::{
+    block::{self, BlockHeader},
+    network::ConsensusParams,
+};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+};
+
+// Headers may run at most this far ahead of the local clock
+const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+// Blocks whose timestamps form the median time past
+const MEDIAN_TIME_SPAN: usize = 11;
+
+// --------------------
+// 256-bit arithmetic
+// --------------------
+
+// Unsigned 256-bit integer, least significant limb first
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    const MAX: U256 = U256([u64::MAX; 4]);
+    const ONE: U256 = U256([1, 0, 0, 0]);
+
+    pub fn from_be_bytes(bytes: &[u8; 32]) -> U256 {
+        let mut limbs = [0u64; 4];
+        for (i, chunk) in bytes.chunks(8).enumerate() {
+            limbs[3 - i] = u64::from_be_bytes(chunk.try_into().unwrap());
+        }
+        U256(limbs)
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.0.iter().rev().enumerate() {
+            bytes[i * 8..i * 8 + 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    fn bits(&self) -> u32 {
+        for (i, limb) in self.0.iter().enumerate().rev() {
+            if *limb != 0 {
+                return 64 * i as u32 + 64 - limb.leading_zeros();
+            }
+        }
+        0
+    }
+
+    fn bit(&self, n: u32) -> bool {
+        self.0[n as usize / 64] >> (n % 64) & 1 == 1
+    }
+
+    fn set_bit(&mut self, n: u32) {
+        self.0[n as usize / 64] |= 1 << (n % 64);
+    }
+
+    fn shl1(self) -> U256 {
+        let mut out = [0u64; 4];
+        let mut carry = 0;
+        for (limb, value) in out.iter_mut().zip(self.0) {
+            *limb = value << 1 | carry;
+            carry = value >> 63;
+        }
+        U256(out)
+    }
+
+    fn not(self) -> U256 {
+        U256(self.0.map(|limb| !limb))
+    }
+
+    // Wrapping addition
+    pub fn add(self, other: U256) -> U256 {
+        let mut out = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in out.iter_mut().enumerate() {
+            let (sum, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+        U256(out)
+    }
+
+    fn sub(self, other: U256) -> U256 {
+        self.add(other.not()).add(U256::ONE)
+    }
+
+    // Long division, starting at the quotient's highest possible bit
+    fn div(self, divisor: U256) -> U256 {
+        assert!(divisor != U256::default(), "division by zero");
+        let mut quotient = U256::default();
+        let mut remainder = U256::default();
+        for n in (0..self.bits()).rev() {
+            remainder = remainder.shl1();
+            if self.bit(n) {
+                remainder.0[0] |= 1;
+            }
+            if remainder >= divisor {
+                remainder = remainder.sub(divisor);
+                quotient.set_bit(n);
+            }
+        }
+        quotient
+    }
+
+    // self * mul / div, saturating at MAX
+    fn mul_div(self, mul: u32, div: u32) -> U256 {
+        // Five limbs hold the intermediate product
+        let mut wide = [0u64; 5];
+        let mut carry = 0u128;
+        for (limb, value) in wide.iter_mut().zip(self.0) {
+            let product = value as u128 * mul as u128 + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        wide[4] = carry as u64;
+
+        let mut remainder = 0u128;
+        for limb in wide.iter_mut().rev() {
+            let value = remainder << 64 | *limb as u128;
+            *limb = (value / div as u128) as u64;
+            remainder = value % div as u128;
+        }
+        if wide[4] != 0 {
+            return U256::MAX;
+        }
+        U256([wide[0], wide[1], wide[2], wide[3]])
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &U256) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &U256) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Expected hashes to meet `bits`: 2^256 / (target + 1)
+pub fn work(bits: u32) -> U256 {
+    let Some(target) = block::bits_to_target(bits) else {
+        return U256::default();
+    };
+    let target = U256::from_be_bytes(&target);
+    // (2^256 - target - 1) / (target + 1) + 1, avoiding 2^256 itself
+    target.not().div(target.add(U256::ONE)).add(U256::ONE)
+}
+
+// --------------------
+// Difficulty
+// --------------------
+
+// Retarget from the period's first and last block times (Core's
+// CalculateNextWorkRequired)
+pub fn calculate_next_bits(
+    params: &ConsensusParams,
+    last_bits: u32,
+    first_time: u32,
+    last_time: u32,
+) -> u32 {
+    if params.no_retargeting {
+        return last_bits;
+    }
+    let timespan = params.pow_target_timespan;
+    let actual = (last_time as i64 - first_time as i64)
+        .clamp(timespan as i64 / 4, timespan as i64 * 4) as u32;
+
+    let limit =
+        U256::from_be_bytes(&block::bits_to_target(params.pow_limit_bits).expect("pow limit"));
+    let last = U256::from_be_bytes(&block::bits_to_target(last_bits).unwrap_or_default());
+    let target = last.mul_div(actual, timespan).min(limit);
+    block::target_to_bits(&target.to_be_bytes())
+}
+
+// --------------------
+// Header chain
+// --------------------
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderError {
+    Duplicate,
+    UnknownParent,
+    CheckpointMismatch,
+    ForkBeforeCheckpoint,
+    BadDifficulty,
+    BadProofOfWork,
+    TimeTooOld,
+    TimeTooNew,
+}
+
+impl HeaderError {
+    pub fn message(self) -> &'static str {
+        match self {
+            HeaderError::Duplicate => "header already known",
+            HeaderError::UnknownParent => "previous header not found",
+            HeaderError::CheckpointMismatch => "header does not match checkpoint",
+            HeaderError::ForkBeforeCheckpoint => "fork below the last checkpoint",
+            HeaderError::BadDifficulty => "incorrect proof of work bits",
+            HeaderError::BadProofOfWork => "hash does not meet target",
+            HeaderError::TimeTooOld => "time is not after median time past",
+            HeaderError::TimeTooNew => "time too far in the future",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChainEntry {
+    pub header: BlockHeader,
+    pub height: u32,
+    // Work of this block and its ancestors down to the anchor
+    pub chainwork: U256,
+}
+
+// Headers accepted on top of a trusted anchor (genesis or a checkpoint at
+// the start of a difficulty period), keyed by block hash, with the
+// most-work tip
+pub struct HeaderChain {
+    params: ConsensusParams,
+    entries: HashMap<String, ChainEntry>,
+    checkpoints: BTreeMap<u32, String>,
+    tip: String,
+}
+
+impl HeaderChain {
+    pub fn new(params: ConsensusParams, anchor: BlockHeader, anchor_height: u32) -> HeaderChain {
+        assert!(
+            anchor_height.is_multiple_of(params.difficulty_adjustment_interval()),
+            "anchor must start a difficulty period"
+        );
+        let hash = anchor.block_hash();
+        let entry = ChainEntry {
+            chainwork: work(anchor.bits),
+            header: anchor,
+            height: anchor_height,
+        };
+        HeaderChain {
+            params,
+            entries: HashMap::from([(hash.clone(), entry)]),
+            checkpoints: BTreeMap::new(),
+            tip: hash,
+        }
+    }
+
+    pub fn add_checkpoint(&mut self, height: u32, hash: &str) {
+        self.checkpoints.insert(height, hash.to_string());
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&ChainEntry> {
+        self.entries.get(hash)
+    }
+
+    pub fn tip(&self) -> &ChainEntry {
+        &self.entries[&self.tip]
+    }
+
+    pub fn tip_hash(&self) -> &str {
+        &self.tip
+    }
+
+    fn parent(&self, entry: &ChainEntry) -> Option<&ChainEntry> {
+        self.entries.get(&entry.header.prev_blockhash)
+    }
+
+    fn ancestor<'a>(&'a self, mut entry: &'a ChainEntry, height: u32) -> Option<&'a ChainEntry> {
+        while entry.height > height {
+            entry = self.parent(entry)?;
+        }
+        (entry.height == height).then_some(entry)
+    }
+
+    // Median of the last eleven block times ending at `entry`
+    pub fn median_time_past(&self, entry: &ChainEntry) -> u32 {
+        let mut times = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut current = Some(entry);
+        while let Some(e) = current.filter(|_| times.len() < MEDIAN_TIME_SPAN) {
+            times.push(e.header.time);
+            current = self.parent(e);
+        }
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    // Bits required of a block with time `time` on top of `prev` (Core's
+    // GetNextWorkRequired)
+    pub fn next_bits(&self, prev: &ChainEntry, time: u32) -> u32 {
+        let params = &self.params;
+        let interval = params.difficulty_adjustment_interval();
+
+        if !(prev.height + 1).is_multiple_of(interval) {
+            if !params.allow_min_difficulty_blocks {
+                return prev.header.bits;
+            }
+            if time > prev.header.time + params.pow_target_spacing * 2 {
+                return params.pow_limit_bits;
+            }
+            // Last block of this period not mined under the min-difficulty rule
+            let mut entry = prev;
+            while !entry.height.is_multiple_of(interval)
+                && entry.header.bits == params.pow_limit_bits
+            {
+                match self.parent(entry) {
+                    Some(parent) => entry = parent,
+                    None => break,
+                }
+            }
+            return entry.header.bits;
+        }
+
+        let first = self
+            .ancestor(prev, prev.height + 1 - interval)
+            .expect("period start is at or above the anchor");
+        calculate_next_bits(
+            params,
+            prev.header.bits,
+            first.header.time,
+            prev.header.time,
+        )
+    }
+
+    // Validate and store a header whose parent is known; `now` (unix time)
+    // bounds timestamps from the future. Returns the header's height.
+    pub fn accept(&mut self, header: BlockHeader, now: Option<u32>) -> Result<u32, HeaderError> {
+        let hash = header.block_hash();
+        if self.entries.contains_key(&hash) {
+            return Err(HeaderError::Duplicate);
+        }
+        let prev = self
+            .entries
+            .get(&header.prev_blockhash)
+            .ok_or(HeaderError::UnknownParent)?;
+        let height = prev.height + 1;
+
+        let tip_height = self.tip().height;
+        if let Some((&checkpoint, _)) = self.checkpoints.range(..=tip_height).next_back() {
+            if height <= checkpoint {
+                return Err(HeaderError::ForkBeforeCheckpoint);
+            }
+        }
+        if self.checkpoints.get(&height).is_some_and(|h| *h != hash) {
+            return Err(HeaderError::CheckpointMismatch);
+        }
+
+        if header.bits != self.next_bits(prev, header.time) {
+            return Err(HeaderError::BadDifficulty);
+        }
+        if !header.check_pow() {
+            return Err(HeaderError::BadProofOfWork);
+        }
+        if header.time <= self.median_time_past(prev) {
+            return Err(HeaderError::TimeTooOld);
+        }
+        if now.is_some_and(|now| header.time > now + MAX_FUTURE_BLOCK_TIME) {
+            return Err(HeaderError::TimeTooNew);
+        }
+
+        let entry = ChainEntry {
+            chainwork: prev.chainwork.add(work(header.bits)),
+            header,
+            height,
+        };
+        if entry.chainwork > self.tip().chainwork {
+            self.tip = hash.clone();
+        }
+        self.entries.insert(hash, entry);
+        Ok(height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retarget_vectors() {
+        // Bitcoin Core's pow_tests, on Bitcoin mainnet parameters
+        let params = ConsensusParams {
+            pow_limit_bits: 0x1d00ffff,
+            pow_target_spacing: 10 * 60,
+            pow_target_timespan: 14 * 24 * 60 * 60,
+            allow_min_difficulty_blocks: false,
+            no_retargeting: false,
+        };
+        assert_eq!(
+            calculate_next_bits(&params, 0x1d00ffff, 1261130161, 1262152739),
+            0x1d00d86a
+        );
+        assert_eq!(
+            calculate_next_bits(&params, 0x1d00ffff, 1231006505, 1233061996),
+            0x1d00ffff
+        );
+        assert_eq!(
+            calculate_next_bits(&params, 0x1c05a3f4, 1279008237, 1279297671),
+            0x1c0168fd
+        );
+        assert_eq!(
+            calculate_next_bits(&params, 0x1c387f6f, 1263163443, 1269211443),
+            0x1d00e1fd
+        );
+
+        // Difficulty 1 is 2^32 + 2^16 + 1 expected hashes
+        assert_eq!(work(0x1d00ffff), U256([0x0001_0001_0001, 0, 0, 0]));
+    }
+
+    fn mine(prev: &ChainEntry, time: u32, bits: u32) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 0x2000_0000,
+            prev_blockhash: prev.header.block_hash(),
+            merkle_root: format!("{:064x}", time),
+            time,
+            bits,
+            nonce: 0,
+        };
+        while !header.check_pow() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    #[test]
+    fn test_header_chain() {
+        // Eight-block periods of one-minute blocks on an easy limit
+        let params = ConsensusParams {
+            pow_limit_bits: 0x207fffff,
+            pow_target_spacing: 60,
+            pow_target_timespan: 8 * 60,
+            allow_min_difficulty_blocks: false,
+            no_retargeting: false,
+        };
+        let genesis = BlockHeader {
+            version: 1,
+            prev_blockhash: "00".repeat(32),
+            merkle_root: "00".repeat(32),
+            time: 1_700_000_000,
+            bits: 0x207fffff,
+            nonce: 0,
+        };
+        let mut chain = HeaderChain::new(params, genesis.clone(), 0);
+
+        // Blocks twice as fast as targeted halve the target at height 8
+        let mut time = genesis.time;
+        for height in 1..=8 {
+            let prev = chain.tip().clone();
+            time += 30;
+            let bits = chain.next_bits(&prev, time);
+            assert_eq!(chain.accept(mine(&prev, time, bits), None), Ok(height));
+        }
+        assert_eq!(chain.tip().header.bits, 0x2037ffff);
+
+        let tip = chain.tip().clone();
+        let fork_base = chain.ancestor(&tip, 6).unwrap().clone();
+
+        // Rejections: wrong bits, stale time, duplicate, orphan, future
+        let next = mine(&tip, time + 30, 0x207fffff);
+        assert_eq!(chain.accept(next, None), Err(HeaderError::BadDifficulty));
+        let stale = mine(&tip, tip.header.time - 200, tip.header.bits);
+        assert_eq!(chain.accept(stale, None), Err(HeaderError::TimeTooOld));
+        assert_eq!(
+            chain.accept(tip.header.clone(), None),
+            Err(HeaderError::Duplicate)
+        );
+        let mut orphan = mine(&tip, time + 30, tip.header.bits);
+        orphan.prev_blockhash = "11".repeat(32);
+        assert_eq!(chain.accept(orphan, None), Err(HeaderError::UnknownParent));
+        let future = mine(&tip, time + 30, tip.header.bits);
+        assert_eq!(
+            chain.accept(future, Some(time - 3 * 60 * 60)),
+            Err(HeaderError::TimeTooNew)
+        );
+
+        // A longer fork from height 6 takes over the tip
+        let mut prev = fork_base.clone();
+        for _ in 0..3 {
+            let time = prev.header.time + 45;
+            let bits = chain.next_bits(&prev, time);
+            let header = mine(&prev, time, bits);
+            let hash = header.block_hash();
+            chain.accept(header, None).unwrap();
+            prev = chain.get(&hash).unwrap().clone();
+        }
+        assert_eq!(chain.tip().height, 9);
+        assert_eq!(chain.tip_hash(), prev.header.block_hash());
+        assert!(chain.tip().chainwork > tip.chainwork);
+
+        // Once the tip passes a checkpoint, forks below it are refused and
+        // headers at checkpointed heights must match
+        chain.add_checkpoint(7, &chain.ancestor(&prev, 7).unwrap().header.block_hash());
+        let late_fork = mine(
+            &fork_base,
+            fork_base.header.time + 50,
+            fork_base.header.bits,
+        );
+        assert_eq!(
+            chain.accept(late_fork, None),
+            Err(HeaderError::ForkBeforeCheckpoint)
+        );
+        chain.add_checkpoint(10, &"22".repeat(32));
+        let next = mine(&prev, prev.header.time + 60, prev.header.bits);
+        assert_eq!(
+            chain.accept(next, None),
+            Err(HeaderError::CheckpointMismatch)
+        );
+    }
+}
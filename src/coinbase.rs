@@ -0,0 +1,175 @@
+use crate::{
+    crypto,
+    script::{self, Opcode, Script},
+    tx::{Transaction, TxIn, TxOut},
+    utils,
+};
+
+// BIP-141 commitment header following OP_RETURN in the commitment output
+const WITNESS_COMMITMENT_HEADER: [u8; 4] = [0xaa, 0x21, 0xa9, 0xed];
+
+// Consensus bounds on the coinbase scriptSig
+const MIN_COINBASE_SCRIPT_SIG: usize = 2;
+const MAX_COINBASE_SCRIPT_SIG: usize = 100;
+
+// --------------------
+// Witness commitment
+// --------------------
+
+// Witness merkle root of a block from the wtxids (display hex) of every
+// transaction after the coinbase, whose own wtxid counts as zero
+pub fn witness_merkle_root(wtxids: &[String]) -> Vec<u8> {
+    let mut hashes = vec![vec![0u8; 32]];
+    for wtxid in wtxids {
+        let mut hash = utils::hex_to_bytes(wtxid);
+        hash.reverse();
+        hashes.push(hash);
+    }
+    crypto::merkle_root(&hashes)
+}
+
+// Double SHA256 of the witness root and the coinbase witness reserved value
+pub fn witness_commitment(witness_root: &[u8], reserved_value: &[u8]) -> Vec<u8> {
+    crypto::double_sha256(&[witness_root, reserved_value].concat())
+}
+
+// OP_RETURN <aa21a9ed || commitment>
+pub fn witness_commitment_script(commitment: &[u8]) -> Vec<u8> {
+    Script::builder()
+        .push_opcode(Opcode::OP_RETURN)
+        .push_slice(&[&WITNESS_COMMITMENT_HEADER[..], commitment].concat())
+        .into_script()
+        .as_bytes()
+        .to_vec()
+}
+
+// --------------------
+// Coinbase builder
+// --------------------
+
+// Coinbase transaction with a zeroed extranonce, split around it for
+// Stratum: coinb1 || extranonce1 || extranonce2 || coinb2 is the txid
+// (non-witness) serialization
+pub struct Coinbase {
+    pub tx: Transaction,
+    pub coinb1: Vec<u8>,
+    pub coinb2: Vec<u8>,
+}
+
+// Build a coinbase for `height`: scriptSig is the BIP34 height, the
+// extranonce placeholder and the pool's `tag`. `payouts` share the block
+// reward; with a `witness_commitment` (the commitment hash, e.g. GBT's
+// default_witness_commitment payload), the commitment output is appended
+// and the input carries the all-zero witness reserved value.
+pub fn build_coinbase(
+    height: u32,
+    extranonce_size: usize,
+    tag: &[u8],
+    payouts: &[TxOut],
+    witness_commitment: Option<&[u8]>,
+) -> Coinbase {
+    assert!(!payouts.is_empty(), "coinbase needs at least one payout");
+
+    let height_push = Script::builder().push_int(height as i64).into_script();
+    let mut script_sig = height_push.as_bytes().to_vec();
+
+    // The extranonce is a single push; its data starts right after the opcode
+    script::push_data(&mut script_sig, &vec![0; extranonce_size]);
+    let extranonce_end = script_sig.len();
+    let extranonce_start = extranonce_end - extranonce_size;
+    if !tag.is_empty() {
+        script::push_data(&mut script_sig, tag);
+    }
+
+    assert!(
+        (MIN_COINBASE_SCRIPT_SIG..=MAX_COINBASE_SCRIPT_SIG).contains(&script_sig.len()),
+        "coinbase scriptSig must be 2 to 100 bytes"
+    );
+
+    let mut outputs = payouts.to_vec();
+    let mut witness = Vec::new();
+    if let Some(commitment) = witness_commitment {
+        assert!(
+            commitment.len() == 32,
+            "witness commitment must be 32 bytes"
+        );
+        outputs.push(TxOut {
+            value: 0,
+            script_pubkey: witness_commitment_script(commitment),
+        });
+        witness.push(vec![0; 32]);
+    }
+
+    let script_sig_len = script_sig.len();
+    let tx = Transaction {
+        version: 1,
+        inputs: vec![TxIn {
+            txid: "00".repeat(32),
+            vout: 0xffffffff,
+            script_sig,
+            sequence: 0xffffffff,
+            witness,
+        }],
+        outputs,
+        locktime: 0,
+    };
+
+    // version (4) + input count (1) + outpoint (36) + scriptSig length
+    let script_sig_offset = 4 + 1 + 36 + utils::varint(script_sig_len).len();
+    let serialized = tx.serialize_no_witness();
+    Coinbase {
+        coinb1: serialized[..script_sig_offset + extranonce_start].to_vec(),
+        coinb2: serialized[script_sig_offset + extranonce_end..].to_vec(),
+        tx,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address;
+
+    #[test]
+    fn test_coinbase_layout() {
+        let payouts = [
+            TxOut {
+                value: 300_000_000,
+                script_pubkey: address::p2wpkh_script(&[1; 20]),
+            },
+            TxOut {
+                value: 12_500_000,
+                script_pubkey: address::p2pkh_script(&[2; 20]),
+            },
+        ];
+        let commitment = witness_commitment(&witness_merkle_root(&[]), &[0; 32]);
+        let coinbase = build_coinbase(840_000, 8, b"/pool/", &payouts, Some(&commitment));
+
+        // BIP34 height push, then the 8-byte extranonce push
+        let script_sig = &coinbase.tx.inputs[0].script_sig;
+        assert_eq!(hex::encode(&script_sig[..4]), "0340d10c");
+        assert_eq!(script_sig[4], 8);
+        assert!(coinbase.coinb1.ends_with(&[0x03, 0x40, 0xd1, 0x0c, 0x08]));
+
+        // Commitment output last, reserved value in the witness
+        let last = coinbase.tx.outputs.last().unwrap();
+        assert_eq!(last.value, 0);
+        assert_eq!(hex::encode(&last.script_pubkey[..6]), "6a24aa21a9ed");
+        assert_eq!(coinbase.tx.inputs[0].witness, vec![vec![0; 32]]);
+
+        // Filling the extranonce reproduces the template around it
+        let fill = |extranonce: &[u8]| {
+            let raw = [&coinbase.coinb1[..], extranonce, &coinbase.coinb2[..]].concat();
+            Transaction::parse(&raw).unwrap()
+        };
+        let filled = fill(&[0xab; 8]);
+        assert_eq!(filled.inputs[0].script_sig[5..13], [0xab; 8]);
+        assert_eq!(filled.outputs, coinbase.tx.outputs);
+        assert_eq!(fill(&[0; 8]).txid(), coinbase.tx.txid());
+        assert_ne!(coinbase.tx.txid(), coinbase.tx.wtxid());
+
+        // Small heights use OP_1..OP_16 as in BIP34's CScript() << height
+        let coinbase = build_coinbase(5, 4, b"", &payouts[..1], None);
+        assert_eq!(coinbase.tx.inputs[0].script_sig[0], Opcode::OP_5 as u8);
+        assert!(coinbase.tx.inputs[0].witness.is_empty());
+    }
+}
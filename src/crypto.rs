@@ -1,4 +1,4 @@
-use crate::tx::UTXO;
+use crate::tx::{Transaction, TxIn, TxOut};
 use crate::utils;
 
 use ripemd::Ripemd160;
@@ -25,87 +25,503 @@ pub fn checksum(data: &[u8]) -> Vec<u8> {
     double_sha256(data)[0..4].to_vec()
 }
 
+pub fn tagged_hash(tag: &str, data: &[u8]) -> Vec<u8> {
+    let tag_hash = sha256(tag.as_bytes());
+    let mut preimage = Vec::with_capacity(64 + data.len());
+    preimage.extend(&tag_hash);
+    preimage.extend(&tag_hash);
+    preimage.extend(data);
+    sha256(&preimage)
+}
+
+// Merkle root of hashes in internal byte order; at each level an odd last
+// hash is paired with itself
+pub fn merkle_root(hashes: &[Vec<u8>]) -> Vec<u8> {
+    assert!(!hashes.is_empty(), "merkle root of an empty list");
+
+    let mut level = hashes.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let right = pair.get(1).unwrap_or(&pair[0]);
+                double_sha256(&[pair[0].as_slice(), right].concat())
+            })
+            .collect();
+    }
+    level.remove(0)
+}
+
+// --------
+// Sighash types
+// --------
+
+pub const SIGHASH_DEFAULT: u32 = 0x00; // BIP341 only: ALL without a trailing byte
+pub const SIGHASH_ALL: u32 = 0x01;
+pub const SIGHASH_NONE: u32 = 0x02;
+pub const SIGHASH_SINGLE: u32 = 0x03;
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+fn sighash_base(sighash_type: u32) -> u32 {
+    sighash_type & 0x1f
+}
+
+fn anyone_can_pay(sighash_type: u32) -> bool {
+    sighash_type & SIGHASH_ANYONECANPAY != 0
+}
+
+fn serialize_outpoint(input: &TxIn) -> Vec<u8> {
+    let mut out = Vec::with_capacity(36);
+    out.extend(utils::hex_to_bytes(&input.txid).into_iter().rev());
+    out.extend(utils::u32_le(input.vout));
+    out
+}
+
 // --------
-// Sighash implementations
+// Sighash cache
 // --------
 
-// Legacy sighash (pre-SegWit)
-pub fn legacy_sighash(utxos: &[UTXO], input_index: usize, outputs_serialized: &[u8]) -> Vec<u8> {
-    let mut tx = Vec::new();
-    tx.extend(utils::u32_le(1)); // version
-    tx.extend(utils::varint(utxos.len()));
+// Per-transaction data shared by every input and sighash type, computed once
+// so signing stays linear in the number of inputs
+pub struct SighashCache {
+    version: u32,
+    locktime: u32,
+    outpoints: Vec<Vec<u8>>,
+    sequences: Vec<u32>,
+    outputs: Vec<Vec<u8>>,
+    outputs_blob: Vec<u8>, // count + all serialized outputs
+
+    // Single SHA256 of the concatenated fields (BIP341); BIP143 hashes are
+    // the SHA256 of these
+    sha_prevouts: Vec<u8>,
+    sha_sequences: Vec<u8>,
+    sha_outputs: Vec<u8>,
 
-    for (i, u) in utxos.iter().enumerate() {
-        tx.extend(utils::hex_to_bytes(&u.txid).into_iter().rev());
-        tx.extend(utils::u32_le(u.vout));
+    // Only available when every spent output is known
+    taproot: Option<TaprootHashes>,
+}
+
+struct TaprootHashes {
+    spent: Vec<TxOut>,
+    sha_amounts: Vec<u8>,
+    sha_scriptpubkeys: Vec<u8>,
+}
 
-        if i == input_index {
-            let script = utils::hex_to_bytes(&u.scriptPubKey);
-            tx.extend(utils::varint(script.len()));
-            tx.extend(script);
+impl SighashCache {
+    // `spent` lists the outputs spent by each input; required for BIP341
+    pub fn new(tx: &Transaction, spent: Option<&[TxOut]>) -> SighashCache {
+        let outpoints: Vec<Vec<u8>> = tx.inputs.iter().map(serialize_outpoint).collect();
+        let sequences: Vec<u32> = tx.inputs.iter().map(|i| i.sequence).collect();
+        let outputs: Vec<Vec<u8>> = tx.outputs.iter().map(|o| o.serialize()).collect();
+
+        let mut outputs_blob = utils::varint(outputs.len());
+        outputs_blob.extend(outputs.concat());
+
+        let taproot = spent.filter(|s| s.len() == tx.inputs.len()).map(|spent| {
+            let mut amounts = Sha256::new();
+            let mut scripts = Sha256::new();
+            for o in spent {
+                amounts.update(utils::u64_le(o.value));
+                scripts.update(utils::varint(o.script_pubkey.len()));
+                scripts.update(&o.script_pubkey);
+            }
+
+            TaprootHashes {
+                spent: spent.to_vec(),
+                sha_amounts: amounts.finalize().to_vec(),
+                sha_scriptpubkeys: scripts.finalize().to_vec(),
+            }
+        });
+
+        SighashCache {
+            version: tx.version,
+            locktime: tx.locktime,
+            sha_prevouts: sha256(&outpoints.concat()),
+            sha_sequences: sha256(
+                &sequences
+                    .iter()
+                    .flat_map(|s| utils::u32_le(*s))
+                    .collect::<Vec<u8>>(),
+            ),
+            sha_outputs: sha256(&outputs.concat()),
+            outpoints,
+            sequences,
+            outputs,
+            outputs_blob,
+            taproot,
+        }
+    }
+
+    // Legacy sighash (pre-SegWit)
+    //
+    // `script_code` is the spent scriptPubKey, or the redeem script for P2SH
+    pub fn legacy(&self, input_index: usize, script_code: &[u8], sighash_type: u32) -> Vec<u8> {
+        let base = sighash_base(sighash_type);
+
+        // Consensus quirk: SINGLE without a matching output signs the number one
+        if base == SIGHASH_SINGLE && input_index >= self.outputs.len() {
+            let mut one = vec![0u8; 32];
+            one[0] = 1;
+            return one;
+        }
+
+        let mut preimage = Sha256::new();
+        preimage.update(utils::u32_le(self.version));
+
+        let signed_inputs = if anyone_can_pay(sighash_type) {
+            input_index..input_index + 1
         } else {
-            tx.push(0x00);
+            0..self.outpoints.len()
+        };
+
+        preimage.update(utils::varint(signed_inputs.len()));
+        for i in signed_inputs {
+            preimage.update(&self.outpoints[i]);
+
+            if i == input_index {
+                preimage.update(utils::varint(script_code.len()));
+                preimage.update(script_code);
+            } else {
+                preimage.update([0x00]);
+            }
+
+            // NONE / SINGLE let other inputs update their sequence
+            if i != input_index && (base == SIGHASH_NONE || base == SIGHASH_SINGLE) {
+                preimage.update(utils::u32_le(0));
+            } else {
+                preimage.update(utils::u32_le(self.sequences[i]));
+            }
         }
 
-        tx.extend(utils::u32_le(0xffffffff));
+        // outputs
+        match base {
+            SIGHASH_NONE => preimage.update([0x00]),
+            SIGHASH_SINGLE => {
+                preimage.update(utils::varint(input_index + 1));
+                for _ in 0..input_index {
+                    // blanked output: value -1, empty script
+                    preimage.update(utils::u64_le(u64::MAX));
+                    preimage.update([0x00]);
+                }
+                preimage.update(&self.outputs[input_index]);
+            }
+            _ => preimage.update(&self.outputs_blob),
+        }
+
+        preimage.update(utils::u32_le(self.locktime));
+        preimage.update(utils::u32_le(sighash_type));
+
+        sha256(&preimage.finalize())
     }
 
-    // outputs
-    tx.extend(outputs_serialized);
+    // BIP143 SegWit v0 sighash
+    pub fn bip143(
+        &self,
+        input_index: usize,
+        script_code: &[u8],
+        amount: u64,
+        sighash_type: u32,
+    ) -> Vec<u8> {
+        let base = sighash_base(sighash_type);
+        let acp = anyone_can_pay(sighash_type);
+        let zero = vec![0u8; 32];
 
-    tx.extend(utils::u32_le(0)); // locktime
-    tx.extend(utils::u32_le(1)); // SIGHASH_ALL
+        let hash_prevouts = if acp {
+            zero.clone()
+        } else {
+            sha256(&self.sha_prevouts)
+        };
 
-    double_sha256(&tx)
+        let hash_sequence = if acp || base == SIGHASH_SINGLE || base == SIGHASH_NONE {
+            zero.clone()
+        } else {
+            sha256(&self.sha_sequences)
+        };
+
+        let hash_outputs = if base != SIGHASH_SINGLE && base != SIGHASH_NONE {
+            sha256(&self.sha_outputs)
+        } else if base == SIGHASH_SINGLE && input_index < self.outputs.len() {
+            double_sha256(&self.outputs[input_index])
+        } else {
+            zero
+        };
+
+        let mut sighash = Vec::with_capacity(156 + script_code.len());
+        sighash.extend(utils::u32_le(self.version));
+        sighash.extend(&hash_prevouts);
+        sighash.extend(&hash_sequence);
+
+        // outpoint
+        sighash.extend(&self.outpoints[input_index]);
+
+        // scriptCode
+        sighash.extend(utils::varint(script_code.len()));
+        sighash.extend(script_code);
+
+        // amount
+        sighash.extend(utils::u64_le(amount));
+
+        // sequence
+        sighash.extend(utils::u32_le(self.sequences[input_index]));
+
+        // hashOutputs
+        sighash.extend(&hash_outputs);
+
+        // locktime + sighash type
+        sighash.extend(utils::u32_le(self.locktime));
+        sighash.extend(utils::u32_le(sighash_type));
+
+        double_sha256(&sighash)
+    }
+
+    // BIP341 Taproot sighash; `leaf` (the tapleaf hash and the position of
+    // the last executed OP_CODESEPARATOR) selects a script-path spend.
+    // Returns None without spent outputs, for an invalid hash type, or for
+    // SINGLE without a matching output.
+    pub fn bip341(
+        &self,
+        input_index: usize,
+        sighash_type: u32,
+        leaf: Option<(&[u8], u32)>,
+        annex: Option<&[u8]>,
+    ) -> Option<Vec<u8>> {
+        let taproot = self.taproot.as_ref()?;
+        if !matches!(sighash_type, 0x00..=0x03 | 0x81..=0x83) {
+            return None;
+        }
+
+        let base = sighash_base(sighash_type);
+        let acp = anyone_can_pay(sighash_type);
+
+        let mut msg = Vec::with_capacity(256);
+        msg.push(0x00); // epoch
+        msg.push(sighash_type as u8);
+        msg.extend(utils::u32_le(self.version));
+        msg.extend(utils::u32_le(self.locktime));
+
+        if !acp {
+            msg.extend(&self.sha_prevouts);
+            msg.extend(&taproot.sha_amounts);
+            msg.extend(&taproot.sha_scriptpubkeys);
+            msg.extend(&self.sha_sequences);
+        }
+
+        if base != SIGHASH_NONE && base != SIGHASH_SINGLE {
+            msg.extend(&self.sha_outputs);
+        }
+
+        let ext_flag = if leaf.is_some() { 1 } else { 0 };
+        msg.push(ext_flag * 2 + annex.is_some() as u8);
+
+        if acp {
+            msg.extend(&self.outpoints[input_index]);
+            msg.extend(taproot.spent[input_index].serialize());
+            msg.extend(utils::u32_le(self.sequences[input_index]));
+        } else {
+            msg.extend(utils::u32_le(input_index as u32));
+        }
+
+        if let Some(annex) = annex {
+            let mut a = utils::varint(annex.len());
+            a.extend(annex);
+            msg.extend(sha256(&a));
+        }
+
+        if base == SIGHASH_SINGLE {
+            msg.extend(sha256(self.outputs.get(input_index)?));
+        }
+
+        if let Some((leaf_hash, codesep_pos)) = leaf {
+            msg.extend(leaf_hash);
+            msg.push(0x00); // key version
+            msg.extend(utils::u32_le(codesep_pos));
+        }
+
+        Some(tagged_hash("TapSighash", &msg))
+    }
 }
 
-// BIP143 SegWit v0 sighash
-pub fn bip143_sighash(
-    utxos: &[UTXO],
-    input_index: usize,
-    script_code: &[u8],
-    amount: u64,
-    outputs_serialized: &[u8],
-) -> Vec<u8> {
-    let mut hash_prevouts = Vec::new();
-    let mut hash_sequence = Vec::new();
-
-    for u in utxos {
-        hash_prevouts.extend(utils::hex_to_bytes(&u.txid).into_iter().rev());
-        hash_prevouts.extend(utils::u32_le(u.vout));
-        hash_sequence.extend(utils::u32_le(0xffffffff));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(hex: &str) -> Transaction {
+        Transaction::parse(&utils::hex_to_bytes(hex)).unwrap()
     }
 
-    let hash_prevouts = double_sha256(&hash_prevouts);
-    let hash_sequence = double_sha256(&hash_sequence);
+    // BIP143 P2SH-P2WSH 6-of-6 example, one signature per sighash type
+    #[test]
+    fn test_bip143_sighash_types() {
+        let tx = tx("010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e0100000000ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688acc0832f05000000001976a9147480a33f950689af511e6e84c138dbbd3c3ee41588ac00000000");
+        let witness_script = utils::hex_to_bytes("56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103b28f0c28bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113d703413d57761b8b9781957b8c0ac1dfe69f492580ca4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58c5d1f47de74683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa1322008af9602b3b14862c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a9f01d9f0c19617681024306b56ae");
 
-    let utxo = &utxos[input_index];
+        let expected = [
+            (
+                0x01,
+                "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c",
+            ),
+            (
+                0x02,
+                "e9733bc60ea13c95c6527066bb975a2ff29a925e80aa14c213f686cbae5d2f36",
+            ),
+            (
+                0x03,
+                "1e1f1c303dc025bd664acb72e583e933fae4cff9148bf78c157d1e8f78530aea",
+            ),
+            (
+                0x81,
+                "2a67f03e63a6a422125878b40b82da593be8d4efaafe88ee528af6e5a9955c6e",
+            ),
+            (
+                0x82,
+                "781ba15f3779d5542ce8ecb5c18716733a5ee42a6f51488ec96154934e2c890a",
+            ),
+            (
+                0x83,
+                "511e8e52ed574121fc1b654970395502128263f62662e076dc6baf05c2e6a99b",
+            ),
+        ];
 
-    let mut sighash = Vec::new();
-    sighash.extend(utils::u32_le(1)); // version
-    sighash.extend(&hash_prevouts);
-    sighash.extend(&hash_sequence);
+        let cache = SighashCache::new(&tx, None);
+        for (sighash_type, hash) in expected {
+            assert_eq!(
+                utils::bytes_to_hex(&cache.bip143(0, &witness_script, 987_654_321, sighash_type)),
+                hash
+            );
+        }
+    }
 
-    // outpoint
-    sighash.extend(utils::hex_to_bytes(&utxo.txid).into_iter().rev());
-    sighash.extend(utils::u32_le(utxo.vout));
+    #[test]
+    fn test_legacy_single_without_output_signs_one() {
+        let mut tx = tx("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa8ef3d1b34c7ca0a5e39de2e2f0e5ec00d3b0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000");
+        tx.outputs.truncate(1);
 
-    // scriptCode
-    sighash.extend(utils::varint(script_code.len()));
-    sighash.extend(script_code);
+        let one = SighashCache::new(&tx, None).legacy(1, &[], SIGHASH_SINGLE);
+        assert_eq!(one[0], 1);
+        assert!(one[1..].iter().all(|b| *b == 0));
+    }
 
-    // amount
-    sighash.extend(utils::u64_le(amount));
+    // BIP341 wallet test vectors, keyPathSpending: one input per sighash type
+    #[test]
+    fn test_bip341_key_path_sighash_vectors() {
+        let tx = tx("02000000097de20cbff686da83a54981d2b9bab3586f4ca7e48f57f5b55963115f3b334e9c010000000000000000d7b7cab57b1393ace2d064f4d4a2cb8af6def61273e127517d44759b6dafdd990000000000fffffffff8e1f583384333689228c5d28eac13366be082dc57441760d957275419a418420000000000fffffffff0689180aa63b30cb162a73c6d2a38b7eeda2a83ece74310fda0843ad604853b0100000000feffffffaa5202bdf6d8ccd2ee0f0202afbbb7461d9264a25e5bfd3c5a52ee1239e0ba6c0000000000feffffff956149bdc66faa968eb2be2d2faa29718acbfe3941215893a2a3446d32acd050000000000000000000e664b9773b88c09c32cb70a2a3e4da0ced63b7ba3b22f848531bbb1d5d5f4c94010000000000000000e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb677a632a74ef7eadfd4eabf0000000000ffffffffa778eb6a263dc090464cd125c466b5a99667720b1c110468831d058aa1b82af10100000000ffffffff0200ca9a3b000000001976a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac807840cb0000000020ac9a87f5594be208f8532db38cff670c450ed2fea8fcdefcc9a663f78bab962b0065cd1d");
+        let spent: Vec<TxOut> = [
+            (
+                "512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343",
+                420_000_000,
+            ),
+            (
+                "5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3",
+                462_000_000,
+            ),
+            (
+                "76a914751e76e8199196d454941c45d1b3a323f1433bd688ac",
+                294_000_000,
+            ),
+            (
+                "5120e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e",
+                504_000_000,
+            ),
+            (
+                "512091b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605",
+                630_000_000,
+            ),
+            ("00147dd65592d0ab2fe0d0257d571abf032cd9db93dc", 378_000_000),
+            (
+                "512075169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831",
+                672_000_000,
+            ),
+            (
+                "5120712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5",
+                546_000_000,
+            ),
+            (
+                "512077e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220",
+                588_000_000,
+            ),
+        ]
+        .iter()
+        .map(|(script, value)| TxOut {
+            value: *value,
+            script_pubkey: utils::hex_to_bytes(script),
+        })
+        .collect();
 
-    // sequence
-    sighash.extend(utils::u32_le(0xffffffff));
+        let cache = SighashCache::new(&tx, Some(&spent));
+        let taproot = cache.taproot.as_ref().unwrap();
+        assert_eq!(
+            utils::bytes_to_hex(&cache.sha_prevouts),
+            "e3b33bb4ef3a52ad1fffb555c0d82828eb22737036eaeb02a235d82b909c4c3f"
+        );
+        assert_eq!(
+            utils::bytes_to_hex(&taproot.sha_amounts),
+            "58a6964a4f5f8f0b642ded0a8a553be7622a719da71d1f5befcefcdee8e0fde6"
+        );
+        assert_eq!(
+            utils::bytes_to_hex(&taproot.sha_scriptpubkeys),
+            "23ad0f61ad2bca5ba6a7693f50fce988e17c3780bf2b1e720cfbb38fbdd52e21"
+        );
+        assert_eq!(
+            utils::bytes_to_hex(&cache.sha_sequences),
+            "18959c7221ab5ce9e26c3cd67b22c24f8baa54bac281d8e6b05e400e6c3a957e"
+        );
+        assert_eq!(
+            utils::bytes_to_hex(&cache.sha_outputs),
+            "a2e6dab7c1f0dcd297c8d61647fd17d821541ea69c3cc37dcbad7f90d4eb4bc5"
+        );
 
-    // outputs
-    sighash.extend(outputs_serialized);
+        let expected = [
+            (
+                0,
+                0x03,
+                "2514a6272f85cfa0f45eb907fcb0d121b808ed37c6ea160a5a9046ed5526d555",
+            ),
+            (
+                1,
+                0x83,
+                "325a644af47e8a5a2591cda0ab0723978537318f10e6a63d4eed783b96a71a4d",
+            ),
+            (
+                3,
+                0x01,
+                "bf013ea93474aa67815b1b6cc441d23b64fa310911d991e713cd34c7f5d46669",
+            ),
+            (
+                4,
+                0x00,
+                "4f900a0bae3f1446fd48490c2958b5a023228f01661cda3496a11da502a7f7ef",
+            ),
+            (
+                6,
+                0x02,
+                "15f25c298eb5cdc7eb1d638dd2d45c97c4c59dcaec6679cfc16ad84f30876b85",
+            ),
+            (
+                7,
+                0x82,
+                "cd292de50313804dabe4685e83f923d2969577191a3e1d2882220dca88cbeb10",
+            ),
+            (
+                8,
+                0x81,
+                "cccb739eca6c13a8a89e6e5cd317ffe55669bbda23f2fd37b0f18755e008edd2",
+            ),
+        ];
+        for (input_index, sighash_type, hash) in expected {
+            let sighash = cache.bip341(input_index, sighash_type, None, None).unwrap();
+            assert_eq!(utils::bytes_to_hex(&sighash), hash, "input {}", input_index);
+        }
+    }
 
-    // locktime + sighash type
-    sighash.extend(utils::u32_le(0)); // locktime
-    sighash.extend(utils::u32_le(1)); // SIGHASH_ALL
+    #[test]
+    fn test_merkle_root() {
+        let a = vec![1u8; 32];
+        let b = vec![2u8; 32];
+        let c = vec![3u8; 32];
+        let ab = double_sha256(&[a.clone(), b.clone()].concat());
+        let cc = double_sha256(&[c.clone(), c.clone()].concat());
 
-    double_sha256(&sighash)
+        assert_eq!(merkle_root(std::slice::from_ref(&a)), a);
+        assert_eq!(merkle_root(&[a, b, c]), double_sha256(&[ab, cc].concat()));
+    }
 }
@@ -0,0 +1,579 @@
+use crate::{
+    address, crypto,
+    hd::{self, ExtendedPubKey, HARDENED},
+    keys,
+    multisig::{self, MultisigKind},
+    network::Network,
+};
+use secp256k1::{Parity, PublicKey, XOnlyPublicKey};
+use serde::Serialize;
+
+// --------------------------------------------------
+// Checksum (BIP-380)
+// --------------------------------------------------
+
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}\
+IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn polymod(c: u64, value: u64) -> u64 {
+    const GENERATOR: [u64; 5] = [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ];
+
+    let top = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ value;
+    for (i, g) in GENERATOR.iter().enumerate() {
+        if (top >> i) & 1 != 0 {
+            c ^= g;
+        }
+    }
+    c
+}
+
+// 8-character checksum of a descriptor without its "#..." suffix
+pub fn checksum(descriptor: &str) -> Option<String> {
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+
+    for ch in descriptor.chars() {
+        let pos = INPUT_CHARSET.find(ch)? as u64;
+        c = polymod(c, pos & 31);
+        class = class * 3 + (pos >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+
+    Some(
+        (0..8)
+            .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+            .collect(),
+    )
+}
+
+// --------------------------------------------------
+// Keys
+// --------------------------------------------------
+
+// Where a key came from: master fingerprint and derivation path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyOrigin {
+    pub fingerprint: [u8; 4],
+    pub path: Vec<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyExpr {
+    // Hex public key; `x_only` when written as 32 bytes inside tr()
+    Single {
+        key: PublicKey,
+        x_only: bool,
+    },
+    // xpub / tpub with unhardened steps, optionally ending in /*
+    Extended {
+        xpub: ExtendedPubKey,
+        path: Vec<u32>,
+        ranged: bool,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorKey {
+    pub origin: Option<KeyOrigin>,
+    pub key: KeyExpr,
+}
+
+impl DescriptorKey {
+    fn parse(s: &str, network: Network, allow_x_only: bool) -> Option<DescriptorKey> {
+        let (origin, key) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (origin, key) = rest.split_once(']')?;
+                (Some(parse_origin(origin)?), key)
+            }
+            None => (None, s),
+        };
+
+        let mut parts = key.split('/');
+        let head = parts.next()?;
+        let steps: Vec<&str> = parts.collect();
+
+        let key = if let Ok(bytes) = hex::decode(head) {
+            if !steps.is_empty() {
+                return None;
+            }
+            match bytes.len() {
+                33 => KeyExpr::Single {
+                    key: PublicKey::from_slice(&bytes).ok()?,
+                    x_only: false,
+                },
+                32 if allow_x_only => KeyExpr::Single {
+                    key: PublicKey::from_x_only_public_key(
+                        XOnlyPublicKey::from_slice(&bytes).ok()?,
+                        Parity::Even,
+                    ),
+                    x_only: true,
+                },
+                _ => return None,
+            }
+        } else {
+            let xpub = ExtendedPubKey::from_base58(head, network)?;
+            let (steps, ranged) = match steps.split_last() {
+                Some((&"*", rest)) => (rest, true),
+                _ => (&steps[..], false),
+            };
+
+            // Watch-only: hardened steps need the private key
+            let path = steps
+                .iter()
+                .map(|step| step.parse::<u32>().ok().filter(|i| *i < HARDENED))
+                .collect::<Option<Vec<u32>>>()?;
+
+            KeyExpr::Extended { xpub, path, ranged }
+        };
+
+        Some(DescriptorKey { origin, key })
+    }
+
+    fn to_string(&self, network: Network) -> String {
+        let mut out = String::new();
+        if let Some(origin) = &self.origin {
+            out.push('[');
+            out.push_str(&hex::encode(origin.fingerprint));
+            out.push_str(&format_path(&origin.path));
+            out.push(']');
+        }
+
+        match &self.key {
+            KeyExpr::Single { key, x_only: true } => {
+                out.push_str(&hex::encode(key.x_only_public_key().0.serialize()))
+            }
+            KeyExpr::Single { key, .. } => out.push_str(&hex::encode(key.serialize())),
+            KeyExpr::Extended { xpub, path, ranged } => {
+                out.push_str(&xpub.to_base58(network));
+                out.push_str(&format_path(path));
+                if *ranged {
+                    out.push_str("/*");
+                }
+            }
+        }
+        out
+    }
+
+    fn is_ranged(&self) -> bool {
+        matches!(self.key, KeyExpr::Extended { ranged: true, .. })
+    }
+
+    // Public key at `index` (ignored for non-ranged keys)
+    fn derive(&self, index: u32) -> PublicKey {
+        match &self.key {
+            KeyExpr::Single { key, .. } => *key,
+            KeyExpr::Extended { xpub, path, ranged } => {
+                let mut path = path.clone();
+                if *ranged {
+                    path.push(index);
+                }
+                xpub.derive_indexes(&path)
+                    .expect("invalid derived key")
+                    .public_key
+            }
+        }
+    }
+}
+
+// "d34db33f/84'/0'/0'"
+fn parse_origin(origin: &str) -> Option<KeyOrigin> {
+    let (fingerprint, path) = origin.split_once('/').unwrap_or((origin, ""));
+    let path = if path.is_empty() {
+        Vec::new()
+    } else {
+        hd::parse_path(&format!("m/{}", path))?
+    };
+
+    Some(KeyOrigin {
+        fingerprint: hex::decode(fingerprint).ok()?.try_into().ok()?,
+        path,
+    })
+}
+
+fn format_path(path: &[u32]) -> String {
+    path.iter()
+        .map(|i| {
+            if *i >= HARDENED {
+                format!("/{}'", i - HARDENED)
+            } else {
+                format!("/{}", i)
+            }
+        })
+        .collect()
+}
+
+// --------------------------------------------------
+// Descriptors
+// --------------------------------------------------
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Descriptor {
+    Pkh(DescriptorKey),
+    Wpkh(DescriptorKey),
+    ShWpkh(DescriptorKey),
+    // multi() / sortedmulti() inside sh(), wsh() or sh(wsh())
+    Multisig {
+        kind: MultisigKind,
+        threshold: usize,
+        keys: Vec<DescriptorKey>,
+        sorted: bool,
+    },
+    // Key path only
+    Tr(DescriptorKey),
+}
+
+// Contents of `name(...)`
+fn call<'a>(s: &'a str, name: &str) -> Option<&'a str> {
+    s.strip_prefix(name)?.strip_prefix('(')?.strip_suffix(')')
+}
+
+impl Descriptor {
+    // Parse a descriptor, verifying its checksum when one is attached
+    pub fn parse(s: &str, network: Network) -> Option<Descriptor> {
+        let body = match s.split_once('#') {
+            Some((body, sum)) => {
+                if checksum(body)? != sum {
+                    return None;
+                }
+                body
+            }
+            None => {
+                checksum(s)?;
+                s
+            }
+        };
+
+        if let Some(inner) = call(body, "pkh") {
+            return Some(Descriptor::Pkh(DescriptorKey::parse(
+                inner, network, false,
+            )?));
+        }
+        if let Some(inner) = call(body, "wpkh") {
+            return Some(Descriptor::Wpkh(DescriptorKey::parse(
+                inner, network, false,
+            )?));
+        }
+        if let Some(inner) = call(body, "tr") {
+            return Some(Descriptor::Tr(DescriptorKey::parse(inner, network, true)?));
+        }
+        if let Some(inner) = call(body, "sh") {
+            if let Some(key) = call(inner, "wpkh") {
+                return Some(Descriptor::ShWpkh(DescriptorKey::parse(
+                    key, network, false,
+                )?));
+            }
+            if let Some(script) = call(inner, "wsh") {
+                return parse_multi(script, MultisigKind::P2shP2wsh, network);
+            }
+            return parse_multi(inner, MultisigKind::P2sh, network);
+        }
+        if let Some(script) = call(body, "wsh") {
+            return parse_multi(script, MultisigKind::P2wsh, network);
+        }
+        None
+    }
+
+    // Canonical form with checksum
+    pub fn to_string(&self, network: Network) -> String {
+        let body = match self {
+            Descriptor::Pkh(key) => format!("pkh({})", key.to_string(network)),
+            Descriptor::Wpkh(key) => format!("wpkh({})", key.to_string(network)),
+            Descriptor::ShWpkh(key) => format!("sh(wpkh({}))", key.to_string(network)),
+            Descriptor::Tr(key) => format!("tr({})", key.to_string(network)),
+            Descriptor::Multisig {
+                kind,
+                threshold,
+                keys,
+                sorted,
+            } => {
+                let keys: Vec<String> = keys.iter().map(|k| k.to_string(network)).collect();
+                let multi = format!(
+                    "{}({},{})",
+                    if *sorted { "sortedmulti" } else { "multi" },
+                    threshold,
+                    keys.join(",")
+                );
+                match kind {
+                    MultisigKind::P2sh => format!("sh({})", multi),
+                    MultisigKind::P2shP2wsh => format!("sh(wsh({}))", multi),
+                    MultisigKind::P2wsh => format!("wsh({})", multi),
+                }
+            }
+        };
+
+        let sum = checksum(&body).expect("descriptor uses only checksum characters");
+        format!("{}#{}", body, sum)
+    }
+
+    // Whether any key ends in /* (one script per index)
+    pub fn is_ranged(&self) -> bool {
+        match self {
+            Descriptor::Pkh(key)
+            | Descriptor::Wpkh(key)
+            | Descriptor::ShWpkh(key)
+            | Descriptor::Tr(key) => key.is_ranged(),
+            Descriptor::Multisig { keys, .. } => keys.iter().any(DescriptorKey::is_ranged),
+        }
+    }
+
+    fn multisig_script(&self, index: u32) -> Option<(MultisigKind, Vec<u8>)> {
+        match self {
+            Descriptor::Multisig {
+                kind,
+                threshold,
+                keys,
+                sorted,
+            } => {
+                let pubkeys: Vec<PublicKey> = keys.iter().map(|k| k.derive(index)).collect();
+                let script = if *sorted {
+                    multisig::multisig_script(*threshold, &pubkeys)
+                } else {
+                    multisig::ordered_multisig_script(*threshold, &pubkeys)
+                };
+                Some((*kind, script))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn script_pubkey(&self, index: u32) -> Vec<u8> {
+        assert!(index < HARDENED, "derivation index out of range");
+
+        match self {
+            Descriptor::Pkh(key) => address::p2pkh_script(&key_hash(key, index)),
+            Descriptor::Wpkh(key) => address::p2wpkh_script(&key_hash(key, index)),
+            Descriptor::ShWpkh(key) => address::p2sh_script(&crypto::hash160(
+                &address::p2wpkh_script(&key_hash(key, index)),
+            )),
+            Descriptor::Tr(key) => {
+                let internal = key.derive(index).x_only_public_key().0;
+                address::p2tr_script(&keys::taproot_output_key(&internal, None).0.serialize())
+            }
+            Descriptor::Multisig { .. } => {
+                let (kind, script) = self.multisig_script(index).unwrap();
+                multisig::script_pubkey(kind, &script)
+            }
+        }
+    }
+
+    pub fn address(&self, index: u32, network: Network) -> String {
+        assert!(index < HARDENED, "derivation index out of range");
+
+        match self {
+            Descriptor::Pkh(key) => address::pubkey_to_address(&key.derive(index), network),
+            Descriptor::Wpkh(key) => {
+                address::pubkey_to_bech32(&key.derive(index), network.bech32_hrp())
+            }
+            Descriptor::ShWpkh(key) => address::pubkey_to_p2sh_p2wpkh(&key.derive(index), network),
+            Descriptor::Tr(key) => {
+                address::pubkey_to_p2tr(&key.derive(index), network.bech32_hrp())
+            }
+            Descriptor::Multisig { .. } => {
+                let (kind, script) = self.multisig_script(index).unwrap();
+                multisig::multisig_address(kind, &script, network)
+            }
+        }
+    }
+}
+
+fn key_hash(key: &DescriptorKey, index: u32) -> Vec<u8> {
+    crypto::hash160(&key.derive(index).serialize())
+}
+
+fn parse_multi(s: &str, kind: MultisigKind, network: Network) -> Option<Descriptor> {
+    let (args, sorted) = match call(s, "sortedmulti") {
+        Some(args) => (args, true),
+        None => (call(s, "multi")?, false),
+    };
+
+    let mut args = args.split(',');
+    let threshold: usize = args.next()?.parse().ok()?;
+    let keys = args
+        .map(|k| DescriptorKey::parse(k, network, false))
+        .collect::<Option<Vec<DescriptorKey>>>()?;
+
+    if threshold == 0 || threshold > keys.len() || keys.len() > kind.max_keys() {
+        return None;
+    }
+
+    Some(Descriptor::Multisig {
+        kind,
+        threshold,
+        keys,
+        sorted,
+    })
+}
+
+// --------------------------------------------------
+// Address derivation
+// --------------------------------------------------
+
+#[derive(Serialize)]
+pub struct DerivedAddress {
+    pub index: u32,
+    pub address: String,
+    pub script_pubkey: String,
+}
+
+// `count` consecutive addresses starting at `start` (a single entry for
+// non-ranged descriptors)
+pub fn derive_addresses(
+    descriptor: &Descriptor,
+    start: u32,
+    count: u32,
+    network: Network,
+) -> Vec<DerivedAddress> {
+    let count = if descriptor.is_ranged() { count } else { 1 };
+
+    (start..start.saturating_add(count))
+        .map(|index| DerivedAddress {
+            index,
+            address: descriptor.address(index, network),
+            script_pubkey: hex::encode(descriptor.script_pubkey(index)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hd::ExtendedPrivKey;
+
+    #[test]
+    fn test_checksum_vectors() {
+        // BIP-380 / Bitcoin Core examples
+        assert_eq!(checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(
+            checksum("addr(mkmZxiEcEd8ZqjQWVZuC6so5dFMKEFpN2j)").unwrap(),
+            "02wpgw69"
+        );
+        assert!(checksum("pkh(\u{e9})").is_none());
+    }
+
+    #[test]
+    fn test_round_trip_and_derivation() {
+        let network = Network::Mainnet;
+        let master = ExtendedPrivKey::from_seed(&[1; 32]);
+        let account = master.derive_path("m/84'/0'/0'").unwrap();
+        let xpub = account.to_public().to_base58(network);
+        let origin = format!("[{}/84'/0'/0']", hex::encode(master.fingerprint()));
+
+        let text = format!("wpkh({}{}/0/*)", origin, xpub);
+        let descriptor = Descriptor::parse(&text, network).unwrap();
+        let canonical = descriptor.to_string(network);
+        assert_eq!(canonical, format!("{}#{}", text, checksum(&text).unwrap()));
+        assert_eq!(
+            Descriptor::parse(&canonical, network),
+            Some(descriptor.clone())
+        );
+        assert!(descriptor.is_ranged());
+
+        // Address 5 matches the private derivation of m/84'/0'/0'/0/5
+        let key = master.derive_path("m/84'/0'/0'/0/5").unwrap().public_key();
+        assert_eq!(
+            descriptor.address(5, network),
+            address::pubkey_to_bech32(&key, network.bech32_hrp())
+        );
+        assert_eq!(
+            descriptor.script_pubkey(5),
+            address::address_to_scriptpubkey(&descriptor.address(5, network), network)
+        );
+
+        // A corrupted checksum, hardened steps after an xpub
+        let mut corrupted = canonical.clone();
+        corrupted.pop();
+        corrupted.push('x');
+        assert!(Descriptor::parse(&corrupted, network).is_none());
+        assert!(Descriptor::parse(&format!("wpkh({}/0'/*)", xpub), network).is_none());
+    }
+
+    #[test]
+    fn test_multisig_and_taproot() {
+        let network = Network::Testnet;
+        let keys: Vec<PublicKey> = (1u8..=3)
+            .map(|i| keys::privkey_to_pubkey(&secp256k1::SecretKey::from_slice(&[i; 32]).unwrap()))
+            .collect();
+        let hex_keys: Vec<String> = keys.iter().map(|k| hex::encode(k.serialize())).collect();
+
+        for (text, kind) in [
+            ("sh(sortedmulti(2,{}))", MultisigKind::P2sh),
+            ("sh(wsh(sortedmulti(2,{})))", MultisigKind::P2shP2wsh),
+            ("wsh(sortedmulti(2,{}))", MultisigKind::P2wsh),
+        ] {
+            let descriptor =
+                Descriptor::parse(&text.replace("{}", &hex_keys.join(",")), network).unwrap();
+            let script = multisig::multisig_script(2, &keys);
+            assert_eq!(
+                descriptor.address(0, network),
+                multisig::multisig_address(kind, &script, network)
+            );
+        }
+
+        // multi() keeps the written order, sortedmulti() does not
+        let reversed: Vec<String> = hex_keys.iter().rev().cloned().collect();
+        let multi = Descriptor::parse(&format!("wsh(multi(1,{}))", reversed.join(",")), network);
+        let sorted = Descriptor::parse(
+            &format!("wsh(sortedmulti(1,{}))", reversed.join(",")),
+            network,
+        );
+        assert_ne!(
+            multi.unwrap().script_pubkey(0),
+            sorted.unwrap().script_pubkey(0)
+        );
+
+        // tr() accepts x-only keys and renders them back as x-only
+        let x_only = &hex_keys[0][2..];
+        let tr = Descriptor::parse(&format!("tr({})", x_only), network).unwrap();
+        assert!(tr
+            .to_string(network)
+            .starts_with(&format!("tr({})#", x_only)));
+    }
+
+    #[test]
+    fn test_multisig_key_limits() {
+        let network = Network::Testnet;
+        let hex_keys: Vec<String> = (1u8..=21)
+            .map(|i| {
+                let key = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+                hex::encode(keys::privkey_to_pubkey(&key).serialize())
+            })
+            .collect();
+        let multi = |wrapper: &str, n: usize| {
+            let text = wrapper.replace("{}", &format!("multi(2,{})", hex_keys[..n].join(",")));
+            Descriptor::parse(&text, network)
+        };
+
+        // Segwit scripts take up to 20 keys, P2SH redeem scripts 15
+        for n in 16..=20 {
+            let descriptor = multi("wsh({})", n).unwrap();
+            let (_, script) = descriptor.multisig_script(0).unwrap();
+            let (m, keys) = multisig::parse_multisig_script(&script).unwrap();
+            assert_eq!((m, keys.len()), (2, n));
+            assert!(multi("sh(wsh({}))", n).is_some());
+            assert!(multi("sh({})", n).is_none());
+        }
+        assert!(multi("sh({})", 15).is_some());
+        assert!(multi("wsh({})", 21).is_none());
+    }
+}
@@ -0,0 +1,421 @@
+use crate::{block::BlockHeader, crypto, tx::Transaction, tx::UTXO, utils};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+
+const HEADERS_SUBSCRIBE: &str = "blockchain.headers.subscribe";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ElectrumError {
+    Server(String),
+    InvalidResponse,
+}
+
+impl ElectrumError {
+    pub fn message(&self) -> String {
+        match self {
+            ElectrumError::Server(e) => format!("server error: {}", e),
+            ElectrumError::InvalidResponse => "invalid response".to_string(),
+        }
+    }
+}
+
+// --------------------
+// Protocol types
+// --------------------
+
+// Electrum script hash: SHA256 of the scriptPubKey, byte-reversed hex
+pub fn scripthash(script_pubkey: &[u8]) -> String {
+    let mut hash = crypto::sha256(script_pubkey);
+    hash.reverse();
+    utils::bytes_to_hex(&hash)
+}
+
+#[derive(Deserialize)]
+struct Unspent {
+    tx_hash: String,
+    tx_pos: u32,
+    height: u32,
+    value: u64,
+}
+
+// blockchain.scripthash.get_history entry; height 0 (or -1 with unconfirmed
+// parents) while in the mempool
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryItem {
+    pub tx_hash: String,
+    pub height: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RawHeader {
+    height: u32,
+    hex: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderNotification {
+    pub height: u32,
+    pub header: BlockHeader,
+}
+
+impl HeaderNotification {
+    fn from_value(value: Value) -> Option<HeaderNotification> {
+        let raw: RawHeader = serde_json::from_value(value).ok()?;
+        Some(HeaderNotification {
+            height: raw.height,
+            header: BlockHeader::parse(&hex::decode(raw.hex).ok()?)?,
+        })
+    }
+}
+
+// --------------------
+// Client
+// --------------------
+
+// What an outstanding request asked for, to decode its response
+enum Pending {
+    ListUnspent(Vec<u8>),
+    GetHistory,
+    GetTransaction(String),
+    Broadcast,
+    SubscribeHeaders,
+}
+
+pub enum Response {
+    Unspent(Vec<UTXO>),
+    History(Vec<HistoryItem>),
+    Transaction(Transaction),
+    // Txid the server reports for a broadcast
+    Broadcast(String),
+    // Current tip from a header subscription
+    Tip(HeaderNotification),
+}
+
+// Electrum protocol state for one connection. The connection itself stays
+// with the caller (e.g. a browser WebSocket): request methods return the
+// message to send, and every message received goes to `handle_message`.
+#[derive(Default)]
+pub struct ElectrumClient {
+    next_id: u64,
+    pending: HashMap<u64, Pending>,
+    // Header notifications received but not yet taken
+    headers: VecDeque<HeaderNotification>,
+}
+
+impl ElectrumClient {
+    pub fn new() -> ElectrumClient {
+        ElectrumClient::default()
+    }
+
+    fn request(&mut self, method: &str, params: Value, pending: Pending) -> String {
+        self.next_id += 1;
+        self.pending.insert(self.next_id, pending);
+        json!({ "jsonrpc": "2.0", "id": self.next_id, "method": method, "params": params })
+            .to_string()
+    }
+
+    // Unspent outputs of a script, including mempool ones (height 0). The
+    // server does not say which came from a coinbase: fetch their
+    // transactions and pass them to `tx::mark_coinbase` before spending.
+    pub fn list_unspent(&mut self, script_pubkey: &[u8]) -> String {
+        self.request(
+            "blockchain.scripthash.listunspent",
+            json!([scripthash(script_pubkey)]),
+            Pending::ListUnspent(script_pubkey.to_vec()),
+        )
+    }
+
+    pub fn get_history(&mut self, script_pubkey: &[u8]) -> String {
+        self.request(
+            "blockchain.scripthash.get_history",
+            json!([scripthash(script_pubkey)]),
+            Pending::GetHistory,
+        )
+    }
+
+    // Raw transaction by txid, checked against the txid it was asked for
+    pub fn get_transaction(&mut self, txid: &str) -> String {
+        self.request(
+            "blockchain.transaction.get",
+            json!([txid]),
+            Pending::GetTransaction(txid.to_string()),
+        )
+    }
+
+    pub fn broadcast(&mut self, raw_tx: &[u8]) -> String {
+        self.request(
+            "blockchain.transaction.broadcast",
+            json!([utils::bytes_to_hex(raw_tx)]),
+            Pending::Broadcast,
+        )
+    }
+
+    // Subscribe to new tips; the response carries the current one, later
+    // ones arrive as notifications for `take_headers`
+    pub fn subscribe_headers(&mut self) -> String {
+        self.request(HEADERS_SUBSCRIBE, json!([]), Pending::SubscribeHeaders)
+    }
+
+    // Feed a message from the server. A response to one of our requests
+    // yields its id and decoded result; header notifications are queued and,
+    // like unparsable messages and responses to unknown ids, yield None.
+    pub fn handle_message(
+        &mut self,
+        message: &str,
+    ) -> Option<(u64, Result<Response, ElectrumError>)> {
+        let mut value: Value = serde_json::from_str(message).ok()?;
+        if value["method"] == HEADERS_SUBSCRIBE {
+            let header = value["params"]
+                .get(0)
+                .cloned()
+                .and_then(HeaderNotification::from_value)?;
+            self.headers.push_back(header);
+            return None;
+        }
+
+        let id = value["id"].as_u64()?;
+        let pending = self.pending.remove(&id)?;
+        if let Some(error) = value.get("error").filter(|e| !e.is_null()) {
+            let message = error["message"].as_str().map(String::from);
+            return Some((
+                id,
+                Err(ElectrumError::Server(message.unwrap_or(error.to_string()))),
+            ));
+        }
+        let response =
+            decode(pending, value["result"].take()).ok_or(ElectrumError::InvalidResponse);
+        Some((id, response))
+    }
+
+    // Header notifications in arrival order
+    pub fn take_headers(&mut self) -> Vec<HeaderNotification> {
+        self.headers.drain(..).collect()
+    }
+}
+
+fn decode(pending: Pending, result: Value) -> Option<Response> {
+    Some(match pending {
+        Pending::ListUnspent(script_pubkey) => {
+            let unspent: Vec<Unspent> = serde_json::from_value(result).ok()?;
+            let script_hex = utils::bytes_to_hex(&script_pubkey);
+            Response::Unspent(
+                unspent
+                    .into_iter()
+                    .map(|u| UTXO {
+                        txid: u.tx_hash,
+                        vout: u.tx_pos,
+                        scriptPubKey: script_hex.clone(),
+                        amount: u.value,
+                        height: Some(u.height),
+                        ..Default::default()
+                    })
+                    .collect(),
+            )
+        }
+        Pending::GetHistory => Response::History(serde_json::from_value(result).ok()?),
+        Pending::GetTransaction(txid) => {
+            let tx = Transaction::parse(&hex::decode(result.as_str()?).ok()?)?;
+            Response::Transaction((tx.txid() == txid).then_some(tx)?)
+        }
+        Pending::Broadcast => Response::Broadcast(result.as_str()?.to_string()),
+        Pending::SubscribeHeaders => Response::Tip(HeaderNotification::from_value(result)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        address, coinbase,
+        tx::{self, TxIn, TxOut},
+    };
+
+    // The server's reply to a request message: `result`, or an error
+    fn answer(request: &str, result: Option<Value>) -> String {
+        let request: Value = serde_json::from_str(request).unwrap();
+        let mut response = json!({ "jsonrpc": "2.0", "id": request["id"] });
+        match result {
+            Some(result) => response["result"] = result,
+            None => response["error"] = json!({ "code": 1, "message": "unknown method" }),
+        }
+        response.to_string()
+    }
+
+    fn header_hex(time: u32) -> String {
+        let header = BlockHeader {
+            version: 1,
+            prev_blockhash: "00".repeat(32),
+            merkle_root: "11".repeat(32),
+            time,
+            bits: 0x207fffff,
+            nonce: 0,
+        };
+        hex::encode(header.serialize())
+    }
+
+    #[test]
+    fn test_unspent_and_history() {
+        let script = address::p2wpkh_script(&[7; 20]);
+        let mut client = ElectrumClient::new();
+
+        let request = client.list_unspent(&script);
+        let sent: Value = serde_json::from_str(&request).unwrap();
+        assert_eq!(sent["method"], "blockchain.scripthash.listunspent");
+        assert_eq!(sent["params"][0], scripthash(&script));
+
+        let unspent = json!([
+            { "tx_hash": "22".repeat(32), "tx_pos": 0, "height": 850_000, "value": 40_000 },
+            { "tx_hash": "33".repeat(32), "tx_pos": 2, "height": 0, "value": 1_000 },
+        ]);
+        let Some((1, Ok(Response::Unspent(utxos)))) =
+            client.handle_message(&answer(&request, Some(unspent)))
+        else {
+            panic!("expected UTXOs");
+        };
+        assert_eq!(utxos.len(), 2);
+        assert_eq!((utxos[0].amount, utxos[0].height), (40_000, Some(850_000)));
+        assert_eq!(utxos[1].confirmations_at(850_010), 0);
+        assert_eq!(utxos[1].scriptPubKey, hex::encode(&script));
+
+        let request = client.get_history(&script);
+        let history = json!([
+            { "tx_hash": "22".repeat(32), "height": 850_000 },
+            { "tx_hash": "33".repeat(32), "height": -1, "fee": 200 },
+        ]);
+        let Some((2, Ok(Response::History(history)))) =
+            client.handle_message(&answer(&request, Some(history)))
+        else {
+            panic!("expected history");
+        };
+        assert_eq!((history[1].height, history[1].fee), (-1, Some(200)));
+    }
+
+    #[test]
+    fn test_transactions_checked_against_txid() {
+        let tx = Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                txid: "22".repeat(32),
+                vout: 1,
+                script_sig: Vec::new(),
+                sequence: 0xfffffffd,
+                witness: vec![vec![1; 71], vec![2; 33]],
+            }],
+            outputs: vec![TxOut {
+                value: 40_000,
+                script_pubkey: address::p2wpkh_script(&[7; 20]),
+            }],
+            locktime: 0,
+        };
+        let raw = json!(hex::encode(tx.serialize()));
+        let mut client = ElectrumClient::new();
+
+        let request = client.get_transaction(&tx.txid());
+        let Some((1, Ok(Response::Transaction(fetched)))) =
+            client.handle_message(&answer(&request, Some(raw.clone())))
+        else {
+            panic!("expected transaction");
+        };
+        assert_eq!(fetched, tx);
+        let request = client.get_transaction(&"44".repeat(32));
+        assert!(matches!(
+            client.handle_message(&answer(&request, Some(raw))),
+            Some((2, Err(ElectrumError::InvalidResponse)))
+        ));
+
+        let request = client.broadcast(&tx.serialize());
+        let sent: Value = serde_json::from_str(&request).unwrap();
+        assert_eq!(sent["params"][0], hex::encode(tx.serialize()));
+        assert!(matches!(
+            client.handle_message(&answer(&request, Some(json!(tx.txid())))),
+            Some((3, Ok(Response::Broadcast(txid)))) if txid == tx.txid()
+        ));
+    }
+
+    #[test]
+    fn test_header_notifications() {
+        let mut client = ElectrumClient::new();
+        let request = client.subscribe_headers();
+        let tip = json!({ "height": 850_000, "hex": header_hex(1) });
+
+        // A notification arriving ahead of the response is queued
+        let notification = json!({ "jsonrpc": "2.0", "method": HEADERS_SUBSCRIBE, "params": [{ "height": 850_001, "hex": header_hex(2) }] });
+        assert!(client.handle_message(&notification.to_string()).is_none());
+        let Some((1, Ok(Response::Tip(tip)))) = client.handle_message(&answer(&request, Some(tip)))
+        else {
+            panic!("expected tip");
+        };
+        assert_eq!(tip.height, 850_000);
+
+        let headers = client.take_headers();
+        assert_eq!(headers.len(), 1);
+        assert_eq!((headers[0].height, headers[0].header.time), (850_001, 2));
+        assert!(client.take_headers().is_empty());
+    }
+
+    #[test]
+    fn test_errors_and_unknown_messages() {
+        let mut client = ElectrumClient::new();
+        let request = client.get_history(&[]);
+        assert!(matches!(
+            client.handle_message(&answer(&request, None)),
+            Some((1, Err(ElectrumError::Server(e)))) if e == "unknown method"
+        ));
+        // Answered already, never asked, or not JSON
+        assert!(client
+            .handle_message(&answer(&request, Some(json!([]))))
+            .is_none());
+        assert!(client.handle_message(r#"{"id":9,"result":[]}"#).is_none());
+        assert!(client.handle_message("not json").is_none());
+
+        // SHA256 of the empty script, byte-reversed
+        assert_eq!(
+            scripthash(&[]),
+            "55b852781b9995a44c939b64e441ae2724b96f99c8f4fb9a141cfc9842c4b0e3"
+        );
+    }
+
+    #[test]
+    fn test_coinbase_unspent_marked_from_its_transaction() {
+        let script = address::p2wpkh_script(&[7; 20]);
+        let reward = coinbase::build_coinbase(
+            850_000,
+            4,
+            b"",
+            &[TxOut {
+                value: 312_500_000,
+                script_pubkey: script.clone(),
+            }],
+            None,
+        )
+        .tx;
+        let mut client = ElectrumClient::new();
+
+        let request = client.list_unspent(&script);
+        let unspent = json!([
+            { "tx_hash": reward.txid(), "tx_pos": 0, "height": 850_000, "value": 312_500_000 },
+            { "tx_hash": "22".repeat(32), "tx_pos": 0, "height": 850_000, "value": 40_000 },
+        ]);
+        let Some((_, Ok(Response::Unspent(mut utxos)))) =
+            client.handle_message(&answer(&request, Some(unspent)))
+        else {
+            panic!("expected UTXOs");
+        };
+        assert!(utxos.iter().all(|u| !u.is_coinbase));
+
+        let request = client.get_transaction(&reward.txid());
+        let raw = json!(hex::encode(reward.serialize()));
+        let Some((_, Ok(Response::Transaction(funding)))) =
+            client.handle_message(&answer(&request, Some(raw)))
+        else {
+            panic!("expected transaction");
+        };
+        tx::mark_coinbase(&mut utxos, &[funding]);
+
+        assert!(utxos[0].is_coinbase && !utxos[1].is_coinbase);
+        assert_eq!(tx::balance(&utxos, 850_010).immature, 312_500_000);
+    }
+}
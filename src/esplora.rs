@@ -0,0 +1,305 @@
+use crate::{
+    feerate::{FeeRecommendation, ECONOMY, NEXT_BLOCK, SIX_BLOCKS},
+    tx::Transaction,
+    tx::UTXO,
+    utils,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// --------------------
+// HTTP
+// --------------------
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HttpRequest {
+    pub method: &'static str,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EsploraError {
+    Http(u16, String),
+    InvalidResponse,
+}
+
+impl EsploraError {
+    pub fn message(&self) -> String {
+        match self {
+            EsploraError::Http(status, body) => format!("HTTP {}: {}", status, body),
+            EsploraError::InvalidResponse => "invalid response".to_string(),
+        }
+    }
+}
+
+// Body of a successful (2xx) response
+pub fn response_body(status: u16, body: &str) -> Result<&str, EsploraError> {
+    if !(200..300).contains(&status) {
+        return Err(EsploraError::Http(status, body.to_string()));
+    }
+    Ok(body)
+}
+
+// --------------------
+// Request builders
+// --------------------
+
+fn get(base_url: &str, path: &str) -> HttpRequest {
+    HttpRequest {
+        method: "GET",
+        url: format!("{}{}", base_url.trim_end_matches('/'), path),
+        body: None,
+    }
+}
+
+pub fn address_utxos_request(base_url: &str, address: &str) -> HttpRequest {
+    get(base_url, &format!("/address/{}/utxo", address))
+}
+
+pub fn tx_status_request(base_url: &str, txid: &str) -> HttpRequest {
+    get(base_url, &format!("/tx/{}/status", txid))
+}
+
+pub fn raw_tx_request(base_url: &str, txid: &str) -> HttpRequest {
+    get(base_url, &format!("/tx/{}/hex", txid))
+}
+
+pub fn fee_estimates_request(base_url: &str) -> HttpRequest {
+    get(base_url, "/fee-estimates")
+}
+
+// POST /tx with the raw transaction hex as the body
+pub fn broadcast_request(base_url: &str, raw_tx: &[u8]) -> HttpRequest {
+    HttpRequest {
+        method: "POST",
+        url: format!("{}/tx", base_url.trim_end_matches('/')),
+        body: Some(utils::bytes_to_hex(raw_tx)),
+    }
+}
+
+// --------------------
+// Response parsers
+// --------------------
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxStatus {
+    pub confirmed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_time: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct AddressUtxo {
+    txid: String,
+    vout: u32,
+    value: u64,
+    status: TxStatus,
+}
+
+// /address/:address/utxo as UTXOs of `script_pubkey`; height 0 while in
+// the mempool
+pub fn parse_address_utxos(body: &str, script_pubkey: &[u8]) -> Option<Vec<UTXO>> {
+    let utxos: Vec<AddressUtxo> = serde_json::from_str(body).ok()?;
+    let script_hex = utils::bytes_to_hex(script_pubkey);
+    Some(
+        utxos
+            .into_iter()
+            .map(|u| UTXO {
+                txid: u.txid,
+                vout: u.vout,
+                scriptPubKey: script_hex.clone(),
+                amount: u.value,
+                height: Some(
+                    u.status
+                        .block_height
+                        .filter(|_| u.status.confirmed)
+                        .unwrap_or(0),
+                ),
+                ..Default::default()
+            })
+            .collect(),
+    )
+}
+
+pub fn parse_tx_status(body: &str) -> Option<TxStatus> {
+    serde_json::from_str(body).ok()
+}
+
+// POST /tx answers with the txid
+pub fn parse_broadcast(body: &str) -> Option<String> {
+    let txid = body.trim();
+    (txid.len() == 64 && hex::decode(txid).is_ok()).then(|| txid.to_string())
+}
+
+// /tx/:txid/hex, checked against the txid it was asked for
+pub fn parse_raw_tx(body: &str, txid: &str) -> Option<Transaction> {
+    let tx = Transaction::parse(&hex::decode(body.trim()).ok()?)?;
+    (tx.txid() == txid).then_some(tx)
+}
+
+// /fee-estimates: confirmation target (blocks) to fee rate (sat/vB)
+pub fn parse_fee_estimates(body: &str) -> Option<BTreeMap<u64, f64>> {
+    let estimates: BTreeMap<String, f64> = serde_json::from_str(body).ok()?;
+    estimates
+        .into_iter()
+        .map(|(target, rate)| Some((target.parse().ok()?, rate)))
+        .collect()
+}
+
+// Fee estimates as a recommendation: each target takes the estimate for the
+// nearest target at or below it (confirming at least as fast), the fastest
+// estimate when there is none, and nothing goes below `min_relay_fee`
+pub fn fee_recommendation(estimates: &BTreeMap<u64, f64>, min_relay_fee: u64) -> FeeRecommendation {
+    let rate = |target: u64| -> u64 {
+        let estimate = estimates
+            .range(..=target)
+            .next_back()
+            .or_else(|| estimates.iter().next())
+            .map(|(_, rate)| *rate);
+        (estimate.unwrap_or(0.0).ceil() as u64).max(min_relay_fee)
+    };
+
+    // A longer target never costs more than a shorter one
+    let economy = rate(ECONOMY);
+    let six_blocks = rate(SIX_BLOCKS).max(economy);
+    let next_block = rate(NEXT_BLOCK).max(six_blocks);
+    FeeRecommendation {
+        next_block,
+        six_blocks,
+        economy,
+        minimum: min_relay_fee,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        address,
+        network::Network,
+        tx::{TxIn, TxOut},
+    };
+
+    const BASE: &str = "https://esplora.example/api";
+
+    #[test]
+    fn test_requests() {
+        let addr = address::segwit_address(0, &[5; 20], Network::Mainnet.bech32_hrp());
+        let txid = "66".repeat(32);
+        // A trailing slash on the base URL is dropped
+        let base = format!("{}/", BASE);
+
+        assert_eq!(
+            address_utxos_request(&base, &addr).url,
+            format!("{}/address/{}/utxo", BASE, addr)
+        );
+        assert_eq!(
+            tx_status_request(&base, &txid).url,
+            format!("{}/tx/{}/status", BASE, txid)
+        );
+        assert_eq!(
+            raw_tx_request(&base, &txid).url,
+            format!("{}/tx/{}/hex", BASE, txid)
+        );
+        assert_eq!(
+            fee_estimates_request(&base).url,
+            format!("{}/fee-estimates", BASE)
+        );
+
+        let raw = [2, 0, 0, 0, 1];
+        assert_eq!(
+            broadcast_request(&base, &raw),
+            HttpRequest {
+                method: "POST",
+                url: format!("{}/tx", BASE),
+                body: Some(hex::encode(raw)),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_address_utxos() {
+        let script = address::p2wpkh_script(&[5; 20]);
+        let body = format!(
+            r#"[{{"txid":"{}","vout":0,"value":25000,"status":{{"confirmed":true,"block_height":840000,"block_hash":"{}","block_time":1713571767}}}},
+                {{"txid":"{}","vout":1,"value":5000,"status":{{"confirmed":false}}}}]"#,
+            "66".repeat(32),
+            "00".repeat(32),
+            "77".repeat(32)
+        );
+
+        let utxos = parse_address_utxos(&body, &script).unwrap();
+        assert_eq!(utxos.len(), 2);
+        assert_eq!((utxos[0].amount, utxos[0].height), (25_000, Some(840_000)));
+        assert_eq!(utxos[1].height, Some(0));
+        assert_eq!(utxos[0].scriptPubKey, hex::encode(&script));
+        assert!(parse_address_utxos("{}", &script).is_none());
+    }
+
+    #[test]
+    fn test_parse_transactions() {
+        let tx = Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                txid: "66".repeat(32),
+                vout: 0,
+                script_sig: Vec::new(),
+                sequence: 0xfffffffd,
+                witness: vec![vec![1; 71], vec![2; 33]],
+            }],
+            outputs: vec![TxOut {
+                value: 25_000,
+                script_pubkey: address::p2wpkh_script(&[5; 20]),
+            }],
+            locktime: 0,
+        };
+        let txid = tx.txid();
+        let raw = hex::encode(tx.serialize());
+
+        assert_eq!(parse_raw_tx(&format!("{}\n", raw), &txid), Some(tx));
+        assert_eq!(parse_raw_tx(&raw, &"88".repeat(32)), None);
+        assert_eq!(
+            parse_tx_status(r#"{"confirmed":false}"#),
+            Some(TxStatus::default())
+        );
+        assert_eq!(parse_broadcast(&txid), Some(txid));
+        assert_eq!(parse_broadcast("sendrawtransaction RPC error"), None);
+    }
+
+    #[test]
+    fn test_fee_recommendation() {
+        let estimates =
+            parse_fee_estimates(r#"{"1": 20.5, "2": 15.1, "3": 9.8, "144": 1.2, "504": 1.0}"#)
+                .unwrap();
+
+        // Six blocks takes the faster 3-block estimate, not the 144 one
+        let rates = fee_recommendation(&estimates, 1);
+        assert_eq!(
+            (rates.next_block, rates.six_blocks, rates.economy),
+            (21, 10, 2)
+        );
+        // Targets below every estimate take the fastest one
+        let sparse: BTreeMap<u64, f64> = [(2, 15.1), (25, 4.0)].into_iter().collect();
+        let rates = fee_recommendation(&sparse, 1);
+        assert_eq!(
+            (rates.next_block, rates.six_blocks, rates.economy),
+            (16, 16, 4)
+        );
+        assert_eq!(fee_recommendation(&BTreeMap::new(), 1).economy, 1);
+    }
+
+    #[test]
+    fn test_response_status() {
+        assert_eq!(response_body(200, "ok"), Ok("ok"));
+        assert_eq!(
+            response_body(404, "Transaction not found"),
+            Err(EsploraError::Http(404, "Transaction not found".to_string()))
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use crate::network::Network;
+use serde::{Deserialize, Serialize};
+
+// Virtual size available to transactions in one block, leaving room for the
+// coinbase
+const BLOCK_VSIZE: u64 = 1_000_000 - 1_000;
+
+// Confirmation targets, in blocks
+pub const NEXT_BLOCK: u64 = 1;
+pub const SIX_BLOCKS: u64 = 6;
+pub const ECONOMY: u64 = 144;
+
+// --------------------
+// Inputs
+// --------------------
+
+// Mempool snapshot: either a fee-rate histogram or the transactions themselves
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum MempoolSnapshot {
+    // [[fee rate (sat/vB), vsize], ...], as from mempool.get_fee_histogram
+    Histogram(Vec<(f64, u64)>),
+    Entries(Vec<MempoolEntry>),
+}
+
+#[derive(Deserialize)]
+pub struct MempoolEntry {
+    pub vsize: u64,
+    pub fee: u64,
+}
+
+// Fee rates paid in a recent block, as from getblockstats (sat/vB)
+#[derive(Deserialize)]
+pub struct BlockFeeRates {
+    // 10th, 25th, 50th, 75th and 90th percentile
+    pub feerate_percentiles: [f64; 5],
+    #[serde(default)]
+    pub minfeerate: f64,
+}
+
+impl MempoolSnapshot {
+    // (fee rate, vsize) pairs, highest fee rate first
+    fn buckets(&self) -> Vec<(f64, u64)> {
+        let mut buckets: Vec<(f64, u64)> = match self {
+            MempoolSnapshot::Histogram(h) => h.clone(),
+            MempoolSnapshot::Entries(entries) => entries
+                .iter()
+                .filter(|e| e.vsize > 0)
+                .map(|e| (e.fee as f64 / e.vsize as f64, e.vsize))
+                .collect(),
+        };
+        buckets.sort_by(|a, b| b.0.total_cmp(&a.0));
+        buckets
+    }
+}
+
+// --------------------
+// Estimation
+// --------------------
+
+// Recommended fee rates (sat/vB) per confirmation target
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct FeeRecommendation {
+    pub next_block: u64,
+    pub six_blocks: u64,
+    pub economy: u64,
+    pub minimum: u64,
+}
+
+// Fee rate of the last transaction that fits into the first `blocks`
+// projected blocks; None when the whole mempool fits
+fn projected_rate(buckets: &[(f64, u64)], blocks: u64) -> Option<f64> {
+    let capacity = BLOCK_VSIZE * blocks;
+    let mut filled = 0;
+    for (rate, vsize) in buckets {
+        filled += vsize;
+        if filled >= capacity {
+            return Some(*rate);
+        }
+    }
+    None
+}
+
+// Median over recent blocks of one of their fee-rate statistics
+fn recent_median(blocks: &[BlockFeeRates], stat: impl Fn(&BlockFeeRates) -> f64) -> Option<f64> {
+    let mut rates: Vec<f64> = blocks.iter().map(stat).collect();
+    if rates.is_empty() {
+        return None;
+    }
+    rates.sort_by(|a, b| a.total_cmp(b));
+    Some(rates[rates.len() / 2])
+}
+
+// Recommend fee rates from the mempool projected into blocks, raised to what
+// recent blocks actually required (their 10th percentile for the next block,
+// their minimum for six blocks), and never below `min_relay_fee`
+pub fn recommend(
+    mempool: &MempoolSnapshot,
+    recent_blocks: &[BlockFeeRates],
+    min_relay_fee: u64,
+) -> FeeRecommendation {
+    let buckets = mempool.buckets();
+    let floor = min_relay_fee as f64;
+
+    let next_block = [
+        projected_rate(&buckets, NEXT_BLOCK),
+        recent_median(recent_blocks, |b| b.feerate_percentiles[0]),
+    ];
+    let six_blocks = [
+        projected_rate(&buckets, SIX_BLOCKS),
+        recent_median(recent_blocks, |b| b.minfeerate),
+    ];
+    let economy = [projected_rate(&buckets, ECONOMY)];
+
+    let rate = |candidates: &[Option<f64>]| -> u64 {
+        let best = candidates.iter().flatten().fold(floor, |a, b| a.max(*b));
+        best.ceil() as u64
+    };
+
+    // A longer target never costs more than a shorter one
+    let economy = rate(&economy);
+    let six_blocks = rate(&six_blocks).max(economy);
+    let next_block = rate(&next_block).max(six_blocks);
+
+    FeeRecommendation {
+        next_block,
+        six_blocks,
+        economy,
+        minimum: min_relay_fee,
+    }
+}
+
+// `recommend` with the network's minimum relay fee as the floor
+pub fn recommend_for_network(
+    mempool: &MempoolSnapshot,
+    recent_blocks: &[BlockFeeRates],
+    network: Network,
+) -> FeeRecommendation {
+    recommend(mempool, recent_blocks, network.min_relay_fee_rate())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recommendations() {
+        // 1.5 blocks at 40 sat/vB, 5 more at 12, then a long tail at 3
+        let mempool: MempoolSnapshot =
+            serde_json::from_str("[[40.0, 1500000], [12.0, 5000000], [3.0, 200000000]]").unwrap();
+        let rates = recommend_for_network(&mempool, &[], Network::Mainnet);
+        assert_eq!(
+            rates,
+            FeeRecommendation {
+                next_block: 40,
+                six_blocks: 12,
+                economy: 3,
+                minimum: 1,
+            }
+        );
+
+        // A nearly empty mempool falls back to the relay floor, unless recent
+        // blocks show higher rates were needed
+        let mempool: MempoolSnapshot =
+            serde_json::from_str(r#"[{"vsize": 200, "fee": 5000}, {"vsize": 150, "fee": 300}]"#)
+                .unwrap();
+        assert_eq!(recommend(&mempool, &[], 2).next_block, 2);
+
+        let blocks: Vec<BlockFeeRates> = serde_json::from_str(
+            r#"[{"feerate_percentiles": [8, 10, 15, 20, 30], "minfeerate": 4},
+                {"feerate_percentiles": [6, 9, 14, 20, 30], "minfeerate": 3.2},
+                {"feerate_percentiles": [30, 40, 50, 60, 70], "minfeerate": 25}]"#,
+        )
+        .unwrap();
+        let rates = recommend(&mempool, &blocks, 1);
+        assert_eq!(
+            (rates.next_block, rates.six_blocks, rates.economy),
+            (8, 4, 1)
+        );
+    }
+}
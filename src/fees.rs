@@ -1,89 +1,290 @@
+use crate::utils;
+use serde::{Deserialize, Serialize};
+
 // Supported script types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ScriptType {
     P2pkh,
     P2shP2wpkh, // wrapped segwit
     P2wpkh,
+    P2wsh,
+    P2tr,     // key path
+    OpReturn, // zero-value data carrier
+    Unknown,
 }
 
 // Detect script type from scriptPubKey hex
 pub fn detect_script_type(script_hex: &str) -> ScriptType {
     let bytes = match hex::decode(script_hex) {
         Ok(b) => b,
-        Err(_) => return ScriptType::P2pkh, // safe fallback
+        Err(_) => return ScriptType::Unknown,
     };
 
     match bytes.as_slice() {
         // P2WPKH: OP_0 <20-byte>
-        [0x00, 0x14, ..] => ScriptType::P2wpkh,
+        [0x00, 0x14, ..] if bytes.len() == 22 => ScriptType::P2wpkh,
+
+        // P2WSH: OP_0 <32-byte>
+        [0x00, 0x20, ..] if bytes.len() == 34 => ScriptType::P2wsh,
 
         // P2SH-P2WPKH: OP_HASH160 <20-byte> OP_EQUAL
-        [0xa9, 0x14, .., 0x87] => ScriptType::P2shP2wpkh,
+        [0xa9, 0x14, .., 0x87] if bytes.len() == 23 => ScriptType::P2shP2wpkh,
 
         // P2PKH: OP_DUP OP_HASH160 <20-byte> OP_EQUALVERIFY OP_CHECKSIG
-        [0x76, 0xa9, 0x14, .., 0x88, 0xac] => ScriptType::P2pkh,
+        [0x76, 0xa9, 0x14, .., 0x88, 0xac] if bytes.len() == 25 => ScriptType::P2pkh,
+
+        // P2TR: OP_1 <32-byte>
+        [0x51, 0x20, ..] if bytes.len() == 34 => ScriptType::P2tr,
 
-        _ => ScriptType::P2pkh,
+        // OP_RETURN <data>
+        [0x6a, ..] => ScriptType::OpReturn,
+
+        _ => ScriptType::Unknown,
     }
 }
 
-// Estimate transaction fee using **true BIP-141 weight units**
-//
-// Returns **fee in satoshis**
-pub fn estimate_fee(
-    input_scripts: &[String],
-    output_scripts: &[String],
-    sat_per_vbyte: u64,
-) -> u64 {
-    let mut total_weight: usize = 0;
-
-    // ------------------------------------------------------------------
-    // INPUTS
-    // ------------------------------------------------------------------
-    for script in input_scripts {
-        match detect_script_type(script) {
-            // Legacy P2PKH input
-            ScriptType::P2pkh => {
-                total_weight += 148 * 4;
-            }
+// --------------------
+// Input sizes
+// --------------------
+
+// ECDSA signature size. A low-S DER signature is at most 71 bytes; signers
+// that grind for a low R value stay within 70 (+1 sighash byte each).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcdsaSig {
+    LowR,
+    HighR,
+}
+
+impl EcdsaSig {
+    // Witness / scriptSig item length, sighash byte included
+    fn len(self) -> usize {
+        match self {
+            EcdsaSig::LowR => 71,
+            EcdsaSig::HighR => 72,
+        }
+    }
+}
+
+const COMPRESSED_KEY: usize = 33;
+const UNCOMPRESSED_KEY: usize = 65;
+
+// How an input is spent, as far as its size is concerned
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputSpec {
+    P2pkh {
+        #[serde(default = "default_true")]
+        compressed: bool,
+    },
+    P2shP2wpkh,
+    P2wpkh,
+    // m-of-n CHECKMULTISIG with compressed keys
+    P2shMultisig {
+        m: usize,
+        n: usize,
+    },
+    P2shP2wshMultisig {
+        m: usize,
+        n: usize,
+    },
+    P2wshMultisig {
+        m: usize,
+        n: usize,
+    },
+    // 64-byte signature, 65 with a non-default sighash type
+    P2trKeyPath {
+        #[serde(default)]
+        sighash_byte: bool,
+    },
+    // Leaf script spend: stack item lengths, the leaf script and a control
+    // block for a leaf `depth` levels down the script tree
+    P2trScriptPath {
+        stack: Vec<usize>,
+        leaf_script_len: usize,
+        depth: usize,
+    },
+    // Anything else, described by its scriptSig length and witness items
+    Custom {
+        script_sig_len: usize,
+        #[serde(default)]
+        witness: Vec<usize>,
+    },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// Length of a minimal push of `len` bytes
+fn push_len(len: usize) -> usize {
+    let opcode = match len {
+        0..=75 => 1,
+        76..=255 => 2,
+        256..=65535 => 3,
+        _ => 5,
+    };
+    opcode + len
+}
+
+// OP_m <key>... OP_n OP_CHECKMULTISIG
+fn multisig_script_len(m: usize, n: usize) -> usize {
+    let small_int = |k: usize| if k <= 16 { 1 } else { 2 };
+    small_int(m) + n * push_len(COMPRESSED_KEY) + small_int(n) + 1
+}
 
-            // P2SH-P2WPKH input
-            ScriptType::P2shP2wpkh => {
-                total_weight += (64 * 4) + 107;
+impl InputSpec {
+    // Spend type inferred from the output script alone; None for scripts
+    // whose witness depends on data the scriptPubKey doesn't reveal (P2WSH,
+    // bare or unknown scripts)
+    pub fn from_script_pubkey(script_hex: &str) -> Option<InputSpec> {
+        match detect_script_type(script_hex) {
+            ScriptType::P2pkh => Some(InputSpec::P2pkh { compressed: true }),
+            ScriptType::P2shP2wpkh => Some(InputSpec::P2shP2wpkh),
+            ScriptType::P2wpkh => Some(InputSpec::P2wpkh),
+            ScriptType::P2tr => Some(InputSpec::P2trKeyPath {
+                sighash_byte: false,
+            }),
+            ScriptType::P2wsh | ScriptType::OpReturn | ScriptType::Unknown => None,
+        }
+    }
+
+    // (scriptSig length, witness item lengths); no items for legacy inputs
+    fn sizes(&self, sig: EcdsaSig) -> (usize, Vec<usize>) {
+        let sig = sig.len();
+        match self {
+            InputSpec::P2pkh { compressed } => {
+                let key = if *compressed {
+                    COMPRESSED_KEY
+                } else {
+                    UNCOMPRESSED_KEY
+                };
+                (push_len(sig) + push_len(key), Vec::new())
             }
+            // scriptSig pushes the 22-byte P2WPKH program
+            InputSpec::P2shP2wpkh => (push_len(22), vec![sig, COMPRESSED_KEY]),
+            InputSpec::P2wpkh => (0, vec![sig, COMPRESSED_KEY]),
+            InputSpec::P2shMultisig { m, n } => {
+                // OP_0 <sig>... <redeem script>
+                let script_sig = 1 + m * push_len(sig) + push_len(multisig_script_len(*m, *n));
+                (script_sig, Vec::new())
+            }
+            InputSpec::P2shP2wshMultisig { m, n } | InputSpec::P2wshMultisig { m, n } => {
+                // Empty CHECKMULTISIG dummy, signatures, witness script
+                let mut witness = vec![0];
+                witness.extend(std::iter::repeat_n(sig, *m));
+                witness.push(multisig_script_len(*m, *n));
 
-            // Native P2WPKH input
-            ScriptType::P2wpkh => {
-                total_weight += (41 * 4) + 107;
+                // scriptSig pushes the 34-byte P2WSH program when wrapped
+                let script_sig = match self {
+                    InputSpec::P2shP2wshMultisig { .. } => push_len(34),
+                    _ => 0,
+                };
+                (script_sig, witness)
+            }
+            InputSpec::P2trKeyPath { sighash_byte } => (0, vec![64 + *sighash_byte as usize]),
+            InputSpec::P2trScriptPath {
+                stack,
+                leaf_script_len,
+                depth,
+            } => {
+                let mut witness = stack.clone();
+                witness.push(*leaf_script_len);
+                witness.push(33 + 32 * depth);
+                (0, witness)
             }
+            InputSpec::Custom {
+                script_sig_len,
+                witness,
+            } => (*script_sig_len, witness.clone()),
         }
     }
+}
+
+// --------------------
+// Transaction weight
+// --------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TxSize {
+    pub weight: usize,
+    pub vsize: usize,
+    pub fee: u64,
+}
+
+// Exact BIP-141 weight of a transaction spending `inputs` and paying to
+// output scripts of the given lengths, and its fee at `sat_per_vbyte`
+pub fn estimate_size(
+    inputs: &[InputSpec],
+    output_script_lens: &[usize],
+    sig: EcdsaSig,
+    sat_per_vbyte: u64,
+) -> TxSize {
+    let sizes: Vec<(usize, Vec<usize>)> = inputs.iter().map(|i| i.sizes(sig)).collect();
+
+    // version + input / output counts + locktime
+    let mut base =
+        4 + utils::varint(inputs.len()).len() + utils::varint(output_script_lens.len()).len() + 4;
+
+    // outpoint (36) + scriptSig + sequence (4)
+    for (script_sig_len, _) in &sizes {
+        base += 36 + utils::varint(*script_sig_len).len() + script_sig_len + 4;
+    }
 
-    // ------------------------------------------------------------------
-    // OUTPUTS
-    // ------------------------------------------------------------------
-    for script in output_scripts {
-        match detect_script_type(script) {
-            ScriptType::P2pkh => total_weight += 34 * 4,
-            ScriptType::P2shP2wpkh => total_weight += 32 * 4,
-            ScriptType::P2wpkh => total_weight += 31 * 4,
+    // value (8) + script length + script
+    for len in output_script_lens {
+        base += 8 + utils::varint(*len).len() + len;
+    }
+
+    // Marker and flag, then every input's item count (0 for legacy inputs)
+    let mut witness = 0;
+    if sizes.iter().any(|(_, items)| !items.is_empty()) {
+        witness += 2;
+        for (_, items) in &sizes {
+            witness += utils::varint(items.len()).len();
+            witness += items
+                .iter()
+                .map(|len| utils::varint(*len).len() + len)
+                .sum::<usize>();
         }
     }
 
-    // ------------------------------------------------------------------
-    // TX OVERHEAD
-    // ------------------------------------------------------------------
-    total_weight += 10 * 4;
+    let weight = base * 4 + witness;
+    let vsize = weight.div_ceil(4);
+    TxSize {
+        weight,
+        vsize,
+        fee: vsize as u64 * sat_per_vbyte,
+    }
+}
 
-    // Convert weight → vbytes (round up, BIP-141 correct)
-    let vbytes = total_weight.div_ceil(4);
+// Estimate a transaction fee (satoshis) from the scriptPubKeys being spent
+// and paid to, assuming non-grinding (high-R) ECDSA signers. None when an
+// input's size does not follow from its scriptPubKey (P2WSH, unknown
+// scripts); `estimate_size` takes explicit input specs for those.
+pub fn estimate_fee(
+    input_scripts: &[String],
+    output_scripts: &[String],
+    sat_per_vbyte: u64,
+) -> Option<u64> {
+    let inputs: Vec<InputSpec> = input_scripts
+        .iter()
+        .map(|s| InputSpec::from_script_pubkey(s))
+        .collect::<Option<_>>()?;
+    let output_lens: Vec<usize> = output_scripts.iter().map(|s| s.len() / 2).collect();
 
-    vbytes as u64 * sat_per_vbyte
+    Some(estimate_size(&inputs, &output_lens, EcdsaSig::HighR, sat_per_vbyte).fee)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        address,
+        keys::Keyring,
+        network::Network,
+        tx::{self, Transaction, UTXO},
+    };
+    use secp256k1::SecretKey;
 
     #[test]
     fn test_auto_detect_fee() {
@@ -94,7 +295,105 @@ mod tests {
 
         let outputs = vec!["001489abcdefabbaabbaabbaabbaabbaabbaabbaabba".to_string()];
 
-        let fee = estimate_fee(&inputs, &outputs, 50);
+        let fee = estimate_fee(&inputs, &outputs, 50).unwrap();
         assert!(fee > 0);
+
+        // P2WSH and unknown inputs have no size to guess
+        let p2wsh = format!("0020{}", "ab".repeat(32));
+        assert_eq!(estimate_fee(&[inputs[0].clone(), p2wsh], &outputs, 1), None);
+        assert_eq!(estimate_fee(&["51".to_string()], &outputs, 1), None);
+    }
+
+    #[test]
+    fn test_op_return_output() {
+        let data_script = "6a0b7061796f75742d31323334".to_string();
+        assert_eq!(detect_script_type(&data_script), ScriptType::OpReturn);
+        assert_eq!(detect_script_type("51"), ScriptType::Unknown);
+
+        let inputs = vec!["001489abcdefabbaabbaabbaabbaabbaabbaabbaabba".to_string()];
+        let outputs = vec!["001489abcdefabbaabbaabbaabbaabbaabbaabbaabba".to_string()];
+        let base = estimate_fee(&inputs, &outputs, 1).unwrap();
+
+        let mut with_data = outputs.clone();
+        with_data.push(data_script);
+
+        // 8 (value) + 1 (script length) + 13 (script)
+        assert_eq!(estimate_fee(&inputs, &with_data, 1), Some(base + 22));
+    }
+
+    #[test]
+    fn test_weights() {
+        // 1-in 2-out P2WPKH: 113 base bytes, 110 witness bytes with a 72-byte
+        // DER signature
+        let p2wpkh = [InputSpec::P2wpkh];
+        let high = estimate_size(&p2wpkh, &[22, 22], EcdsaSig::HighR, 2);
+        let low = estimate_size(&p2wpkh, &[22, 22], EcdsaSig::LowR, 2);
+        assert_eq!(
+            (high.weight, high.vsize, high.fee),
+            (4 * 113 + 110, 141, 282)
+        );
+        assert_eq!(low.weight, high.weight - 1);
+
+        // Legacy-only transactions carry no marker, flag or item counts
+        let legacy = estimate_size(
+            &[InputSpec::P2pkh { compressed: true }],
+            &[25],
+            EcdsaSig::HighR,
+            1,
+        );
+        assert_eq!(legacy.weight, 4 * (10 + 36 + 1 + 107 + 4 + 34));
+
+        // 2-of-3 P2WSH: dummy, two signatures, 105-byte witness script
+        let wsh = InputSpec::P2wshMultisig { m: 2, n: 3 };
+        assert_eq!(wsh.sizes(EcdsaSig::LowR), (0, vec![0, 71, 71, 105]));
+
+        // Taproot script path: control block for a leaf two levels deep
+        let leaf = InputSpec::P2trScriptPath {
+            stack: vec![64],
+            leaf_script_len: 34,
+            depth: 2,
+        };
+        assert_eq!(leaf.sizes(EcdsaSig::LowR).1, vec![64, 34, 97]);
+
+        // 253 outputs need a 3-byte count
+        let many = estimate_size(&p2wpkh, &[22; 253], EcdsaSig::HighR, 1);
+        let fewer = estimate_size(&p2wpkh, &[22; 252], EcdsaSig::HighR, 1);
+        assert_eq!(many.weight - fewer.weight, 4 * (31 + 2));
+
+        // Unrecognised inputs are not guessed
+        assert_eq!(InputSpec::from_script_pubkey(&"00".repeat(3)), None);
+    }
+
+    #[test]
+    fn test_matches_signed_transaction() {
+        let mut keyring = Keyring::default();
+        keyring.add(SecretKey::from_slice(&[3; 32]).unwrap());
+        let pubkey = keyring.change_pubkey();
+        let to = address::pubkey_to_address(&pubkey, Network::Testnet);
+
+        let script = address::p2wpkh_script(&crate::crypto::hash160(&pubkey.serialize()));
+        let utxos: Vec<UTXO> = serde_json::from_str(&format!(
+            r#"[{{"txid":"{}","vout":0,"scriptPubKey":"{}","amount":100000}}]"#,
+            "11".repeat(32),
+            hex::encode(&script)
+        ))
+        .unwrap();
+        let result = tx::create_and_sign(&utxos, &to, 50_000, 1_000, &keyring, false, None);
+        let signed = Transaction::parse(&utils::hex_to_bytes(&result.raw_tx)).unwrap();
+
+        let inputs: Vec<InputSpec> = utxos
+            .iter()
+            .map(|u| InputSpec::from_script_pubkey(&u.scriptPubKey).unwrap())
+            .collect();
+        let outputs: Vec<usize> = signed
+            .outputs
+            .iter()
+            .map(|o| o.script_pubkey.len())
+            .collect();
+
+        // The signer doesn't grind R, so the real weight lies between the two
+        let low = estimate_size(&inputs, &outputs, EcdsaSig::LowR, 1).weight;
+        let high = estimate_size(&inputs, &outputs, EcdsaSig::HighR, 1).weight;
+        assert!(low - 1 <= signed.weight() && signed.weight() <= high);
     }
 }
@@ -0,0 +1,291 @@
+use crate::{crypto, network::Network};
+use hmac::{Hmac, Mac};
+use secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey};
+use sha2::Sha512;
+
+// Child indexes at or above this value use hardened derivation
+pub const HARDENED: u32 = 0x8000_0000;
+
+// --------------------------------------------------
+// BIP32 extended private key
+// --------------------------------------------------
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedPrivKey {
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub secret: SecretKey,
+}
+
+impl ExtendedPrivKey {
+    // Master key from a BIP32 seed (16..64 bytes)
+    pub fn from_seed(seed: &[u8]) -> ExtendedPrivKey {
+        assert!((16..=64).contains(&seed.len()), "invalid seed length");
+
+        let i = hmac_sha512(b"Bitcoin seed", seed);
+
+        ExtendedPrivKey {
+            depth: 0,
+            parent_fingerprint: [0; 4],
+            child_number: 0,
+            chain_code: i[32..].try_into().unwrap(),
+            secret: SecretKey::from_slice(&i[..32]).expect("invalid master key"),
+        }
+    }
+
+    // Decode a Base58Check xprv / tprv for `network`
+    pub fn from_base58(s: &str, network: Network) -> Option<ExtendedPrivKey> {
+        let data = bs58::decode(s).into_vec().ok()?;
+        if data.len() != 82 || crypto::checksum(&data[..78]) != data[78..] {
+            return None;
+        }
+
+        if u32::from_be_bytes(data[0..4].try_into().ok()?) != network.xprv_version() {
+            return None;
+        }
+
+        // key data is 0x00 || 32-byte secret
+        if data[45] != 0x00 {
+            return None;
+        }
+
+        Some(ExtendedPrivKey {
+            depth: data[4],
+            parent_fingerprint: data[5..9].try_into().ok()?,
+            child_number: u32::from_be_bytes(data[9..13].try_into().ok()?),
+            chain_code: data[13..45].try_into().ok()?,
+            secret: SecretKey::from_slice(&data[46..78]).ok()?,
+        })
+    }
+
+    pub fn to_base58(&self, network: Network) -> String {
+        let mut payload = Vec::with_capacity(82);
+        payload.extend(network.xprv_version().to_be_bytes());
+        payload.push(self.depth);
+        payload.extend(self.parent_fingerprint);
+        payload.extend(self.child_number.to_be_bytes());
+        payload.extend(self.chain_code);
+        payload.push(0x00);
+        payload.extend(self.secret.secret_bytes());
+
+        let checksum = crypto::checksum(&payload);
+        payload.extend(&checksum);
+
+        bs58::encode(payload).into_string()
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from_secret_key(&Secp256k1::new(), &self.secret)
+    }
+
+    // First 4 bytes of hash160(pubkey), used as the child's parent fingerprint
+    pub fn fingerprint(&self) -> [u8; 4] {
+        crypto::hash160(&self.public_key().serialize())[..4]
+            .try_into()
+            .unwrap()
+    }
+
+    // CKDpriv
+    pub fn derive_child(&self, index: u32) -> ExtendedPrivKey {
+        let mut data = Vec::with_capacity(37);
+        if index >= HARDENED {
+            data.push(0x00);
+            data.extend(self.secret.secret_bytes());
+        } else {
+            data.extend(self.public_key().serialize());
+        }
+        data.extend(index.to_be_bytes());
+
+        let i = hmac_sha512(&self.chain_code, &data);
+        let tweak =
+            Scalar::from_be_bytes(i[..32].try_into().unwrap()).expect("derived tweak out of range");
+
+        ExtendedPrivKey {
+            depth: self.depth + 1,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code: i[32..].try_into().unwrap(),
+            secret: self
+                .secret
+                .add_tweak(&tweak)
+                .expect("derived key out of range"),
+        }
+    }
+
+    pub fn derive_path(&self, path: &str) -> Option<ExtendedPrivKey> {
+        Some(
+            parse_path(path)?
+                .into_iter()
+                .fold(self.clone(), |key, index| key.derive_child(index)),
+        )
+    }
+
+    // Neutered (watch-only) counterpart
+    pub fn to_public(&self) -> ExtendedPubKey {
+        ExtendedPubKey {
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            public_key: self.public_key(),
+        }
+    }
+}
+
+// --------------------------------------------------
+// BIP32 extended public key
+// --------------------------------------------------
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedPubKey {
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub public_key: PublicKey,
+}
+
+impl ExtendedPubKey {
+    // Decode a Base58Check xpub / tpub for `network`
+    pub fn from_base58(s: &str, network: Network) -> Option<ExtendedPubKey> {
+        let data = bs58::decode(s).into_vec().ok()?;
+        if data.len() != 82 || crypto::checksum(&data[..78]) != data[78..] {
+            return None;
+        }
+
+        if u32::from_be_bytes(data[0..4].try_into().ok()?) != network.xpub_version() {
+            return None;
+        }
+
+        Some(ExtendedPubKey {
+            depth: data[4],
+            parent_fingerprint: data[5..9].try_into().ok()?,
+            child_number: u32::from_be_bytes(data[9..13].try_into().ok()?),
+            chain_code: data[13..45].try_into().ok()?,
+            public_key: PublicKey::from_slice(&data[45..78]).ok()?,
+        })
+    }
+
+    pub fn to_base58(&self, network: Network) -> String {
+        let mut payload = Vec::with_capacity(82);
+        payload.extend(network.xpub_version().to_be_bytes());
+        payload.push(self.depth);
+        payload.extend(self.parent_fingerprint);
+        payload.extend(self.child_number.to_be_bytes());
+        payload.extend(self.chain_code);
+        payload.extend(self.public_key.serialize());
+
+        let checksum = crypto::checksum(&payload);
+        payload.extend(&checksum);
+
+        bs58::encode(payload).into_string()
+    }
+
+    pub fn fingerprint(&self) -> [u8; 4] {
+        crypto::hash160(&self.public_key.serialize())[..4]
+            .try_into()
+            .unwrap()
+    }
+
+    // CKDpub; None for hardened indexes
+    pub fn derive_child(&self, index: u32) -> Option<ExtendedPubKey> {
+        if index >= HARDENED {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity(37);
+        data.extend(self.public_key.serialize());
+        data.extend(index.to_be_bytes());
+
+        let i = hmac_sha512(&self.chain_code, &data);
+        let tweak = Scalar::from_be_bytes(i[..32].try_into().unwrap()).ok()?;
+
+        Some(ExtendedPubKey {
+            depth: self.depth + 1,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code: i[32..].try_into().unwrap(),
+            public_key: self
+                .public_key
+                .add_exp_tweak(&Secp256k1::verification_only(), &tweak)
+                .ok()?,
+        })
+    }
+
+    pub fn derive_indexes(&self, indexes: &[u32]) -> Option<ExtendedPubKey> {
+        indexes
+            .iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+}
+
+// Parse "m/84'/0'/0'/0/5" (hardened as ' or h) into child indexes
+pub fn parse_path(path: &str) -> Option<Vec<u32>> {
+    let mut parts = path.split('/');
+    if parts.next()? != "m" {
+        return None;
+    }
+
+    parts
+        .map(|part| {
+            let (num, hardened) = match part.strip_suffix(['\'', 'h']) {
+                Some(n) => (n, true),
+                None => (part, false),
+            };
+            let index: u32 = num.parse().ok()?;
+            if index >= HARDENED {
+                return None;
+            }
+            Some(if hardened { index + HARDENED } else { index })
+        })
+        .collect()
+}
+
+fn hmac_sha512(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BIP32 test vector 1
+    #[test]
+    fn test_vector_1() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedPrivKey::from_seed(&seed);
+
+        assert_eq!(
+            master.to_base58(Network::Mainnet),
+            "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi"
+        );
+
+        let child = master.derive_path("m/0'/1").unwrap();
+        assert_eq!(
+            child.to_base58(Network::Mainnet),
+            "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs"
+        );
+
+        let encoded = child.to_base58(Network::Mainnet);
+        assert_eq!(
+            ExtendedPrivKey::from_base58(&encoded, Network::Mainnet),
+            Some(child.clone())
+        );
+        assert!(ExtendedPrivKey::from_base58(&encoded, Network::Testnet).is_none());
+
+        let xpub = child.to_public();
+        assert_eq!(
+            xpub.to_base58(Network::Mainnet),
+            "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ"
+        );
+
+        // Public derivation matches private derivation for normal children
+        let grandchild = child.derive_path("m/2'/2/1000000000").unwrap();
+        let from_xpub = grandchild.to_public();
+        let parent = child.derive_path("m/2'").unwrap().to_public();
+        assert_eq!(parent.derive_indexes(&[2, 1_000_000_000]), Some(from_xpub));
+        assert!(parent.derive_child(HARDENED).is_none());
+    }
+}
@@ -0,0 +1,1279 @@
+use crate::{
+    address,
+    crypto::{self, SighashCache},
+    keys,
+    script::{self, encode_num, Instruction, Instructions, Script},
+    tx::{Transaction, TxOut, UTXO},
+    utils,
+};
+use ripemd::Ripemd160;
+use secp256k1::{ecdsa, schnorr, Message, PublicKey, Secp256k1, VerifyOnly, XOnlyPublicKey};
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+// --------------------------------------------------
+// Consensus limits
+// --------------------------------------------------
+
+const MAX_SCRIPT_SIZE: usize = 10_000;
+const MAX_ELEMENT_SIZE: usize = 520;
+const MAX_OPS_PER_SCRIPT: usize = 201;
+const MAX_STACK_SIZE: usize = 1000;
+const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+
+// Tapscript (BIP342): each signature check spends 50 of a budget of 50 plus
+// the witness size
+const VALIDATION_WEIGHT_PER_SIGOP: i64 = 50;
+const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+// Control block: leaf version and internal key, then up to 128 path hashes
+const CONTROL_BASE_SIZE: usize = 33;
+const CONTROL_NODE_SIZE: usize = 32;
+const CONTROL_MAX_NODES: usize = 128;
+
+const LOCKTIME_THRESHOLD: i64 = 500_000_000;
+const SEQUENCE_FINAL: u32 = 0xffffffff;
+const SEQUENCE_DISABLE_FLAG: i64 = 1 << 31;
+const SEQUENCE_TYPE_FLAG: i64 = 1 << 22;
+const SEQUENCE_MASK: i64 = 0x0000ffff;
+
+// --------------------------------------------------
+// Errors
+// --------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    UtxoMismatch,
+    ScriptSize,
+    PushSize,
+    OpCount,
+    StackSize,
+    BadOpcode,
+    DisabledOpcode,
+    UnbalancedConditional,
+    InvalidStackOperation,
+    InvalidAltstackOperation,
+    OpReturn,
+    Verify,
+    EqualVerify,
+    NumEqualVerify,
+    CheckSigVerify,
+    CheckMultisigVerify,
+    NumOverflow,
+    PubkeyCount,
+    SigCount,
+    SigDer,
+    SigNullDummy,
+    NegativeLocktime,
+    UnsatisfiedLocktime,
+    SigPushOnly,
+    EvalFalse,
+    CleanStack,
+    WitnessProgramWrongLength,
+    WitnessProgramWitnessEmpty,
+    WitnessProgramMismatch,
+    WitnessMalleated,
+    WitnessMalleatedP2sh,
+    WitnessUnexpected,
+    SchnorrSigSize,
+    SchnorrSigHashtype,
+    SchnorrSig,
+    TaprootWrongControlSize,
+    TapscriptMinimalIf,
+    TapscriptCheckMultisig,
+    TapscriptEmptyPubkey,
+    TapscriptValidationWeight,
+}
+
+impl ScriptError {
+    pub fn message(self) -> &'static str {
+        match self {
+            ScriptError::UtxoMismatch => "UTXO does not match the input outpoint",
+            ScriptError::ScriptSize => "script is too large",
+            ScriptError::PushSize => "push exceeds 520 bytes",
+            ScriptError::OpCount => "operation limit exceeded",
+            ScriptError::StackSize => "stack size limit exceeded",
+            ScriptError::BadOpcode => "bad opcode",
+            ScriptError::DisabledOpcode => "disabled opcode",
+            ScriptError::UnbalancedConditional => "unbalanced conditional",
+            ScriptError::InvalidStackOperation => "operation on too few stack items",
+            ScriptError::InvalidAltstackOperation => "operation on empty altstack",
+            ScriptError::OpReturn => "OP_RETURN executed",
+            ScriptError::Verify => "OP_VERIFY failed",
+            ScriptError::EqualVerify => "OP_EQUALVERIFY failed",
+            ScriptError::NumEqualVerify => "OP_NUMEQUALVERIFY failed",
+            ScriptError::CheckSigVerify => "OP_CHECKSIGVERIFY failed",
+            ScriptError::CheckMultisigVerify => "OP_CHECKMULTISIGVERIFY failed",
+            ScriptError::NumOverflow => "script number overflow",
+            ScriptError::PubkeyCount => "invalid multisig public key count",
+            ScriptError::SigCount => "invalid multisig signature count",
+            ScriptError::SigDer => "non-DER signature",
+            ScriptError::SigNullDummy => "OP_CHECKMULTISIG dummy is not empty",
+            ScriptError::NegativeLocktime => "negative locktime",
+            ScriptError::UnsatisfiedLocktime => "locktime requirement not satisfied",
+            ScriptError::SigPushOnly => "P2SH scriptSig is not push-only",
+            ScriptError::EvalFalse => "script evaluated to false",
+            ScriptError::CleanStack => "witness script left extra stack items",
+            ScriptError::WitnessProgramWrongLength => "witness program has the wrong length",
+            ScriptError::WitnessProgramWitnessEmpty => "witness program with empty witness",
+            ScriptError::WitnessProgramMismatch => "witness does not match the program",
+            ScriptError::WitnessMalleated => "native witness input with a scriptSig",
+            ScriptError::WitnessMalleatedP2sh => "P2SH witness scriptSig is not a single push",
+            ScriptError::WitnessUnexpected => "witness on a non-witness input",
+            ScriptError::SchnorrSigSize => "invalid Schnorr signature size",
+            ScriptError::SchnorrSigHashtype => "invalid Schnorr signature hash type",
+            ScriptError::SchnorrSig => "invalid Schnorr signature",
+            ScriptError::TaprootWrongControlSize => "invalid Taproot control block size",
+            ScriptError::TapscriptMinimalIf => "Tapscript OP_IF argument is not minimal",
+            ScriptError::TapscriptCheckMultisig => "OP_CHECKMULTISIG is disabled in Tapscript",
+            ScriptError::TapscriptEmptyPubkey => "empty public key in Tapscript",
+            ScriptError::TapscriptValidationWeight => "Tapscript signature budget exceeded",
+        }
+    }
+}
+
+// One input that failed verification, serialized for the JS side
+#[derive(Debug, Serialize)]
+pub struct InputFailure {
+    pub input: usize,
+    pub error: &'static str,
+}
+
+// --------------------------------------------------
+// Transaction verification
+// --------------------------------------------------
+
+// Verify every input of `tx` against the UTXO it spends (`utxos[i]` for
+// input i); returns the inputs that fail, empty when the transaction is valid
+pub fn verify_transaction(tx: &Transaction, utxos: &[UTXO]) -> Vec<InputFailure> {
+    assert_eq!(utxos.len(), tx.inputs.len(), "expected one UTXO per input");
+
+    let spent: Vec<TxOut> = utxos
+        .iter()
+        .map(|u| TxOut {
+            value: u.amount,
+            script_pubkey: utils::hex_to_bytes(&u.scriptPubKey),
+        })
+        .collect();
+    let cache = SighashCache::new(tx, Some(&spent));
+    let secp = Secp256k1::verification_only();
+
+    let mut failures = Vec::new();
+    for (i, utxo) in utxos.iter().enumerate() {
+        let input = &tx.inputs[i];
+        let result = if input.txid != utxo.txid || input.vout != utxo.vout {
+            Err(ScriptError::UtxoMismatch)
+        } else {
+            let checker = Checker {
+                tx,
+                input_index: i,
+                amount: utxo.amount,
+                cache: &cache,
+                secp: &secp,
+                leaf: None,
+            };
+            verify_input(&checker, &spent[i].script_pubkey)
+        };
+
+        if let Err(e) = result {
+            failures.push(InputFailure {
+                input: i,
+                error: e.message(),
+            });
+        }
+    }
+    failures
+}
+
+fn verify_input(checker: &Checker, script_pubkey: &[u8]) -> Result<(), ScriptError> {
+    let input = &checker.tx.inputs[checker.input_index];
+    let script_sig = &input.script_sig;
+    let witness = &input.witness;
+
+    let mut stack = Vec::new();
+    eval_script(&mut stack, script_sig, SigVersion::Base, checker)?;
+    let p2sh_stack = stack.clone();
+
+    eval_script(&mut stack, script_pubkey, SigVersion::Base, checker)?;
+    if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+        return Err(ScriptError::EvalFalse);
+    }
+
+    let mut had_witness = false;
+    if let Some((version, program)) = witness_program(script_pubkey) {
+        had_witness = true;
+        if !script_sig.is_empty() {
+            return Err(ScriptError::WitnessMalleated);
+        }
+        verify_witness_program(checker, witness, version, program, false)?;
+    }
+
+    if is_p2sh(script_pubkey) {
+        if !Script::from_bytes(script_sig.clone()).is_push_only() {
+            return Err(ScriptError::SigPushOnly);
+        }
+
+        let mut stack = p2sh_stack;
+        let redeem_script = stack.pop().ok_or(ScriptError::EvalFalse)?;
+        eval_script(&mut stack, &redeem_script, SigVersion::Base, checker)?;
+        if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+            return Err(ScriptError::EvalFalse);
+        }
+
+        if let Some((version, program)) = witness_program(&redeem_script) {
+            had_witness = true;
+            let mut single_push = Vec::new();
+            script::push_data(&mut single_push, &redeem_script);
+            if *script_sig != single_push {
+                return Err(ScriptError::WitnessMalleatedP2sh);
+            }
+            verify_witness_program(checker, witness, version, program, true)?;
+        }
+    }
+
+    if !had_witness && !witness.is_empty() {
+        return Err(ScriptError::WitnessUnexpected);
+    }
+    Ok(())
+}
+
+fn verify_witness_program(
+    checker: &Checker,
+    witness: &[Vec<u8>],
+    version: u8,
+    program: &[u8],
+    is_p2sh: bool,
+) -> Result<(), ScriptError> {
+    match (version, program.len()) {
+        // P2WSH: the last item is the script, committed to by SHA256
+        (0, 32) => {
+            let (script, stack) = witness
+                .split_last()
+                .ok_or(ScriptError::WitnessProgramWitnessEmpty)?;
+            if crypto::sha256(script) != program {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+            execute_witness_script(checker, stack.to_vec(), script, SigVersion::WitnessV0)
+        }
+        // P2WPKH: signature and key run through the equivalent P2PKH script
+        (0, 20) => {
+            if witness.len() != 2 {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+            execute_witness_script(
+                checker,
+                witness.to_vec(),
+                &address::p2pkh_script(program),
+                SigVersion::WitnessV0,
+            )
+        }
+        (0, _) => Err(ScriptError::WitnessProgramWrongLength),
+        (1, 32) if !is_p2sh => {
+            let mut stack = witness.to_vec();
+            if stack.is_empty() {
+                return Err(ScriptError::WitnessProgramWitnessEmpty);
+            }
+
+            let annex = if stack.len() >= 2 && stack.last().unwrap().first() == Some(&0x50) {
+                stack.pop()
+            } else {
+                None
+            };
+
+            if stack.len() == 1 {
+                return checker.check_schnorr(&stack[0], program, None, annex.as_deref());
+            }
+
+            // Script path: <inputs...> <script> <control block>
+            let control = stack.pop().unwrap();
+            let script = stack.pop().unwrap();
+            let leaf_hash = verify_taproot_commitment(program, &script, &control)?;
+            // Unknown leaf versions are left for future soft forks
+            if control[0] & 0xfe != keys::TAPSCRIPT_LEAF_VERSION {
+                return Ok(());
+            }
+
+            let leaf_checker = Checker {
+                leaf: Some(Leaf {
+                    hash: leaf_hash,
+                    annex,
+                    budget: VALIDATION_WEIGHT_OFFSET + witness_size(witness) as i64,
+                }),
+                ..*checker
+            };
+            execute_tapscript(&leaf_checker, stack, &script)
+        }
+        // Unknown versions are left for future soft forks
+        _ => Ok(()),
+    }
+}
+
+fn execute_witness_script(
+    checker: &Checker,
+    mut stack: Vec<Vec<u8>>,
+    script: &[u8],
+    sig_version: SigVersion,
+) -> Result<(), ScriptError> {
+    if stack.iter().any(|item| item.len() > MAX_ELEMENT_SIZE) {
+        return Err(ScriptError::PushSize);
+    }
+
+    eval_script(&mut stack, script, sig_version, checker)?;
+
+    if stack.len() != 1 {
+        return Err(ScriptError::CleanStack);
+    }
+    if !cast_to_bool(&stack[0]) {
+        return Err(ScriptError::EvalFalse);
+    }
+    Ok(())
+}
+
+// Check that `script` is a leaf of the tree committed to by the output key
+// `program`, along the control block's path; returns the leaf hash
+fn verify_taproot_commitment(
+    program: &[u8],
+    script: &[u8],
+    control: &[u8],
+) -> Result<Vec<u8>, ScriptError> {
+    if control.len() < CONTROL_BASE_SIZE
+        || !(control.len() - CONTROL_BASE_SIZE).is_multiple_of(CONTROL_NODE_SIZE)
+        || (control.len() - CONTROL_BASE_SIZE) / CONTROL_NODE_SIZE > CONTROL_MAX_NODES
+    {
+        return Err(ScriptError::TaprootWrongControlSize);
+    }
+
+    let internal = XOnlyPublicKey::from_slice(&control[1..CONTROL_BASE_SIZE])
+        .map_err(|_| ScriptError::WitnessProgramMismatch)?;
+    let leaf_hash = keys::tap_leaf_hash(script, control[0] & 0xfe);
+    let root = control[CONTROL_BASE_SIZE..]
+        .chunks(CONTROL_NODE_SIZE)
+        .fold(leaf_hash.clone(), |node, sibling| {
+            keys::tap_branch_hash(&node, sibling)
+        });
+
+    let (output_key, parity) = keys::taproot_output_key(&internal, Some(&root));
+    if output_key.serialize() != program || parity.to_u8() != control[0] & 1 {
+        return Err(ScriptError::WitnessProgramMismatch);
+    }
+    Ok(leaf_hash)
+}
+
+// BIP342: any OP_SUCCESS opcode makes the script succeed unexecuted; else
+// it runs like a witness script, without the size and opcode limits
+fn execute_tapscript(
+    checker: &Checker,
+    stack: Vec<Vec<u8>>,
+    script: &[u8],
+) -> Result<(), ScriptError> {
+    for instruction in Instructions::new(script) {
+        match instruction {
+            Instruction::Op(op) if is_op_success(op) => return Ok(()),
+            Instruction::Truncated => return Err(ScriptError::BadOpcode),
+            _ => {}
+        }
+    }
+
+    if stack.len() > MAX_STACK_SIZE {
+        return Err(ScriptError::StackSize);
+    }
+    execute_witness_script(checker, stack, script, SigVersion::Tapscript)
+}
+
+// Opcodes BIP342 reserves for future upgrades
+fn is_op_success(op: u8) -> bool {
+    matches!(
+        op,
+        0x50 | 0x62
+            | 0x7e..=0x81
+            | 0x83..=0x86
+            | 0x89
+            | 0x8a
+            | 0x8d
+            | 0x8e
+            | 0x95..=0x99
+            | 0xbb..=0xfe
+    )
+}
+
+// Serialized size of a witness stack
+fn witness_size(witness: &[Vec<u8>]) -> usize {
+    utils::varint(witness.len()).len()
+        + witness
+            .iter()
+            .map(|item| utils::varint(item.len()).len() + item.len())
+            .sum::<usize>()
+}
+
+// --------------------------------------------------
+// Script shapes
+// --------------------------------------------------
+
+fn is_p2sh(script: &[u8]) -> bool {
+    script.len() == 23 && script[0] == 0xa9 && script[1] == 0x14 && script[22] == 0x87
+}
+
+// Version and program of a witness scriptPubKey: OP_n <2..40 bytes>
+fn witness_program(script: &[u8]) -> Option<(u8, &[u8])> {
+    if script.len() < 4 || script.len() > 42 || script[1] as usize != script.len() - 2 {
+        return None;
+    }
+
+    match script[0] {
+        0x00 => Some((0, &script[2..])),
+        0x51..=0x60 => Some((script[0] - 0x50, &script[2..])),
+        _ => None,
+    }
+}
+
+// Legacy scripts remove the signature itself from the signed script code
+fn find_and_delete(script: &[u8], sig: &[u8]) -> Vec<u8> {
+    let mut pattern = Vec::new();
+    script::push_data(&mut pattern, sig);
+
+    let mut result = Vec::with_capacity(script.len());
+    let mut instructions = Instructions::new(script);
+    let mut start = 0;
+    while instructions.next().is_some() {
+        let end = instructions.position();
+        if script[start..end] != pattern[..] {
+            result.extend(&script[start..end]);
+        }
+        start = end;
+    }
+    result
+}
+
+// --------------------------------------------------
+// Script numbers
+// --------------------------------------------------
+
+fn decode_num(data: &[u8], max_len: usize) -> Result<i64, ScriptError> {
+    script::decode_num(data, max_len).ok_or(ScriptError::NumOverflow)
+}
+
+// Any non-zero byte is true, except a lone sign bit (negative zero)
+fn cast_to_bool(data: &[u8]) -> bool {
+    match data.split_last() {
+        Some((&last, rest)) => rest.iter().any(|b| *b != 0) || (last != 0 && last != 0x80),
+        None => false,
+    }
+}
+
+fn bool_item(value: bool) -> Vec<u8> {
+    if value {
+        vec![1]
+    } else {
+        Vec::new()
+    }
+}
+
+// --------------------------------------------------
+// Signature checking
+// --------------------------------------------------
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SigVersion {
+    Base,
+    WitnessV0,
+    Tapscript,
+}
+
+// The Tapscript leaf being executed
+struct Leaf {
+    hash: Vec<u8>,
+    annex: Option<Vec<u8>>,
+    // Validation weight left for signature checks
+    budget: i64,
+}
+
+struct Checker<'a> {
+    tx: &'a Transaction,
+    input_index: usize,
+    amount: u64,
+    cache: &'a SighashCache,
+    secp: &'a Secp256k1<VerifyOnly>,
+    leaf: Option<Leaf>,
+}
+
+impl Checker<'_> {
+    // ECDSA signature (DER + hash type byte) over the legacy or BIP143 digest
+    fn check_ecdsa(
+        &self,
+        sig: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> Result<bool, ScriptError> {
+        let Some((&hash_type, der)) = sig.split_last() else {
+            return Ok(false);
+        };
+        if !is_valid_signature_encoding(sig) {
+            return Err(ScriptError::SigDer);
+        }
+
+        let (Ok(mut signature), Ok(pubkey)) = (
+            ecdsa::Signature::from_der_lax(der),
+            PublicKey::from_slice(pubkey),
+        ) else {
+            return Ok(false);
+        };
+        // High-S is only a relay policy rule
+        signature.normalize_s();
+
+        let sighash = match sig_version {
+            SigVersion::Base => self
+                .cache
+                .legacy(self.input_index, script_code, hash_type as u32),
+            SigVersion::WitnessV0 => {
+                self.cache
+                    .bip143(self.input_index, script_code, self.amount, hash_type as u32)
+            }
+            SigVersion::Tapscript => unreachable!("Tapscript signatures are Schnorr"),
+        };
+
+        let msg = Message::from_digest_slice(&sighash).unwrap();
+        Ok(self.secp.verify_ecdsa(&msg, &signature, &pubkey).is_ok())
+    }
+
+    // BIP341 key path or BIP342 script path (`leaf` with the last executed
+    // OP_CODESEPARATOR position): 64-byte signature, or 65 with an explicit
+    // hash type
+    fn check_schnorr(
+        &self,
+        sig: &[u8],
+        key: &[u8],
+        leaf: Option<(&[u8], u32)>,
+        annex: Option<&[u8]>,
+    ) -> Result<(), ScriptError> {
+        let (sig, hash_type) = match sig.len() {
+            64 => (sig, crypto::SIGHASH_DEFAULT),
+            65 if sig[64] != 0x00 => (&sig[..64], sig[64] as u32),
+            65 => return Err(ScriptError::SchnorrSigHashtype),
+            _ => return Err(ScriptError::SchnorrSigSize),
+        };
+
+        let sighash = self
+            .cache
+            .bip341(self.input_index, hash_type, leaf, annex)
+            .ok_or(ScriptError::SchnorrSigHashtype)?;
+
+        let key = XOnlyPublicKey::from_slice(key).map_err(|_| ScriptError::SchnorrSig)?;
+        let sig = schnorr::Signature::from_slice(sig).map_err(|_| ScriptError::SchnorrSig)?;
+        let msg = Message::from_digest_slice(&sighash).unwrap();
+
+        self.secp
+            .verify_schnorr(&sig, &msg, &key)
+            .map_err(|_| ScriptError::SchnorrSig)
+    }
+
+    // BIP342 OP_CHECKSIG(VERIFY/ADD): an empty signature is a valid "no",
+    // any other must verify. Keys other than 32 bytes are left for future
+    // soft forks.
+    fn check_tapscript_sig(
+        &self,
+        sig: &[u8],
+        pubkey: &[u8],
+        codesep_pos: u32,
+        budget: &mut i64,
+    ) -> Result<bool, ScriptError> {
+        if pubkey.is_empty() {
+            return Err(ScriptError::TapscriptEmptyPubkey);
+        }
+        if sig.is_empty() {
+            return Ok(false);
+        }
+
+        *budget -= VALIDATION_WEIGHT_PER_SIGOP;
+        if *budget < 0 {
+            return Err(ScriptError::TapscriptValidationWeight);
+        }
+
+        if pubkey.len() == 32 {
+            let leaf = self.leaf.as_ref().expect("Tapscript without a leaf");
+            self.check_schnorr(
+                sig,
+                pubkey,
+                Some((&leaf.hash, codesep_pos)),
+                leaf.annex.as_deref(),
+            )?;
+        }
+        Ok(true)
+    }
+
+    fn check_locktime(&self, locktime: i64) -> bool {
+        locktime_satisfied(self.tx, self.input_index, locktime)
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        sequence_satisfied(self.tx, self.input_index, sequence)
+    }
+}
+
+// OP_CHECKLOCKTIMEVERIFY rule for input `input_index` of `tx`
+pub fn locktime_satisfied(tx: &Transaction, input_index: usize, locktime: i64) -> bool {
+    let tx_locktime = tx.locktime as i64;
+
+    // Both must be heights or both timestamps
+    let same_kind = (tx_locktime < LOCKTIME_THRESHOLD) == (locktime < LOCKTIME_THRESHOLD);
+
+    // A final sequence disables the transaction's nLockTime
+    same_kind && locktime <= tx_locktime && tx.inputs[input_index].sequence != SEQUENCE_FINAL
+}
+
+// OP_CHECKSEQUENCEVERIFY rule (BIP68 relative locktime) for an input
+pub fn sequence_satisfied(tx: &Transaction, input_index: usize, sequence: i64) -> bool {
+    let tx_sequence = tx.inputs[input_index].sequence as i64;
+
+    if (tx.version as i32) < 2 || tx_sequence & SEQUENCE_DISABLE_FLAG != 0 {
+        return false;
+    }
+
+    let mask = SEQUENCE_TYPE_FLAG | SEQUENCE_MASK;
+    let (required, actual) = (sequence & mask, tx_sequence & mask);
+
+    // Both must be block counts or both time intervals
+    (required < SEQUENCE_TYPE_FLAG) == (actual < SEQUENCE_TYPE_FLAG) && required <= actual
+}
+
+// BIP66 strict DER, including the trailing hash type byte
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    let len = sig.len();
+    if !(9..=73).contains(&len) || sig[0] != 0x30 || sig[1] as usize != len - 3 {
+        return false;
+    }
+
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= len {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != len {
+        return false;
+    }
+
+    // R: positive integer without excess padding
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+
+    // S: same rules
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    !(len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0)
+}
+
+// --------------------------------------------------
+// Script evaluation
+// --------------------------------------------------
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::InvalidStackOperation)
+}
+
+fn pop_num(stack: &mut Vec<Vec<u8>>) -> Result<i64, ScriptError> {
+    decode_num(&pop(stack)?, 4)
+}
+
+// Item `depth` places below the top (0 = top)
+fn peek(stack: &[Vec<u8>], depth: usize) -> Result<&Vec<u8>, ScriptError> {
+    stack
+        .len()
+        .checked_sub(depth + 1)
+        .map(|i| &stack[i])
+        .ok_or(ScriptError::InvalidStackOperation)
+}
+
+fn require(stack: &[Vec<u8>], n: usize) -> Result<(), ScriptError> {
+    if stack.len() < n {
+        return Err(ScriptError::InvalidStackOperation);
+    }
+    Ok(())
+}
+
+fn eval_script(
+    stack: &mut Vec<Vec<u8>>,
+    script: &[u8],
+    sig_version: SigVersion,
+    checker: &Checker,
+) -> Result<(), ScriptError> {
+    let tapscript = sig_version == SigVersion::Tapscript;
+    if script.len() > MAX_SCRIPT_SIZE && !tapscript {
+        return Err(ScriptError::ScriptSize);
+    }
+
+    let mut alt_stack: Vec<Vec<u8>> = Vec::new();
+    let mut exec_stack: Vec<bool> = Vec::new();
+    let mut op_count = 0;
+    // Just past the last executed OP_CODESEPARATOR
+    let mut code_start = 0;
+    // Tapscript signs the opcode index of the last OP_CODESEPARATOR instead
+    let mut codesep_pos = 0xffffffff;
+    let mut budget = checker.leaf.as_ref().map_or(0, |leaf| leaf.budget);
+    let mut instructions = Instructions::new(script);
+    let mut op_index = 0;
+
+    while let Some(instruction) = instructions.next() {
+        op_index += 1;
+        let (op, data) = match instruction {
+            Instruction::Push { opcode, data } => (opcode, data),
+            Instruction::Op(op) => (op, &[][..]),
+            Instruction::Truncated => return Err(ScriptError::BadOpcode),
+        };
+        let executing = !exec_stack.contains(&false);
+
+        if data.len() > MAX_ELEMENT_SIZE {
+            return Err(ScriptError::PushSize);
+        }
+
+        if op > 0x60 && !tapscript {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::OpCount);
+            }
+        }
+
+        // Fail even in unexecuted branches
+        match op {
+            0x7e..=0x81 | 0x83..=0x86 | 0x8d | 0x8e | 0x95..=0x99 => {
+                return Err(ScriptError::DisabledOpcode)
+            }
+            0x65 | 0x66 => return Err(ScriptError::BadOpcode),
+            _ => {}
+        }
+
+        if !executing && !(0x63..=0x68).contains(&op) {
+            continue;
+        }
+
+        match op {
+            // Pushes
+            0x00..=0x4e => stack.push(data.to_vec()),
+            0x4f => stack.push(encode_num(-1)),
+            0x51..=0x60 => stack.push(encode_num((op - 0x50) as i64)),
+
+            // Flow control
+            0x61 | 0xb0 | 0xb3..=0xb9 => {} // OP_NOP, OP_NOP1, OP_NOP4-10
+            0x63 | 0x64 => {
+                let mut value = false;
+                if executing {
+                    let condition = pop(stack)?;
+                    if tapscript && !(condition.is_empty() || condition == [0x01]) {
+                        return Err(ScriptError::TapscriptMinimalIf);
+                    }
+                    value = cast_to_bool(&condition);
+                    if op == 0x64 {
+                        value = !value;
+                    }
+                }
+                exec_stack.push(value);
+            }
+            0x67 => {
+                let top = exec_stack
+                    .last_mut()
+                    .ok_or(ScriptError::UnbalancedConditional)?;
+                *top = !*top;
+            }
+            0x68 => {
+                exec_stack.pop().ok_or(ScriptError::UnbalancedConditional)?;
+            }
+            0x69 => {
+                if !cast_to_bool(&pop(stack)?) {
+                    return Err(ScriptError::Verify);
+                }
+            }
+            0x6a => return Err(ScriptError::OpReturn),
+
+            // Stack operations
+            0x6b => alt_stack.push(pop(stack)?),
+            0x6c => stack.push(
+                alt_stack
+                    .pop()
+                    .ok_or(ScriptError::InvalidAltstackOperation)?,
+            ),
+            0x6d => {
+                require(stack, 2)?;
+                stack.truncate(stack.len() - 2);
+            }
+            0x6e => {
+                require(stack, 2)?;
+                stack.extend_from_within(stack.len() - 2..);
+            }
+            0x6f => {
+                require(stack, 3)?;
+                stack.extend_from_within(stack.len() - 3..);
+            }
+            0x70 => {
+                require(stack, 4)?;
+                stack.extend_from_within(stack.len() - 4..stack.len() - 2);
+            }
+            0x71 => {
+                require(stack, 6)?;
+                let start = stack.len() - 6;
+                let moved: Vec<Vec<u8>> = stack.drain(start..start + 2).collect();
+                stack.extend(moved);
+            }
+            0x72 => {
+                require(stack, 4)?;
+                let start = stack.len() - 4;
+                stack[start..].rotate_left(2);
+            }
+            0x73 => {
+                let top = peek(stack, 0)?.clone();
+                if cast_to_bool(&top) {
+                    stack.push(top);
+                }
+            }
+            0x74 => stack.push(encode_num(stack.len() as i64)),
+            0x75 => {
+                pop(stack)?;
+            }
+            0x76 => stack.push(peek(stack, 0)?.clone()),
+            0x77 => {
+                require(stack, 2)?;
+                stack.remove(stack.len() - 2);
+            }
+            0x78 => stack.push(peek(stack, 1)?.clone()),
+            0x79 | 0x7a => {
+                let n = pop_num(stack)?;
+                if n < 0 || n as usize >= stack.len() {
+                    return Err(ScriptError::InvalidStackOperation);
+                }
+                let index = stack.len() - 1 - n as usize;
+                let item = if op == 0x7a {
+                    stack.remove(index)
+                } else {
+                    stack[index].clone()
+                };
+                stack.push(item);
+            }
+            0x7b => {
+                require(stack, 3)?;
+                let start = stack.len() - 3;
+                stack[start..].rotate_left(1);
+            }
+            0x7c => {
+                require(stack, 2)?;
+                let len = stack.len();
+                stack.swap(len - 1, len - 2);
+            }
+            0x7d => {
+                require(stack, 2)?;
+                let top = peek(stack, 0)?.clone();
+                stack.insert(stack.len() - 2, top);
+            }
+            0x82 => stack.push(encode_num(peek(stack, 0)?.len() as i64)),
+
+            // Bitwise logic
+            0x87 | 0x88 => {
+                let a = pop(stack)?;
+                let b = pop(stack)?;
+                if op == 0x88 {
+                    if a != b {
+                        return Err(ScriptError::EqualVerify);
+                    }
+                } else {
+                    stack.push(bool_item(a == b));
+                }
+            }
+
+            // Arithmetic
+            0x8b | 0x8c | 0x8f..=0x92 => {
+                let n = pop_num(stack)?;
+                let result = match op {
+                    0x8b => n + 1,
+                    0x8c => n - 1,
+                    0x8f => -n,
+                    0x90 => n.abs(),
+                    0x91 => (n == 0) as i64,
+                    _ => (n != 0) as i64,
+                };
+                stack.push(encode_num(result));
+            }
+            0x93 | 0x94 | 0x9a..=0xa4 => {
+                let b = pop_num(stack)?;
+                let a = pop_num(stack)?;
+                let result = match op {
+                    0x93 => a + b,
+                    0x94 => a - b,
+                    0x9a => (a != 0 && b != 0) as i64,
+                    0x9b => (a != 0 || b != 0) as i64,
+                    0x9c | 0x9d => (a == b) as i64,
+                    0x9e => (a != b) as i64,
+                    0x9f => (a < b) as i64,
+                    0xa0 => (a > b) as i64,
+                    0xa1 => (a <= b) as i64,
+                    0xa2 => (a >= b) as i64,
+                    0xa3 => a.min(b),
+                    _ => a.max(b),
+                };
+
+                if op == 0x9d {
+                    if result == 0 {
+                        return Err(ScriptError::NumEqualVerify);
+                    }
+                } else {
+                    stack.push(encode_num(result));
+                }
+            }
+            0xa5 => {
+                let max = pop_num(stack)?;
+                let min = pop_num(stack)?;
+                let x = pop_num(stack)?;
+                stack.push(bool_item(min <= x && x < max));
+            }
+
+            // Crypto
+            0xa6 => {
+                let item = pop(stack)?;
+                stack.push(Ripemd160::digest(&item).to_vec());
+            }
+            0xa7 => {
+                let item = pop(stack)?;
+                stack.push(Sha1::digest(&item).to_vec());
+            }
+            0xa8 => {
+                let item = pop(stack)?;
+                stack.push(Sha256::digest(&item).to_vec());
+            }
+            0xa9 => {
+                let item = pop(stack)?;
+                stack.push(crypto::hash160(&item));
+            }
+            0xaa => {
+                let item = pop(stack)?;
+                stack.push(crypto::double_sha256(&item));
+            }
+            0xab => {
+                code_start = instructions.position();
+                codesep_pos = op_index - 1;
+            }
+            0xac | 0xad if tapscript => {
+                let pubkey = pop(stack)?;
+                let sig = pop(stack)?;
+                let valid = checker.check_tapscript_sig(&sig, &pubkey, codesep_pos, &mut budget)?;
+                if op == 0xad {
+                    if !valid {
+                        return Err(ScriptError::CheckSigVerify);
+                    }
+                } else {
+                    stack.push(bool_item(valid));
+                }
+            }
+            0xac | 0xad => {
+                let pubkey = pop(stack)?;
+                let sig = pop(stack)?;
+
+                let mut script_code = script[code_start..].to_vec();
+                if sig_version == SigVersion::Base {
+                    script_code = find_and_delete(&script_code, &sig);
+                }
+
+                let valid = checker.check_ecdsa(&sig, &pubkey, &script_code, sig_version)?;
+                if op == 0xad {
+                    if !valid {
+                        return Err(ScriptError::CheckSigVerify);
+                    }
+                } else {
+                    stack.push(bool_item(valid));
+                }
+            }
+            0xae | 0xaf if tapscript => return Err(ScriptError::TapscriptCheckMultisig),
+            0xae | 0xaf => {
+                let valid = check_multisig(stack, &script[code_start..], sig_version, checker)?;
+                op_count += valid.1;
+                if op_count > MAX_OPS_PER_SCRIPT {
+                    return Err(ScriptError::OpCount);
+                }
+
+                if op == 0xaf {
+                    if !valid.0 {
+                        return Err(ScriptError::CheckMultisigVerify);
+                    }
+                } else {
+                    stack.push(bool_item(valid.0));
+                }
+            }
+
+            // OP_CHECKSIGADD: <sig> <n> <pubkey> -> n + (sig is valid)
+            0xba if tapscript => {
+                let pubkey = pop(stack)?;
+                let n = pop_num(stack)?;
+                let sig = pop(stack)?;
+                let valid = checker.check_tapscript_sig(&sig, &pubkey, codesep_pos, &mut budget)?;
+                stack.push(encode_num(n + valid as i64));
+            }
+
+            // Locktime
+            0xb1 => {
+                let locktime = decode_num(peek(stack, 0)?, 5)?;
+                if locktime < 0 {
+                    return Err(ScriptError::NegativeLocktime);
+                }
+                if !checker.check_locktime(locktime) {
+                    return Err(ScriptError::UnsatisfiedLocktime);
+                }
+            }
+            0xb2 => {
+                let sequence = decode_num(peek(stack, 0)?, 5)?;
+                if sequence < 0 {
+                    return Err(ScriptError::NegativeLocktime);
+                }
+                // Disabled sequences behave as OP_NOP3
+                if sequence & SEQUENCE_DISABLE_FLAG == 0 && !checker.check_sequence(sequence) {
+                    return Err(ScriptError::UnsatisfiedLocktime);
+                }
+            }
+
+            _ => return Err(ScriptError::BadOpcode),
+        }
+
+        if stack.len() + alt_stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+    }
+
+    if !exec_stack.is_empty() {
+        return Err(ScriptError::UnbalancedConditional);
+    }
+    Ok(())
+}
+
+// OP_CHECKMULTISIG: <dummy> <sig>... <m> <pubkey>... <n>. Returns whether
+// the signatures are valid and the key count, which adds to the op count.
+fn check_multisig(
+    stack: &mut Vec<Vec<u8>>,
+    script_code: &[u8],
+    sig_version: SigVersion,
+    checker: &Checker,
+) -> Result<(bool, usize), ScriptError> {
+    let key_count = decode_num(peek(stack, 0)?, 4)?;
+    if !(0..=MAX_PUBKEYS_PER_MULTISIG).contains(&key_count) {
+        return Err(ScriptError::PubkeyCount);
+    }
+    let key_count = key_count as usize;
+
+    let sig_count = decode_num(peek(stack, key_count + 1)?, 4)?;
+    if sig_count < 0 || sig_count as usize > key_count {
+        return Err(ScriptError::SigCount);
+    }
+    let sig_count = sig_count as usize;
+
+    // Items below the top: n, keys, m, sigs, then the dummy
+    let total = key_count + sig_count + 2;
+    require(stack, total + 1)?;
+    let items = stack.split_off(stack.len() - total);
+    let keys: Vec<&Vec<u8>> = items[sig_count + 1..total - 1].iter().rev().collect();
+    let sigs: Vec<&Vec<u8>> = items[..sig_count].iter().rev().collect();
+
+    let mut script_code = script_code.to_vec();
+    if sig_version == SigVersion::Base {
+        for sig in &sigs {
+            script_code = find_and_delete(&script_code, sig);
+        }
+    }
+
+    // Signatures must appear in the same order as their keys
+    let mut key_index = 0;
+    let mut sig_index = 0;
+    let mut valid = true;
+    while valid && sig_index < sigs.len() {
+        if checker.check_ecdsa(sigs[sig_index], keys[key_index], &script_code, sig_version)? {
+            sig_index += 1;
+        }
+        key_index += 1;
+        if sigs.len() - sig_index > keys.len() - key_index {
+            valid = false;
+        }
+    }
+
+    // BIP147: the extra item consumed by the off-by-one bug must be empty
+    if !pop(stack)?.is_empty() {
+        return Err(ScriptError::SigNullDummy);
+    }
+
+    Ok((valid, key_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        keys::Keyring,
+        multisig::{self, MultisigKind},
+        network::Network,
+        psbt::Psbt,
+        tx,
+    };
+    use secp256k1::SecretKey;
+
+    fn utxo(script_pubkey: &[u8], seed: u8) -> UTXO {
+        UTXO {
+            txid: hex::encode([seed; 32]),
+            vout: 0,
+            scriptPubKey: utils::bytes_to_hex(script_pubkey),
+            amount: 50_000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_script_numbers() {
+        for n in [0, 1, -1, 127, 128, -128, 255, 256, 0x7fffffff, -0x7fffffff] {
+            assert_eq!(decode_num(&encode_num(n), 4), Ok(n));
+        }
+        assert_eq!(encode_num(-1), vec![0x81]);
+        assert_eq!(encode_num(128), vec![0x80, 0x00]);
+        assert!(!cast_to_bool(&[0x00, 0x80]));
+        assert_eq!(decode_num(&[0; 5], 4), Err(ScriptError::NumOverflow));
+    }
+
+    #[test]
+    fn test_verifies_signed_single_key_inputs() {
+        let network = Network::Testnet;
+        let secret = SecretKey::from_slice(&[3; 32]).unwrap();
+        let mut keyring = Keyring::default();
+        keyring.add(secret);
+        let pubkey = crate::keys::privkey_to_pubkey(&secret);
+        let pubkey_hash = crypto::hash160(&pubkey.serialize());
+
+        let scripts = [
+            address::p2pkh_script(&pubkey_hash),
+            address::p2wpkh_script(&pubkey_hash),
+            address::p2sh_script(&crypto::hash160(&address::p2wpkh_script(&pubkey_hash))),
+            address::address_to_scriptpubkey(
+                &address::pubkey_to_p2tr(&pubkey, network.bech32_hrp()),
+                network,
+            ),
+        ];
+        let utxos: Vec<UTXO> = scripts
+            .iter()
+            .enumerate()
+            .map(|(i, s)| utxo(s, i as u8 + 1))
+            .collect();
+
+        let to = address::pubkey_hash_to_address(&[9; 20], network);
+        let result = tx::create_payment(
+            &utxos,
+            &[tx::Recipient {
+                address: to,
+                amount: 0,
+                max: true,
+                subtract_fee: false,
+            }],
+            2,
+            &keyring,
+            network,
+            None,
+        );
+        let mut signed = Transaction::parse(&utils::hex_to_bytes(&result.raw_tx)).unwrap();
+        assert!(verify_transaction(&signed, &utxos).is_empty());
+
+        // Changing an output invalidates every SIGHASH_ALL signature
+        signed.outputs[0].value -= 1;
+        let failures = verify_transaction(&signed, &utxos);
+        assert_eq!(failures.len(), 4);
+        assert_eq!(failures[0].error, ScriptError::EvalFalse.message());
+        assert_eq!(failures[3].error, ScriptError::SchnorrSig.message());
+    }
+
+    #[test]
+    fn test_verifies_multisig_and_null_dummy() {
+        let secrets: Vec<SecretKey> = (1u8..=3)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let secp = Secp256k1::new();
+        let pubkeys: Vec<PublicKey> = secrets.iter().map(|k| k.public_key(&secp)).collect();
+        let script = multisig::multisig_script(2, &pubkeys);
+
+        for kind in [
+            MultisigKind::P2sh,
+            MultisigKind::P2shP2wsh,
+            MultisigKind::P2wsh,
+        ] {
+            // Full funding transaction, needed for the legacy P2SH input
+            let funding = Transaction {
+                version: 2,
+                inputs: vec![tx::TxIn::spending(&utxo(&[], 7))],
+                outputs: vec![TxOut {
+                    value: 50_000,
+                    script_pubkey: multisig::script_pubkey(kind, &script),
+                }],
+                locktime: 0,
+            };
+            let utxos = [UTXO {
+                txid: funding.txid(),
+                ..utxo(&funding.outputs[0].script_pubkey, 7)
+            }];
+            let to = multisig::multisig_address(kind, &script, Network::Testnet);
+            let mut psbt: Psbt = multisig::create_multisig_psbt(
+                &utxos,
+                &[funding],
+                &to,
+                20_000,
+                1_000,
+                2,
+                &pubkeys,
+                kind,
+                Network::Testnet,
+            );
+            psbt.sign(&secrets[0]);
+            psbt.sign(&secrets[1]);
+            assert!(psbt.finalize());
+
+            let mut signed = psbt.extract();
+            assert!(verify_transaction(&signed, &utxos).is_empty());
+
+            if kind == MultisigKind::P2wsh {
+                signed.inputs[0].witness[0] = vec![1];
+                let failures = verify_transaction(&signed, &utxos);
+                assert_eq!(failures[0].error, ScriptError::SigNullDummy.message());
+            }
+        }
+    }
+
+    #[test]
+    fn test_locktime_checks() {
+        let secp = Secp256k1::verification_only();
+        let mut tx = Transaction {
+            version: 2,
+            inputs: vec![crate::tx::TxIn::spending(&utxo(&[], 1))],
+            outputs: vec![],
+            locktime: 100,
+        };
+        tx.inputs[0].sequence = 10;
+        let cache = SighashCache::new(&tx, None);
+        let checker = Checker {
+            tx: &tx,
+            input_index: 0,
+            amount: 0,
+            cache: &cache,
+            secp: &secp,
+            leaf: None,
+        };
+
+        let run = |script: &[u8]| eval_script(&mut Vec::new(), script, SigVersion::Base, &checker);
+
+        // <100> CLTV, <101> CLTV, <10> CSV, <11> CSV
+        assert_eq!(run(&[0x01, 100, 0xb1]), Ok(()));
+        assert_eq!(
+            run(&[0x01, 101, 0xb1]),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        assert_eq!(run(&[0x5a, 0xb2]), Ok(()));
+        assert_eq!(run(&[0x5b, 0xb2]), Err(ScriptError::UnsatisfiedLocktime));
+
+        // Conditionals and arithmetic: 2 3 ADD 5 EQUAL IF 1 ELSE RETURN ENDIF
+        let mut stack = Vec::new();
+        let script = [0x52, 0x53, 0x93, 0x55, 0x87, 0x63, 0x51, 0x67, 0x6a, 0x68];
+        assert_eq!(
+            eval_script(&mut stack, &script, SigVersion::Base, &checker),
+            Ok(())
+        );
+        assert_eq!(stack, vec![vec![1]]);
+    }
+}
@@ -1,6 +1,7 @@
-use crate::{crypto, network::Network};
+use crate::{crypto, hd::ExtendedPrivKey, network::Network, utils};
 use rand::rngs::OsRng;
-use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use secp256k1::{Keypair, Parity, PublicKey, Scalar, Secp256k1, SecretKey, XOnlyPublicKey};
+use serde::Deserialize;
 
 // Generate a new compressed WIF private key
 pub fn generate_wif(network: Network) -> String {
@@ -19,20 +20,35 @@ pub fn generate_wif(network: Network) -> String {
     bs58::encode(payload).into_string()
 }
 
-// Decode WIF into SecretKey
+// Decode a compressed WIF into SecretKey, for the segwit and Taproot
+// forms that cannot commit to an uncompressed key
 pub fn wif_to_privkey(wif: &str, network: Network) -> SecretKey {
+    let (secret, compressed) = decode_wif(wif, network);
+    assert!(compressed, "key not compressed");
+    secret
+}
+
+// Decode compressed or uncompressed (paper wallet) WIF.
+// Returns the key and whether it is marked compressed.
+pub fn decode_wif(wif: &str, network: Network) -> (SecretKey, bool) {
     let data = bs58::decode(wif).into_vec().expect("invalid WIF");
 
-    // 1 (prefix) + 32 (key) + 1 (compressed) + 4 (checksum)
-    assert!(data.len() == 38, "invalid WIF length");
+    // 1 (prefix) + 32 (key) + [1 (compressed)] + 4 (checksum)
+    assert!(data.len() == 37 || data.len() == 38, "invalid WIF length");
     assert!(data[0] == network.wif_prefix(), "wrong network");
-    assert!(data[33] == 0x01, "key not compressed");
+
+    let compressed = data.len() == 38;
+    if compressed {
+        assert!(data[33] == 0x01, "invalid compression flag");
+    }
 
     // Verify checksum
-    let checksum = crypto::checksum(&data[..34]);
-    assert_eq!(&data[34..], &checksum, "invalid WIF checksum");
+    let body = data.len() - 4;
+    let checksum = crypto::checksum(&data[..body]);
+    assert_eq!(&data[body..], &checksum, "invalid WIF checksum");
 
-    SecretKey::from_slice(&data[1..33]).expect("invalid private key")
+    let secret = SecretKey::from_slice(&data[1..33]).expect("invalid private key");
+    (secret, compressed)
 }
 
 // Derive compressed public key from private key
@@ -40,3 +56,180 @@ pub fn privkey_to_pubkey(secret: &SecretKey) -> PublicKey {
     let secp = Secp256k1::new();
     PublicKey::from_secret_key(&secp, secret)
 }
+
+// --------------------
+// Taproot (BIP341)
+// --------------------
+
+// Leaf version of BIP342 Tapscript leaves
+pub const TAPSCRIPT_LEAF_VERSION: u8 = 0xc0;
+
+// BIP341 "H" point: an internal key without a known private key, leaving
+// only the script path
+const UNSPENDABLE_INTERNAL_KEY: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+pub fn unspendable_internal_key() -> XOnlyPublicKey {
+    XOnlyPublicKey::from_slice(&UNSPENDABLE_INTERNAL_KEY).unwrap()
+}
+
+pub fn tap_leaf_hash(script: &[u8], leaf_version: u8) -> Vec<u8> {
+    let mut data = vec![leaf_version];
+    data.extend(utils::varint(script.len()));
+    data.extend(script);
+    crypto::tagged_hash("TapLeaf", &data)
+}
+
+// Parent of two script tree nodes, which are hashed in sorted order
+pub fn tap_branch_hash(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    crypto::tagged_hash("TapBranch", &[first, second].concat())
+}
+
+// TapTweak scalar committing `internal` to an optional script tree root
+fn taproot_tweak(internal: &XOnlyPublicKey, merkle_root: Option<&[u8]>) -> Scalar {
+    let mut data = internal.serialize().to_vec();
+    if let Some(root) = merkle_root {
+        data.extend(root);
+    }
+
+    let tweak = crypto::tagged_hash("TapTweak", &data);
+    Scalar::from_be_bytes(tweak.try_into().unwrap()).expect("taproot tweak out of range")
+}
+
+// Output key Q = P + H_TapTweak(P || root)·G committed to by a P2TR script
+pub fn taproot_output_key(
+    internal: &XOnlyPublicKey,
+    merkle_root: Option<&[u8]>,
+) -> (XOnlyPublicKey, Parity) {
+    let secp = Secp256k1::new();
+    internal
+        .add_tweak(&secp, &taproot_tweak(internal, merkle_root))
+        .expect("taproot output key out of range")
+}
+
+// Keypair for key-path spends of the output key
+pub fn taproot_keypair(secret: &SecretKey, merkle_root: Option<&[u8]>) -> Keypair {
+    let secp = Secp256k1::new();
+    let keypair = Keypair::from_secret_key(&secp, secret);
+    let (internal, _) = keypair.x_only_public_key();
+
+    keypair
+        .add_xonly_tweak(&secp, &taproot_tweak(&internal, merkle_root))
+        .expect("taproot tweak out of range")
+}
+
+// --------------------
+// Keyring
+// --------------------
+
+// Private key plus the public key encoding its addresses commit to
+pub struct SigningKey {
+    pub secret: SecretKey,
+    pub pubkey: PublicKey,
+    pub compressed: bool,
+}
+
+impl SigningKey {
+    // Key from a compressed or uncompressed WIF, keeping its encoding
+    pub fn from_wif(wif: &str, network: Network) -> SigningKey {
+        let (secret, compressed) = decode_wif(wif, network);
+        SigningKey {
+            secret,
+            pubkey: privkey_to_pubkey(&secret),
+            compressed,
+        }
+    }
+
+    // Serialized pubkey as pushed in scriptSig / witness
+    pub fn pubkey_bytes(&self) -> Vec<u8> {
+        if self.compressed {
+            self.pubkey.serialize().to_vec()
+        } else {
+            self.pubkey.serialize_uncompressed().to_vec()
+        }
+    }
+
+    pub fn hash160(&self) -> Vec<u8> {
+        crypto::hash160(&self.pubkey_bytes())
+    }
+
+    // Key-path-only P2TR output key
+    pub fn taproot_output_key(&self) -> XOnlyPublicKey {
+        taproot_output_key(&self.pubkey.x_only_public_key().0, None).0
+    }
+}
+
+// Signing keys available to the tx builder, matched to inputs by hash160(pubkey)
+#[derive(Default)]
+pub struct Keyring {
+    keys: Vec<SigningKey>,
+}
+
+// JSON form: {"wifs": [...], "xprv": "...", "paths": ["m/84'/0'/0'/0/0", ...]}
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct KeyringSpec {
+    wifs: Vec<String>,
+    xprv: Option<String>,
+    paths: Vec<String>,
+}
+
+impl Keyring {
+    pub fn from_wif(wif: &str, network: Network) -> Keyring {
+        Keyring {
+            keys: vec![SigningKey::from_wif(wif, network)],
+        }
+    }
+
+    pub fn from_json(json: &str, network: Network) -> Keyring {
+        let spec: KeyringSpec = serde_json::from_str(json).expect("invalid keyring JSON");
+        let mut keyring = Keyring::default();
+
+        for wif in &spec.wifs {
+            keyring.keys.push(SigningKey::from_wif(wif, network));
+        }
+
+        if let Some(xprv) = &spec.xprv {
+            let root = ExtendedPrivKey::from_base58(xprv, network).expect("invalid xprv");
+            for path in &spec.paths {
+                let child = root.derive_path(path).expect("invalid derivation path");
+                keyring.add(child.secret);
+            }
+        } else {
+            assert!(spec.paths.is_empty(), "derivation paths require an xprv");
+        }
+
+        assert!(!keyring.keys.is_empty(), "keyring is empty");
+        keyring
+    }
+
+    pub fn add(&mut self, secret: SecretKey) {
+        self.add_key(secret, true);
+    }
+
+    pub fn add_key(&mut self, secret: SecretKey, compressed: bool) {
+        let pubkey = privkey_to_pubkey(&secret);
+        self.keys.push(SigningKey {
+            secret,
+            pubkey,
+            compressed,
+        });
+    }
+
+    pub fn find(&self, pred: impl Fn(&SigningKey) -> bool) -> Option<&SigningKey> {
+        self.keys.iter().find(|k| pred(k))
+    }
+
+    // Key whose serialized pubkey hashes to `hash160`
+    pub fn find_by_hash160(&self, hash160: &[u8]) -> Option<&SigningKey> {
+        self.find(|k| k.hash160() == hash160)
+    }
+
+    // Key receiving change: the first one added
+    pub fn change_pubkey(&self) -> PublicKey {
+        self.keys.first().expect("keyring is empty").pubkey
+    }
+}
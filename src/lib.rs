@@ -1,13 +1,33 @@
-use crate::network::Network;
+use crate::network::{ConsensusParams, Network};
 use wasm_bindgen::prelude::*;
 
 mod address;
+mod block;
+mod chain;
+mod coinbase;
 mod crypto;
+mod descriptor;
+mod electrum;
+mod esplora;
+mod feerate;
 mod fees;
+mod hd;
+mod interpreter;
 mod keys;
+mod miniscript;
+mod multisig;
 mod network;
+mod policy;
+mod psbt;
+mod rpc;
+mod scan;
+mod script;
+mod spv;
+mod stratum;
+mod sweep;
 mod tx;
 mod utils;
+mod wallet;
 
 #[wasm_bindgen]
 pub fn generate_wif(mainnet: bool) -> String {
@@ -18,6 +38,37 @@ pub fn generate_wif(mainnet: bool) -> String {
     })
 }
 
+// BIP32 root xprv / tprv from a hex seed, for use in keyring JSON
+#[wasm_bindgen]
+pub fn xprv_from_seed(seed_hex: &str, mainnet: bool) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let seed = hex::decode(seed_hex).expect("invalid seed hex");
+    hd::ExtendedPrivKey::from_seed(&seed).to_base58(net)
+}
+
+// Extended public key (xpub / tpub) at `path` below an xprv, e.g. the
+// account key for a watch-only descriptor
+#[wasm_bindgen]
+pub fn xpub_from_xprv(xprv: &str, path: &str, mainnet: bool) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    hd::ExtendedPrivKey::from_base58(xprv, net)
+        .expect("invalid xprv")
+        .derive_path(path)
+        .expect("invalid derivation path")
+        .to_public()
+        .to_base58(net)
+}
+
 #[wasm_bindgen]
 pub fn wif_to_address(wif: &str, mainnet: bool) -> String {
     let net = if mainnet {
@@ -26,9 +77,9 @@ pub fn wif_to_address(wif: &str, mainnet: bool) -> String {
         Network::Testnet
     };
 
-    let privkey = keys::wif_to_privkey(wif, net);
-    let pubkey = keys::privkey_to_pubkey(&privkey);
-    address::pubkey_to_address(&pubkey, net)
+    // P2PKH of the pubkey encoding the WIF is flagged with
+    let key = keys::SigningKey::from_wif(wif, net);
+    address::pubkey_hash_to_address(&key.hash160(), net)
 }
 
 #[wasm_bindgen]
@@ -44,6 +95,20 @@ pub fn pubkey_to_bech32_wasm(wif: &str, mainnet: bool) -> String {
     address::pubkey_to_bech32(&pubkey, net.bech32_hrp())
 }
 
+// Key-path-only Taproot (P2TR) address for a WIF
+#[wasm_bindgen]
+pub fn pubkey_to_p2tr_wasm(wif: &str, mainnet: bool) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let privkey = keys::wif_to_privkey(wif, net);
+    let pubkey = keys::privkey_to_pubkey(&privkey);
+    address::pubkey_to_p2tr(&pubkey, net.bech32_hrp())
+}
+
 #[wasm_bindgen]
 pub fn validate_address(addr: &str, mainnet: bool) -> bool {
     address::validate_address(
@@ -69,6 +134,7 @@ pub fn estimate_fee_wasm(
         serde_json::from_str(output_scripts_json).expect("invalid output scripts JSON");
 
     fees::estimate_fee(&input_scripts, &output_scripts, sat_per_byte)
+        .expect("unsupported input script")
 }
 
 #[wasm_bindgen]
@@ -85,6 +151,57 @@ pub fn estimate_fee_from_utxos_wasm(
         serde_json::from_str(output_scripts_json).expect("invalid output scripts JSON");
 
     fees::estimate_fee(&input_scripts, &output_scripts, sat_per_byte)
+        .expect("unsupported input script")
+}
+
+// Exact weight, vsize and fee for inputs described as JSON specs, e.g.
+// [{"type":"p2wpkh"},{"type":"p2wsh_multisig","m":2,"n":3}], paying to the
+// given output scripts; `low_r` assumes signers that grind for low R
+#[wasm_bindgen]
+pub fn estimate_tx_size(
+    inputs_json: &str,
+    output_scripts_json: &str,
+    sat_per_vbyte: u64,
+    low_r: bool,
+) -> String {
+    let inputs: Vec<fees::InputSpec> =
+        serde_json::from_str(inputs_json).expect("invalid input specs JSON");
+    let output_scripts: Vec<String> =
+        serde_json::from_str(output_scripts_json).expect("invalid output scripts JSON");
+
+    let output_lens: Vec<usize> = output_scripts.iter().map(|s| s.len() / 2).collect();
+    let sig = if low_r {
+        fees::EcdsaSig::LowR
+    } else {
+        fees::EcdsaSig::HighR
+    };
+    serde_json::to_string(&fees::estimate_size(
+        &inputs,
+        &output_lens,
+        sig,
+        sat_per_vbyte,
+    ))
+    .expect("serialize size")
+}
+
+// Recommended sat/vB for the next block, six blocks and economy, from a
+// mempool snapshot (fee-rate histogram or [{"vsize","fee"}]) and recent
+// getblockstats-style block fee rates; never below the network relay floor
+#[wasm_bindgen]
+pub fn recommend_fee_rates(mempool_json: &str, recent_blocks_json: &str, mainnet: bool) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let mempool: feerate::MempoolSnapshot =
+        serde_json::from_str(mempool_json).expect("invalid mempool JSON");
+    let blocks: Vec<feerate::BlockFeeRates> =
+        serde_json::from_str(recent_blocks_json).expect("invalid block fee rates JSON");
+
+    serde_json::to_string(&feerate::recommend_for_network(&mempool, &blocks, net))
+        .expect("serialize fee rates")
 }
 
 #[wasm_bindgen]
@@ -95,6 +212,28 @@ pub struct WasmTxResult {
     effective_fee: u64,
 }
 
+impl WasmTxResult {
+    // Refuse results paying an absurd fee (over 10,000 sat/vB or 0.1 coin)
+    // unless the caller passes `allow_absurd_fee`
+    fn checked(result: tx::TxResult, allow_absurd_fee: bool) -> WasmTxResult {
+        if let Err(e) = policy::check_fee(
+            result.effective_fee,
+            result.vbytes,
+            &policy::FeeLimits::default(),
+            allow_absurd_fee,
+        ) {
+            panic!("{}", e.message());
+        }
+
+        WasmTxResult {
+            raw_tx: result.raw_tx,
+            psbt: result.psbt,
+            vbytes: result.vbytes,
+            effective_fee: result.effective_fee,
+        }
+    }
+}
+
 #[wasm_bindgen]
 impl WasmTxResult {
     #[wasm_bindgen(getter)]
@@ -118,7 +257,10 @@ impl WasmTxResult {
     }
 }
 
+// Spend every UTXO given (only the confirmed, mature ones with a
+// `tip_height`) to `to_address`, change back to the key
 #[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
 pub fn create_signed_tx_full(
     utxos_json: &str,
     to_address: &str,
@@ -126,12 +268,1440 @@ pub fn create_signed_tx_full(
     fee: u64,
     wif: &str,
     mainnet: bool,
+    tip_height: Option<u32>,
+    allow_absurd_fee: bool,
+) -> WasmTxResult {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let utxos = parse_utxos(utxos_json, tip_height);
+    let keyring = keys::Keyring::from_wif(wif, net);
+    let tx_result = tx::create_and_sign(&utxos, to_address, amount, fee, &keyring, mainnet, None);
+    WasmTxResult::checked(tx_result, allow_absurd_fee)
+}
+
+// Same as `create_signed_tx_full`, plus a zero-value OP_RETURN output
+// carrying `data` (hex when `data_is_hex`, UTF-8 text otherwise)
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn create_signed_tx_with_data(
+    utxos_json: &str,
+    to_address: &str,
+    amount: u64,
+    fee: u64,
+    wif: &str,
+    mainnet: bool,
+    data: &str,
+    data_is_hex: bool,
+    tip_height: Option<u32>,
+    allow_absurd_fee: bool,
+) -> WasmTxResult {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let payload = if data_is_hex {
+        hex::decode(data).expect("invalid OP_RETURN hex")
+    } else {
+        data.as_bytes().to_vec()
+    };
+
+    let tx_result = tx::create_and_sign(
+        &parse_utxos(utxos_json, tip_height),
+        to_address,
+        amount,
+        fee,
+        &keys::Keyring::from_wif(wif, net),
+        mainnet,
+        Some(&payload),
+    );
+    WasmTxResult::checked(tx_result, allow_absurd_fee)
+}
+
+// Sign with a keyring: {"wifs": [...], "xprv": "...", "paths": [...]}.
+// Each input is matched to its key; change goes to the first key.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn create_signed_tx_keyring(
+    utxos_json: &str,
+    to_address: &str,
+    amount: u64,
+    fee: u64,
+    keyring_json: &str,
+    mainnet: bool,
+    tip_height: Option<u32>,
+    allow_absurd_fee: bool,
+) -> WasmTxResult {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let utxos = parse_utxos(utxos_json, tip_height);
+    let keyring = keys::Keyring::from_json(keyring_json, net);
+    let tx_result = tx::create_and_sign(&utxos, to_address, amount, fee, &keyring, mainnet, None);
+    WasmTxResult::checked(tx_result, allow_absurd_fee)
+}
+
+// UTXO list, keeping only confirmed, mature outputs when the tip is known
+fn parse_utxos(utxos_json: &str, tip_height: Option<u32>) -> Vec<tx::UTXO> {
+    let utxos: Vec<tx::UTXO> = serde_json::from_str(utxos_json).expect("invalid UTXO JSON");
+    match tip_height {
+        Some(tip) => tx::spendable_utxos(&utxos, tip, 1),
+        None => utxos,
+    }
+}
+
+// Spendable, immature (coinbase) and unconfirmed totals at `tip_height`
+#[wasm_bindgen]
+pub fn utxo_balance(utxos_json: &str, tip_height: u32) -> String {
+    let utxos = parse_utxos(utxos_json, None);
+    serde_json::to_string(&tx::balance(&utxos, tip_height)).expect("serialize balance")
+}
+
+// UTXOs with `is_coinbase` set for those whose funding transaction (JSON
+// list of raw hex, e.g. fetched after Electrum's or the node's listunspent)
+// is a coinbase
+#[wasm_bindgen]
+pub fn mark_coinbase_utxos(utxos_json: &str, funding_txs_json: &str) -> String {
+    let mut utxos = parse_utxos(utxos_json, None);
+    let funding_txs: Vec<String> =
+        serde_json::from_str(funding_txs_json).expect("invalid transactions JSON");
+    let funding_txs: Vec<tx::Transaction> = funding_txs
+        .iter()
+        .map(|t| tx::Transaction::parse(&utils::hex_to_bytes(t)).expect("invalid transaction"))
+        .collect();
+    tx::mark_coinbase(&mut utxos, &funding_txs);
+    serde_json::to_string(&utxos).expect("serialize UTXOs")
+}
+
+// UTXOs spendable at `tip_height` with at least `min_confirmations`
+#[wasm_bindgen]
+pub fn spendable_utxos(utxos_json: &str, tip_height: u32, min_confirmations: u32) -> String {
+    let utxos = parse_utxos(utxos_json, None);
+    serde_json::to_string(&tx::spendable_utxos(&utxos, tip_height, min_confirmations))
+        .expect("serialize UTXOs")
+}
+
+// Pay a JSON list of recipients ({"address", "amount", "max", "subtract_fee"})
+// at `sat_per_vbyte`, the fee computed from the final signed size. With a
+// `tip_height`, only confirmed and mature UTXOs are spent.
+#[wasm_bindgen]
+pub fn create_payment_tx(
+    utxos_json: &str,
+    recipients_json: &str,
+    sat_per_vbyte: u64,
+    keyring_json: &str,
+    mainnet: bool,
+    tip_height: Option<u32>,
+    allow_absurd_fee: bool,
+) -> WasmTxResult {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let utxos = parse_utxos(utxos_json, tip_height);
+    let recipients: Vec<tx::Recipient> =
+        serde_json::from_str(recipients_json).expect("invalid recipients JSON");
+    let keyring = keys::Keyring::from_json(keyring_json, net);
+
+    let tx_result = tx::create_payment(&utxos, &recipients, sat_per_vbyte, &keyring, net, None);
+    WasmTxResult::checked(tx_result, allow_absurd_fee)
+}
+
+// Send everything spendable minus the exact fee to `to_address`
+#[wasm_bindgen]
+pub fn send_max_tx(
+    utxos_json: &str,
+    to_address: &str,
+    sat_per_vbyte: u64,
+    wif: &str,
+    mainnet: bool,
+    tip_height: Option<u32>,
+    allow_absurd_fee: bool,
+) -> WasmTxResult {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let utxos = parse_utxos(utxos_json, tip_height);
+    let recipient = tx::Recipient {
+        address: to_address.to_string(),
+        amount: 0,
+        max: true,
+        subtract_fee: false,
+    };
+    let keyring = keys::Keyring::from_wif(wif, net);
+
+    let tx_result = tx::create_payment(&utxos, &[recipient], sat_per_vbyte, &keyring, net, None);
+    WasmTxResult::checked(tx_result, allow_absurd_fee)
+}
+
+// --------------------
+// Paper wallet sweep
+// --------------------
+
+// JSON object with the uncompressed P2PKH, P2PKH, P2WPKH and P2SH-P2WPKH
+// addresses of a (compressed or uncompressed) WIF
+#[wasm_bindgen]
+pub fn sweep_addresses(wif: &str, mainnet: bool) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    serde_json::to_string(&sweep::sweep_addresses(wif, net)).expect("serialize addresses")
+}
+
+// Spend all (spendable, with a `tip_height`) UTXOs of a WIF to `to_address`,
+// fee from the final size, no change
+#[wasm_bindgen]
+pub fn sweep_wif(
+    utxos_json: &str,
+    wif: &str,
+    to_address: &str,
+    sat_per_vbyte: u64,
+    mainnet: bool,
+    tip_height: Option<u32>,
+    allow_absurd_fee: bool,
 ) -> WasmTxResult {
-    let tx_result = tx::create_and_sign(utxos_json, to_address, amount, fee, wif, mainnet);
-    WasmTxResult {
-        raw_tx: tx_result.raw_tx,
-        psbt: tx_result.psbt,
-        vbytes: tx_result.vbytes,
-        effective_fee: tx_result.effective_fee,
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let utxos = parse_utxos(utxos_json, tip_height);
+    let tx_result = sweep::sweep(&utxos, wif, to_address, sat_per_vbyte, net);
+    WasmTxResult::checked(tx_result, allow_absurd_fee)
+}
+
+// --------------------
+// Multisig wallets
+// --------------------
+
+fn parse_pubkeys(pubkeys_json: &str) -> Vec<secp256k1::PublicKey> {
+    let pubkeys: Vec<String> =
+        serde_json::from_str(pubkeys_json).expect("invalid public keys JSON");
+
+    pubkeys
+        .iter()
+        .map(|k| {
+            secp256k1::PublicKey::from_slice(&utils::hex_to_bytes(k)).expect("invalid public key")
+        })
+        .collect()
+}
+
+fn parse_multisig_kind(kind: &str) -> multisig::MultisigKind {
+    multisig::MultisigKind::parse(kind).expect("kind must be p2sh, p2sh-p2wsh or p2wsh")
+}
+
+// BIP-67 sorted m-of-n redeem / witness script (hex)
+#[wasm_bindgen]
+pub fn multisig_script_hex(m: usize, pubkeys_json: &str) -> String {
+    let pubkeys = parse_pubkeys(pubkeys_json);
+    utils::bytes_to_hex(&multisig::multisig_script(m, &pubkeys))
+}
+
+#[wasm_bindgen]
+pub fn multisig_address(m: usize, pubkeys_json: &str, kind: &str, mainnet: bool) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let pubkeys = parse_pubkeys(pubkeys_json);
+    let kind = parse_multisig_kind(kind);
+    assert!(
+        pubkeys.len() <= kind.max_keys(),
+        "too many keys for {:?}",
+        kind
+    );
+    let script = multisig::multisig_script(m, &pubkeys);
+    multisig::multisig_address(kind, &script, net)
+}
+
+// Unsigned PSBT (hex) spending multisig UTXOs (only confirmed, mature ones
+// with a `tip_height`), change back to the wallet. `prev_txs_json` lists the
+// raw funding transactions (hex); P2SH inputs need theirs.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn create_multisig_psbt(
+    utxos_json: &str,
+    prev_txs_json: &str,
+    to_address: &str,
+    amount: u64,
+    fee: u64,
+    m: usize,
+    pubkeys_json: &str,
+    kind: &str,
+    mainnet: bool,
+    tip_height: Option<u32>,
+) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let utxos = parse_utxos(utxos_json, tip_height);
+    let prev_txs: Vec<String> =
+        serde_json::from_str(prev_txs_json).expect("invalid previous transactions JSON");
+    let prev_txs: Vec<tx::Transaction> = prev_txs
+        .iter()
+        .map(|t| {
+            tx::Transaction::parse(&utils::hex_to_bytes(t)).expect("invalid previous transaction")
+        })
+        .collect();
+    let psbt = multisig::create_multisig_psbt(
+        &utxos,
+        &prev_txs,
+        to_address,
+        amount,
+        fee,
+        m,
+        &parse_pubkeys(pubkeys_json),
+        parse_multisig_kind(kind),
+        net,
+    );
+    utils::bytes_to_hex(&psbt.serialize())
+}
+
+fn parse_psbt(psbt_hex: &str) -> psbt::Psbt {
+    psbt::Psbt::parse(&utils::hex_to_bytes(psbt_hex)).expect("invalid PSBT")
+}
+
+// Select the sighash flags (e.g. 0x81 = ALL|ANYONECANPAY) signers must use
+// for one PSBT input
+#[wasm_bindgen]
+pub fn set_psbt_sighash_type(psbt_hex: &str, input_index: usize, sighash_type: u32) -> String {
+    let mut psbt = parse_psbt(psbt_hex);
+    psbt.inputs
+        .get_mut(input_index)
+        .expect("input index out of range")
+        .sighash_type = Some(sighash_type);
+    utils::bytes_to_hex(&psbt.serialize())
+}
+
+// Add this key's partial signatures to a PSBT (hex in, hex out)
+#[wasm_bindgen]
+pub fn sign_psbt(psbt_hex: &str, wif: &str, mainnet: bool) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let mut psbt = parse_psbt(psbt_hex);
+    psbt.sign(&keys::wif_to_privkey(wif, net));
+    utils::bytes_to_hex(&psbt.serialize())
+}
+
+// Merge signatures from a JSON array of PSBT hex strings
+#[wasm_bindgen]
+pub fn combine_psbts(psbts_json: &str) -> String {
+    let psbts: Vec<String> = serde_json::from_str(psbts_json).expect("invalid PSBT list JSON");
+    let (first, rest) = psbts.split_first().expect("no PSBTs to combine");
+
+    let mut combined = parse_psbt(first);
+    for other in rest {
+        combined.combine(&parse_psbt(other));
+    }
+    utils::bytes_to_hex(&combined.serialize())
+}
+
+// Finalize every input and return the broadcastable raw transaction hex
+#[wasm_bindgen]
+pub fn finalize_psbt(psbt_hex: &str) -> String {
+    let mut psbt = parse_psbt(psbt_hex);
+    assert!(psbt.finalize(), "not enough signatures to finalize PSBT");
+    utils::bytes_to_hex(&psbt.extract().serialize())
+}
+
+// --------------------
+// Scripts
+// --------------------
+
+// Bitcoin Core-style ASM for a script (hex)
+#[wasm_bindgen]
+pub fn script_to_asm(script_hex: &str) -> String {
+    script::Script::from_bytes(utils::hex_to_bytes(script_hex)).to_asm()
+}
+
+// Script hex from ASM; pushes are encoded minimally
+#[wasm_bindgen]
+pub fn script_from_asm(asm: &str) -> String {
+    let script = script::Script::from_asm(asm).expect("invalid script ASM");
+    utils::bytes_to_hex(script.as_bytes())
+}
+
+// Whether every push in the script uses its smallest encoding
+#[wasm_bindgen]
+pub fn script_is_minimal(script_hex: &str) -> bool {
+    script::Script::from_bytes(utils::hex_to_bytes(script_hex)).is_minimal()
+}
+
+// JSON list of {"opcode", "data", "minimal"}, one per instruction
+#[wasm_bindgen]
+pub fn script_instructions(script_hex: &str) -> String {
+    let script = script::Script::from_bytes(utils::hex_to_bytes(script_hex));
+    serde_json::to_string(&script::describe(&script)).expect("serialize instructions")
+}
+
+// --------------------
+// Output descriptors
+// --------------------
+
+fn parse_descriptor(descriptor: &str, net: Network) -> descriptor::Descriptor {
+    descriptor::Descriptor::parse(descriptor, net).expect("invalid descriptor or checksum")
+}
+
+// Canonical form of a descriptor with its checksum appended
+#[wasm_bindgen]
+pub fn normalize_descriptor(descriptor: &str, mainnet: bool) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    parse_descriptor(descriptor, net).to_string(net)
+}
+
+// JSON list of {"index", "address", "script_pubkey"} for `count` indexes
+// from `start`; a single entry for non-ranged descriptors
+#[wasm_bindgen]
+pub fn descriptor_addresses(descriptor: &str, start: u32, count: u32, mainnet: bool) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let descriptor = parse_descriptor(descriptor, net);
+    serde_json::to_string(&descriptor::derive_addresses(
+        &descriptor,
+        start,
+        count,
+        net,
+    ))
+    .expect("serialize addresses")
+}
+
+// --------------------
+// Miniscript
+// --------------------
+
+fn miniscript_context(tapscript: bool) -> miniscript::Context {
+    if tapscript {
+        miniscript::Context::Tap
+    } else {
+        miniscript::Context::Segwitv0
     }
 }
+
+fn parse_miniscript(ms: &str, tapscript: bool) -> miniscript::Miniscript {
+    miniscript::Miniscript::parse(ms, miniscript_context(tapscript)).expect("invalid miniscript")
+}
+
+// Compile a spending policy, e.g. "or(9@thresh(2,pk(A),pk(B),pk(C)),
+// and(pk(D),older(12960)))", to miniscript text
+#[wasm_bindgen]
+pub fn compile_policy(policy: &str, tapscript: bool) -> String {
+    miniscript::compile_policy(policy, miniscript_context(tapscript))
+        .expect("policy cannot be compiled")
+        .to_text()
+}
+
+// Script hex for a miniscript
+#[wasm_bindgen]
+pub fn miniscript_script(ms: &str, tapscript: bool) -> String {
+    utils::bytes_to_hex(&parse_miniscript(ms, tapscript).encode())
+}
+
+// P2WSH address for a miniscript, or for Tapscript a P2TR address with the
+// miniscript as its only leaf (no key path)
+#[wasm_bindgen]
+pub fn miniscript_address(ms: &str, tapscript: bool, mainnet: bool) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let ms = parse_miniscript(ms, tapscript);
+    if tapscript {
+        address::segwit_address(1, &ms.tap_leaf().output_key, net.bech32_hrp())
+    } else {
+        address::script_to_p2wsh_address(&ms.encode(), net.bech32_hrp())
+    }
+}
+
+// Fee for standard inputs plus P2WSH inputs spending the given miniscripts
+// (sized by their largest satisfaction)
+#[wasm_bindgen]
+pub fn estimate_fee_miniscript_wasm(
+    input_scripts_json: &str,
+    miniscripts_json: &str,
+    output_scripts_json: &str,
+    sat_per_byte: u64,
+) -> u64 {
+    let input_scripts: Vec<String> =
+        serde_json::from_str(input_scripts_json).expect("invalid input scripts JSON");
+    let miniscripts: Vec<String> =
+        serde_json::from_str(miniscripts_json).expect("invalid miniscript list JSON");
+    let output_scripts: Vec<String> =
+        serde_json::from_str(output_scripts_json).expect("invalid output scripts JSON");
+
+    let mut inputs: Vec<fees::InputSpec> = input_scripts
+        .iter()
+        .map(|s| fees::InputSpec::from_script_pubkey(s).expect("unsupported input script"))
+        .collect();
+    for ms in &miniscripts {
+        let ms = parse_miniscript(ms, false);
+        let mut witness = ms
+            .max_satisfaction()
+            .expect("miniscript cannot be satisfied");
+        witness.push(ms.encode().len());
+        inputs.push(fees::InputSpec::Custom {
+            script_sig_len: 0,
+            witness,
+        });
+    }
+
+    let output_lens: Vec<usize> = output_scripts.iter().map(|s| s.len() / 2).collect();
+    fees::estimate_size(&inputs, &output_lens, fees::EcdsaSig::HighR, sat_per_byte).fee
+}
+
+// Attach a miniscript to the PSBT inputs spending it (witness script or
+// Tapscript leaf), so that `sign_psbt` can sign them; returns PSBT hex
+#[wasm_bindgen]
+pub fn prepare_psbt_miniscript(psbt_hex: &str, ms: &str, tapscript: bool) -> String {
+    let mut psbt = parse_psbt(psbt_hex);
+    let prepared = miniscript::prepare_psbt(&mut psbt, &parse_miniscript(ms, tapscript));
+    assert!(prepared > 0, "no input spends this miniscript");
+    utils::bytes_to_hex(&psbt.serialize())
+}
+
+// Finalize the PSBT's inputs that spend this miniscript (P2WSH, or P2TR for
+// Tapscript), using its signatures and a JSON list of hex hash preimages;
+// returns PSBT hex
+#[wasm_bindgen]
+pub fn finalize_psbt_miniscript(
+    psbt_hex: &str,
+    ms: &str,
+    tapscript: bool,
+    preimages_json: &str,
+) -> String {
+    let preimages: Vec<String> =
+        serde_json::from_str(preimages_json).expect("invalid preimage list JSON");
+    let preimages: Vec<Vec<u8>> = preimages.iter().map(|p| utils::hex_to_bytes(p)).collect();
+
+    let mut psbt = parse_psbt(psbt_hex);
+    let ms = parse_miniscript(ms, tapscript);
+    let finalized = miniscript::finalize_psbt(&mut psbt, &ms, &preimages);
+    assert!(finalized > 0, "no input could be satisfied");
+    utils::bytes_to_hex(&psbt.serialize())
+}
+
+// --------------------
+// Relay policy
+// --------------------
+
+// Smallest standard value for an output paying to this script
+#[wasm_bindgen]
+pub fn dust_threshold(script_hex: &str) -> u64 {
+    policy::dust_threshold(&utils::hex_to_bytes(script_hex), policy::DUST_RELAY_FEE)
+}
+
+// Standardness rules a transaction breaks, given the UTXOs it spends (in
+// input order); JSON list of messages, empty when it would be relayed
+#[wasm_bindgen]
+pub fn check_standard_tx(raw_tx_hex: &str, utxos_json: &str) -> String {
+    let transaction =
+        tx::Transaction::parse(&utils::hex_to_bytes(raw_tx_hex)).expect("invalid transaction");
+    let utxos: Vec<tx::UTXO> = serde_json::from_str(utxos_json).expect("invalid UTXO JSON");
+    let spent: Vec<tx::TxOut> = utxos
+        .iter()
+        .map(|u| tx::TxOut {
+            value: u.amount,
+            script_pubkey: utils::hex_to_bytes(&u.scriptPubKey),
+        })
+        .collect();
+
+    let errors: Vec<&str> = policy::check_standard(&transaction, &spent)
+        .into_iter()
+        .map(policy::PolicyError::message)
+        .collect();
+    serde_json::to_string(&errors).expect("serialize policy errors")
+}
+
+// --------------------
+// Blocks
+// --------------------
+
+#[derive(serde::Serialize)]
+struct HeaderJson {
+    hash: String,
+    version: u32,
+    prev_blockhash: String,
+    merkle_root: String,
+    time: u32,
+    bits: u32,
+    nonce: u32,
+    pow_valid: bool,
+}
+
+impl HeaderJson {
+    fn new(header: &block::BlockHeader) -> HeaderJson {
+        HeaderJson {
+            hash: header.block_hash(),
+            version: header.version,
+            prev_blockhash: header.prev_blockhash.clone(),
+            merkle_root: header.merkle_root.clone(),
+            time: header.time,
+            bits: header.bits,
+            nonce: header.nonce,
+            pow_valid: header.check_pow(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct BlockJson {
+    header: HeaderJson,
+    txids: Vec<String>,
+    size: usize,
+    weight: usize,
+    merkle_root_valid: bool,
+    witness_commitment_valid: bool,
+}
+
+// JSON fields of an 80-byte header (hex), with its hash and whether it meets
+// its own nBits target
+#[wasm_bindgen]
+pub fn parse_block_header(header_hex: &str) -> String {
+    let header =
+        block::BlockHeader::parse(&utils::hex_to_bytes(header_hex)).expect("invalid block header");
+    serde_json::to_string(&HeaderJson::new(&header)).expect("serialize header")
+}
+
+// JSON summary of a full block (hex): header, txids, size, weight and the
+// merkle root and witness commitment checks
+#[wasm_bindgen]
+pub fn parse_block(block_hex: &str) -> String {
+    let block = block::Block::parse(&utils::hex_to_bytes(block_hex)).expect("invalid block");
+    serde_json::to_string(&BlockJson {
+        header: HeaderJson::new(&block.header),
+        txids: block.txdata.iter().map(tx::Transaction::txid).collect(),
+        size: block.serialize().len(),
+        weight: block.weight(),
+        merkle_root_valid: block.check_merkle_root(),
+        witness_commitment_valid: block.check_witness_commitment(),
+    })
+    .expect("serialize block")
+}
+
+// --------------------
+// SPV
+// --------------------
+
+// Check an Electrum blockchain.transaction.get_merkle branch (JSON list of
+// display-hex hashes) for the transaction at `pos` against a block header
+#[wasm_bindgen]
+pub fn verify_merkle_branch(txid: &str, merkle_json: &str, pos: u32, header_hex: &str) -> bool {
+    let branch: Vec<String> =
+        serde_json::from_str(merkle_json).expect("invalid merkle branch JSON");
+    let header =
+        block::BlockHeader::parse(&utils::hex_to_bytes(header_hex)).expect("invalid block header");
+    spv::verify_merkle_branch(txid, &branch, pos, &header)
+}
+
+#[derive(serde::Serialize)]
+struct TxOutProofJson {
+    header: HeaderJson,
+    txids: Vec<String>,
+}
+
+// Verify a gettxoutproof / merkleblock; JSON {header, txids} with the txids
+// it proves
+#[wasm_bindgen]
+pub fn verify_txout_proof(proof_hex: &str) -> String {
+    let proof = spv::MerkleBlock::parse(&utils::hex_to_bytes(proof_hex)).expect("invalid proof");
+    let txids = proof.verify().expect("proof does not match its header");
+    serde_json::to_string(&TxOutProofJson {
+        header: HeaderJson::new(&proof.header),
+        txids,
+    })
+    .expect("serialize proof")
+}
+
+// --------------------
+// Header chain
+// --------------------
+
+// Header chain on top of a trusted anchor header (genesis, or a checkpoint
+// starting a difficulty period), validated with the chain's consensus
+// parameters: JSON {"pow_limit_bits", "pow_target_spacing",
+// "pow_target_timespan", "allow_min_difficulty_blocks", "no_retargeting"}
+#[wasm_bindgen]
+pub struct WasmHeaderChain {
+    chain: chain::HeaderChain,
+}
+
+#[wasm_bindgen]
+impl WasmHeaderChain {
+    #[wasm_bindgen(constructor)]
+    pub fn new(params_json: &str, anchor_header_hex: &str, anchor_height: u32) -> WasmHeaderChain {
+        let params: ConsensusParams =
+            serde_json::from_str(params_json).expect("invalid consensus parameters JSON");
+        assert!(
+            params.pow_target_spacing > 0
+                && params.pow_target_timespan >= params.pow_target_spacing,
+            "invalid consensus parameters"
+        );
+        let anchor = block::BlockHeader::parse(&utils::hex_to_bytes(anchor_header_hex))
+            .expect("invalid block header");
+        WasmHeaderChain {
+            chain: chain::HeaderChain::new(params, anchor, anchor_height),
+        }
+    }
+
+    pub fn add_checkpoint(&mut self, height: u32, hash: &str) {
+        self.chain.add_checkpoint(height, hash);
+    }
+
+    // Validate and store a header; JSON {"height"} or {"error"}. `now` is
+    // the local unix time, 0 to skip the future-time check.
+    pub fn accept_header(&mut self, header_hex: &str, now: u32) -> String {
+        let header = block::BlockHeader::parse(&utils::hex_to_bytes(header_hex))
+            .expect("invalid block header");
+        let result = match self.chain.accept(header, (now != 0).then_some(now)) {
+            Ok(height) => serde_json::json!({ "height": height }),
+            Err(e) => serde_json::json!({ "error": e.message() }),
+        };
+        result.to_string()
+    }
+
+    // Height of a known header, if any
+    pub fn height_of(&self, hash: &str) -> Option<u32> {
+        self.chain.get(hash).map(|entry| entry.height)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn tip_hash(&self) -> String {
+        self.chain.tip_hash().to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn tip_height(&self) -> u32 {
+        self.chain.tip().height
+    }
+
+    // Work accumulated since the anchor, big-endian hex
+    #[wasm_bindgen(getter)]
+    pub fn tip_chainwork(&self) -> String {
+        utils::bytes_to_hex(&self.chain.tip().chainwork.to_be_bytes())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn tip_median_time_past(&self) -> u32 {
+        self.chain.median_time_past(self.chain.tip())
+    }
+}
+
+// --------------------
+// Electrum
+// --------------------
+
+// Electrum script hash of an address, for blockchain.scripthash.* calls
+#[wasm_bindgen]
+pub fn electrum_scripthash(address: &str, mainnet: bool) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+    electrum::scripthash(&address::address_to_scriptpubkey(address, net))
+}
+
+#[derive(serde::Serialize)]
+struct TipJson {
+    height: u32,
+    header: HeaderJson,
+}
+
+impl TipJson {
+    fn new(notification: &electrum::HeaderNotification) -> TipJson {
+        TipJson {
+            height: notification.height,
+            header: HeaderJson::new(&notification.header),
+        }
+    }
+}
+
+// Electrum protocol over a connection JS owns, e.g. a WebSocket: each
+// request method returns the message to send (its `id` comes back with the
+// response) and every message received goes to `handle_message`
+#[wasm_bindgen]
+pub struct WasmElectrumClient {
+    client: electrum::ElectrumClient,
+    net: Network,
+}
+
+impl WasmElectrumClient {
+    fn script(&self, address: &str) -> Vec<u8> {
+        address::address_to_scriptpubkey(address, self.net)
+    }
+}
+
+#[wasm_bindgen]
+impl WasmElectrumClient {
+    #[wasm_bindgen(constructor)]
+    pub fn new(mainnet: bool) -> WasmElectrumClient {
+        let net = if mainnet {
+            Network::Mainnet
+        } else {
+            Network::Testnet
+        };
+        WasmElectrumClient {
+            client: electrum::ElectrumClient::new(),
+            net,
+        }
+    }
+
+    // Responds with the address's UTXOs, ready for the tx builders and
+    // `estimate_fee_from_utxos_wasm` once `mark_coinbase_utxos` has flagged
+    // coinbase outputs from their `get_transaction` results
+    pub fn list_unspent(&mut self, address: &str) -> String {
+        let script = self.script(address);
+        self.client.list_unspent(&script)
+    }
+
+    // Responds with a list of {tx_hash, height, fee?}
+    pub fn get_history(&mut self, address: &str) -> String {
+        let script = self.script(address);
+        self.client.get_history(&script)
+    }
+
+    // Responds with the raw transaction hex
+    pub fn get_transaction(&mut self, txid: &str) -> String {
+        self.client.get_transaction(txid)
+    }
+
+    // Responds with the txid
+    pub fn broadcast(&mut self, raw_tx_hex: &str) -> String {
+        self.client.broadcast(&utils::hex_to_bytes(raw_tx_hex))
+    }
+
+    // Responds with the current tip {height, header}; later tips arrive
+    // through `take_headers`
+    pub fn subscribe_headers(&mut self) -> String {
+        self.client.subscribe_headers()
+    }
+
+    // Feed a message from the server: JSON {id, result} or {id, error} for a
+    // response to one of our requests, null for anything else (header
+    // notifications are queued for `take_headers`)
+    pub fn handle_message(&mut self, message: &str) -> String {
+        let Some((id, response)) = self.client.handle_message(message) else {
+            return "null".to_string();
+        };
+        let result = match response {
+            Ok(electrum::Response::Unspent(utxos)) => serde_json::to_value(utxos),
+            Ok(electrum::Response::History(history)) => serde_json::to_value(history),
+            Ok(electrum::Response::Transaction(tx)) => {
+                Ok(utils::bytes_to_hex(&tx.serialize()).into())
+            }
+            Ok(electrum::Response::Broadcast(txid)) => Ok(txid.into()),
+            Ok(electrum::Response::Tip(tip)) => serde_json::to_value(TipJson::new(&tip)),
+            Err(e) => {
+                return serde_json::json!({ "id": id, "error": e.message() }).to_string();
+            }
+        };
+        serde_json::json!({ "id": id, "result": result.expect("serialize response") }).to_string()
+    }
+
+    // New tips since the last call, as a JSON list of {height, header}
+    pub fn take_headers(&mut self) -> String {
+        let tips: Vec<TipJson> = self
+            .client
+            .take_headers()
+            .iter()
+            .map(TipJson::new)
+            .collect();
+        serde_json::to_string(&tips).expect("serialize tips")
+    }
+}
+
+// --------------------
+// Esplora
+// --------------------
+
+// Esplora REST API at `base_url`, fetched by JS: each `*_request` returns
+// JSON {method, url, body?} to fetch, and the matching `parse_*` takes the
+// response status and body
+#[wasm_bindgen]
+pub struct WasmEsploraClient {
+    base_url: String,
+    net: Network,
+}
+
+// Body of a 2xx response
+fn esplora_body(status: u16, body: &str) -> &str {
+    esplora::response_body(status, body).unwrap_or_else(|e| panic!("{}", e.message()))
+}
+
+fn esplora_invalid() -> String {
+    esplora::EsploraError::InvalidResponse.message()
+}
+
+fn http_request_json(request: &esplora::HttpRequest) -> String {
+    serde_json::to_string(request).expect("serialize request")
+}
+
+#[wasm_bindgen]
+impl WasmEsploraClient {
+    #[wasm_bindgen(constructor)]
+    pub fn new(base_url: &str, mainnet: bool) -> WasmEsploraClient {
+        let net = if mainnet {
+            Network::Mainnet
+        } else {
+            Network::Testnet
+        };
+        WasmEsploraClient {
+            base_url: base_url.to_string(),
+            net,
+        }
+    }
+
+    pub fn address_utxos_request(&self, address: &str) -> String {
+        http_request_json(&esplora::address_utxos_request(&self.base_url, address))
+    }
+
+    // UTXOs of the address as JSON, as from `WasmElectrumClient::list_unspent`
+    pub fn parse_address_utxos(&self, address: &str, status: u16, body: &str) -> String {
+        let script = address::address_to_scriptpubkey(address, self.net);
+        let utxos = esplora::parse_address_utxos(esplora_body(status, body), &script)
+            .unwrap_or_else(|| panic!("{}", esplora_invalid()));
+        serde_json::to_string(&utxos).expect("serialize UTXOs")
+    }
+
+    pub fn tx_status_request(&self, txid: &str) -> String {
+        http_request_json(&esplora::tx_status_request(&self.base_url, txid))
+    }
+
+    // JSON {confirmed, block_height?, block_hash?, block_time?}
+    pub fn parse_tx_status(&self, status: u16, body: &str) -> String {
+        let tx_status = esplora::parse_tx_status(esplora_body(status, body))
+            .unwrap_or_else(|| panic!("{}", esplora_invalid()));
+        serde_json::to_string(&tx_status).expect("serialize status")
+    }
+
+    pub fn raw_tx_request(&self, txid: &str) -> String {
+        http_request_json(&esplora::raw_tx_request(&self.base_url, txid))
+    }
+
+    // Raw transaction hex, checked against the txid it was asked for
+    pub fn parse_raw_tx(&self, txid: &str, status: u16, body: &str) -> String {
+        let tx = esplora::parse_raw_tx(esplora_body(status, body), txid)
+            .unwrap_or_else(|| panic!("{}", esplora_invalid()));
+        utils::bytes_to_hex(&tx.serialize())
+    }
+
+    pub fn fee_estimates_request(&self) -> String {
+        http_request_json(&esplora::fee_estimates_request(&self.base_url))
+    }
+
+    // Fee rates as JSON {next_block, six_blocks, economy, minimum}
+    pub fn parse_fee_rates(&self, status: u16, body: &str) -> String {
+        let estimates = esplora::parse_fee_estimates(esplora_body(status, body))
+            .unwrap_or_else(|| panic!("{}", esplora_invalid()));
+        let rates = esplora::fee_recommendation(&estimates, self.net.min_relay_fee_rate());
+        serde_json::to_string(&rates).expect("serialize fee rates")
+    }
+
+    pub fn broadcast_request(&self, raw_tx_hex: &str) -> String {
+        http_request_json(&esplora::broadcast_request(
+            &self.base_url,
+            &utils::hex_to_bytes(raw_tx_hex),
+        ))
+    }
+
+    // Txid of the broadcast transaction
+    pub fn parse_broadcast(&self, status: u16, body: &str) -> String {
+        esplora::parse_broadcast(esplora_body(status, body))
+            .unwrap_or_else(|| panic!("{}", esplora_invalid()))
+    }
+}
+
+// --------------------
+// Node RPC
+// --------------------
+
+#[derive(serde::Serialize)]
+struct SmartFeeJson {
+    sat_per_vbyte: Option<f64>,
+    blocks: u32,
+    errors: Vec<String>,
+}
+
+// Bitcoin Core RPC posted by JS: each `*_request` returns a JSON-RPC body to
+// send, and the matching `parse_*` takes the node's response body
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct WasmNodeClient {
+    next_id: u64,
+}
+
+impl WasmNodeClient {
+    fn id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+fn rpc_result<T>(result: Result<T, rpc::RpcError>) -> T {
+    result.unwrap_or_else(|e| panic!("{}", e.message()))
+}
+
+#[wasm_bindgen]
+impl WasmNodeClient {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmNodeClient {
+        WasmNodeClient::default()
+    }
+
+    // Optionally limited to a JSON list of addresses
+    pub fn list_unspent_request(
+        &mut self,
+        min_conf: u32,
+        max_conf: u32,
+        addresses_json: &str,
+    ) -> String {
+        let addresses: Vec<String> =
+            serde_json::from_str(addresses_json).expect("invalid address list JSON");
+        rpc::list_unspent_request(self.id(), min_conf, max_conf, &addresses)
+    }
+
+    // UTXOs as JSON; flag coinbase outputs with `mark_coinbase_utxos` and
+    // the transactions from `get_transaction_request`
+    pub fn parse_list_unspent(&self, body: &str) -> String {
+        let utxos = rpc_result(rpc::parse_list_unspent(body));
+        serde_json::to_string(&utxos).expect("serialize UTXOs")
+    }
+
+    pub fn get_raw_transaction_request(&mut self, txid: &str) -> String {
+        rpc::get_raw_transaction_request(self.id(), txid)
+    }
+
+    // Raw transaction hex, checked against the txid it was asked for
+    pub fn parse_raw_transaction(&self, txid: &str, body: &str) -> String {
+        let tx = rpc_result(rpc::parse_raw_transaction(body, txid));
+        utils::bytes_to_hex(&tx.serialize())
+    }
+
+    pub fn get_transaction_request(&mut self, txid: &str) -> String {
+        rpc::get_transaction_request(self.id(), txid)
+    }
+
+    // Raw hex of a wallet transaction, checked against the txid it was
+    // asked for
+    pub fn parse_wallet_transaction(&self, txid: &str, body: &str) -> String {
+        let tx = rpc_result(rpc::parse_wallet_transaction(body, txid));
+        utils::bytes_to_hex(&tx.serialize())
+    }
+
+    pub fn send_raw_transaction_request(
+        &mut self,
+        raw_tx_hex: &str,
+        max_fee_rate: Option<f64>,
+    ) -> String {
+        rpc::send_raw_transaction_request(self.id(), &utils::hex_to_bytes(raw_tx_hex), max_fee_rate)
+    }
+
+    // Txid of the broadcast transaction
+    pub fn parse_send_raw_transaction(&self, body: &str) -> String {
+        rpc_result(rpc::parse_txid(body))
+    }
+
+    pub fn estimate_smart_fee_request(&mut self, conf_target: u32, mode: &str) -> String {
+        rpc::estimate_smart_fee_request(self.id(), conf_target, mode)
+    }
+
+    // JSON {sat_per_vbyte, blocks, errors}
+    pub fn parse_estimate_smart_fee(&self, body: &str) -> String {
+        let fee = rpc_result(rpc::parse_smart_fee(body));
+        serde_json::to_string(&SmartFeeJson {
+            sat_per_vbyte: fee.sat_per_vbyte(),
+            blocks: fee.blocks,
+            errors: fee.errors,
+        })
+        .expect("serialize fee")
+    }
+
+    pub fn get_block_template_request(&mut self) -> String {
+        rpc::get_block_template_request(self.id())
+    }
+
+    // Block template JSON, as taken by `stratum_job`
+    pub fn parse_block_template(&self, body: &str) -> String {
+        let template = rpc_result(rpc::parse_block_template(body));
+        serde_json::to_string(&template).expect("serialize template")
+    }
+
+    // Takes a JSON list of raw transaction hex
+    pub fn test_mempool_accept_request(&mut self, raw_txs_json: &str) -> String {
+        let raw_txs: Vec<String> =
+            serde_json::from_str(raw_txs_json).expect("invalid transaction list JSON");
+        let raw_txs: Vec<Vec<u8>> = raw_txs.iter().map(|tx| utils::hex_to_bytes(tx)).collect();
+        rpc::test_mempool_accept_request(self.id(), &raw_txs)
+    }
+
+    // JSON list of {txid, wtxid?, allowed, vsize?, fees?, reject-reason?}
+    pub fn parse_test_mempool_accept(&self, body: &str) -> String {
+        let results = rpc_result(rpc::parse_mempool_accept(body));
+        serde_json::to_string(&results).expect("serialize results")
+    }
+}
+
+// --------------------
+// Mining
+// --------------------
+
+#[derive(serde::Deserialize)]
+struct Payout {
+    address: String,
+    amount: u64,
+}
+
+// [{"address", "amount"}] as coinbase outputs
+fn parse_payouts(payouts_json: &str, net: Network) -> Vec<tx::TxOut> {
+    let payouts: Vec<Payout> = serde_json::from_str(payouts_json).expect("invalid payouts JSON");
+    payouts
+        .iter()
+        .map(|p| tx::TxOut {
+            value: p.amount,
+            script_pubkey: address::address_to_scriptpubkey(&p.address, net),
+        })
+        .collect()
+}
+
+#[derive(serde::Serialize)]
+struct CoinbaseJson {
+    tx: String,
+    txid: String,
+    wtxid: String,
+    coinb1: String,
+    coinb2: String,
+}
+
+// Witness commitment (hex) for a block whose non-coinbase transactions have
+// these wtxids, with the all-zero witness reserved value
+#[wasm_bindgen]
+pub fn witness_commitment(wtxids_json: &str) -> String {
+    let wtxids: Vec<String> = serde_json::from_str(wtxids_json).expect("invalid wtxid list JSON");
+    let root = coinbase::witness_merkle_root(&wtxids);
+    utils::bytes_to_hex(&coinbase::witness_commitment(&root, &[0; 32]))
+}
+
+// Coinbase paying [{"address", "amount"}] with the BIP34 height, a zeroed
+// `extranonce_size`-byte extranonce and the pool tag (hex). An empty
+// `witness_commitment_hex` leaves out the commitment output. Returns JSON
+// {tx, txid, wtxid, coinb1, coinb2}, coinb1/coinb2 split around the
+// extranonce.
+#[wasm_bindgen]
+pub fn build_coinbase_tx(
+    height: u32,
+    extranonce_size: usize,
+    tag_hex: &str,
+    payouts_json: &str,
+    witness_commitment_hex: &str,
+    mainnet: bool,
+) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+
+    let outputs = parse_payouts(payouts_json, net);
+    let commitment =
+        (!witness_commitment_hex.is_empty()).then(|| utils::hex_to_bytes(witness_commitment_hex));
+
+    let cb = coinbase::build_coinbase(
+        height,
+        extranonce_size,
+        &utils::hex_to_bytes(tag_hex),
+        &outputs,
+        commitment.as_deref(),
+    );
+    serde_json::to_string(&CoinbaseJson {
+        tx: utils::bytes_to_hex(&cb.tx.serialize()),
+        txid: cb.tx.txid(),
+        wtxid: cb.tx.wtxid(),
+        coinb1: utils::bytes_to_hex(&cb.coinb1),
+        coinb2: utils::bytes_to_hex(&cb.coinb2),
+    })
+    .expect("serialize coinbase")
+}
+
+// --------------------
+// Stratum
+// --------------------
+
+// Build a Stratum job from getblocktemplate JSON. The returned job JSON
+// carries the `mining.notify` params under "notify" and is passed back to
+// `validate_share`.
+#[allow(clippy::too_many_arguments)]
+#[wasm_bindgen]
+pub fn stratum_job(
+    template_json: &str,
+    job_id: &str,
+    payouts_json: &str,
+    extranonce1_size: usize,
+    extranonce2_size: usize,
+    tag_hex: &str,
+    clean_jobs: bool,
+    mainnet: bool,
+) -> String {
+    let net = if mainnet {
+        Network::Mainnet
+    } else {
+        Network::Testnet
+    };
+    let template: stratum::BlockTemplate =
+        serde_json::from_str(template_json).expect("invalid block template");
+    let payouts = parse_payouts(payouts_json, net);
+
+    let job = stratum::build_job(
+        &template,
+        job_id,
+        &payouts,
+        extranonce1_size,
+        extranonce2_size,
+        &utils::hex_to_bytes(tag_hex),
+        clean_jobs,
+    );
+    let mut value = serde_json::to_value(&job).unwrap();
+    value["notify"] = job.notify_params();
+    value.to_string()
+}
+
+#[derive(serde::Serialize)]
+struct ShareJson {
+    accepted: bool,
+    error: Option<&'static str>,
+    #[serde(flatten)]
+    share: Option<stratum::Share>,
+}
+
+// Validate `mining.submit` params against a job from `stratum_job`
+#[wasm_bindgen]
+pub fn validate_share(
+    job_json: &str,
+    extranonce1_hex: &str,
+    submit_params_json: &str,
+    share_difficulty: f64,
+) -> String {
+    let job: stratum::Job = serde_json::from_str(job_json).expect("invalid job");
+    let params: Vec<String> =
+        serde_json::from_str(submit_params_json).expect("invalid submit params");
+    let submit = stratum::Submit::from_params(&params).expect("invalid submit params");
+
+    let result = match stratum::validate_share(
+        &job,
+        &utils::hex_to_bytes(extranonce1_hex),
+        &submit,
+        share_difficulty,
+    ) {
+        Ok(share) => ShareJson {
+            accepted: true,
+            error: None,
+            share: Some(share),
+        },
+        Err(e) => ShareJson {
+            accepted: false,
+            error: Some(e.message()),
+            share: None,
+        },
+    };
+    serde_json::to_string(&result).unwrap()
+}
+
+// --------------------
+// Account discovery
+// --------------------
+
+fn scan_purpose(purpose: u32) -> scan::Purpose {
+    scan::Purpose::from_number(purpose).expect("purpose must be 44, 49, 84 or 86")
+}
+
+// Discover the used addresses of an account xpub (at m/purpose'/coin'/
+// account') for BIP44/49/84/86, stopping after `gap_limit` (default 20)
+// unused addresses per chain. JS looks up each address from `next_lookups`,
+// e.g. with `WasmElectrumClient::get_history`, and feeds the answers to
+// `record` until `is_done`.
+#[wasm_bindgen]
+pub struct WasmAccountScan {
+    scan: scan::AccountScan,
+}
+
+#[wasm_bindgen]
+impl WasmAccountScan {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        account_xpub: &str,
+        purpose: u32,
+        gap_limit: Option<u32>,
+        mainnet: bool,
+    ) -> WasmAccountScan {
+        let net = if mainnet {
+            Network::Mainnet
+        } else {
+            Network::Testnet
+        };
+        let scan = scan::AccountScan::new(
+            account_xpub,
+            scan_purpose(purpose),
+            gap_limit.unwrap_or(scan::DEFAULT_GAP_LIMIT),
+            net,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+        WasmAccountScan { scan }
+    }
+
+    // JSON list of {change, index, address, script_pubkey} to look up next
+    pub fn next_lookups(&mut self) -> String {
+        serde_json::to_string(&self.scan.next_lookups()).expect("serialize lookups")
+    }
+
+    // Whether an address from `next_lookups` has any history, with its
+    // UTXOs as JSON
+    pub fn record(&mut self, script_pubkey_hex: &str, used: bool, utxos_json: &str) {
+        let utxos: Vec<tx::UTXO> = serde_json::from_str(utxos_json).expect("invalid UTXO JSON");
+        self.scan
+            .record(&utils::hex_to_bytes(script_pubkey_hex), used, utxos)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn is_done(&self) -> bool {
+        self.scan.is_done()
+    }
+
+    // JSON {addresses, utxos, next_receive_index, next_change_index}
+    pub fn result(&self) -> String {
+        serde_json::to_string(&self.scan.result()).expect("serialize scan")
+    }
+}
+
+// --------------------
+// Wallet
+// --------------------
+
+// Stateful wallet over watched addresses and descriptors, fed raw
+// transactions and blocks
+#[wasm_bindgen]
+pub struct WasmWallet {
+    wallet: wallet::Wallet,
+    net: Network,
+}
+
+#[wasm_bindgen]
+impl WasmWallet {
+    #[wasm_bindgen(constructor)]
+    pub fn new(mainnet: bool) -> WasmWallet {
+        let net = if mainnet {
+            Network::Mainnet
+        } else {
+            Network::Testnet
+        };
+        WasmWallet {
+            wallet: wallet::Wallet::new(),
+            net,
+        }
+    }
+
+    pub fn watch_address(&mut self, address: &str) {
+        let script = address::checked_scriptpubkey(address, self.net).expect("invalid address");
+        self.wallet.watch_script(&script);
+    }
+
+    // Watch the first `count` scripts of a descriptor
+    pub fn watch_descriptor(&mut self, descriptor: &str, count: u32) {
+        let descriptor = parse_descriptor(descriptor, self.net);
+        self.wallet.watch_descriptor(&descriptor, count);
+    }
+
+    // Store a raw transaction if it concerns the wallet; `height` is None
+    // while unconfirmed
+    pub fn add_transaction(&mut self, raw_tx_hex: &str, height: Option<u32>) -> bool {
+        let transaction =
+            tx::Transaction::parse(&utils::hex_to_bytes(raw_tx_hex)).expect("invalid transaction");
+        self.wallet.add_transaction(transaction, height)
+    }
+
+    // Confirm a raw block's wallet transactions at `height`
+    pub fn add_block(&mut self, block_hex: &str, height: u32) -> usize {
+        let block = block::Block::parse(&utils::hex_to_bytes(block_hex)).expect("invalid block");
+        self.wallet.add_block(&block, height)
+    }
+
+    pub fn disconnect_above(&mut self, height: u32) {
+        self.wallet.disconnect_above(height);
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn tip_height(&self) -> u32 {
+        self.wallet.tip_height()
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_tip_height(&mut self, height: u32) {
+        self.wallet.set_tip(height);
+    }
+
+    // Unspent outputs as JSON, for `create_payment_tx` and `send_max_tx`
+    pub fn utxos(&self) -> String {
+        serde_json::to_string(&self.wallet.utxos()).expect("serialize UTXOs")
+    }
+
+    // JSON {spendable, immature, unconfirmed}
+    pub fn balance(&self) -> String {
+        serde_json::to_string(&self.wallet.balance()).expect("serialize balance")
+    }
+
+    // JSON list of {txid, height, received, sent, net, fee}, oldest first
+    pub fn history(&self) -> String {
+        serde_json::to_string(&self.wallet.history()).expect("serialize history")
+    }
+}
+
+// --------------------
+// Verification
+// --------------------
+
+// Check every input of a signed transaction against the UTXOs it spends (in
+// input order); JSON list of {"input", "error"}, empty when all inputs verify
+#[wasm_bindgen]
+pub fn verify_tx(raw_tx_hex: &str, utxos_json: &str) -> String {
+    let transaction =
+        tx::Transaction::parse(&utils::hex_to_bytes(raw_tx_hex)).expect("invalid transaction");
+    let utxos: Vec<tx::UTXO> = serde_json::from_str(utxos_json).expect("invalid UTXO JSON");
+
+    serde_json::to_string(&interpreter::verify_transaction(&transaction, &utxos))
+        .expect("serialize failures")
+}
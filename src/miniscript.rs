@@ -0,0 +1,1548 @@
+use crate::{
+    address, crypto, interpreter, keys,
+    psbt::Psbt,
+    script::{self, Opcode, Script},
+    utils,
+};
+use ripemd::{Digest, Ripemd160};
+use secp256k1::PublicKey;
+use std::collections::BTreeMap;
+
+// Script context: P2WSH (segwit v0) or a Tapscript leaf
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Context {
+    Segwitv0,
+    Tap,
+}
+
+// Largest multi() in P2WSH; multi_a() has no such limit besides script size
+const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+// Worst-case witness items used for size analysis
+const MAX_ECDSA_SIG_SIZE: usize = 73; // DER + sighash byte
+const MAX_SCHNORR_SIG_SIZE: usize = 65; // with explicit sighash byte
+
+// --------------------------------------------------
+// Types
+// --------------------------------------------------
+
+// Basic types: B (base), V (verify), K (key), W (wrapped)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Base {
+    B,
+    V,
+    K,
+    W,
+}
+
+// Correctness properties: z (zero-arg), o (one-arg), n (non-zero top),
+// d (dissatisfiable), u (unit result)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Type {
+    pub base: Base,
+    pub z: bool,
+    pub o: bool,
+    pub n: bool,
+    pub d: bool,
+    pub u: bool,
+}
+
+impl Type {
+    const fn new(base: Base) -> Type {
+        Type {
+            base,
+            z: false,
+            o: false,
+            n: false,
+            d: false,
+            u: false,
+        }
+    }
+
+    fn is(&self, base: Base) -> bool {
+        self.base == base
+    }
+
+    fn is_bdu(&self) -> bool {
+        self.base == Base::B && self.d && self.u
+    }
+}
+
+// --------------------------------------------------
+// Fragments
+// --------------------------------------------------
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fragment {
+    False,
+    True,
+    PkK(PublicKey),
+    PkH(PublicKey),
+    Older(u32),
+    After(u32),
+    Sha256(Vec<u8>),
+    Hash256(Vec<u8>),
+    Ripemd160(Vec<u8>),
+    Hash160(Vec<u8>),
+    // Wrappers a: s: c: d: v: j: n:
+    Alt(Box<Miniscript>),
+    Swap(Box<Miniscript>),
+    Check(Box<Miniscript>),
+    DupIf(Box<Miniscript>),
+    Verify(Box<Miniscript>),
+    NonZero(Box<Miniscript>),
+    ZeroNotEqual(Box<Miniscript>),
+    AndV(Box<Miniscript>, Box<Miniscript>),
+    AndB(Box<Miniscript>, Box<Miniscript>),
+    AndOr(Box<Miniscript>, Box<Miniscript>, Box<Miniscript>),
+    OrB(Box<Miniscript>, Box<Miniscript>),
+    OrC(Box<Miniscript>, Box<Miniscript>),
+    OrD(Box<Miniscript>, Box<Miniscript>),
+    OrI(Box<Miniscript>, Box<Miniscript>),
+    Thresh(usize, Vec<Miniscript>),
+    Multi(usize, Vec<PublicKey>),
+    MultiA(usize, Vec<PublicKey>),
+}
+
+// A type-checked miniscript expression
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Miniscript {
+    pub node: Fragment,
+    pub ty: Type,
+    pub context: Context,
+}
+
+impl Miniscript {
+    // Type-check `node`; None when the fragment's argument types don't fit
+    pub fn new(node: Fragment, context: Context) -> Option<Miniscript> {
+        let ty = type_of(&node, context)?;
+        Some(Miniscript { node, ty, context })
+    }
+
+    fn boxed(self) -> Box<Miniscript> {
+        Box::new(self)
+    }
+
+    // Parse miniscript text (e.g. "or_d(pk(A),and_v(v:pkh(B),older(144)))");
+    // the top level must be of type B
+    pub fn parse(s: &str, context: Context) -> Option<Miniscript> {
+        let ms = parse_node(s, context)?;
+        ms.ty.is(Base::B).then_some(ms)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut script = Vec::new();
+        self.encode_into(&mut script);
+        script
+    }
+
+    // Witness for the cheapest satisfaction with what `satisfier` provides
+    pub fn satisfy(&self, satisfier: &Satisfier) -> Option<Vec<Vec<u8>>> {
+        satisfy(self, &Provider::Available(satisfier)).sat
+    }
+
+    // Item lengths of the largest satisfying witness stack; the witness
+    // script itself is not included
+    pub fn max_satisfaction(&self) -> Option<Vec<usize>> {
+        let witness = satisfy(self, &Provider::WorstCase(self.context)).sat?;
+        Some(witness.iter().map(Vec::len).collect())
+    }
+}
+
+fn type_of(node: &Fragment, context: Context) -> Option<Type> {
+    use Base::*;
+
+    let mut t = Type::new(B);
+    match node {
+        Fragment::False => {
+            t.z = true;
+            t.u = true;
+            t.d = true;
+        }
+        Fragment::True => {
+            t.z = true;
+            t.u = true;
+        }
+        Fragment::PkK(_) => {
+            t = Type::new(K);
+            (t.o, t.n, t.d, t.u) = (true, true, true, true);
+        }
+        Fragment::PkH(_) => {
+            t = Type::new(K);
+            (t.n, t.d, t.u) = (true, true, true);
+        }
+        Fragment::Older(n) | Fragment::After(n) => {
+            if *n == 0 || *n >= 0x8000_0000 {
+                return None;
+            }
+            t.z = true;
+        }
+        Fragment::Sha256(_)
+        | Fragment::Hash256(_)
+        | Fragment::Ripemd160(_)
+        | Fragment::Hash160(_) => {
+            (t.o, t.n, t.d, t.u) = (true, true, true, true);
+        }
+        Fragment::Alt(x) => {
+            let x = x.ty;
+            if !x.is(B) {
+                return None;
+            }
+            t = Type::new(W);
+            (t.d, t.u) = (x.d, x.u);
+        }
+        Fragment::Swap(x) => {
+            let x = x.ty;
+            if !x.is(B) || !x.o {
+                return None;
+            }
+            t = Type::new(W);
+            (t.d, t.u) = (x.d, x.u);
+        }
+        Fragment::Check(x) => {
+            let x = x.ty;
+            if !x.is(K) {
+                return None;
+            }
+            (t.o, t.n, t.d, t.u) = (x.o, x.n, x.d, true);
+        }
+        Fragment::DupIf(x) => {
+            let x = x.ty;
+            if !x.is(V) || !x.z {
+                return None;
+            }
+            // MINIMALIF makes the result a unit in Tapscript only
+            (t.o, t.n, t.d, t.u) = (true, true, true, context == Context::Tap);
+        }
+        Fragment::Verify(x) => {
+            let x = x.ty;
+            if !x.is(B) {
+                return None;
+            }
+            t = Type::new(V);
+            (t.z, t.o, t.n) = (x.z, x.o, x.n);
+        }
+        Fragment::NonZero(x) => {
+            let x = x.ty;
+            if !x.is(B) || !x.n {
+                return None;
+            }
+            (t.o, t.n, t.d, t.u) = (x.o, true, true, x.u);
+        }
+        Fragment::ZeroNotEqual(x) => {
+            let x = x.ty;
+            if !x.is(B) {
+                return None;
+            }
+            (t.z, t.o, t.n, t.d, t.u) = (x.z, x.o, x.n, x.d, true);
+        }
+        Fragment::AndV(x, y) => {
+            let (x, y) = (x.ty, y.ty);
+            if !x.is(V) || y.is(W) {
+                return None;
+            }
+            t = Type::new(y.base);
+            t.z = x.z && y.z;
+            t.o = (x.z && y.o) || (x.o && y.z);
+            t.n = x.n || (x.z && y.n);
+            t.u = y.u;
+        }
+        Fragment::AndB(x, y) => {
+            let (x, y) = (x.ty, y.ty);
+            if !x.is(B) || !y.is(W) {
+                return None;
+            }
+            t.z = x.z && y.z;
+            t.o = (x.z && y.o) || (x.o && y.z);
+            t.n = x.n || (x.z && y.n);
+            t.d = x.d && y.d;
+            t.u = true;
+        }
+        Fragment::AndOr(x, y, z) => {
+            let (x, y, z) = (x.ty, y.ty, z.ty);
+            if !x.is_bdu() || y.base != z.base || y.is(W) {
+                return None;
+            }
+            t = Type::new(y.base);
+            t.z = x.z && y.z && z.z;
+            t.o = (x.z && y.o && z.o) || (x.o && y.z && z.z);
+            t.d = z.d;
+            t.u = y.u && z.u;
+        }
+        Fragment::OrB(x, z) => {
+            let (x, z) = (x.ty, z.ty);
+            if !x.is(B) || !x.d || !z.is(W) || !z.d {
+                return None;
+            }
+            t.z = x.z && z.z;
+            t.o = (x.z && z.o) || (x.o && z.z);
+            t.d = true;
+            t.u = true;
+        }
+        Fragment::OrC(x, z) => {
+            let (x, z) = (x.ty, z.ty);
+            if !x.is_bdu() || !z.is(V) {
+                return None;
+            }
+            t = Type::new(V);
+            t.z = x.z && z.z;
+            t.o = x.o && z.z;
+        }
+        Fragment::OrD(x, z) => {
+            let (x, z) = (x.ty, z.ty);
+            if !x.is_bdu() || !z.is(B) {
+                return None;
+            }
+            t.z = x.z && z.z;
+            t.o = x.o && z.z;
+            t.d = z.d;
+            t.u = z.u;
+        }
+        Fragment::OrI(x, z) => {
+            let (x, z) = (x.ty, z.ty);
+            if x.base != z.base || x.is(W) {
+                return None;
+            }
+            t = Type::new(x.base);
+            t.o = x.z && z.z;
+            t.d = x.d || z.d;
+            t.u = x.u && z.u;
+        }
+        Fragment::Thresh(k, subs) => {
+            if *k == 0 || *k > subs.len() {
+                return None;
+            }
+            for (i, sub) in subs.iter().enumerate() {
+                let expected = if i == 0 { B } else { W };
+                if !sub.ty.is(expected) || !sub.ty.d || !sub.ty.u {
+                    return None;
+                }
+            }
+            let non_zero = subs.iter().filter(|s| !s.ty.z).count();
+            t.z = non_zero == 0;
+            t.o = non_zero == 1 && subs.iter().all(|s| s.ty.z || s.ty.o);
+            t.d = true;
+            t.u = true;
+        }
+        Fragment::Multi(k, keys) => {
+            if context != Context::Segwitv0
+                || *k == 0
+                || *k > keys.len()
+                || keys.len() > MAX_PUBKEYS_PER_MULTISIG
+            {
+                return None;
+            }
+            (t.n, t.d, t.u) = (true, true, true);
+        }
+        Fragment::MultiA(k, keys) => {
+            if context != Context::Tap || *k == 0 || *k > keys.len() {
+                return None;
+            }
+            (t.d, t.u) = (true, true);
+        }
+    }
+    Some(t)
+}
+
+// --------------------------------------------------
+// Parsing
+// --------------------------------------------------
+
+// Split "name(a,b(c,d))" into ("name", ["a", "b(c,d)"]); bare words have no args
+fn split_call(s: &str) -> Option<(&str, Vec<&str>)> {
+    let Some(open) = s.find('(') else {
+        return Some((s, Vec::new()));
+    };
+    let inner = s[open + 1..].strip_suffix(')')?;
+
+    let mut args = Vec::new();
+    let mut depth: u32 = 0;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.checked_sub(1)?,
+            ',' if depth == 0 => {
+                args.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return None;
+    }
+    args.push(&inner[start..]);
+    Some((&s[..open], args))
+}
+
+fn parse_key(s: &str, context: Context) -> Option<PublicKey> {
+    let bytes = hex::decode(s).ok()?;
+    match (bytes.len(), context) {
+        (33, _) => PublicKey::from_slice(&bytes).ok(),
+        (32, Context::Tap) => Some(PublicKey::from_x_only_public_key(
+            secp256k1::XOnlyPublicKey::from_slice(&bytes).ok()?,
+            secp256k1::Parity::Even,
+        )),
+        _ => None,
+    }
+}
+
+fn parse_hash(s: &str, len: usize) -> Option<Vec<u8>> {
+    hex::decode(s).ok().filter(|h| h.len() == len)
+}
+
+fn parse_node(s: &str, context: Context) -> Option<Miniscript> {
+    // Wrapper letters precede the first ':' outside any parentheses
+    let open = s.find('(').unwrap_or(s.len());
+    let (wrappers, body) = match s[..open].find(':') {
+        Some(colon) => (&s[..colon], &s[colon + 1..]),
+        None => ("", s),
+    };
+
+    let (name, args) = split_call(body)?;
+    let sub = |i: usize| parse_node(args.get(i)?, context).map(Miniscript::boxed);
+    let arity = |n: usize| (args.len() == n).then_some(());
+    let new = |node| Miniscript::new(node, context);
+
+    let mut ms = match name {
+        "0" => new(Fragment::False)?,
+        "1" => new(Fragment::True)?,
+        "pk_k" | "pk" | "pk_h" | "pkh" => {
+            arity(1)?;
+            let key = parse_key(args[0], context)?;
+            let inner = if name.starts_with("pk_h") || name == "pkh" {
+                new(Fragment::PkH(key))?
+            } else {
+                new(Fragment::PkK(key))?
+            };
+            if name == "pk" || name == "pkh" {
+                new(Fragment::Check(inner.boxed()))?
+            } else {
+                inner
+            }
+        }
+        "older" | "after" => {
+            arity(1)?;
+            let n: u32 = args[0].parse().ok()?;
+            new(if name == "older" {
+                Fragment::Older(n)
+            } else {
+                Fragment::After(n)
+            })?
+        }
+        "sha256" => new(Fragment::Sha256(parse_hash(args.first()?, 32)?))?,
+        "hash256" => new(Fragment::Hash256(parse_hash(args.first()?, 32)?))?,
+        "ripemd160" => new(Fragment::Ripemd160(parse_hash(args.first()?, 20)?))?,
+        "hash160" => new(Fragment::Hash160(parse_hash(args.first()?, 20)?))?,
+        "and_v" | "and_b" | "or_b" | "or_c" | "or_d" | "or_i" | "and_n" => {
+            arity(2)?;
+            let (x, y) = (sub(0)?, sub(1)?);
+            new(match name {
+                "and_v" => Fragment::AndV(x, y),
+                "and_b" => Fragment::AndB(x, y),
+                "or_b" => Fragment::OrB(x, y),
+                "or_c" => Fragment::OrC(x, y),
+                "or_d" => Fragment::OrD(x, y),
+                "or_i" => Fragment::OrI(x, y),
+                _ => Fragment::AndOr(x, y, new(Fragment::False)?.boxed()),
+            })?
+        }
+        "andor" => {
+            arity(3)?;
+            new(Fragment::AndOr(sub(0)?, sub(1)?, sub(2)?))?
+        }
+        "thresh" => {
+            let k: usize = args.first()?.parse().ok()?;
+            let subs = args[1..]
+                .iter()
+                .map(|a| parse_node(a, context))
+                .collect::<Option<Vec<Miniscript>>>()?;
+            new(Fragment::Thresh(k, subs))?
+        }
+        "multi" | "multi_a" => {
+            let k: usize = args.first()?.parse().ok()?;
+            let keys = args[1..]
+                .iter()
+                .map(|a| parse_key(a, context))
+                .collect::<Option<Vec<PublicKey>>>()?;
+            new(if name == "multi" {
+                Fragment::Multi(k, keys)
+            } else {
+                Fragment::MultiA(k, keys)
+            })?
+        }
+        _ => return None,
+    };
+
+    for wrapper in wrappers.chars().rev() {
+        let x = ms.boxed();
+        ms = match wrapper {
+            'a' => new(Fragment::Alt(x))?,
+            's' => new(Fragment::Swap(x))?,
+            'c' => new(Fragment::Check(x))?,
+            'd' => new(Fragment::DupIf(x))?,
+            'v' => new(Fragment::Verify(x))?,
+            'j' => new(Fragment::NonZero(x))?,
+            'n' => new(Fragment::ZeroNotEqual(x))?,
+            't' => new(Fragment::AndV(x, new(Fragment::True)?.boxed()))?,
+            'l' => new(Fragment::OrI(new(Fragment::False)?.boxed(), x))?,
+            'u' => new(Fragment::OrI(x, new(Fragment::False)?.boxed()))?,
+            _ => return None,
+        };
+    }
+    Some(ms)
+}
+
+// --------------------------------------------------
+// Display
+// --------------------------------------------------
+
+impl Miniscript {
+    fn key_hex(&self, key: &PublicKey) -> String {
+        hex::encode(key_bytes(key, self.context))
+    }
+
+    // Canonical text; `pk`, `pkh`, `and_n`, `t:`, `l:` and `u:` shorthands
+    // are used where they apply
+    pub fn to_text(&self) -> String {
+        // Wrapper prefix (letters without the ':') and the wrapped expression
+        let (wrapper, inner): (char, &Miniscript) = match &self.node {
+            Fragment::Check(x) => match &x.node {
+                Fragment::PkK(k) => return format!("pk({})", self.key_hex(k)),
+                Fragment::PkH(k) => return format!("pkh({})", self.key_hex(k)),
+                _ => ('c', x),
+            },
+            Fragment::Alt(x) => ('a', x),
+            Fragment::Swap(x) => ('s', x),
+            Fragment::DupIf(x) => ('d', x),
+            Fragment::Verify(x) => ('v', x),
+            Fragment::NonZero(x) => ('j', x),
+            Fragment::ZeroNotEqual(x) => ('n', x),
+            Fragment::AndV(x, y) if y.node == Fragment::True => ('t', x),
+            Fragment::OrI(x, z) if x.node == Fragment::False => ('l', z),
+            Fragment::OrI(x, z) if z.node == Fragment::False => ('u', x),
+            _ => return self.fragment_text(),
+        };
+
+        let inner = inner.to_text();
+        // Merge with the inner wrapper letters: "s" + "n:x" -> "sn:x"
+        match inner.split_once(':') {
+            Some((letters, _)) if !letters.contains('(') => format!("{}{}", wrapper, inner),
+            _ => format!("{}:{}", wrapper, inner),
+        }
+    }
+
+    fn fragment_text(&self) -> String {
+        let keys = |keys: &[PublicKey]| -> String {
+            keys.iter()
+                .map(|k| self.key_hex(k))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        match &self.node {
+            Fragment::False => "0".to_string(),
+            Fragment::True => "1".to_string(),
+            Fragment::PkK(k) => format!("pk_k({})", self.key_hex(k)),
+            Fragment::PkH(k) => format!("pk_h({})", self.key_hex(k)),
+            Fragment::Older(n) => format!("older({})", n),
+            Fragment::After(n) => format!("after({})", n),
+            Fragment::Sha256(h) => format!("sha256({})", hex::encode(h)),
+            Fragment::Hash256(h) => format!("hash256({})", hex::encode(h)),
+            Fragment::Ripemd160(h) => format!("ripemd160({})", hex::encode(h)),
+            Fragment::Hash160(h) => format!("hash160({})", hex::encode(h)),
+            Fragment::AndV(x, y) => format!("and_v({},{})", x.to_text(), y.to_text()),
+            Fragment::AndB(x, y) => format!("and_b({},{})", x.to_text(), y.to_text()),
+            Fragment::AndOr(x, y, z) if z.node == Fragment::False => {
+                format!("and_n({},{})", x.to_text(), y.to_text())
+            }
+            Fragment::AndOr(x, y, z) => {
+                format!("andor({},{},{})", x.to_text(), y.to_text(), z.to_text())
+            }
+            Fragment::OrB(x, z) => format!("or_b({},{})", x.to_text(), z.to_text()),
+            Fragment::OrC(x, z) => format!("or_c({},{})", x.to_text(), z.to_text()),
+            Fragment::OrD(x, z) => format!("or_d({},{})", x.to_text(), z.to_text()),
+            Fragment::OrI(x, z) => format!("or_i({},{})", x.to_text(), z.to_text()),
+            Fragment::Thresh(k, subs) => {
+                let subs: Vec<String> = subs.iter().map(Miniscript::to_text).collect();
+                format!("thresh({},{})", k, subs.join(","))
+            }
+            Fragment::Multi(k, ks) => format!("multi({},{})", k, keys(ks)),
+            Fragment::MultiA(k, ks) => format!("multi_a({},{})", k, keys(ks)),
+            // Wrappers are rendered by `to_text`
+            _ => self.to_text(),
+        }
+    }
+}
+
+// --------------------------------------------------
+// Script encoding
+// --------------------------------------------------
+
+// Keys are 33-byte compressed in P2WSH and 32-byte x-only in Tapscript
+fn key_bytes(key: &PublicKey, context: Context) -> Vec<u8> {
+    match context {
+        Context::Segwitv0 => key.serialize().to_vec(),
+        Context::Tap => key.x_only_public_key().0.serialize().to_vec(),
+    }
+}
+
+impl Miniscript {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        let op = |out: &mut Vec<u8>, op: Opcode| out.push(op as u8);
+        let push = |out: &mut Vec<u8>, data: &[u8]| script::push_data(out, data);
+        let int = |out: &mut Vec<u8>, n: i64| {
+            out.extend(Script::builder().push_int(n).into_script().as_bytes())
+        };
+
+        match &self.node {
+            Fragment::False => op(out, Opcode::OP_0),
+            Fragment::True => op(out, Opcode::OP_1),
+            Fragment::PkK(k) => push(out, &key_bytes(k, self.context)),
+            Fragment::PkH(k) => {
+                op(out, Opcode::OP_DUP);
+                op(out, Opcode::OP_HASH160);
+                push(out, &crypto::hash160(&key_bytes(k, self.context)));
+                op(out, Opcode::OP_EQUALVERIFY);
+            }
+            Fragment::Older(n) => {
+                int(out, *n as i64);
+                op(out, Opcode::OP_CHECKSEQUENCEVERIFY);
+            }
+            Fragment::After(n) => {
+                int(out, *n as i64);
+                op(out, Opcode::OP_CHECKLOCKTIMEVERIFY);
+            }
+            Fragment::Sha256(h)
+            | Fragment::Hash256(h)
+            | Fragment::Ripemd160(h)
+            | Fragment::Hash160(h) => {
+                // SIZE <32> EQUALVERIFY <hash op> <h> EQUAL
+                op(out, Opcode::OP_SIZE);
+                int(out, 32);
+                op(out, Opcode::OP_EQUALVERIFY);
+                op(
+                    out,
+                    match &self.node {
+                        Fragment::Sha256(_) => Opcode::OP_SHA256,
+                        Fragment::Hash256(_) => Opcode::OP_HASH256,
+                        Fragment::Ripemd160(_) => Opcode::OP_RIPEMD160,
+                        _ => Opcode::OP_HASH160,
+                    },
+                );
+                push(out, h);
+                op(out, Opcode::OP_EQUAL);
+            }
+            Fragment::Alt(x) => {
+                op(out, Opcode::OP_TOALTSTACK);
+                x.encode_into(out);
+                op(out, Opcode::OP_FROMALTSTACK);
+            }
+            Fragment::Swap(x) => {
+                op(out, Opcode::OP_SWAP);
+                x.encode_into(out);
+            }
+            Fragment::Check(x) => {
+                x.encode_into(out);
+                op(out, Opcode::OP_CHECKSIG);
+            }
+            Fragment::DupIf(x) => {
+                op(out, Opcode::OP_DUP);
+                op(out, Opcode::OP_IF);
+                x.encode_into(out);
+                op(out, Opcode::OP_ENDIF);
+            }
+            Fragment::Verify(x) => {
+                x.encode_into(out);
+                // Fold into the VERIFY form of the last opcode where one exists
+                let verify_form = match out.last().copied().and_then(Opcode::from_u8) {
+                    Some(Opcode::OP_CHECKSIG) => Some(Opcode::OP_CHECKSIGVERIFY),
+                    Some(Opcode::OP_CHECKMULTISIG) => Some(Opcode::OP_CHECKMULTISIGVERIFY),
+                    Some(Opcode::OP_EQUAL) => Some(Opcode::OP_EQUALVERIFY),
+                    Some(Opcode::OP_NUMEQUAL) => Some(Opcode::OP_NUMEQUALVERIFY),
+                    _ => None,
+                };
+                match verify_form {
+                    Some(v) if ends_with_opcode(x) => *out.last_mut().unwrap() = v as u8,
+                    _ => op(out, Opcode::OP_VERIFY),
+                }
+            }
+            Fragment::NonZero(x) => {
+                op(out, Opcode::OP_SIZE);
+                op(out, Opcode::OP_0NOTEQUAL);
+                op(out, Opcode::OP_IF);
+                x.encode_into(out);
+                op(out, Opcode::OP_ENDIF);
+            }
+            Fragment::ZeroNotEqual(x) => {
+                x.encode_into(out);
+                op(out, Opcode::OP_0NOTEQUAL);
+            }
+            Fragment::AndV(x, y) => {
+                x.encode_into(out);
+                y.encode_into(out);
+            }
+            Fragment::AndB(x, y) => {
+                x.encode_into(out);
+                y.encode_into(out);
+                op(out, Opcode::OP_BOOLAND);
+            }
+            Fragment::AndOr(x, y, z) => {
+                x.encode_into(out);
+                op(out, Opcode::OP_NOTIF);
+                z.encode_into(out);
+                op(out, Opcode::OP_ELSE);
+                y.encode_into(out);
+                op(out, Opcode::OP_ENDIF);
+            }
+            Fragment::OrB(x, z) => {
+                x.encode_into(out);
+                z.encode_into(out);
+                op(out, Opcode::OP_BOOLOR);
+            }
+            Fragment::OrC(x, z) => {
+                x.encode_into(out);
+                op(out, Opcode::OP_NOTIF);
+                z.encode_into(out);
+                op(out, Opcode::OP_ENDIF);
+            }
+            Fragment::OrD(x, z) => {
+                x.encode_into(out);
+                op(out, Opcode::OP_IFDUP);
+                op(out, Opcode::OP_NOTIF);
+                z.encode_into(out);
+                op(out, Opcode::OP_ENDIF);
+            }
+            Fragment::OrI(x, z) => {
+                op(out, Opcode::OP_IF);
+                x.encode_into(out);
+                op(out, Opcode::OP_ELSE);
+                z.encode_into(out);
+                op(out, Opcode::OP_ENDIF);
+            }
+            Fragment::Thresh(k, subs) => {
+                for (i, sub) in subs.iter().enumerate() {
+                    sub.encode_into(out);
+                    if i > 0 {
+                        op(out, Opcode::OP_ADD);
+                    }
+                }
+                int(out, *k as i64);
+                op(out, Opcode::OP_EQUAL);
+            }
+            Fragment::Multi(k, keys) => {
+                int(out, *k as i64);
+                for key in keys {
+                    push(out, &key_bytes(key, self.context));
+                }
+                int(out, keys.len() as i64);
+                op(out, Opcode::OP_CHECKMULTISIG);
+            }
+            Fragment::MultiA(k, keys) => {
+                for (i, key) in keys.iter().enumerate() {
+                    push(out, &key_bytes(key, self.context));
+                    op(
+                        out,
+                        if i == 0 {
+                            Opcode::OP_CHECKSIG
+                        } else {
+                            Opcode::OP_CHECKSIGADD
+                        },
+                    );
+                }
+                int(out, *k as i64);
+                op(out, Opcode::OP_NUMEQUAL);
+            }
+        }
+    }
+}
+
+// Whether the script of `ms` ends in an opcode that has a VERIFY form, as
+// opposed to a push that happens to hold the same byte
+fn ends_with_opcode(ms: &Miniscript) -> bool {
+    match &ms.node {
+        Fragment::Check(_) | Fragment::Multi(..) | Fragment::MultiA(..) | Fragment::Thresh(..) => {
+            true
+        }
+        Fragment::Sha256(_)
+        | Fragment::Hash256(_)
+        | Fragment::Ripemd160(_)
+        | Fragment::Hash160(_) => true,
+        Fragment::AndV(_, y) | Fragment::Swap(y) => ends_with_opcode(y),
+        _ => false,
+    }
+}
+
+// --------------------------------------------------
+// Policy compilation
+// --------------------------------------------------
+
+// Spending policy: pk(K), after(n), older(n), sha256(H), hash256(H),
+// ripemd160(H), hash160(H), and(P,Q), or([w@]P,[w@]Q), thresh(k,P,...)
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Policy {
+    Key(PublicKey),
+    After(u32),
+    Older(u32),
+    Hash(Fragment),
+    And(Box<Policy>, Box<Policy>),
+    Or(Vec<(u32, Policy)>),
+    Thresh(usize, Vec<Policy>),
+}
+
+fn parse_policy(s: &str, context: Context) -> Option<Policy> {
+    let (name, args) = split_call(s)?;
+    let one = || (args.len() == 1).then(|| args[0]);
+
+    Some(match name {
+        "pk" => Policy::Key(parse_key(one()?, context)?),
+        "after" => Policy::After(one()?.parse().ok()?),
+        "older" => Policy::Older(one()?.parse().ok()?),
+        "sha256" => Policy::Hash(Fragment::Sha256(parse_hash(one()?, 32)?)),
+        "hash256" => Policy::Hash(Fragment::Hash256(parse_hash(one()?, 32)?)),
+        "ripemd160" => Policy::Hash(Fragment::Ripemd160(parse_hash(one()?, 20)?)),
+        "hash160" => Policy::Hash(Fragment::Hash160(parse_hash(one()?, 20)?)),
+        "and" if args.len() == 2 => Policy::And(
+            Box::new(parse_policy(args[0], context)?),
+            Box::new(parse_policy(args[1], context)?),
+        ),
+        "or" if args.len() == 2 => Policy::Or(
+            args.iter()
+                .map(|a| {
+                    let (weight, sub) = match a.split_once('@') {
+                        Some((w, sub)) if !w.contains('(') => (w.parse().ok()?, sub),
+                        _ => (1, *a),
+                    };
+                    Some((weight, parse_policy(sub, context)?))
+                })
+                .collect::<Option<Vec<_>>>()?,
+        ),
+        "thresh" if args.len() >= 2 => {
+            let k: usize = args[0].parse().ok()?;
+            let subs = args[1..]
+                .iter()
+                .map(|a| parse_policy(a, context))
+                .collect::<Option<Vec<Policy>>>()?;
+            if k == 0 || k > subs.len() {
+                return None;
+            }
+            Policy::Thresh(k, subs)
+        }
+        _ => return None,
+    })
+}
+
+// Compile a policy to a type-B miniscript. The more likely branch of an
+// `or` (by weight) is placed where it is cheapest to satisfy.
+pub fn compile_policy(policy: &str, context: Context) -> Option<Miniscript> {
+    let policy = parse_policy(&policy.replace(char::is_whitespace, ""), context)?;
+    let ms = compile(&policy, context)?;
+    ms.ty.is(Base::B).then_some(ms)
+}
+
+fn compile(policy: &Policy, context: Context) -> Option<Miniscript> {
+    let new = |node| Miniscript::new(node, context);
+
+    match policy {
+        Policy::Key(k) => new(Fragment::Check(new(Fragment::PkK(*k))?.boxed())),
+        Policy::After(n) => new(Fragment::After(*n)),
+        Policy::Older(n) => new(Fragment::Older(*n)),
+        Policy::Hash(h) => new(h.clone()),
+        Policy::And(x, y) => {
+            let x = new(Fragment::Verify(compile(x, context)?.boxed()))?;
+            new(Fragment::AndV(x.boxed(), compile(y, context)?.boxed()))
+        }
+        Policy::Or(branches) => {
+            let mut branches = branches.clone();
+            branches.sort_by_key(|(weight, _)| std::cmp::Reverse(*weight));
+            let x = compile(&branches[0].1, context)?;
+            let z = compile(&branches[1].1, context)?;
+
+            if x.ty.is_bdu() {
+                new(Fragment::OrD(x.boxed(), z.boxed()))
+            } else if z.ty.is_bdu() {
+                new(Fragment::OrD(z.boxed(), x.boxed()))
+            } else {
+                new(Fragment::OrI(x.boxed(), z.boxed()))
+            }
+        }
+        Policy::Thresh(k, subs) => {
+            let keys: Option<Vec<PublicKey>> = subs
+                .iter()
+                .map(|s| match s {
+                    Policy::Key(k) => Some(*k),
+                    _ => None,
+                })
+                .collect();
+
+            match (keys, context) {
+                (Some(keys), Context::Segwitv0) if keys.len() <= MAX_PUBKEYS_PER_MULTISIG => {
+                    return new(Fragment::Multi(*k, keys));
+                }
+                (Some(keys), Context::Tap) => return new(Fragment::MultiA(*k, keys)),
+                _ => {}
+            }
+
+            if *k == subs.len() {
+                let (last, rest) = subs.split_last()?;
+                return rest
+                    .iter()
+                    .rev()
+                    .try_fold(compile(last, context)?, |acc, sub| {
+                        let x = new(Fragment::Verify(compile(sub, context)?.boxed()))?;
+                        new(Fragment::AndV(x.boxed(), acc.boxed()))
+                    });
+            }
+
+            // thresh(k, X1, W2, ...): every argument dissatisfiable and unit
+            let mut args = Vec::with_capacity(subs.len());
+            for (i, sub) in subs.iter().enumerate() {
+                let mut ms = compile(sub, context)?;
+                if !ms.ty.u {
+                    ms = new(Fragment::ZeroNotEqual(ms.boxed()))?;
+                }
+                if !ms.ty.d {
+                    ms = new(Fragment::OrI(new(Fragment::False)?.boxed(), ms.boxed()))?;
+                }
+                if i > 0 {
+                    ms = if ms.ty.o {
+                        new(Fragment::Swap(ms.boxed()))?
+                    } else {
+                        new(Fragment::Alt(ms.boxed()))?
+                    };
+                }
+                args.push(ms);
+            }
+            new(Fragment::Thresh(*k, args))
+        }
+    }
+}
+
+// --------------------------------------------------
+// Satisfaction
+// --------------------------------------------------
+
+// What a finalizer has available for one input
+pub struct Satisfier<'a> {
+    // Signatures keyed by the key as it appears in the script (33-byte
+    // compressed for P2WSH), as in PSBT partial signatures
+    pub signatures: &'a BTreeMap<Vec<u8>, Vec<u8>>,
+    pub preimages: &'a [Vec<u8>],
+    // Spending transaction and input, for older() / after()
+    pub tx: &'a crate::tx::Transaction,
+    pub input_index: usize,
+}
+
+// Real satisfaction picks the smallest witness; worst-case analysis
+// assumes everything is available and picks the largest
+enum Provider<'a> {
+    Available(&'a Satisfier<'a>),
+    WorstCase(Context),
+}
+
+impl Provider<'_> {
+    fn signature(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Provider::Available(s) => s.signatures.get(key).cloned(),
+            Provider::WorstCase(Context::Segwitv0) => Some(vec![0; MAX_ECDSA_SIG_SIZE]),
+            Provider::WorstCase(Context::Tap) => Some(vec![0; MAX_SCHNORR_SIG_SIZE]),
+        }
+    }
+
+    fn preimage(&self, hash: &Fragment) -> Option<Vec<u8>> {
+        let Provider::Available(s) = self else {
+            return Some(vec![0; 32]);
+        };
+
+        s.preimages
+            .iter()
+            .find(|p| {
+                p.len() == 32
+                    && match hash {
+                        Fragment::Sha256(h) => crypto::sha256(p) == *h,
+                        Fragment::Hash256(h) => crypto::double_sha256(p) == *h,
+                        Fragment::Ripemd160(h) => Ripemd160::digest(p).as_slice() == h.as_slice(),
+                        Fragment::Hash160(h) => crypto::hash160(p) == *h,
+                        _ => false,
+                    }
+            })
+            .cloned()
+    }
+
+    fn older(&self, n: u32) -> bool {
+        match self {
+            Provider::Available(s) => {
+                interpreter::sequence_satisfied(s.tx, s.input_index, n as i64)
+            }
+            Provider::WorstCase(_) => true,
+        }
+    }
+
+    fn after(&self, n: u32) -> bool {
+        match self {
+            Provider::Available(s) => {
+                interpreter::locktime_satisfied(s.tx, s.input_index, n as i64)
+            }
+            Provider::WorstCase(_) => true,
+        }
+    }
+
+    // Preferred of two candidate witnesses
+    fn pick(&self, a: Option<Witness>, b: Option<Witness>) -> Option<Witness> {
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                let a_smaller = witness_size(&a) <= witness_size(&b);
+                let take_a = match self {
+                    Provider::Available(_) => a_smaller,
+                    Provider::WorstCase(_) => !a_smaller,
+                };
+                Some(if take_a { a } else { b })
+            }
+            (a, None) => a,
+            (None, b) => b,
+        }
+    }
+}
+
+type Witness = Vec<Vec<u8>>;
+
+struct Satisfactions {
+    sat: Option<Witness>,
+    dissat: Option<Witness>,
+}
+
+fn witness_size(witness: &Witness) -> usize {
+    witness
+        .iter()
+        .map(|item| utils::varint(item.len()).len() + item.len())
+        .sum()
+}
+
+// Concatenate witnesses; later parts end up on top of the stack
+fn cat(parts: &[&Option<Witness>]) -> Option<Witness> {
+    let mut out = Vec::new();
+    for part in parts {
+        out.extend(part.as_ref()?.iter().cloned());
+    }
+    Some(out)
+}
+
+fn satisfy(ms: &Miniscript, provider: &Provider) -> Satisfactions {
+    let one = Some(vec![vec![1u8]]);
+    let empty = Some(vec![Vec::new()]);
+    let done = |sat, dissat| Satisfactions { sat, dissat };
+
+    match &ms.node {
+        Fragment::False => done(None, Some(Vec::new())),
+        Fragment::True => done(Some(Vec::new()), None),
+        Fragment::PkK(k) => {
+            let sig = provider.signature(&key_bytes(k, ms.context));
+            done(sig.map(|s| vec![s]), empty)
+        }
+        Fragment::PkH(k) => {
+            let key = key_bytes(k, ms.context);
+            let sig = provider.signature(&key);
+            done(
+                sig.map(|s| vec![s, key.clone()]),
+                Some(vec![Vec::new(), key]),
+            )
+        }
+        Fragment::Older(n) => done(provider.older(*n).then(Vec::new), None),
+        Fragment::After(n) => done(provider.after(*n).then(Vec::new), None),
+        Fragment::Sha256(_)
+        | Fragment::Hash256(_)
+        | Fragment::Ripemd160(_)
+        | Fragment::Hash160(_) => done(
+            provider.preimage(&ms.node).map(|p| vec![p]),
+            Some(vec![vec![0; 32]]),
+        ),
+        Fragment::Alt(x) | Fragment::Swap(x) | Fragment::Check(x) | Fragment::ZeroNotEqual(x) => {
+            satisfy(x, provider)
+        }
+        Fragment::DupIf(x) => done(cat(&[&satisfy(x, provider).sat, &one]), empty),
+        Fragment::Verify(x) => done(satisfy(x, provider).sat, None),
+        Fragment::NonZero(x) => done(satisfy(x, provider).sat, empty),
+        Fragment::AndV(x, y) => {
+            let (x, y) = (satisfy(x, provider), satisfy(y, provider));
+            done(cat(&[&y.sat, &x.sat]), None)
+        }
+        Fragment::AndB(x, y) => {
+            let (x, y) = (satisfy(x, provider), satisfy(y, provider));
+            done(cat(&[&y.sat, &x.sat]), cat(&[&y.dissat, &x.dissat]))
+        }
+        Fragment::AndOr(x, y, z) => {
+            let (x, y, z) = (
+                satisfy(x, provider),
+                satisfy(y, provider),
+                satisfy(z, provider),
+            );
+            done(
+                provider.pick(cat(&[&y.sat, &x.sat]), cat(&[&z.sat, &x.dissat])),
+                cat(&[&z.dissat, &x.dissat]),
+            )
+        }
+        Fragment::OrB(x, z) => {
+            let (x, z) = (satisfy(x, provider), satisfy(z, provider));
+            done(
+                provider.pick(cat(&[&z.dissat, &x.sat]), cat(&[&z.sat, &x.dissat])),
+                cat(&[&z.dissat, &x.dissat]),
+            )
+        }
+        Fragment::OrC(x, z) | Fragment::OrD(x, z) => {
+            let (x, z) = (satisfy(x, provider), satisfy(z, provider));
+            let sat = provider.pick(x.sat, cat(&[&z.sat, &x.dissat]));
+            let dissat = match ms.node {
+                Fragment::OrD(..) => cat(&[&z.dissat, &x.dissat]),
+                _ => None,
+            };
+            done(sat, dissat)
+        }
+        Fragment::OrI(x, z) => {
+            let (x, z) = (satisfy(x, provider), satisfy(z, provider));
+            done(
+                provider.pick(cat(&[&x.sat, &one]), cat(&[&z.sat, &empty])),
+                provider.pick(cat(&[&x.dissat, &one]), cat(&[&z.dissat, &empty])),
+            )
+        }
+        Fragment::Thresh(k, subs) => {
+            let subs: Vec<Satisfactions> = subs.iter().map(|s| satisfy(s, provider)).collect();
+            done(thresh_satisfaction(*k, &subs, provider), {
+                let all: Vec<&Option<Witness>> = subs.iter().rev().map(|s| &s.dissat).collect();
+                cat(&all)
+            })
+        }
+        Fragment::Multi(k, keys) => {
+            let sigs: Vec<Vec<u8>> = keys
+                .iter()
+                .filter_map(|key| provider.signature(&key_bytes(key, ms.context)))
+                .take(*k)
+                .collect();
+
+            // Leading empty item is the CHECKMULTISIG dummy
+            let sat = (sigs.len() == *k).then(|| {
+                let mut witness = vec![Vec::new()];
+                witness.extend(sigs);
+                witness
+            });
+            done(sat, Some(vec![Vec::new(); k + 1]))
+        }
+        Fragment::MultiA(k, keys) => {
+            // The first key's signature is consumed first, so it goes on top
+            let mut witness = Vec::with_capacity(keys.len());
+            let mut signed = 0;
+            for key in keys {
+                match provider.signature(&key_bytes(key, ms.context)) {
+                    Some(sig) if signed < *k => {
+                        witness.push(sig);
+                        signed += 1;
+                    }
+                    _ => witness.push(Vec::new()),
+                }
+            }
+            witness.reverse();
+            done(
+                (signed == *k).then_some(witness),
+                Some(vec![Vec::new(); keys.len()]),
+            )
+        }
+    }
+}
+
+// Choose which `k` arguments to satisfy: those whose satisfaction costs the
+// least (or most, for worst-case analysis) relative to dissatisfying them
+fn thresh_satisfaction(k: usize, subs: &[Satisfactions], provider: &Provider) -> Option<Witness> {
+    let dissat_size = |s: &Satisfactions| s.dissat.as_ref().map(witness_size);
+    let mut candidates: Vec<(usize, i64)> = subs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, s)| {
+            let sat = witness_size(s.sat.as_ref()?) as i64;
+            Some((i, sat - dissat_size(s).unwrap_or(0) as i64))
+        })
+        .collect();
+
+    match provider {
+        Provider::Available(_) => candidates.sort_by_key(|c| c.1),
+        Provider::WorstCase(_) => candidates.sort_by_key(|c| std::cmp::Reverse(c.1)),
+    }
+    if candidates.len() < k {
+        return None;
+    }
+    let chosen: Vec<usize> = candidates[..k].iter().map(|c| c.0).collect();
+
+    let parts: Vec<&Option<Witness>> = subs
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, s)| {
+            if chosen.contains(&i) {
+                &s.sat
+            } else {
+                &s.dissat
+            }
+        })
+        .collect();
+    cat(&parts)
+}
+
+// --------------------------------------------------
+// PSBT finalization
+// --------------------------------------------------
+
+// Single-leaf Taproot output for a Tapscript miniscript, under the BIP341
+// unspendable internal key so that only the script can spend it
+pub struct TapLeaf {
+    pub script: Vec<u8>,
+    pub leaf_hash: Vec<u8>,
+    pub output_key: Vec<u8>,
+    pub control_block: Vec<u8>,
+}
+
+impl Miniscript {
+    pub fn tap_leaf(&self) -> TapLeaf {
+        assert!(self.context == Context::Tap, "not a Tapscript miniscript");
+
+        let script = self.encode();
+        let internal = keys::unspendable_internal_key();
+        let leaf_hash = keys::tap_leaf_hash(&script, keys::TAPSCRIPT_LEAF_VERSION);
+        let (output_key, parity) = keys::taproot_output_key(&internal, Some(&leaf_hash));
+
+        let mut control_block = vec![keys::TAPSCRIPT_LEAF_VERSION | parity.to_u8()];
+        control_block.extend(internal.serialize());
+        TapLeaf {
+            script,
+            leaf_hash,
+            output_key: output_key.serialize().to_vec(),
+            control_block,
+        }
+    }
+
+    // scriptPubKey paying to this miniscript: P2WSH or single-leaf P2TR
+    pub fn script_pubkey(&self) -> Vec<u8> {
+        match self.context {
+            Context::Segwitv0 => address::p2wsh_script(&crypto::sha256(&self.encode())),
+            Context::Tap => address::p2tr_script(&self.tap_leaf().output_key),
+        }
+    }
+}
+
+// How a PSBT input spends a miniscript
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Spend {
+    P2wsh,
+    P2shP2wsh,
+    Tapscript,
+}
+
+fn spend_of(ms: &Miniscript, spent: &[u8]) -> Option<Spend> {
+    let program = ms.script_pubkey();
+    if spent == program {
+        return Some(match ms.context {
+            Context::Segwitv0 => Spend::P2wsh,
+            Context::Tap => Spend::Tapscript,
+        });
+    }
+
+    let wrapped = address::p2sh_script(&crypto::hash160(&program));
+    (ms.context == Context::Segwitv0 && spent == wrapped).then_some(Spend::P2shP2wsh)
+}
+
+// Attach the miniscript to every PSBT input spending it (the witness script,
+// or the BIP371 leaf script) so signers can find their keys in it.
+// Returns the number of inputs updated.
+pub fn prepare_psbt(psbt: &mut Psbt, ms: &Miniscript) -> usize {
+    let mut prepared = 0;
+    for i in 0..psbt.inputs.len() {
+        let Some(spent) = psbt.spent_output(i) else {
+            continue;
+        };
+        let input = &mut psbt.inputs[i];
+        match spend_of(ms, &spent.script_pubkey) {
+            Some(Spend::P2wsh) => input.witness_script = Some(ms.encode()),
+            Some(Spend::P2shP2wsh) => {
+                let program = address::p2wsh_script(&crypto::sha256(&ms.encode()));
+                input.redeem_script = Some(program);
+                input.witness_script = Some(ms.encode());
+            }
+            Some(Spend::Tapscript) => {
+                let leaf = ms.tap_leaf();
+                let mut script = leaf.script;
+                script.push(keys::TAPSCRIPT_LEAF_VERSION);
+                input.tap_leaf_scripts.insert(leaf.control_block, script);
+            }
+            None => continue,
+        }
+        prepared += 1;
+    }
+    prepared
+}
+
+// Finalize every P2WSH / P2SH-P2WSH input whose witness script is `ms`, or
+// every P2TR input with `ms` as its Tapscript leaf, using the PSBT's partial
+// (or Tapscript) signatures and the given hash preimages.
+// Returns the number of inputs finalized.
+pub fn finalize_psbt(psbt: &mut Psbt, ms: &Miniscript, preimages: &[Vec<u8>]) -> usize {
+    let script = ms.encode();
+    let leaf = (ms.context == Context::Tap).then(|| ms.tap_leaf());
+
+    let mut finalized = 0;
+    for i in 0..psbt.inputs.len() {
+        let input = &psbt.inputs[i];
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+        let Some(spent) = psbt.spent_output(i) else {
+            continue;
+        };
+        let Some(spend) = spend_of(ms, &spent.script_pubkey) else {
+            continue;
+        };
+
+        // Tapscript signatures are keyed by x-only key and leaf hash
+        let signatures = match &leaf {
+            Some(leaf) => input
+                .tap_script_sigs
+                .iter()
+                .filter(|(key, _)| key[32..] == leaf.leaf_hash[..])
+                .map(|(key, sig)| (key[..32].to_vec(), sig.clone()))
+                .collect(),
+            None => input.partial_sigs.clone(),
+        };
+        let satisfier = Satisfier {
+            signatures: &signatures,
+            preimages,
+            tx: &psbt.unsigned_tx,
+            input_index: i,
+        };
+        let Some(mut witness) = ms.satisfy(&satisfier) else {
+            continue;
+        };
+        witness.push(script.clone());
+
+        let input = &mut psbt.inputs[i];
+        match spend {
+            Spend::P2wsh => {}
+            Spend::P2shP2wsh => {
+                let mut script_sig = Vec::new();
+                script::push_data(
+                    &mut script_sig,
+                    &address::p2wsh_script(&crypto::sha256(&script)),
+                );
+                input.final_script_sig = Some(script_sig);
+            }
+            Spend::Tapscript => witness.push(leaf.as_ref().unwrap().control_block.clone()),
+        }
+        input.final_script_witness = Some(witness);
+
+        // Finalized inputs drop everything but the UTXO (BIP-174)
+        input.partial_sigs.clear();
+        input.tap_script_sigs.clear();
+        input.tap_leaf_scripts.clear();
+        input.tap_internal_key = None;
+        input.sighash_type = None;
+        input.redeem_script = None;
+        input.witness_script = None;
+        finalized += 1;
+    }
+    finalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interpreter,
+        tx::{Transaction, TxIn, TxOut, UTXO},
+    };
+    use secp256k1::{Secp256k1, SecretKey};
+
+    fn officers() -> (Vec<SecretKey>, Vec<String>) {
+        let secp = Secp256k1::new();
+        let secrets: Vec<SecretKey> = (1u8..=3)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let keys = secrets
+            .iter()
+            .map(|s| hex::encode(s.public_key(&secp).serialize()))
+            .collect();
+        (secrets, keys)
+    }
+
+    #[test]
+    fn test_parse_type_and_encode() {
+        let (_, keys) = officers();
+
+        let text = format!("or_d(pk({}),and_v(v:pkh({}),older(144)))", keys[0], keys[1]);
+        let ms = Miniscript::parse(&text, Context::Segwitv0).unwrap();
+        assert_eq!(ms.to_text(), text);
+        // B, not dissatisfiable (and_v has no dissatisfaction)
+        assert!(ms.ty.is(Base::B) && !ms.ty.d);
+
+        let asm = Script::from_bytes(ms.encode()).to_asm();
+        assert!(asm.contains("OP_CHECKSIG OP_IFDUP OP_NOTIF OP_DUP OP_HASH160"));
+        assert!(
+            asm.ends_with("OP_EQUALVERIFY OP_CHECKSIGVERIFY 144 OP_CHECKSEQUENCEVERIFY OP_ENDIF")
+        );
+
+        // Type errors: v: of a K, multi_a in P2WSH, older(0)
+        assert!(Miniscript::parse(&format!("v:pk_k({})", keys[0]), Context::Segwitv0).is_none());
+        assert!(Miniscript::parse(&format!("multi_a(1,{})", keys[0]), Context::Segwitv0).is_none());
+        assert!(Miniscript::parse("older(0)", Context::Segwitv0).is_none());
+
+        // Wrapper chains round-trip
+        let text = format!("thresh(2,pk({}),s:pk({}),sln:after(100))", keys[0], keys[1]);
+        let ms = Miniscript::parse(&text, Context::Segwitv0).unwrap();
+        assert_eq!(ms.to_text(), text);
+    }
+
+    #[test]
+    fn test_treasury_policy_spends() {
+        let (secrets, keys) = officers();
+        let policy = format!(
+            "or(9@thresh(2,pk({a}),pk({b}),pk({c})),and(thresh(1,pk({a}),pk({b}),pk({c})),older(12960)))",
+            a = keys[0],
+            b = keys[1],
+            c = keys[2]
+        );
+        let ms = compile_policy(&policy, Context::Segwitv0).unwrap();
+        assert!(ms.to_text().starts_with("or_d(multi(2,"));
+
+        let max_items = ms.max_satisfaction().unwrap();
+        let size: usize = max_items.iter().map(|len| 1 + len).sum();
+        assert!(max_items.len() >= 3 && size >= 2 * MAX_ECDSA_SIG_SIZE);
+
+        let witness_script = ms.encode();
+        let utxo = UTXO {
+            txid: "22".repeat(32),
+            vout: 0,
+            scriptPubKey: hex::encode(address::p2wsh_script(&crypto::sha256(&witness_script))),
+            amount: 100_000,
+            ..Default::default()
+        };
+
+        // Two officers now, or one officer once the input is 12960 blocks old
+        for (signers, sequence) in [(vec![0, 2], 0xffffffff), (vec![1], 12960)] {
+            let mut tx = Transaction {
+                version: 2,
+                inputs: vec![TxIn::spending(&utxo)],
+                outputs: vec![TxOut {
+                    value: 90_000,
+                    script_pubkey: address::p2wpkh_script(&[7; 20]),
+                }],
+                locktime: 0,
+            };
+            tx.inputs[0].sequence = sequence;
+
+            let mut psbt = Psbt::from_unsigned_tx(tx);
+            psbt.inputs[0].witness_utxo = Some(TxOut {
+                value: utxo.amount,
+                script_pubkey: utils::hex_to_bytes(&utxo.scriptPubKey),
+            });
+            psbt.inputs[0].witness_script = Some(witness_script.clone());
+
+            for i in &signers {
+                assert_eq!(psbt.sign(&secrets[*i]), 1);
+            }
+            assert_eq!(finalize_psbt(&mut psbt, &ms, &[]), 1);
+
+            let signed = psbt.extract();
+            let failures = interpreter::verify_transaction(&signed, std::slice::from_ref(&utxo));
+            assert!(failures.is_empty(), "{:?}", failures);
+            assert!(witness_size(&signed.inputs[0].witness) <= size + witness_script.len() + 3);
+        }
+
+        // One officer without the delay cannot spend
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: 2,
+            inputs: vec![TxIn::spending(&utxo)],
+            outputs: vec![],
+            locktime: 0,
+        });
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: utxo.amount,
+            script_pubkey: utils::hex_to_bytes(&utxo.scriptPubKey),
+        });
+        psbt.inputs[0].witness_script = Some(witness_script);
+        psbt.sign(&secrets[0]);
+        assert_eq!(finalize_psbt(&mut psbt, &ms, &[]), 0);
+    }
+
+    #[test]
+    fn test_tapscript_multi_a() {
+        let (secrets, keys) = officers();
+        let x_only: Vec<&str> = keys.iter().map(|k| &k[2..]).collect();
+        let policy = format!(
+            "thresh(2,pk({}),pk({}),pk({}))",
+            x_only[0], x_only[1], x_only[2]
+        );
+
+        let ms = compile_policy(&policy, Context::Tap).unwrap();
+        assert!(ms.to_text().starts_with("multi_a(2,"));
+        assert_eq!(ms.max_satisfaction(), Some(vec![0, 65, 65]));
+
+        // <k1> CHECKSIG <k2> CHECKSIGADD <k3> CHECKSIGADD 2 NUMEQUAL
+        let script = ms.encode();
+        assert_eq!(script.len(), 3 * 33 + 3 + 2);
+        assert_eq!(script[33], Opcode::OP_CHECKSIG as u8);
+
+        // Spend the single-leaf P2TR output with two of the three keys
+        let utxo = UTXO {
+            txid: "33".repeat(32),
+            vout: 1,
+            scriptPubKey: hex::encode(ms.script_pubkey()),
+            amount: 50_000,
+            ..Default::default()
+        };
+        let unsigned = Transaction {
+            version: 2,
+            inputs: vec![TxIn::spending(&utxo)],
+            outputs: vec![TxOut {
+                value: 49_000,
+                script_pubkey: address::p2wpkh_script(&[7; 20]),
+            }],
+            locktime: 0,
+        };
+        let new_psbt = || {
+            let mut psbt = Psbt::from_unsigned_tx(unsigned.clone());
+            psbt.inputs[0].witness_utxo = Some(TxOut {
+                value: utxo.amount,
+                script_pubkey: ms.script_pubkey(),
+            });
+            assert_eq!(prepare_psbt(&mut psbt, &ms), 1);
+            psbt
+        };
+
+        let mut psbt = new_psbt();
+        assert_eq!(psbt.sign(&secrets[0]), 1);
+        assert_eq!(psbt.sign(&secrets[2]), 1);
+        let psbt = Psbt::parse(&psbt.serialize()).unwrap();
+        let mut psbt_final = psbt.clone();
+        assert_eq!(finalize_psbt(&mut psbt_final, &ms, &[]), 1);
+
+        // <sig3> <> <sig1> <script> <control block>
+        let signed = psbt_final.extract();
+        let witness = &signed.inputs[0].witness;
+        assert_eq!(witness.len(), 5);
+        assert!(witness[1].is_empty());
+        assert_eq!(witness[4], ms.tap_leaf().control_block);
+        let failures = interpreter::verify_transaction(&signed, std::slice::from_ref(&utxo));
+        assert!(failures.is_empty(), "{:?}", failures);
+
+        // A bad signature, or a control block for the wrong output key
+        // parity, is rejected
+        for (item, byte) in [(0, 10), (4, 0)] {
+            let mut tampered = signed.clone();
+            tampered.inputs[0].witness[item][byte] ^= 1;
+            assert_eq!(
+                interpreter::verify_transaction(&tampered, std::slice::from_ref(&utxo)).len(),
+                1
+            );
+        }
+
+        // One signature is not enough
+        let mut psbt = new_psbt();
+        psbt.sign(&secrets[1]);
+        assert_eq!(finalize_psbt(&mut psbt, &ms, &[]), 0);
+    }
+}
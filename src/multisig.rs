@@ -0,0 +1,388 @@
+use crate::{
+    address, crypto,
+    network::Network,
+    policy,
+    psbt::Psbt,
+    script::{Opcode, Script},
+    tx::{Transaction, TxIn, TxOut, UTXO},
+    utils,
+};
+use secp256k1::PublicKey;
+
+// OP_CHECKMULTISIG's key limit, which bounds witness scripts
+pub const MAX_MULTISIG_KEYS: usize = 20;
+// P2SH redeem scripts stop at 520 bytes: 15 compressed keys
+pub const MAX_P2SH_MULTISIG_KEYS: usize = 15;
+
+// --------------------------------------------------
+// Wallet kinds
+// --------------------------------------------------
+
+// How the multisig script is committed to in the scriptPubKey
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MultisigKind {
+    P2sh,
+    P2shP2wsh, // wrapped segwit
+    P2wsh,
+}
+
+impl MultisigKind {
+    pub fn parse(s: &str) -> Option<MultisigKind> {
+        match s {
+            "p2sh" => Some(MultisigKind::P2sh),
+            "p2sh-p2wsh" => Some(MultisigKind::P2shP2wsh),
+            "p2wsh" => Some(MultisigKind::P2wsh),
+            _ => None,
+        }
+    }
+
+    // Most keys a script committed this way can hold
+    pub fn max_keys(self) -> usize {
+        match self {
+            MultisigKind::P2sh => MAX_P2SH_MULTISIG_KEYS,
+            MultisigKind::P2shP2wsh | MultisigKind::P2wsh => MAX_MULTISIG_KEYS,
+        }
+    }
+}
+
+// --------------------------------------------------
+// Script construction
+// --------------------------------------------------
+
+// BIP-67: order compressed public keys lexicographically
+pub fn sort_pubkeys(pubkeys: &[PublicKey]) -> Vec<PublicKey> {
+    let mut sorted = pubkeys.to_vec();
+    sorted.sort_by_key(|k| k.serialize());
+    sorted
+}
+
+// OP_m <pubkey>... OP_n OP_CHECKMULTISIG over BIP-67 sorted keys
+pub fn multisig_script(m: usize, pubkeys: &[PublicKey]) -> Vec<u8> {
+    ordered_multisig_script(m, &sort_pubkeys(pubkeys))
+}
+
+// Same, keeping the keys in the given order (descriptor `multi()`)
+pub fn ordered_multisig_script(m: usize, pubkeys: &[PublicKey]) -> Vec<u8> {
+    let n = pubkeys.len();
+    assert!(
+        m >= 1 && m <= n && n <= MAX_MULTISIG_KEYS,
+        "invalid multisig threshold"
+    );
+
+    let mut builder = Script::builder().push_int(m as i64);
+    for key in pubkeys {
+        builder = builder.push_slice(&key.serialize());
+    }
+    builder
+        .push_int(n as i64)
+        .push_opcode(Opcode::OP_CHECKMULTISIG)
+        .into_bytes()
+}
+
+// Split a bare multisig script into its threshold and public keys. Counts
+// above 16 are one-byte pushes.
+pub fn parse_multisig_script(script: &[u8]) -> Option<(usize, Vec<Vec<u8>>)> {
+    let (m, rest) = match script {
+        [op @ 0x51..=0x60, rest @ ..] => ((op - 0x50) as usize, rest),
+        [0x01, n @ 17..=20, rest @ ..] => (*n as usize, rest),
+        _ => return None,
+    };
+    let (n, mut keys_data) = match rest {
+        [keys @ .., op @ 0x51..=0x60, 0xae] => ((op - 0x50) as usize, keys),
+        [keys @ .., 0x01, n @ 17..=20, 0xae] => (*n as usize, keys),
+        _ => return None,
+    };
+
+    let mut keys = Vec::new();
+    while let Some((&len, tail)) = keys_data.split_first() {
+        let len = len as usize;
+        if (len != 33 && len != 65) || tail.len() < len {
+            return None;
+        }
+        keys.push(tail[..len].to_vec());
+        keys_data = &tail[len..];
+    }
+
+    (n == keys.len() && m <= n).then_some((m, keys))
+}
+
+// scriptPubKey locking funds to `script`
+pub fn script_pubkey(kind: MultisigKind, script: &[u8]) -> Vec<u8> {
+    match kind {
+        MultisigKind::P2sh => address::p2sh_script(&crypto::hash160(script)),
+        MultisigKind::P2shP2wsh => address::p2sh_script(&crypto::hash160(&p2wsh_program(script))),
+        MultisigKind::P2wsh => p2wsh_program(script),
+    }
+}
+
+// Script revealed in the scriptSig (P2SH and P2SH-P2WSH only)
+pub fn redeem_script(kind: MultisigKind, script: &[u8]) -> Option<Vec<u8>> {
+    match kind {
+        MultisigKind::P2sh => Some(script.to_vec()),
+        MultisigKind::P2shP2wsh => Some(p2wsh_program(script)),
+        MultisigKind::P2wsh => None,
+    }
+}
+
+// Script revealed in the witness (segwit kinds only)
+pub fn witness_script(kind: MultisigKind, script: &[u8]) -> Option<Vec<u8>> {
+    match kind {
+        MultisigKind::P2sh => None,
+        MultisigKind::P2shP2wsh | MultisigKind::P2wsh => Some(script.to_vec()),
+    }
+}
+
+pub fn multisig_address(kind: MultisigKind, script: &[u8], network: Network) -> String {
+    match kind {
+        MultisigKind::P2sh => address::script_to_p2sh_address(script, network),
+        MultisigKind::P2shP2wsh => address::script_to_p2sh_address(&p2wsh_program(script), network),
+        MultisigKind::P2wsh => address::script_to_p2wsh_address(script, network.bech32_hrp()),
+    }
+}
+
+fn p2wsh_program(script: &[u8]) -> Vec<u8> {
+    address::p2wsh_script(&crypto::sha256(script))
+}
+
+// --------------------------------------------------
+// Unsigned spend (PSBT)
+// --------------------------------------------------
+
+// Build an unsigned PSBT spending multisig UTXOs; change returns to the
+// same multisig script. Legacy P2SH inputs carry their full previous
+// transaction (BIP174 non_witness_utxo), looked up by txid in `prev_txs`.
+#[allow(clippy::too_many_arguments)]
+pub fn create_multisig_psbt(
+    utxos: &[UTXO],
+    prev_txs: &[Transaction],
+    to_address: &str,
+    amount: u64,
+    fee: u64,
+    m: usize,
+    pubkeys: &[PublicKey],
+    kind: MultisigKind,
+    network: Network,
+) -> Psbt {
+    assert!(
+        pubkeys.len() <= kind.max_keys(),
+        "too many keys for {:?}",
+        kind
+    );
+    let script = multisig_script(m, pubkeys);
+    let own_script_pubkey = script_pubkey(kind, &script);
+
+    let total_in: u64 = utxos.iter().map(|u| u.amount).sum();
+    assert!(total_in >= amount + fee, "insufficient funds");
+
+    for utxo in utxos {
+        assert!(
+            utils::hex_to_bytes(&utxo.scriptPubKey) == own_script_pubkey,
+            "UTXO does not belong to this multisig wallet"
+        );
+    }
+
+    let mut outputs = vec![TxOut {
+        value: amount,
+        script_pubkey: address::address_to_scriptpubkey(to_address, network),
+    }];
+
+    let change = total_in - amount - fee;
+    let has_change = change >= policy::dust_threshold(&own_script_pubkey, policy::DUST_RELAY_FEE);
+    if has_change {
+        outputs.push(TxOut {
+            value: change,
+            script_pubkey: own_script_pubkey,
+        });
+    }
+
+    let unsigned_tx = Transaction {
+        version: 1,
+        inputs: utxos.iter().map(TxIn::spending).collect(),
+        outputs,
+        locktime: 0,
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx);
+
+    for (input, utxo) in psbt.inputs.iter_mut().zip(utxos) {
+        let spent = TxOut {
+            value: utxo.amount,
+            script_pubkey: utils::hex_to_bytes(&utxo.scriptPubKey),
+        };
+        if kind == MultisigKind::P2sh {
+            let prev_tx = prev_txs
+                .iter()
+                .find(|tx| tx.txid() == utxo.txid)
+                .expect("missing previous transaction for P2SH input");
+            assert!(
+                prev_tx.outputs.get(utxo.vout as usize) == Some(&spent),
+                "previous transaction does not match UTXO"
+            );
+            input.non_witness_utxo = Some(prev_tx.clone());
+        } else {
+            input.witness_utxo = Some(spent);
+        }
+        input.redeem_script = redeem_script(kind, &script);
+        input.witness_script = witness_script(kind, &script);
+    }
+
+    if has_change {
+        let change_output = &mut psbt.outputs[1];
+        change_output.redeem_script = redeem_script(kind, &script);
+        change_output.witness_script = witness_script(kind, &script);
+    }
+
+    psbt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::{Secp256k1, SecretKey};
+
+    fn keys() -> Vec<SecretKey> {
+        (1u8..=3)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect()
+    }
+
+    // Funding transaction paying `amount` to `kind`'s scriptPubKey, and
+    // the UTXO it creates
+    fn funding(kind: MultisigKind, script: &[u8], amount: u64) -> (Transaction, UTXO) {
+        let prev_tx = Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                txid: "11".repeat(32),
+                vout: 0,
+                script_sig: vec![0x51],
+                sequence: 0xffffffff,
+                witness: Vec::new(),
+            }],
+            outputs: vec![TxOut {
+                value: amount,
+                script_pubkey: script_pubkey(kind, script),
+            }],
+            locktime: 0,
+        };
+        let utxo = UTXO {
+            txid: prev_tx.txid(),
+            vout: 0,
+            scriptPubKey: utils::bytes_to_hex(&prev_tx.outputs[0].script_pubkey),
+            amount,
+            ..Default::default()
+        };
+        (prev_tx, utxo)
+    }
+
+    #[test]
+    fn test_sorted_keys_are_order_independent() {
+        let secp = Secp256k1::new();
+        let pubkeys: Vec<PublicKey> = keys().iter().map(|k| k.public_key(&secp)).collect();
+        let mut reversed = pubkeys.clone();
+        reversed.reverse();
+
+        let script = multisig_script(2, &pubkeys);
+        assert_eq!(script, multisig_script(2, &reversed));
+
+        let (m, parsed) = parse_multisig_script(&script).unwrap();
+        assert_eq!(m, 2);
+        assert_eq!(parsed.len(), 3);
+        assert!(parsed.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_two_of_three_spend() {
+        let secp = Secp256k1::new();
+        let secrets = keys();
+        let pubkeys: Vec<PublicKey> = secrets.iter().map(|k| k.public_key(&secp)).collect();
+
+        for kind in [
+            MultisigKind::P2sh,
+            MultisigKind::P2shP2wsh,
+            MultisigKind::P2wsh,
+        ] {
+            let script = multisig_script(2, &pubkeys);
+            let (prev_tx, utxo) = funding(kind, &script, 100_000);
+            let to = multisig_address(kind, &script, Network::Testnet);
+
+            let mut first = create_multisig_psbt(
+                std::slice::from_ref(&utxo),
+                std::slice::from_ref(&prev_tx),
+                &to,
+                50_000,
+                1_000,
+                2,
+                &pubkeys,
+                kind,
+                Network::Testnet,
+            );
+            let mut second = Psbt::parse(&first.serialize()).unwrap();
+
+            assert_eq!(first.sign(&secrets[0]), 1);
+            assert_eq!(second.sign(&secrets[2]), 1);
+
+            // One signature is not enough
+            assert!(!first.clone().finalize());
+
+            first.combine(&second);
+            assert!(first.finalize());
+
+            let signed = first.extract();
+            assert_eq!(signed.outputs.len(), 2);
+            assert_eq!(signed.has_witness(), kind != MultisigKind::P2sh);
+            if kind != MultisigKind::P2sh {
+                // dummy, two signatures, witness script
+                assert_eq!(signed.inputs[0].witness.len(), 4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_p2sh_input_carries_previous_transaction() {
+        let secp = Secp256k1::new();
+        let pubkeys: Vec<PublicKey> = keys().iter().map(|k| k.public_key(&secp)).collect();
+        let script = multisig_script(2, &pubkeys);
+        let (prev_tx, utxo) = funding(MultisigKind::P2sh, &script, 100_000);
+        let to = multisig_address(MultisigKind::P2sh, &script, Network::Testnet);
+
+        let psbt = create_multisig_psbt(
+            std::slice::from_ref(&utxo),
+            std::slice::from_ref(&prev_tx),
+            &to,
+            50_000,
+            1_000,
+            2,
+            &pubkeys,
+            MultisigKind::P2sh,
+            Network::Testnet,
+        );
+        let parsed = Psbt::parse(&psbt.serialize()).unwrap();
+
+        assert_eq!(parsed.inputs[0].non_witness_utxo, Some(prev_tx.clone()));
+        assert!(parsed.inputs[0].witness_utxo.is_none());
+        assert_eq!(parsed.spent_output(0), Some(prev_tx.outputs[0].clone()));
+        assert_eq!(parsed.serialize(), psbt.serialize());
+    }
+
+    #[test]
+    #[should_panic(expected = "missing previous transaction")]
+    fn test_p2sh_input_requires_previous_transaction() {
+        let secp = Secp256k1::new();
+        let pubkeys: Vec<PublicKey> = keys().iter().map(|k| k.public_key(&secp)).collect();
+        let script = multisig_script(2, &pubkeys);
+        let (_, utxo) = funding(MultisigKind::P2sh, &script, 100_000);
+        let to = multisig_address(MultisigKind::P2sh, &script, Network::Testnet);
+
+        create_multisig_psbt(
+            &[utxo],
+            &[],
+            &to,
+            50_000,
+            1_000,
+            2,
+            &pubkeys,
+            MultisigKind::P2sh,
+            Network::Testnet,
+        );
+    }
+}
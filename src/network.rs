@@ -1,3 +1,28 @@
+use serde::Deserialize;
+
+// Proof-of-work consensus rules for header validation. These come from the
+// chain's own parameters (supplied by the caller), not from `Network`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct ConsensusParams {
+    // Easiest allowed target, compact
+    pub pow_limit_bits: u32,
+    // Seconds between blocks the retarget aims for
+    pub pow_target_spacing: u32,
+    // Seconds each difficulty period should take
+    pub pow_target_timespan: u32,
+    // Testnet rule: a block more than two spacings after its parent may use
+    // the pow limit
+    pub allow_min_difficulty_blocks: bool,
+    pub no_retargeting: bool,
+}
+
+impl ConsensusParams {
+    // Blocks per difficulty period
+    pub fn difficulty_adjustment_interval(&self) -> u32 {
+        self.pow_target_timespan / self.pow_target_spacing
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Network {
     Mainnet,
@@ -29,6 +54,22 @@ impl Network {
         }
     }
 
+    // BIP32 extended private key version bytes (xprv / tprv)
+    pub fn xprv_version(self) -> u32 {
+        match self {
+            Network::Mainnet => 0x0488_ADE4,
+            Network::Testnet => 0x0435_8394,
+        }
+    }
+
+    // BIP32 extended public key version bytes (xpub / tpub)
+    pub fn xpub_version(self) -> u32 {
+        match self {
+            Network::Mainnet => 0x0488_B21E,
+            Network::Testnet => 0x0435_87CF,
+        }
+    }
+
     // Bech32 HRP (SegWit v0+)
     pub fn bech32_hrp(self) -> &'static str {
         match self {
@@ -37,6 +78,14 @@ impl Network {
         }
     }
 
+    // Minimum relay fee rate (sat/vB); fee estimates never go below it
+    pub fn min_relay_fee_rate(self) -> u64 {
+        match self {
+            Network::Mainnet => 1,
+            Network::Testnet => 1,
+        }
+    }
+
     // Return true if HRP matches this network
     #[allow(dead_code)]
     pub fn matches_bech32(self, addr: &str) -> bool {
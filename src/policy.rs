@@ -0,0 +1,362 @@
+use crate::{
+    multisig,
+    script::{Instruction, Script},
+    tx::{Transaction, TxOut},
+    utils,
+};
+
+// Relay policy defaults (Bitcoin Core values)
+pub const DUST_RELAY_FEE: u64 = 3; // sat/vB
+const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1_650;
+const MAX_OP_RETURN_RELAY: usize = 83; // whole script, OP_RETURN included
+const MAX_STANDARD_TX_SIGOPS_COST: usize = 16_000;
+const MAX_BARE_MULTISIG_KEYS: usize = 3;
+const WITNESS_SCALE_FACTOR: usize = 4;
+
+// Absurd-fee guard defaults: 10,000 sat/vB (0.1 per kvB) and 0.1 coin
+pub const MAX_FEE_RATE: u64 = 10_000;
+pub const MAX_FEE: u64 = 10_000_000;
+
+// --------------------
+// Dust
+// --------------------
+
+fn is_witness_program(script: &[u8]) -> bool {
+    matches!(script, [0x00 | 0x51..=0x60, len, program @ ..]
+        if (2..=40).contains(&(*len as usize)) && program.len() == *len as usize)
+}
+
+fn is_unspendable(script: &[u8]) -> bool {
+    script.first() == Some(&0x6a)
+}
+
+// Smallest output value worth relaying: an output is dust when spending it
+// would cost more than a third of its value at `dust_relay_fee` sat/vB.
+// Unspendable (OP_RETURN) outputs have no dust limit.
+pub fn dust_threshold(script_pubkey: &[u8], dust_relay_fee: u64) -> u64 {
+    if is_unspendable(script_pubkey) {
+        return 0;
+    }
+
+    let output_size = 8 + utils::varint(script_pubkey.len()).len() + script_pubkey.len();
+
+    // outpoint + scriptSig length + sequence, plus a typical signature and
+    // key, discounted for witness programs
+    let input_size = if is_witness_program(script_pubkey) {
+        32 + 4 + 1 + 107 / WITNESS_SCALE_FACTOR + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+
+    (output_size + input_size) as u64 * dust_relay_fee
+}
+
+// --------------------
+// Standardness
+// --------------------
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PolicyError {
+    Version,
+    TxWeight,
+    ScriptSigSize,
+    ScriptSigNotPushOnly,
+    NonStandardInput,
+    NonStandardOutput,
+    BareMultisig,
+    OpReturnSize,
+    MultipleOpReturn,
+    Dust,
+    SigopsCost,
+    AbsurdFeeRate,
+    AbsurdFee,
+}
+
+impl PolicyError {
+    pub fn message(self) -> &'static str {
+        match self {
+            PolicyError::Version => "transaction version is not standard",
+            PolicyError::TxWeight => "transaction weight exceeds the standard limit",
+            PolicyError::ScriptSigSize => "scriptSig is too large",
+            PolicyError::ScriptSigNotPushOnly => "scriptSig is not push-only",
+            PolicyError::NonStandardInput => "input spends a non-standard script",
+            PolicyError::NonStandardOutput => "output script is not standard",
+            PolicyError::BareMultisig => "bare multisig output has too many keys",
+            PolicyError::OpReturnSize => "OP_RETURN output is too large",
+            PolicyError::MultipleOpReturn => "more than one OP_RETURN output",
+            PolicyError::Dust => "output value is below the dust threshold",
+            PolicyError::SigopsCost => "too many signature operations",
+            PolicyError::AbsurdFeeRate => "fee rate is absurdly high",
+            PolicyError::AbsurdFee => "fee is absurdly high",
+        }
+    }
+}
+
+// Output script templates relayed by default
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputType {
+    PubKeyHash,
+    ScriptHash,
+    WitnessV0KeyHash,
+    WitnessV0ScriptHash,
+    Taproot,
+    WitnessUnknown,
+    Multisig(usize),
+    NullData,
+}
+
+fn output_type(script: &[u8]) -> Option<OutputType> {
+    match script {
+        [0x76, 0xa9, 0x14, .., 0x88, 0xac] if script.len() == 25 => Some(OutputType::PubKeyHash),
+        [0xa9, 0x14, .., 0x87] if script.len() == 23 => Some(OutputType::ScriptHash),
+        [0x00, 0x14, ..] if script.len() == 22 => Some(OutputType::WitnessV0KeyHash),
+        [0x00, 0x20, ..] if script.len() == 34 => Some(OutputType::WitnessV0ScriptHash),
+        [0x51, 0x20, ..] if script.len() == 34 => Some(OutputType::Taproot),
+        // Other v0 program lengths are unspendable, so not standard
+        [0x00, ..] => None,
+        _ if is_witness_program(script) => Some(OutputType::WitnessUnknown),
+        [0x6a, ..] if Script::from_bytes(script[1..].to_vec()).is_push_only() => {
+            Some(OutputType::NullData)
+        }
+        _ => multisig::parse_multisig_script(script)
+            .map(|(_, keys)| OutputType::Multisig(keys.len())),
+    }
+}
+
+// Last push of a scriptSig: the redeem script of a P2SH spend
+fn redeem_script(script_sig: &[u8]) -> Option<Vec<u8>> {
+    Script::from_bytes(script_sig.to_vec())
+        .instructions()
+        .filter_map(|i| match i {
+            Instruction::Push { data, .. } => Some(data.to_vec()),
+            _ => None,
+        })
+        .last()
+}
+
+// Signature operations for a witness program spend
+fn witness_sigops(program: &[u8], witness: &[Vec<u8>]) -> usize {
+    match output_type(program) {
+        Some(OutputType::WitnessV0KeyHash) => 1,
+        Some(OutputType::WitnessV0ScriptHash) => witness
+            .last()
+            .map_or(0, |ws| Script::from_bytes(ws.clone()).sigop_count(true)),
+        _ => 0,
+    }
+}
+
+// BIP-141 signature operation cost: legacy sigops count 4 each, witness
+// sigops 1. `spent` holds the output each input spends, in input order.
+pub fn sigop_cost(tx: &Transaction, spent: &[TxOut]) -> usize {
+    let legacy: usize = tx
+        .inputs
+        .iter()
+        .map(|i| &i.script_sig)
+        .chain(tx.outputs.iter().map(|o| &o.script_pubkey))
+        .map(|s| Script::from_bytes(s.clone()).sigop_count(false))
+        .sum();
+
+    let mut cost = legacy * WITNESS_SCALE_FACTOR;
+    for (input, prevout) in tx.inputs.iter().zip(spent) {
+        let mut program = prevout.script_pubkey.clone();
+        if output_type(&program) == Some(OutputType::ScriptHash) {
+            let Some(redeem) = redeem_script(&input.script_sig) else {
+                continue;
+            };
+            cost += Script::from_bytes(redeem.clone()).sigop_count(true) * WITNESS_SCALE_FACTOR;
+            program = redeem;
+        }
+        if is_witness_program(&program) {
+            cost += witness_sigops(&program, &input.witness);
+        }
+    }
+    cost
+}
+
+// Every standardness rule `tx` breaks; empty when it would be relayed.
+// `spent` holds the output each input spends, in input order.
+pub fn check_standard(tx: &Transaction, spent: &[TxOut]) -> Vec<PolicyError> {
+    assert!(
+        spent.len() == tx.inputs.len(),
+        "one spent output is required per input"
+    );
+
+    let mut errors = Vec::new();
+    let mut fail = |e: PolicyError| {
+        if !errors.contains(&e) {
+            errors.push(e);
+        }
+    };
+
+    if !(1..=2).contains(&tx.version) {
+        fail(PolicyError::Version);
+    }
+    if tx.weight() > MAX_STANDARD_TX_WEIGHT {
+        fail(PolicyError::TxWeight);
+    }
+
+    for (input, prevout) in tx.inputs.iter().zip(spent) {
+        if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            fail(PolicyError::ScriptSigSize);
+        }
+        if !Script::from_bytes(input.script_sig.clone()).is_push_only() {
+            fail(PolicyError::ScriptSigNotPushOnly);
+        }
+        match output_type(&prevout.script_pubkey) {
+            None | Some(OutputType::NullData) => fail(PolicyError::NonStandardInput),
+            _ => {}
+        }
+    }
+
+    let mut op_returns = 0;
+    for output in &tx.outputs {
+        match output_type(&output.script_pubkey) {
+            None => fail(PolicyError::NonStandardOutput),
+            Some(OutputType::Multisig(n)) if n > MAX_BARE_MULTISIG_KEYS => {
+                fail(PolicyError::BareMultisig)
+            }
+            Some(OutputType::NullData) => {
+                op_returns += 1;
+                if output.script_pubkey.len() > MAX_OP_RETURN_RELAY {
+                    fail(PolicyError::OpReturnSize);
+                }
+            }
+            Some(_) => {
+                if output.value < dust_threshold(&output.script_pubkey, DUST_RELAY_FEE) {
+                    fail(PolicyError::Dust);
+                }
+            }
+        }
+    }
+    if op_returns > 1 {
+        fail(PolicyError::MultipleOpReturn);
+    }
+
+    if sigop_cost(tx, spent) > MAX_STANDARD_TX_SIGOPS_COST {
+        fail(PolicyError::SigopsCost);
+    }
+    errors
+}
+
+// --------------------
+// Absurd-fee guard
+// --------------------
+
+// Highest fee rate (sat/vB) and absolute fee a transaction may pay before
+// it is treated as a mistake
+#[derive(Clone, Copy, Debug)]
+pub struct FeeLimits {
+    pub max_fee_rate: u64,
+    pub max_fee: u64,
+}
+
+impl Default for FeeLimits {
+    fn default() -> FeeLimits {
+        FeeLimits {
+            max_fee_rate: MAX_FEE_RATE,
+            max_fee: MAX_FEE,
+        }
+    }
+}
+
+// Reject a fee above `limits`, unless the caller explicitly allows it
+pub fn check_fee(
+    fee: u64,
+    vsize: u64,
+    limits: &FeeLimits,
+    allow_absurd_fee: bool,
+) -> Result<(), PolicyError> {
+    if allow_absurd_fee {
+        return Ok(());
+    }
+    if fee > limits.max_fee {
+        return Err(PolicyError::AbsurdFee);
+    }
+    if fee > limits.max_fee_rate.saturating_mul(vsize.max(1)) {
+        return Err(PolicyError::AbsurdFeeRate);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx::TxIn;
+
+    fn output(value: u64, script_hex: &str) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey: utils::hex_to_bytes(script_hex),
+        }
+    }
+
+    #[test]
+    fn test_dust_per_script_type() {
+        let p2pkh = utils::hex_to_bytes("76a91489abcdefabbaabbaabbaabbaabbaabbaabbaabba88ac");
+        let p2wpkh = utils::hex_to_bytes("001489abcdefabbaabbaabbaabbaabbaabbaabbaabba");
+        let p2tr = [vec![0x51, 0x20], vec![1; 32]].concat();
+
+        assert_eq!(dust_threshold(&p2pkh, DUST_RELAY_FEE), 546);
+        assert_eq!(dust_threshold(&p2wpkh, DUST_RELAY_FEE), 294);
+        assert_eq!(dust_threshold(&p2tr, DUST_RELAY_FEE), 330);
+        assert_eq!(dust_threshold(&[0x6a, 0x01, 0x00], DUST_RELAY_FEE), 0);
+        assert_eq!(dust_threshold(&p2wpkh, 1), 98);
+    }
+
+    #[test]
+    fn test_standardness() {
+        let p2wpkh = "001489abcdefabbaabbaabbaabbaabbaabbaabbaabba";
+        let spent = [output(100_000, p2wpkh)];
+        let mut tx = Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                txid: "11".repeat(32),
+                vout: 0,
+                script_sig: Vec::new(),
+                sequence: 0xffffffff,
+                witness: vec![vec![0; 72], vec![2; 33]],
+            }],
+            outputs: vec![output(50_000, p2wpkh), output(0, "6a0568656c6c6f")],
+            locktime: 0,
+        };
+        assert!(check_standard(&tx, &spent).is_empty());
+        assert_eq!(sigop_cost(&tx, &spent), 1);
+
+        // Dust, a second and oversized OP_RETURN, a 4-key bare multisig
+        tx.outputs[0].value = 293;
+        tx.outputs
+            .push(output(0, &format!("6a4c51{}", "00".repeat(81))));
+        let keys: String = (0..4).map(|_| format!("21{}", "02".repeat(33))).collect();
+        tx.outputs.push(output(1_000, &format!("51{}54ae", keys)));
+        tx.version = 3;
+
+        assert_eq!(
+            check_standard(&tx, &spent),
+            vec![
+                PolicyError::Version,
+                PolicyError::Dust,
+                PolicyError::OpReturnSize,
+                PolicyError::BareMultisig,
+                PolicyError::MultipleOpReturn,
+            ]
+        );
+        // Bare CHECKMULTISIG outputs count 20 legacy sigops
+        assert_eq!(sigop_cost(&tx, &spent), 1 + 20 * 4);
+    }
+
+    #[test]
+    fn test_absurd_fee_guard() {
+        let limits = FeeLimits::default();
+        assert_eq!(check_fee(1_000, 141, &limits, false), Ok(()));
+        assert_eq!(
+            check_fee(10 * 100_000_000, 141, &limits, false),
+            Err(PolicyError::AbsurdFee)
+        );
+        assert_eq!(
+            check_fee(2_000_000, 141, &limits, false),
+            Err(PolicyError::AbsurdFeeRate)
+        );
+        assert_eq!(check_fee(10 * 100_000_000, 141, &limits, true), Ok(()));
+    }
+}
@@ -0,0 +1,616 @@
+use crate::{
+    crypto, keys, multisig, script,
+    tx::{Transaction, TxOut},
+    utils,
+};
+use rand::rngs::OsRng;
+use secp256k1::{Keypair, Message, Secp256k1, SecretKey};
+use std::collections::BTreeMap;
+
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+// Global key types
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+
+// Input key types
+const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+const PSBT_IN_REDEEM_SCRIPT: u8 = 0x04;
+const PSBT_IN_WITNESS_SCRIPT: u8 = 0x05;
+const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+const PSBT_IN_TAP_KEY_SIG: u8 = 0x13;
+const PSBT_IN_TAP_SCRIPT_SIG: u8 = 0x14;
+const PSBT_IN_TAP_LEAF_SCRIPT: u8 = 0x15;
+const PSBT_IN_TAP_INTERNAL_KEY: u8 = 0x17;
+
+// Output key types
+const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
+const PSBT_OUT_WITNESS_SCRIPT: u8 = 0x01;
+
+// --------------------
+// PSBT maps (BIP-174)
+// --------------------
+#[derive(Clone, Debug, Default)]
+pub struct PsbtInput {
+    pub non_witness_utxo: Option<Transaction>,
+    pub witness_utxo: Option<TxOut>,
+    pub partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>, // pubkey -> DER sig + sighash byte
+    pub sighash_type: Option<u32>,
+    pub redeem_script: Option<Vec<u8>>,
+    pub witness_script: Option<Vec<u8>>,
+    pub final_script_sig: Option<Vec<u8>>,
+    pub final_script_witness: Option<Vec<Vec<u8>>>,
+    pub tap_key_sig: Option<Vec<u8>>, // BIP371 key path signature
+    pub tap_script_sigs: BTreeMap<Vec<u8>, Vec<u8>>, // x-only key || leaf hash -> sig
+    pub tap_leaf_scripts: BTreeMap<Vec<u8>, Vec<u8>>, // control block -> script || leaf version
+    pub tap_internal_key: Option<Vec<u8>>, // BIP371 x-only internal key
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PsbtOutput {
+    pub redeem_script: Option<Vec<u8>>,
+    pub witness_script: Option<Vec<u8>>,
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Psbt {
+    pub unsigned_tx: Transaction,
+    pub inputs: Vec<PsbtInput>,
+    pub outputs: Vec<PsbtOutput>,
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+// Where the signed script for an input lives
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SpendKind {
+    P2sh,
+    P2shP2wsh,
+    P2wsh,
+}
+
+impl Psbt {
+    pub fn from_unsigned_tx(unsigned_tx: Transaction) -> Psbt {
+        Psbt {
+            inputs: vec![PsbtInput::default(); unsigned_tx.inputs.len()],
+            outputs: vec![PsbtOutput::default(); unsigned_tx.outputs.len()],
+            unsigned_tx,
+            unknown: BTreeMap::new(),
+        }
+    }
+
+    // --------------------
+    // Encoding
+    // --------------------
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut psbt = PSBT_MAGIC.to_vec();
+
+        kv(
+            &mut psbt,
+            PSBT_GLOBAL_UNSIGNED_TX,
+            &[],
+            &self.unsigned_tx.serialize_no_witness(),
+        );
+        unknown_kv(&mut psbt, &self.unknown);
+        psbt.push(0x00);
+
+        for input in &self.inputs {
+            if let Some(prev_tx) = &input.non_witness_utxo {
+                kv(
+                    &mut psbt,
+                    PSBT_IN_NON_WITNESS_UTXO,
+                    &[],
+                    &prev_tx.serialize(),
+                );
+            }
+            if let Some(utxo) = &input.witness_utxo {
+                kv(&mut psbt, PSBT_IN_WITNESS_UTXO, &[], &utxo.serialize());
+            }
+            for (pubkey, sig) in &input.partial_sigs {
+                kv(&mut psbt, PSBT_IN_PARTIAL_SIG, pubkey, sig);
+            }
+            if let Some(sighash_type) = input.sighash_type {
+                kv(
+                    &mut psbt,
+                    PSBT_IN_SIGHASH_TYPE,
+                    &[],
+                    &utils::u32_le(sighash_type),
+                );
+            }
+            if let Some(script) = &input.redeem_script {
+                kv(&mut psbt, PSBT_IN_REDEEM_SCRIPT, &[], script);
+            }
+            if let Some(script) = &input.witness_script {
+                kv(&mut psbt, PSBT_IN_WITNESS_SCRIPT, &[], script);
+            }
+            if let Some(script_sig) = &input.final_script_sig {
+                kv(&mut psbt, PSBT_IN_FINAL_SCRIPTSIG, &[], script_sig);
+            }
+            if let Some(witness) = &input.final_script_witness {
+                kv(
+                    &mut psbt,
+                    PSBT_IN_FINAL_SCRIPTWITNESS,
+                    &[],
+                    &serialize_witness(witness),
+                );
+            }
+            if let Some(sig) = &input.tap_key_sig {
+                kv(&mut psbt, PSBT_IN_TAP_KEY_SIG, &[], sig);
+            }
+            for (key, sig) in &input.tap_script_sigs {
+                kv(&mut psbt, PSBT_IN_TAP_SCRIPT_SIG, key, sig);
+            }
+            for (control, leaf) in &input.tap_leaf_scripts {
+                kv(&mut psbt, PSBT_IN_TAP_LEAF_SCRIPT, control, leaf);
+            }
+            if let Some(key) = &input.tap_internal_key {
+                kv(&mut psbt, PSBT_IN_TAP_INTERNAL_KEY, &[], key);
+            }
+            unknown_kv(&mut psbt, &input.unknown);
+            psbt.push(0x00);
+        }
+
+        for output in &self.outputs {
+            if let Some(script) = &output.redeem_script {
+                kv(&mut psbt, PSBT_OUT_REDEEM_SCRIPT, &[], script);
+            }
+            if let Some(script) = &output.witness_script {
+                kv(&mut psbt, PSBT_OUT_WITNESS_SCRIPT, &[], script);
+            }
+            unknown_kv(&mut psbt, &output.unknown);
+            psbt.push(0x00);
+        }
+
+        psbt
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Psbt> {
+        let mut pos = 0;
+        if utils::read_bytes(data, &mut pos, PSBT_MAGIC.len())? != PSBT_MAGIC {
+            return None;
+        }
+
+        let mut unsigned_tx = None;
+        let mut unknown = BTreeMap::new();
+        for (key, value) in read_map(data, &mut pos)? {
+            match key[0] {
+                PSBT_GLOBAL_UNSIGNED_TX if key.len() == 1 => {
+                    unsigned_tx = Some(Transaction::parse(&value)?)
+                }
+                _ => {
+                    unknown.insert(key, value);
+                }
+            }
+        }
+
+        let unsigned_tx = unsigned_tx?;
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx);
+        psbt.unknown = unknown;
+
+        for input in psbt.inputs.iter_mut() {
+            for (key, value) in read_map(data, &mut pos)? {
+                match (key[0], key.len()) {
+                    (PSBT_IN_NON_WITNESS_UTXO, 1) => {
+                        input.non_witness_utxo = Some(Transaction::parse(&value)?)
+                    }
+                    (PSBT_IN_WITNESS_UTXO, 1) => {
+                        input.witness_utxo = Some(TxOut::parse(&value, &mut 0)?)
+                    }
+                    (PSBT_IN_PARTIAL_SIG, _) => {
+                        input.partial_sigs.insert(key[1..].to_vec(), value);
+                    }
+                    (PSBT_IN_SIGHASH_TYPE, 1) => {
+                        input.sighash_type = Some(utils::read_u32_le(&value, &mut 0)?)
+                    }
+                    (PSBT_IN_REDEEM_SCRIPT, 1) => input.redeem_script = Some(value),
+                    (PSBT_IN_WITNESS_SCRIPT, 1) => input.witness_script = Some(value),
+                    (PSBT_IN_FINAL_SCRIPTSIG, 1) => input.final_script_sig = Some(value),
+                    (PSBT_IN_FINAL_SCRIPTWITNESS, 1) => {
+                        input.final_script_witness = Some(parse_witness(&value)?)
+                    }
+                    (PSBT_IN_TAP_KEY_SIG, 1) => input.tap_key_sig = Some(value),
+                    (PSBT_IN_TAP_SCRIPT_SIG, 65) => {
+                        input.tap_script_sigs.insert(key[1..].to_vec(), value);
+                    }
+                    (PSBT_IN_TAP_LEAF_SCRIPT, _) if !value.is_empty() => {
+                        input.tap_leaf_scripts.insert(key[1..].to_vec(), value);
+                    }
+                    (PSBT_IN_TAP_INTERNAL_KEY, 1) => input.tap_internal_key = Some(value),
+                    _ => {
+                        input.unknown.insert(key, value);
+                    }
+                }
+            }
+        }
+
+        for output in psbt.outputs.iter_mut() {
+            for (key, value) in read_map(data, &mut pos)? {
+                match (key[0], key.len()) {
+                    (PSBT_OUT_REDEEM_SCRIPT, 1) => output.redeem_script = Some(value),
+                    (PSBT_OUT_WITNESS_SCRIPT, 1) => output.witness_script = Some(value),
+                    _ => {
+                        output.unknown.insert(key, value);
+                    }
+                }
+            }
+        }
+
+        (pos == data.len()).then_some(psbt)
+    }
+
+    // --------------------
+    // Spent outputs
+    // --------------------
+
+    // Output spent by input `index`, from witness_utxo or the full previous tx
+    pub fn spent_output(&self, index: usize) -> Option<TxOut> {
+        let input = &self.inputs[index];
+        if let Some(utxo) = &input.witness_utxo {
+            return Some(utxo.clone());
+        }
+
+        let prev_tx = input.non_witness_utxo.as_ref()?;
+        let txin = &self.unsigned_tx.inputs[index];
+        if prev_tx.txid() != txin.txid {
+            return None;
+        }
+        prev_tx.outputs.get(txin.vout as usize).cloned()
+    }
+
+    // --------------------
+    // Signer
+    // --------------------
+
+    // Add a partial signature, using the input's sighash type (ALL when
+    // unset, DEFAULT for Tapscript), to every script input whose script
+    // contains the key or its hash (multisig, miniscript), and to every
+    // Tapscript leaf containing its x-only key.
+    // Returns the number of inputs signed.
+    pub fn sign(&mut self, privkey: &SecretKey) -> usize {
+        let secp = Secp256k1::new();
+        let pubkey = keys::privkey_to_pubkey(privkey).serialize().to_vec();
+        let keypair = Keypair::from_secret_key(&secp, privkey);
+        let x_only = keypair.x_only_public_key().0.serialize();
+
+        // BIP341 signatures commit to every spent output
+        let spent_outputs: Option<Vec<TxOut>> = (0..self.inputs.len())
+            .map(|i| self.spent_output(i))
+            .collect();
+        let cache = crypto::SighashCache::new(&self.unsigned_tx, spent_outputs.as_deref());
+
+        let mut signed = 0;
+        for i in 0..self.inputs.len() {
+            let input = &self.inputs[i];
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                continue;
+            }
+
+            let spent = self
+                .spent_output(i)
+                .expect("PSBT input is missing its UTXO");
+
+            let leaf_hashes: Vec<Vec<u8>> = input
+                .tap_leaf_scripts
+                .values()
+                .filter_map(|leaf| {
+                    let (&version, script) = leaf.split_last()?;
+                    (version == keys::TAPSCRIPT_LEAF_VERSION && script_has_key(script, &x_only))
+                        .then(|| keys::tap_leaf_hash(script, version))
+                })
+                .collect();
+            if !leaf_hashes.is_empty() {
+                let sighash_type = input.sighash_type.unwrap_or(crypto::SIGHASH_DEFAULT);
+                for leaf_hash in leaf_hashes {
+                    // Miniscript never uses OP_CODESEPARATOR
+                    let sighash = cache
+                        .bip341(i, sighash_type, Some((&leaf_hash, 0xffffffff)), None)
+                        .expect("invalid Tapscript sighash type");
+                    let sig = secp.sign_schnorr_with_rng(
+                        &Message::from_digest_slice(&sighash).unwrap(),
+                        &keypair,
+                        &mut OsRng,
+                    );
+
+                    let mut sig_bytes = sig.as_ref().to_vec();
+                    if sighash_type != crypto::SIGHASH_DEFAULT {
+                        sig_bytes.push(sighash_type as u8);
+                    }
+                    let key = [&x_only[..], &leaf_hash].concat();
+                    self.inputs[i].tap_script_sigs.insert(key, sig_bytes);
+                }
+                signed += 1;
+                continue;
+            }
+
+            let (kind, script) = match spend_script(input, &spent.script_pubkey) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            if !script_has_key(&script, &pubkey) {
+                continue;
+            }
+
+            let sighash_type = input.sighash_type.unwrap_or(crypto::SIGHASH_ALL);
+            let sighash = match kind {
+                SpendKind::P2sh => cache.legacy(i, &script, sighash_type),
+                SpendKind::P2shP2wsh | SpendKind::P2wsh => {
+                    cache.bip143(i, &script, spent.value, sighash_type)
+                }
+            };
+
+            let sig = secp.sign_ecdsa(&Message::from_digest_slice(&sighash).unwrap(), privkey);
+            let mut sig_der = sig.serialize_der().to_vec();
+            sig_der.push(sighash_type as u8);
+
+            self.inputs[i].partial_sigs.insert(pubkey.clone(), sig_der);
+            signed += 1;
+        }
+
+        signed
+    }
+
+    // --------------------
+    // Combiner
+    // --------------------
+    pub fn combine(&mut self, other: &Psbt) {
+        assert!(
+            self.unsigned_tx.txid() == other.unsigned_tx.txid(),
+            "PSBTs spend different transactions"
+        );
+
+        for (mine, theirs) in self.inputs.iter_mut().zip(&other.inputs) {
+            for (pubkey, sig) in &theirs.partial_sigs {
+                mine.partial_sigs
+                    .entry(pubkey.clone())
+                    .or_insert_with(|| sig.clone());
+            }
+            if mine.non_witness_utxo.is_none() {
+                mine.non_witness_utxo = theirs.non_witness_utxo.clone();
+            }
+            if mine.witness_utxo.is_none() {
+                mine.witness_utxo = theirs.witness_utxo.clone();
+            }
+            if mine.sighash_type.is_none() {
+                mine.sighash_type = theirs.sighash_type;
+            }
+            if mine.redeem_script.is_none() {
+                mine.redeem_script = theirs.redeem_script.clone();
+            }
+            if mine.witness_script.is_none() {
+                mine.witness_script = theirs.witness_script.clone();
+            }
+            if mine.final_script_sig.is_none() {
+                mine.final_script_sig = theirs.final_script_sig.clone();
+            }
+            if mine.final_script_witness.is_none() {
+                mine.final_script_witness = theirs.final_script_witness.clone();
+            }
+            if mine.tap_key_sig.is_none() {
+                mine.tap_key_sig = theirs.tap_key_sig.clone();
+            }
+            for (key, sig) in &theirs.tap_script_sigs {
+                mine.tap_script_sigs
+                    .entry(key.clone())
+                    .or_insert_with(|| sig.clone());
+            }
+            for (control, leaf) in &theirs.tap_leaf_scripts {
+                mine.tap_leaf_scripts
+                    .entry(control.clone())
+                    .or_insert_with(|| leaf.clone());
+            }
+            if mine.tap_internal_key.is_none() {
+                mine.tap_internal_key = theirs.tap_internal_key.clone();
+            }
+            for (k, v) in &theirs.unknown {
+                mine.unknown.entry(k.clone()).or_insert_with(|| v.clone());
+            }
+        }
+
+        for (mine, theirs) in self.outputs.iter_mut().zip(&other.outputs) {
+            if mine.redeem_script.is_none() {
+                mine.redeem_script = theirs.redeem_script.clone();
+            }
+            if mine.witness_script.is_none() {
+                mine.witness_script = theirs.witness_script.clone();
+            }
+            for (k, v) in &theirs.unknown {
+                mine.unknown.entry(k.clone()).or_insert_with(|| v.clone());
+            }
+        }
+
+        for (k, v) in &other.unknown {
+            self.unknown.entry(k.clone()).or_insert_with(|| v.clone());
+        }
+    }
+
+    // --------------------
+    // Finalizer / extractor
+    // --------------------
+
+    // Build final scriptSig / witness for every input with enough signatures.
+    // Returns true once all inputs are final.
+    pub fn finalize(&mut self) -> bool {
+        for i in 0..self.inputs.len() {
+            let spent = match self.spent_output(i) {
+                Some(o) => o.script_pubkey,
+                None => continue,
+            };
+
+            let input = &mut self.inputs[i];
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                continue;
+            }
+
+            // Taproot key path: the signature is the whole witness
+            if let (Some(sig), [0x51, 0x20, ..]) = (&input.tap_key_sig, spent.as_slice()) {
+                input.final_script_witness = Some(vec![sig.clone()]);
+                input.tap_key_sig = None;
+                continue;
+            }
+
+            let (kind, script) = match spend_script(input, &spent) {
+                Some(v) => v,
+                None => continue,
+            };
+            let (m, keys_in_script) = match multisig::parse_multisig_script(&script) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            // Signatures must appear in script key order
+            let sigs: Vec<Vec<u8>> = keys_in_script
+                .iter()
+                .filter_map(|k| input.partial_sigs.get(k).cloned())
+                .take(m)
+                .collect();
+            if sigs.len() < m {
+                continue;
+            }
+
+            // OP_0 dummy element consumed by OP_CHECKMULTISIG
+            let mut stack = vec![Vec::new()];
+            stack.extend(sigs);
+
+            match kind {
+                SpendKind::P2sh => {
+                    let mut script_sig = vec![0x00];
+                    for item in &stack[1..] {
+                        script::push_data(&mut script_sig, item);
+                    }
+                    script::push_data(&mut script_sig, &script);
+                    input.final_script_sig = Some(script_sig);
+                }
+                SpendKind::P2shP2wsh => {
+                    let mut script_sig = Vec::new();
+                    let redeem = input.redeem_script.clone().unwrap_or_default();
+                    script::push_data(&mut script_sig, &redeem);
+                    input.final_script_sig = Some(script_sig);
+
+                    stack.push(script);
+                    input.final_script_witness = Some(stack);
+                }
+                SpendKind::P2wsh => {
+                    stack.push(script);
+                    input.final_script_witness = Some(stack);
+                }
+            }
+
+            // Finalized inputs drop everything but the UTXO (BIP-174)
+            input.partial_sigs.clear();
+            input.sighash_type = None;
+            input.redeem_script = None;
+            input.witness_script = None;
+        }
+
+        self.is_finalized()
+    }
+
+    pub fn is_finalized(&self) -> bool {
+        self.inputs
+            .iter()
+            .all(|i| i.final_script_sig.is_some() || i.final_script_witness.is_some())
+    }
+
+    pub fn extract(&self) -> Transaction {
+        assert!(self.is_finalized(), "PSBT is not fully signed");
+
+        let mut tx = self.unsigned_tx.clone();
+        for (txin, input) in tx.inputs.iter_mut().zip(&self.inputs) {
+            txin.script_sig = input.final_script_sig.clone().unwrap_or_default();
+            txin.witness = input.final_script_witness.clone().unwrap_or_default();
+        }
+        tx
+    }
+}
+
+// Resolve the script committed to by `spent`, checking it against the
+// redeem / witness script supplied in the input
+fn spend_script(input: &PsbtInput, spent: &[u8]) -> Option<(SpendKind, Vec<u8>)> {
+    match spent {
+        [0x00, 0x20, program @ ..] if program.len() == 32 => {
+            let ws = input.witness_script.clone()?;
+            (crypto::sha256(&ws) == program).then_some((SpendKind::P2wsh, ws))
+        }
+        [0xa9, 0x14, hash @ .., 0x87] if hash.len() == 20 => {
+            let redeem = input.redeem_script.as_ref()?;
+            if crypto::hash160(redeem) != hash {
+                return None;
+            }
+
+            match redeem.as_slice() {
+                [0x00, 0x20, program @ ..] if program.len() == 32 => {
+                    let ws = input.witness_script.clone()?;
+                    (crypto::sha256(&ws) == program).then_some((SpendKind::P2shP2wsh, ws))
+                }
+                _ => Some((SpendKind::P2sh, redeem.clone())),
+            }
+        }
+        _ => None,
+    }
+}
+
+// Whether `script` pushes the key itself or its HASH160
+fn script_has_key(script: &[u8], pubkey: &[u8]) -> bool {
+    let key_hash = crypto::hash160(pubkey);
+    script::Instructions::new(script).any(|ins| match ins {
+        script::Instruction::Push { data, .. } => data == pubkey || data == key_hash,
+        _ => false,
+    })
+}
+
+// --------------------
+// Key-value helpers
+// --------------------
+fn kv(psbt: &mut Vec<u8>, key_type: u8, key_data: &[u8], value: &[u8]) {
+    psbt.extend(utils::varint(1 + key_data.len()));
+    psbt.push(key_type);
+    psbt.extend(key_data);
+    psbt.extend(utils::varint(value.len()));
+    psbt.extend(value);
+}
+
+fn unknown_kv(psbt: &mut Vec<u8>, entries: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    for (key, value) in entries {
+        psbt.extend(utils::varint(key.len()));
+        psbt.extend(key);
+        psbt.extend(utils::varint(value.len()));
+        psbt.extend(value);
+    }
+}
+
+// Read one map up to its 0x00 separator
+fn read_map(data: &[u8], pos: &mut usize) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut entries = Vec::new();
+    loop {
+        let key = utils::read_var_bytes(data, pos)?;
+        if key.is_empty() {
+            return Some(entries);
+        }
+        let value = utils::read_var_bytes(data, pos)?;
+        entries.push((key.to_vec(), value.to_vec()));
+    }
+}
+
+fn serialize_witness(witness: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = utils::varint(witness.len());
+    for item in witness {
+        out.extend(utils::varint(item.len()));
+        out.extend(item);
+    }
+    out
+}
+
+fn parse_witness(data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut pos = 0;
+    let count = utils::read_varint(data, &mut pos)?;
+    let mut items = Vec::with_capacity(count.min(data.len()));
+    for _ in 0..count {
+        items.push(utils::read_var_bytes(data, &mut pos)?.to_vec());
+    }
+    (pos == data.len()).then_some(items)
+}
@@ -0,0 +1,384 @@
+use crate::{
+    stratum::BlockTemplate,
+    tx::{Transaction, UTXO},
+    utils,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// Satoshis per coin, for the node's decimal amounts
+const COIN: f64 = 100_000_000.0;
+
+// Node amounts (coins) to satoshis
+pub fn to_sats(amount: f64) -> u64 {
+    (amount * COIN).round() as u64
+}
+
+// --------------------
+// JSON-RPC envelope
+// --------------------
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RpcError {
+    Rpc(i64, String),
+    InvalidResponse,
+}
+
+impl RpcError {
+    pub fn message(&self) -> String {
+        match self {
+            RpcError::Rpc(code, message) => format!("RPC error {}: {}", code, message),
+            RpcError::InvalidResponse => "invalid response".to_string(),
+        }
+    }
+}
+
+pub fn request_body(id: u64, method: &str, params: Value) -> String {
+    json!({ "jsonrpc": "1.0", "id": id, "method": method, "params": params }).to_string()
+}
+
+// The result of a response body, or its error object
+pub fn parse_response(body: &str) -> Result<Value, RpcError> {
+    let mut response: Value = serde_json::from_str(body).map_err(|_| RpcError::InvalidResponse)?;
+    match response.get("error").filter(|e| !e.is_null()) {
+        Some(error) => Err(RpcError::Rpc(
+            error["code"].as_i64().unwrap_or_default(),
+            error["message"].as_str().unwrap_or_default().to_string(),
+        )),
+        None => Ok(response["result"].take()),
+    }
+}
+
+fn parse_result<R: serde::de::DeserializeOwned>(body: &str) -> Result<R, RpcError> {
+    serde_json::from_value(parse_response(body)?).map_err(|_| RpcError::InvalidResponse)
+}
+
+// --------------------
+// Models
+// --------------------
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct Unspent {
+    txid: String,
+    vout: u32,
+    scriptPubKey: String,
+    amount: f64,
+    confirmations: u32,
+}
+
+#[derive(Deserialize)]
+struct WalletTransaction {
+    hex: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct SmartFee {
+    // Coins per kvB; absent when the node has too little data
+    #[serde(default)]
+    pub feerate: Option<f64>,
+    #[serde(default)]
+    pub errors: Vec<String>,
+    pub blocks: u32,
+}
+
+impl SmartFee {
+    pub fn sat_per_vbyte(&self) -> Option<f64> {
+        self.feerate.map(|rate| rate * COIN / 1_000.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MempoolFees {
+    pub base: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MempoolAccept {
+    pub txid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wtxid: Option<String>,
+    pub allowed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vsize: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fees: Option<MempoolFees>,
+    #[serde(
+        default,
+        rename = "reject-reason",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub reject_reason: Option<String>,
+}
+
+// --------------------
+// Requests
+// --------------------
+
+// Request bodies for Bitcoin Core's RPC, posted by the caller (e.g. JS
+// `fetch`); each response body goes to the matching parser below
+
+// Wallet UTXOs with `min_conf..=max_conf` confirmations, optionally limited
+// to `addresses`
+pub fn list_unspent_request(id: u64, min_conf: u32, max_conf: u32, addresses: &[String]) -> String {
+    request_body(id, "listunspent", json!([min_conf, max_conf, addresses]))
+}
+
+pub fn get_raw_transaction_request(id: u64, txid: &str) -> String {
+    request_body(id, "getrawtransaction", json!([txid, false]))
+}
+
+// Wallet transaction; unlike getrawtransaction it needs no -txindex for
+// confirmed transactions paying the wallet
+pub fn get_transaction_request(id: u64, txid: &str) -> String {
+    request_body(id, "gettransaction", json!([txid]))
+}
+
+// Broadcast; `max_fee_rate` (coins per kvB) overrides the node's limit
+pub fn send_raw_transaction_request(id: u64, raw_tx: &[u8], max_fee_rate: Option<f64>) -> String {
+    let mut params = vec![json!(utils::bytes_to_hex(raw_tx))];
+    params.extend(max_fee_rate.map(|rate| json!(rate)));
+    request_body(id, "sendrawtransaction", Value::Array(params))
+}
+
+// `mode` is "economical" or "conservative"
+pub fn estimate_smart_fee_request(id: u64, conf_target: u32, mode: &str) -> String {
+    request_body(id, "estimatesmartfee", json!([conf_target, mode]))
+}
+
+pub fn get_block_template_request(id: u64) -> String {
+    request_body(id, "getblocktemplate", json!([{ "rules": ["segwit"] }]))
+}
+
+pub fn test_mempool_accept_request(id: u64, raw_txs: &[Vec<u8>]) -> String {
+    let raw: Vec<String> = raw_txs.iter().map(|tx| utils::bytes_to_hex(tx)).collect();
+    request_body(id, "testmempoolaccept", json!([raw]))
+}
+
+// --------------------
+// Response parsers
+// --------------------
+
+// Core's listunspent does not flag coinbase outputs: fetch each UTXO's
+// transaction (gettransaction) and pass them to `tx::mark_coinbase` before
+// spending
+pub fn parse_list_unspent(body: &str) -> Result<Vec<UTXO>, RpcError> {
+    let unspent: Vec<Unspent> = parse_result(body)?;
+    Ok(unspent
+        .into_iter()
+        .map(|u| UTXO {
+            txid: u.txid,
+            vout: u.vout,
+            scriptPubKey: u.scriptPubKey,
+            amount: to_sats(u.amount),
+            confirmations: Some(u.confirmations),
+            ..Default::default()
+        })
+        .collect())
+}
+
+// Raw transaction, checked against the txid it was asked for
+pub fn parse_raw_transaction(body: &str, txid: &str) -> Result<Transaction, RpcError> {
+    let raw: String = parse_result(body)?;
+    checked_transaction(&raw, txid)
+}
+
+// The raw transaction in a gettransaction result, checked against its txid
+pub fn parse_wallet_transaction(body: &str, txid: &str) -> Result<Transaction, RpcError> {
+    let wallet_tx: WalletTransaction = parse_result(body)?;
+    checked_transaction(&wallet_tx.hex, txid)
+}
+
+fn checked_transaction(raw: &str, txid: &str) -> Result<Transaction, RpcError> {
+    let tx = hex::decode(raw)
+        .ok()
+        .and_then(|raw| Transaction::parse(&raw))
+        .ok_or(RpcError::InvalidResponse)?;
+    (tx.txid() == txid)
+        .then_some(tx)
+        .ok_or(RpcError::InvalidResponse)
+}
+
+// Txid of a sendrawtransaction
+pub fn parse_txid(body: &str) -> Result<String, RpcError> {
+    parse_result(body)
+}
+
+pub fn parse_smart_fee(body: &str) -> Result<SmartFee, RpcError> {
+    parse_result(body)
+}
+
+pub fn parse_block_template(body: &str) -> Result<BlockTemplate, RpcError> {
+    parse_result(body)
+}
+
+pub fn parse_mempool_accept(body: &str) -> Result<Vec<MempoolAccept>, RpcError> {
+    parse_result(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        address, coinbase,
+        tx::{self, TxIn, TxOut},
+    };
+
+    // Successful response body carrying `result` (JSON)
+    fn reply(result: &str) -> String {
+        format!(r#"{{"result":{},"error":null,"id":1}}"#, result)
+    }
+
+    #[test]
+    fn test_request_bodies() {
+        let body: Value = serde_json::from_str(&list_unspent_request(
+            7,
+            1,
+            9_999_999,
+            &["bc1qexample".to_string()],
+        ))
+        .unwrap();
+        assert_eq!(
+            body,
+            json!({ "jsonrpc": "1.0", "id": 7, "method": "listunspent", "params": [1, 9_999_999, ["bc1qexample"]] })
+        );
+
+        let raw = [2, 0, 0, 0, 1];
+        let body: Value =
+            serde_json::from_str(&send_raw_transaction_request(1, &raw, Some(0.1))).unwrap();
+        assert_eq!(body["params"], json!([hex::encode(raw), 0.1]));
+        let body: Value =
+            serde_json::from_str(&send_raw_transaction_request(1, &raw, None)).unwrap();
+        assert_eq!(body["params"], json!([hex::encode(raw)]));
+
+        let body: Value = serde_json::from_str(&get_block_template_request(2)).unwrap();
+        assert_eq!(body["params"][0]["rules"], json!(["segwit"]));
+    }
+
+    #[test]
+    fn test_parse_unspent_and_transactions() {
+        let tx = Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                txid: "99".repeat(32),
+                vout: 3,
+                script_sig: Vec::new(),
+                sequence: 0xfffffffd,
+                witness: vec![vec![1; 71], vec![2; 33]],
+            }],
+            outputs: vec![TxOut {
+                value: 12_345,
+                script_pubkey: address::p2wpkh_script(&[8; 20]),
+            }],
+            locktime: 0,
+        };
+        let txid = tx.txid();
+
+        let utxos = parse_list_unspent(&reply(&format!(
+            r#"[{{"txid":"{}","vout":0,"address":"x","scriptPubKey":"0014{}","amount":0.00012345,"confirmations":6,"spendable":true,"safe":true}}]"#,
+            txid,
+            "08".repeat(20)
+        )))
+        .unwrap();
+        assert_eq!((utxos[0].amount, utxos[0].confirmations), (12_345, Some(6)));
+        assert_eq!(utxos[0].confirmations_at(850_000), 6);
+
+        let raw = reply(&format!(r#""{}""#, hex::encode(tx.serialize())));
+        assert_eq!(parse_raw_transaction(&raw, &txid), Ok(tx));
+        assert_eq!(
+            parse_raw_transaction(&raw, &"aa".repeat(32)),
+            Err(RpcError::InvalidResponse)
+        );
+        assert_eq!(parse_txid(&reply(&format!(r#""{}""#, txid))), Ok(txid));
+    }
+
+    #[test]
+    fn test_coinbase_unspent_marked_from_wallet_transaction() {
+        let reward = coinbase::build_coinbase(
+            850_000,
+            4,
+            b"",
+            &[TxOut {
+                value: 312_500_000,
+                script_pubkey: address::p2wpkh_script(&[8; 20]),
+            }],
+            None,
+        )
+        .tx;
+        let txid = reward.txid();
+
+        let body: Value = serde_json::from_str(&get_transaction_request(4, &txid)).unwrap();
+        assert_eq!(
+            (&body["method"], &body["params"]),
+            (&json!("gettransaction"), &json!([txid]))
+        );
+
+        let mut utxos = parse_list_unspent(&reply(&format!(
+            r#"[{{"txid":"{}","vout":0,"scriptPubKey":"0014{}","amount":3.125,"confirmations":10}}]"#,
+            txid,
+            "08".repeat(20)
+        )))
+        .unwrap();
+        assert!(!utxos[0].is_coinbase);
+
+        let wallet_tx = reply(&format!(
+            r#"{{"amount":3.125,"confirmations":10,"generated":true,"txid":"{}","hex":"{}"}}"#,
+            txid,
+            hex::encode(reward.serialize())
+        ));
+        let funding = parse_wallet_transaction(&wallet_tx, &txid).unwrap();
+        assert_eq!(
+            parse_wallet_transaction(&wallet_tx, &"aa".repeat(32)),
+            Err(RpcError::InvalidResponse)
+        );
+        tx::mark_coinbase(&mut utxos, &[funding]);
+        assert!(utxos[0].is_coinbase && !utxos[0].is_mature(850_009));
+    }
+
+    #[test]
+    fn test_parse_fees_and_templates() {
+        let fee = parse_smart_fee(&reply(r#"{"feerate":0.00012,"blocks":2}"#)).unwrap();
+        assert_eq!(fee.sat_per_vbyte(), Some(12.0));
+        let fee = parse_smart_fee(&reply(
+            r#"{"errors":["Insufficient data or no feerate found"],"blocks":0}"#,
+        ))
+        .unwrap();
+        assert_eq!(fee.sat_per_vbyte(), None);
+
+        let template = parse_block_template(&reply(&format!(
+            r#"{{"version":536870912,"previousblockhash":"{}","transactions":[],"coinbasevalue":312500000,"bits":"17034219","curtime":1700000000,"height":850000,"mintime":1699990000}}"#,
+            "00".repeat(32)
+        )))
+        .unwrap();
+        assert_eq!(
+            (template.height, template.coinbasevalue),
+            (850_000, 312_500_000)
+        );
+
+        let accepted = parse_mempool_accept(&reply(&format!(
+            r#"[{{"txid":"{}","wtxid":"{}","allowed":true,"vsize":110,"fees":{{"base":0.0000111}}}},{{"txid":"{}","allowed":false,"reject-reason":"min relay fee not met"}}]"#,
+            "99".repeat(32),
+            "9a".repeat(32),
+            "aa".repeat(32)
+        )))
+        .unwrap();
+        assert!(accepted[0].allowed && !accepted[1].allowed);
+        assert_eq!(to_sats(accepted[0].fees.as_ref().unwrap().base), 1_110);
+        assert_eq!(
+            accepted[1].reject_reason.as_deref(),
+            Some("min relay fee not met")
+        );
+    }
+
+    #[test]
+    fn test_rpc_errors() {
+        assert_eq!(
+            parse_txid(
+                r#"{"result":null,"error":{"code":-32601,"message":"Method not found"},"id":1}"#
+            ),
+            Err(RpcError::Rpc(-32601, "Method not found".to_string()))
+        );
+        assert_eq!(parse_txid("<html>"), Err(RpcError::InvalidResponse));
+        assert_eq!(parse_txid(&reply("42")), Err(RpcError::InvalidResponse));
+    }
+}
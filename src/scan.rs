@@ -0,0 +1,287 @@
+use crate::{descriptor::Descriptor, network::Network, tx::UTXO, utils};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+// BIP44 recommends stopping after 20 unused addresses in a row
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+// External (receive) and internal (change) chains below the account key
+const RECEIVE: u32 = 0;
+const CHANGE: u32 = 1;
+
+// --------------------
+// Purposes
+// --------------------
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Purpose {
+    Bip44, // P2PKH
+    Bip49, // P2SH-P2WPKH
+    Bip84, // P2WPKH
+    Bip86, // P2TR key path
+}
+
+impl Purpose {
+    pub fn from_number(purpose: u32) -> Option<Purpose> {
+        match purpose {
+            44 => Some(Purpose::Bip44),
+            49 => Some(Purpose::Bip49),
+            84 => Some(Purpose::Bip84),
+            86 => Some(Purpose::Bip86),
+            _ => None,
+        }
+    }
+
+    // Ranged descriptor for one chain of an account xpub
+    pub fn descriptor(self, account_xpub: &str, chain: u32) -> String {
+        let key = format!("{}/{}/*", account_xpub, chain);
+        match self {
+            Purpose::Bip44 => format!("pkh({})", key),
+            Purpose::Bip49 => format!("sh(wpkh({}))", key),
+            Purpose::Bip84 => format!("wpkh({})", key),
+            Purpose::Bip86 => format!("tr({})", key),
+        }
+    }
+}
+
+// --------------------
+// Scanner
+// --------------------
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AccountAddress {
+    pub change: bool,
+    pub index: u32,
+    pub address: String,
+    pub script_pubkey: String,
+}
+
+#[derive(Default, Serialize)]
+pub struct ScanResult {
+    pub addresses: Vec<AccountAddress>,
+    pub utxos: Vec<UTXO>,
+    pub next_receive_index: u32,
+    pub next_change_index: u32,
+}
+
+struct Chain {
+    descriptor: Descriptor,
+    // Indexes handed out for lookup are 0..requested
+    requested: u32,
+    // UTXOs of used addresses, None for unused ones, by index
+    answers: BTreeMap<u32, Option<Vec<UTXO>>>,
+    next_unused: u32,
+}
+
+// Gap-limit walk of the receive and change chains of an account, driven by
+// the caller: look up each address from `next_lookups` (e.g. its Electrum
+// history) in any order, `record` the answers, and repeat until `is_done`
+pub struct AccountScan {
+    chains: Vec<Chain>,
+    gap_limit: u32,
+    network: Network,
+    // Script of every address handed out, to its chain and index
+    scripts: HashMap<Vec<u8>, (usize, u32)>,
+}
+
+impl AccountScan {
+    pub fn new(
+        account_xpub: &str,
+        purpose: Purpose,
+        gap_limit: u32,
+        network: Network,
+    ) -> Result<AccountScan, String> {
+        if gap_limit == 0 {
+            return Err("gap limit must be at least 1".into());
+        }
+        let chains = [RECEIVE, CHANGE]
+            .into_iter()
+            .map(|chain| {
+                let descriptor =
+                    Descriptor::parse(&purpose.descriptor(account_xpub, chain), network)
+                        .ok_or("invalid account xpub")?;
+                Ok(Chain {
+                    descriptor,
+                    requested: 0,
+                    answers: BTreeMap::new(),
+                    next_unused: 0,
+                })
+            })
+            .collect::<Result<Vec<Chain>, String>>()?;
+
+        Ok(AccountScan {
+            chains,
+            gap_limit,
+            network,
+            scripts: HashMap::new(),
+        })
+    }
+
+    fn address(&self, chain: usize, index: u32) -> AccountAddress {
+        let descriptor = &self.chains[chain].descriptor;
+        AccountAddress {
+            change: chain == CHANGE as usize,
+            index,
+            address: descriptor.address(index, self.network),
+            script_pubkey: utils::bytes_to_hex(&descriptor.script_pubkey(index)),
+        }
+    }
+
+    // Addresses within `gap_limit` of the last used one on each chain that
+    // were not handed out yet
+    pub fn next_lookups(&mut self) -> Vec<AccountAddress> {
+        let mut lookups = Vec::new();
+        for chain in 0..self.chains.len() {
+            let limit = self.chains[chain].next_unused + self.gap_limit;
+            for index in self.chains[chain].requested..limit {
+                let script = self.chains[chain].descriptor.script_pubkey(index);
+                self.scripts.insert(script, (chain, index));
+                lookups.push(self.address(chain, index));
+            }
+            self.chains[chain].requested = self.chains[chain].requested.max(limit);
+        }
+        lookups
+    }
+
+    // Whether a handed-out address was ever used on chain, with its unspent
+    // outputs
+    pub fn record(
+        &mut self,
+        script_pubkey: &[u8],
+        used: bool,
+        utxos: Vec<UTXO>,
+    ) -> Result<(), String> {
+        let &(chain, index) = self
+            .scripts
+            .get(script_pubkey)
+            .ok_or("script is not an address of this scan")?;
+        let chain = &mut self.chains[chain];
+        chain.answers.insert(index, used.then_some(utxos));
+        if used {
+            chain.next_unused = chain.next_unused.max(index + 1);
+        }
+        Ok(())
+    }
+
+    // Every address up to the gap limit on both chains has been answered
+    pub fn is_done(&self) -> bool {
+        self.chains.iter().all(|chain| {
+            chain.requested >= chain.next_unused + self.gap_limit
+                && chain.answers.len() as u32 == chain.requested
+        })
+    }
+
+    // Used addresses (receive first, by index) and their UTXOs so far
+    pub fn result(&self) -> ScanResult {
+        let mut result = ScanResult {
+            next_receive_index: self.chains[RECEIVE as usize].next_unused,
+            next_change_index: self.chains[CHANGE as usize].next_unused,
+            ..Default::default()
+        };
+        for (chain, state) in self.chains.iter().enumerate() {
+            for (&index, utxos) in &state.answers {
+                if let Some(utxos) = utxos {
+                    result.addresses.push(self.address(chain, index));
+                    result.utxos.extend(utxos.iter().cloned());
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hd::ExtendedPrivKey;
+
+    // Answer every lookup from a fixed set of used scripts, newest lookups
+    // first; returns how many lookups were made
+    fn run(scan: &mut AccountScan, used: &HashMap<Vec<u8>, u64>) -> usize {
+        let mut lookups = 0;
+        while !scan.is_done() {
+            let batch = scan.next_lookups();
+            lookups += batch.len();
+            for lookup in batch.iter().rev() {
+                let script = utils::hex_to_bytes(&lookup.script_pubkey);
+                let utxos = used.get(&script).map(|&amount| UTXO {
+                    txid: "ab".repeat(32),
+                    vout: lookup.index,
+                    scriptPubKey: lookup.script_pubkey.clone(),
+                    amount,
+                    ..Default::default()
+                });
+                scan.record(&script, utxos.is_some(), utxos.into_iter().collect())
+                    .unwrap();
+            }
+        }
+        lookups
+    }
+
+    fn account_xpub(purpose: u32) -> String {
+        ExtendedPrivKey::from_seed(&[7; 32])
+            .derive_path(&format!("m/{}'/0'/0'", purpose))
+            .unwrap()
+            .to_public()
+            .to_base58(Network::Mainnet)
+    }
+
+    #[test]
+    fn test_gap_limit_scan() {
+        let net = Network::Mainnet;
+        let xpub = account_xpub(84);
+
+        let receive = Descriptor::parse(&Purpose::Bip84.descriptor(&xpub, 0), net).unwrap();
+        let change = Descriptor::parse(&Purpose::Bip84.descriptor(&xpub, 1), net).unwrap();
+        // Receive 0, 3 and 7 used (gaps shorter than the limit), change 0
+        let used: HashMap<Vec<u8>, u64> = [
+            (receive.script_pubkey(0), 1_000),
+            (receive.script_pubkey(3), 2_000),
+            (receive.script_pubkey(7), 3_000),
+            (change.script_pubkey(0), 500),
+            // Beyond the gap: never reached
+            (receive.script_pubkey(13), 9_999),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut scan = AccountScan::new(&xpub, Purpose::Bip84, 5, net).unwrap();
+        // 8 + 5 receive lookups, 1 + 5 change lookups
+        assert_eq!(run(&mut scan, &used), 19);
+        assert!(scan.next_lookups().is_empty());
+
+        let result = scan.result();
+        let found: Vec<(bool, u32)> = result
+            .addresses
+            .iter()
+            .map(|a| (a.change, a.index))
+            .collect();
+        assert_eq!(found, [(false, 0), (false, 3), (false, 7), (true, 0)]);
+        assert_eq!(result.addresses[0].address, receive.address(0, net));
+        assert_eq!(
+            (result.next_receive_index, result.next_change_index),
+            (8, 1)
+        );
+        assert_eq!(result.utxos.iter().map(|u| u.amount).sum::<u64>(), 6_500);
+    }
+
+    #[test]
+    fn test_unused_account_and_bad_input() {
+        let net = Network::Mainnet;
+        let xpub = account_xpub(86);
+
+        // An unused account stops after the gap on both chains
+        let mut scan = AccountScan::new(&xpub, Purpose::Bip86, 20, net).unwrap();
+        assert_eq!(run(&mut scan, &HashMap::new()), 40);
+        assert!(scan.result().addresses.is_empty());
+
+        assert!(scan.record(&[0x51], true, Vec::new()).is_err());
+        assert_eq!(
+            AccountScan::new(&xpub, Purpose::Bip86, 0, net).err(),
+            Some("gap limit must be at least 1".to_string())
+        );
+        assert!(AccountScan::new("xpub", Purpose::Bip84, 20, net).is_err());
+        assert_eq!(Purpose::from_number(49), Some(Purpose::Bip49));
+        assert_eq!(Purpose::from_number(45), None);
+    }
+}
@@ -0,0 +1,661 @@
+use crate::utils;
+use serde::Serialize;
+
+// --------------------------------------------------
+// Opcodes
+// --------------------------------------------------
+
+macro_rules! opcodes {
+    ($($name:ident = $value:expr),* $(,)?) => {
+        // Every named opcode; direct pushes (0x01-0x4b) and undefined bytes
+        // have no variant
+        #[allow(non_camel_case_types)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[repr(u8)]
+        pub enum Opcode {
+            $($name = $value),*
+        }
+
+        impl Opcode {
+            pub fn from_u8(byte: u8) -> Option<Opcode> {
+                match byte {
+                    $($value => Some(Opcode::$name),)*
+                    _ => None,
+                }
+            }
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Opcode::$name => stringify!($name),)*
+                }
+            }
+
+            // Accepts "OP_DUP" or "DUP", plus the OP_FALSE / OP_TRUE /
+            // OP_NOP2 / OP_NOP3 aliases
+            pub fn from_name(name: &str) -> Option<Opcode> {
+                let name = name.strip_prefix("OP_").unwrap_or(name);
+                match name {
+                    "FALSE" => return Some(Opcode::OP_0),
+                    "TRUE" => return Some(Opcode::OP_1),
+                    "NOP2" => return Some(Opcode::OP_CHECKLOCKTIMEVERIFY),
+                    "NOP3" => return Some(Opcode::OP_CHECKSEQUENCEVERIFY),
+                    _ => {}
+                }
+                $(
+                    if stringify!($name)[3..] == *name {
+                        return Some(Opcode::$name);
+                    }
+                )*
+                None
+            }
+        }
+    };
+}
+
+opcodes! {
+    OP_0 = 0x00,
+    OP_PUSHDATA1 = 0x4c,
+    OP_PUSHDATA2 = 0x4d,
+    OP_PUSHDATA4 = 0x4e,
+    OP_1NEGATE = 0x4f,
+    OP_RESERVED = 0x50,
+    OP_1 = 0x51,
+    OP_2 = 0x52,
+    OP_3 = 0x53,
+    OP_4 = 0x54,
+    OP_5 = 0x55,
+    OP_6 = 0x56,
+    OP_7 = 0x57,
+    OP_8 = 0x58,
+    OP_9 = 0x59,
+    OP_10 = 0x5a,
+    OP_11 = 0x5b,
+    OP_12 = 0x5c,
+    OP_13 = 0x5d,
+    OP_14 = 0x5e,
+    OP_15 = 0x5f,
+    OP_16 = 0x60,
+
+    // Flow control
+    OP_NOP = 0x61,
+    OP_VER = 0x62,
+    OP_IF = 0x63,
+    OP_NOTIF = 0x64,
+    OP_VERIF = 0x65,
+    OP_VERNOTIF = 0x66,
+    OP_ELSE = 0x67,
+    OP_ENDIF = 0x68,
+    OP_VERIFY = 0x69,
+    OP_RETURN = 0x6a,
+
+    // Stack
+    OP_TOALTSTACK = 0x6b,
+    OP_FROMALTSTACK = 0x6c,
+    OP_2DROP = 0x6d,
+    OP_2DUP = 0x6e,
+    OP_3DUP = 0x6f,
+    OP_2OVER = 0x70,
+    OP_2ROT = 0x71,
+    OP_2SWAP = 0x72,
+    OP_IFDUP = 0x73,
+    OP_DEPTH = 0x74,
+    OP_DROP = 0x75,
+    OP_DUP = 0x76,
+    OP_NIP = 0x77,
+    OP_OVER = 0x78,
+    OP_PICK = 0x79,
+    OP_ROLL = 0x7a,
+    OP_ROT = 0x7b,
+    OP_SWAP = 0x7c,
+    OP_TUCK = 0x7d,
+
+    // Splice and bitwise logic
+    OP_CAT = 0x7e,
+    OP_SUBSTR = 0x7f,
+    OP_LEFT = 0x80,
+    OP_RIGHT = 0x81,
+    OP_SIZE = 0x82,
+    OP_INVERT = 0x83,
+    OP_AND = 0x84,
+    OP_OR = 0x85,
+    OP_XOR = 0x86,
+    OP_EQUAL = 0x87,
+    OP_EQUALVERIFY = 0x88,
+    OP_RESERVED1 = 0x89,
+    OP_RESERVED2 = 0x8a,
+
+    // Arithmetic
+    OP_1ADD = 0x8b,
+    OP_1SUB = 0x8c,
+    OP_2MUL = 0x8d,
+    OP_2DIV = 0x8e,
+    OP_NEGATE = 0x8f,
+    OP_ABS = 0x90,
+    OP_NOT = 0x91,
+    OP_0NOTEQUAL = 0x92,
+    OP_ADD = 0x93,
+    OP_SUB = 0x94,
+    OP_MUL = 0x95,
+    OP_DIV = 0x96,
+    OP_MOD = 0x97,
+    OP_LSHIFT = 0x98,
+    OP_RSHIFT = 0x99,
+    OP_BOOLAND = 0x9a,
+    OP_BOOLOR = 0x9b,
+    OP_NUMEQUAL = 0x9c,
+    OP_NUMEQUALVERIFY = 0x9d,
+    OP_NUMNOTEQUAL = 0x9e,
+    OP_LESSTHAN = 0x9f,
+    OP_GREATERTHAN = 0xa0,
+    OP_LESSTHANOREQUAL = 0xa1,
+    OP_GREATERTHANOREQUAL = 0xa2,
+    OP_MIN = 0xa3,
+    OP_MAX = 0xa4,
+    OP_WITHIN = 0xa5,
+
+    // Crypto
+    OP_RIPEMD160 = 0xa6,
+    OP_SHA1 = 0xa7,
+    OP_SHA256 = 0xa8,
+    OP_HASH160 = 0xa9,
+    OP_HASH256 = 0xaa,
+    OP_CODESEPARATOR = 0xab,
+    OP_CHECKSIG = 0xac,
+    OP_CHECKSIGVERIFY = 0xad,
+    OP_CHECKMULTISIG = 0xae,
+    OP_CHECKMULTISIGVERIFY = 0xaf,
+
+    // Expansion
+    OP_NOP1 = 0xb0,
+    OP_CHECKLOCKTIMEVERIFY = 0xb1,
+    OP_CHECKSEQUENCEVERIFY = 0xb2,
+    OP_NOP4 = 0xb3,
+    OP_NOP5 = 0xb4,
+    OP_NOP6 = 0xb5,
+    OP_NOP7 = 0xb6,
+    OP_NOP8 = 0xb7,
+    OP_NOP9 = 0xb8,
+    OP_NOP10 = 0xb9,
+    OP_CHECKSIGADD = 0xba,
+
+    OP_INVALIDOPCODE = 0xff,
+}
+
+// --------------------------------------------------
+// Script numbers
+// --------------------------------------------------
+
+// Little-endian sign-magnitude integer; None when longer than `max_len`
+pub fn decode_num(data: &[u8], max_len: usize) -> Option<i64> {
+    if data.len() > max_len {
+        return None;
+    }
+
+    let Some(&last) = data.last() else {
+        return Some(0);
+    };
+
+    let mut value: i64 = 0;
+    for (i, byte) in data.iter().enumerate() {
+        value |= (*byte as i64) << (8 * i);
+    }
+
+    // The top bit of the last byte is the sign
+    if last & 0x80 != 0 {
+        Some(-(value & !(0x80i64 << (8 * (data.len() - 1)))))
+    } else {
+        Some(value)
+    }
+}
+
+pub fn encode_num(value: i64) -> Vec<u8> {
+    if value == 0 {
+        return Vec::new();
+    }
+
+    let negative = value < 0;
+    let mut abs = value.unsigned_abs();
+    let mut out = Vec::new();
+    while abs > 0 {
+        out.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+
+    if out.last().unwrap() & 0x80 != 0 {
+        out.push(if negative { 0x80 } else { 0x00 });
+    } else if negative {
+        *out.last_mut().unwrap() |= 0x80;
+    }
+    out
+}
+
+// --------------------------------------------------
+// Pushes
+// --------------------------------------------------
+
+// Append `data` with the smallest push opcode (direct push / OP_PUSHDATA1 /
+// OP_PUSHDATA2 / OP_PUSHDATA4), without small-integer substitution
+pub fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    match data.len() {
+        0..=75 => script.push(data.len() as u8),
+        76..=0xff => {
+            script.push(Opcode::OP_PUSHDATA1 as u8);
+            script.push(data.len() as u8);
+        }
+        0x100..=0xffff => {
+            script.push(Opcode::OP_PUSHDATA2 as u8);
+            script.extend((data.len() as u16).to_le_bytes());
+        }
+        len => {
+            script.push(Opcode::OP_PUSHDATA4 as u8);
+            script.extend(u32::try_from(len).expect("push too large").to_le_bytes());
+        }
+    }
+    script.extend(data);
+}
+
+// Opcode a minimal push of `data` must use (BIP62 rule 3 / MINIMALDATA)
+fn minimal_push_opcode(data: &[u8]) -> u8 {
+    match data {
+        [] => Opcode::OP_0 as u8,
+        [n @ 1..=16] => Opcode::OP_1 as u8 + n - 1,
+        [0x81] => Opcode::OP_1NEGATE as u8,
+        _ => match data.len() {
+            1..=75 => data.len() as u8,
+            76..=0xff => Opcode::OP_PUSHDATA1 as u8,
+            0x100..=0xffff => Opcode::OP_PUSHDATA2 as u8,
+            _ => Opcode::OP_PUSHDATA4 as u8,
+        },
+    }
+}
+
+// --------------------------------------------------
+// Instructions
+// --------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction<'a> {
+    // OP_0, a direct push or OP_PUSHDATA1/2/4 with its data
+    Push { opcode: u8, data: &'a [u8] },
+    // Any other opcode, including OP_1NEGATE and OP_1-OP_16
+    Op(u8),
+    // A push running past the end of the script; always the last item
+    Truncated,
+}
+
+impl Instruction<'_> {
+    // Whether a push uses the smallest possible encoding
+    pub fn is_minimal(&self) -> bool {
+        match self {
+            Instruction::Push { opcode, data } => *opcode == minimal_push_opcode(data),
+            Instruction::Op(_) => true,
+            Instruction::Truncated => false,
+        }
+    }
+}
+
+pub struct Instructions<'a> {
+    script: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Instructions<'a> {
+    pub fn new(script: &'a [u8]) -> Instructions<'a> {
+        Instructions { script, pos: 0 }
+    }
+
+    // Offset just past the last instruction returned
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn read_push(&mut self, opcode: u8) -> Option<&'a [u8]> {
+        let mut pos = self.pos;
+        let len = match opcode {
+            0x00..=0x4b => opcode as usize,
+            0x4c => utils::read_bytes(self.script, &mut pos, 1)?[0] as usize,
+            0x4d => u16::from_le_bytes(
+                utils::read_bytes(self.script, &mut pos, 2)?
+                    .try_into()
+                    .ok()?,
+            ) as usize,
+            _ => utils::read_u32_le(self.script, &mut pos)? as usize,
+        };
+        let data = utils::read_bytes(self.script, &mut pos, len)?;
+        self.pos = pos;
+        Some(data)
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Instruction<'a>;
+
+    fn next(&mut self) -> Option<Instruction<'a>> {
+        let opcode = *self.script.get(self.pos)?;
+        self.pos += 1;
+
+        if opcode > Opcode::OP_PUSHDATA4 as u8 {
+            return Some(Instruction::Op(opcode));
+        }
+
+        match self.read_push(opcode) {
+            Some(data) => Some(Instruction::Push { opcode, data }),
+            None => {
+                self.pos = self.script.len();
+                Some(Instruction::Truncated)
+            }
+        }
+    }
+}
+
+// --------------------------------------------------
+// Script
+// --------------------------------------------------
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Script(Vec<u8>);
+
+impl Script {
+    pub fn from_bytes(bytes: Vec<u8>) -> Script {
+        Script(bytes)
+    }
+
+    pub fn builder() -> Builder {
+        Builder(Vec::new())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions::new(&self.0)
+    }
+
+    // Signature operations: CHECKSIG counts 1 and CHECKMULTISIG 20, or, when
+    // `accurate`, the key count pushed just before it (as for P2SH redeem and
+    // witness scripts)
+    pub fn sigop_count(&self, accurate: bool) -> usize {
+        let mut count = 0;
+        let mut last_op = None;
+        for instruction in self.instructions() {
+            let op = match instruction {
+                Instruction::Op(op) => op,
+                Instruction::Push { opcode, .. } => opcode,
+                Instruction::Truncated => break,
+            };
+            count += match Opcode::from_u8(op) {
+                Some(Opcode::OP_CHECKSIG | Opcode::OP_CHECKSIGVERIFY) => 1,
+                Some(Opcode::OP_CHECKMULTISIG | Opcode::OP_CHECKMULTISIGVERIFY) => match last_op {
+                    Some(n) if accurate && (0x51..=0x60).contains(&n) => (n - 0x50) as usize,
+                    _ => 20,
+                },
+                _ => 0,
+            };
+            last_op = Some(op);
+        }
+        count
+    }
+
+    // Only pushes (opcodes up to OP_16), as required of P2SH scriptSigs
+    pub fn is_push_only(&self) -> bool {
+        self.instructions().all(|i| match i {
+            Instruction::Push { .. } => true,
+            Instruction::Op(op) => op <= Opcode::OP_16 as u8,
+            Instruction::Truncated => false,
+        })
+    }
+
+    // Every push uses its smallest encoding and the script parses fully
+    pub fn is_minimal(&self) -> bool {
+        self.instructions().all(|i| i.is_minimal())
+    }
+
+    // Bitcoin Core ASM: numeric pushes as decimal, other pushes as hex,
+    // "[error]" for a truncated push. Pushes that would not read back the
+    // same are written as raw "0x" bytes.
+    pub fn to_asm(&self) -> String {
+        let mut parts = Vec::new();
+        let mut instructions = self.instructions();
+        loop {
+            let start = instructions.pos;
+            let Some(instruction) = instructions.next() else {
+                break;
+            };
+            let part = match instruction {
+                Instruction::Push { data, .. } => asm_push(data, &self.0[start..instructions.pos]),
+                Instruction::Op(op) if op == Opcode::OP_1NEGATE as u8 => "-1".to_string(),
+                Instruction::Op(op @ 0x51..=0x60) => (op - 0x50).to_string(),
+                Instruction::Op(op) => op_name(op).to_string(),
+                Instruction::Truncated => "[error]".to_string(),
+            };
+            parts.push(part);
+        }
+        parts.join(" ")
+    }
+
+    // Inverse of `to_asm`, producing minimal pushes. Also accepts opcode
+    // names without the OP_ prefix and "0x"-prefixed raw bytes.
+    pub fn from_asm(asm: &str) -> Option<Script> {
+        let mut builder = Script::builder();
+        for token in asm.split_whitespace() {
+            builder = if let Some(op) = Opcode::from_name(token) {
+                builder.push_opcode(op)
+            } else if let Some(n) = parse_asm_number(token) {
+                builder.push_int(n)
+            } else if let Some(raw) = token.strip_prefix("0x") {
+                builder.push_raw(&hex::decode(raw).ok()?)
+            } else {
+                builder.push_slice(&hex::decode(token).ok()?)
+            };
+        }
+        Some(builder.into_script())
+    }
+}
+
+// Name of any non-push opcode
+pub fn op_name(op: u8) -> &'static str {
+    Opcode::from_u8(op).map_or("OP_UNKNOWN", Opcode::name)
+}
+
+// A push written so that `from_asm` reads back the same bytes: a number,
+// hex data, or the raw "0x" instruction when neither would round-trip
+// (non-minimal pushes, or hex made only of decimal digits)
+fn asm_push(data: &[u8], raw: &[u8]) -> String {
+    if let Some(n) = decode_num(data, 4) {
+        if Script::builder().push_int(n).into_bytes() == raw {
+            return n.to_string();
+        }
+    }
+
+    let hex = utils::bytes_to_hex(data);
+    if parse_asm_number(&hex).is_none() && Script::builder().push_slice(data).into_bytes() == raw {
+        return hex;
+    }
+    format!("0x{}", utils::bytes_to_hex(raw))
+}
+
+// Canonical decimal within the 4-byte script number range
+fn parse_asm_number(token: &str) -> Option<i64> {
+    let digits = token.strip_prefix('-').unwrap_or(token);
+    if digits.is_empty()
+        || !digits.bytes().all(|b| b.is_ascii_digit())
+        || (digits.len() > 1 && digits.starts_with('0'))
+    {
+        return None;
+    }
+
+    token
+        .parse::<i64>()
+        .ok()
+        .filter(|n| n.unsigned_abs() <= 0x7fffffff)
+}
+
+// --------------------------------------------------
+// Builder
+// --------------------------------------------------
+
+pub struct Builder(Vec<u8>);
+
+impl Builder {
+    pub fn push_opcode(mut self, op: Opcode) -> Builder {
+        self.0.push(op as u8);
+        self
+    }
+
+    // Minimal push: OP_0, OP_1-OP_16 and OP_1NEGATE stand in for their
+    // one-byte values
+    pub fn push_slice(mut self, data: &[u8]) -> Builder {
+        let opcode = minimal_push_opcode(data);
+        if opcode > Opcode::OP_PUSHDATA4 as u8 || opcode == Opcode::OP_0 as u8 {
+            self.0.push(opcode);
+        } else {
+            push_data(&mut self.0, data);
+        }
+        self
+    }
+
+    // Script number, as small-integer opcodes where possible
+    pub fn push_int(mut self, n: i64) -> Builder {
+        match n {
+            0 => self.0.push(Opcode::OP_0 as u8),
+            -1 => self.0.push(Opcode::OP_1NEGATE as u8),
+            1..=16 => self.0.push(Opcode::OP_1 as u8 + n as u8 - 1),
+            _ => push_data(&mut self.0, &encode_num(n)),
+        }
+        self
+    }
+
+    // Bytes appended verbatim
+    pub fn push_raw(mut self, bytes: &[u8]) -> Builder {
+        self.0.extend(bytes);
+        self
+    }
+
+    pub fn into_script(self) -> Script {
+        Script(self.0)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+// --------------------------------------------------
+// Explorer view
+// --------------------------------------------------
+
+#[derive(Serialize)]
+pub struct InstructionInfo {
+    pub opcode: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    pub minimal: bool,
+}
+
+pub fn describe(script: &Script) -> Vec<InstructionInfo> {
+    script
+        .instructions()
+        .map(|instruction| {
+            let (opcode, data) = match instruction {
+                Instruction::Push { opcode, data } => {
+                    let name = match opcode {
+                        0x01..=0x4b => format!("OP_PUSHBYTES_{}", opcode),
+                        _ => op_name(opcode).to_string(),
+                    };
+                    (name, Some(utils::bytes_to_hex(data)))
+                }
+                Instruction::Op(op) => (op_name(op).to_string(), None),
+                Instruction::Truncated => ("[error]".to_string(), None),
+            };
+
+            InstructionInfo {
+                opcode,
+                data,
+                minimal: instruction.is_minimal(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asm_round_trip() {
+        let p2pkh = "76a914".to_string() + &"ab".repeat(20) + "88ac";
+        let script = Script::from_bytes(utils::hex_to_bytes(&p2pkh));
+        let asm = script.to_asm();
+        assert_eq!(
+            asm,
+            format!(
+                "OP_DUP OP_HASH160 {} OP_EQUALVERIFY OP_CHECKSIG",
+                "ab".repeat(20)
+            )
+        );
+        assert_eq!(Script::from_asm(&asm), Some(script));
+
+        // Small integers and short pushes render as numbers
+        let script = Script::from_asm("0 -1 16 1000 CHECKLOCKTIMEVERIFY OP_DROP").unwrap();
+        assert_eq!(utils::bytes_to_hex(script.as_bytes()), "004f6002e803b175");
+        assert_eq!(
+            script.to_asm(),
+            "0 -1 16 1000 OP_CHECKLOCKTIMEVERIFY OP_DROP"
+        );
+
+        assert_eq!(Script::from_asm("OP_DUP zz"), None);
+    }
+
+    #[test]
+    fn test_asm_round_trip_ambiguous_pushes() {
+        // A 5-byte push whose hex reads as a decimal, a non-minimal <05>,
+        // negative zero and a 3-byte push through OP_PUSHDATA1
+        let bytes = utils::hex_to_bytes("05123456789001050180514c03010203");
+        let script = Script::from_bytes(bytes);
+        let asm = script.to_asm();
+        assert_eq!(asm, "0x051234567890 0x0105 0x0180 1 0x4c03010203");
+        assert_eq!(Script::from_asm(&asm), Some(script));
+
+        // Hex that is not a canonical number stays hex
+        let script = Script::builder()
+            .push_slice(&[0x01, 0x23, 0x45, 0x67, 0x89])
+            .into_script();
+        assert_eq!(script.to_asm(), "0123456789");
+        assert_eq!(Script::from_asm(&script.to_asm()), Some(script));
+    }
+
+    #[test]
+    fn test_minimal_pushes() {
+        assert!(Script::builder()
+            .push_slice(&[5])
+            .into_script()
+            .is_minimal());
+        assert_eq!(Script::builder().push_slice(&[5]).into_bytes(), vec![0x55]);
+
+        // <05> as a direct push, and a 3-byte push through OP_PUSHDATA1
+        assert!(!Script::from_bytes(vec![0x01, 0x05]).is_minimal());
+        assert!(!Script::from_bytes(vec![0x4c, 0x03, 1, 2, 3]).is_minimal());
+
+        let truncated = Script::from_bytes(vec![0x76, 0x4c, 0x05, 0x00]);
+        assert_eq!(truncated.to_asm(), "OP_DUP [error]");
+        assert!(!truncated.is_push_only());
+    }
+
+    #[test]
+    fn test_push_data_length_boundaries() {
+        for (len, prefix) in [
+            (75, vec![75]),
+            (76, vec![0x4c, 76]),
+            (0xff, vec![0x4c, 0xff]),
+            (0x100, vec![0x4d, 0x00, 0x01]),
+            (0xffff, vec![0x4d, 0xff, 0xff]),
+            (0x10000, vec![0x4e, 0x00, 0x00, 0x01, 0x00]),
+        ] {
+            let mut script = Vec::new();
+            push_data(&mut script, &vec![7; len]);
+            assert_eq!(script[..prefix.len()], prefix[..]);
+            assert_eq!(script.len(), prefix.len() + len);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use crate::{
+    block::{display_hex, internal_bytes, BlockHeader},
+    crypto, utils,
+};
+
+// Smallest possible transaction, bounding the transactions a block can hold
+const MIN_TX_SIZE: u32 = 60;
+const MAX_BLOCK_SIZE: u32 = 1_000_000;
+
+fn hash_pair(left: &[u8], right: &[u8]) -> Vec<u8> {
+    crypto::double_sha256(&[left, right].concat())
+}
+
+// --------------------
+// Electrum merkle branch
+// --------------------
+
+// Check a blockchain.transaction.get_merkle result: `branch` holds the
+// sibling hashes (display hex) from the leaf up and `pos` is the
+// transaction's index in the block
+pub fn verify_merkle_branch(txid: &str, branch: &[String], pos: u32, header: &BlockHeader) -> bool {
+    // No tree is deeper than the 32 bits of pos, and every bit of pos must
+    // be consumed by the branch
+    if branch.len() > 32 || pos.checked_shr(branch.len() as u32).unwrap_or(0) != 0 {
+        return false;
+    }
+
+    let mut hash = internal_bytes(txid);
+    for (level, sibling) in branch.iter().enumerate() {
+        let sibling = internal_bytes(sibling);
+        if sibling.len() != 32 {
+            return false;
+        }
+        hash = if (pos >> level) & 1 == 1 {
+            hash_pair(&sibling, &hash)
+        } else {
+            hash_pair(&hash, &sibling)
+        };
+    }
+    display_hex(&hash) == header.merkle_root
+}
+
+// --------------------
+// Partial merkle tree (gettxoutproof / merkleblock)
+// --------------------
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleBlock {
+    pub header: BlockHeader,
+    pub total_transactions: u32,
+    pub hashes: Vec<Vec<u8>>, // internal order
+    pub flags: Vec<u8>,
+}
+
+// Walks the tree depth-first as in Core's CPartialMerkleTree
+struct Traversal<'a> {
+    proof: &'a MerkleBlock,
+    bits_used: usize,
+    hashes_used: usize,
+    matches: Vec<Vec<u8>>,
+}
+
+impl Traversal<'_> {
+    fn width(&self, height: u32) -> u32 {
+        let total = self.proof.total_transactions as u64;
+        ((total + (1 << height) - 1) >> height) as u32
+    }
+
+    fn next_bit(&mut self) -> Option<bool> {
+        let byte = self.proof.flags.get(self.bits_used / 8)?;
+        let bit = byte >> (self.bits_used % 8) & 1 == 1;
+        self.bits_used += 1;
+        Some(bit)
+    }
+
+    fn next_hash(&mut self) -> Option<Vec<u8>> {
+        let hash = self.proof.hashes.get(self.hashes_used)?.clone();
+        self.hashes_used += 1;
+        Some(hash)
+    }
+
+    fn traverse(&mut self, height: u32, pos: u32) -> Option<Vec<u8>> {
+        let parent_of_match = self.next_bit()?;
+        if height == 0 || !parent_of_match {
+            let hash = self.next_hash()?;
+            if height == 0 && parent_of_match {
+                self.matches.push(hash.clone());
+            }
+            return Some(hash);
+        }
+
+        let left = self.traverse(height - 1, pos * 2)?;
+        let right = if pos * 2 + 1 < self.width(height - 1) {
+            let right = self.traverse(height - 1, pos * 2 + 1)?;
+            // Identical siblings allow forging the tree (CVE-2012-2459)
+            if right == left {
+                return None;
+            }
+            right
+        } else {
+            left.clone()
+        };
+        Some(hash_pair(&left, &right))
+    }
+}
+
+impl MerkleBlock {
+    pub fn parse(data: &[u8]) -> Option<MerkleBlock> {
+        let mut pos = 0;
+        let header = BlockHeader::parse_from(data, &mut pos)?;
+        let total_transactions = utils::read_u32_le(data, &mut pos)?;
+
+        let count = utils::read_varint(data, &mut pos)?;
+        let mut hashes = Vec::with_capacity(count.min(data.len() / 32));
+        for _ in 0..count {
+            hashes.push(utils::read_bytes(data, &mut pos, 32)?.to_vec());
+        }
+        let flags = utils::read_var_bytes(data, &mut pos)?.to_vec();
+
+        (pos == data.len()).then_some(MerkleBlock {
+            header,
+            total_transactions,
+            hashes,
+            flags,
+        })
+    }
+
+    // Merkle root (display hex) and the matched txids (display hex); None
+    // for malformed trees
+    pub fn extract_matches(&self) -> Option<(String, Vec<String>)> {
+        let total = self.total_transactions;
+        if total == 0 || total > MAX_BLOCK_SIZE / MIN_TX_SIZE {
+            return None;
+        }
+        if self.hashes.len() > total as usize || self.flags.len() * 8 < self.hashes.len() {
+            return None;
+        }
+
+        let mut traversal = Traversal {
+            proof: self,
+            bits_used: 0,
+            hashes_used: 0,
+            matches: Vec::new(),
+        };
+        let mut height = 0;
+        while traversal.width(height) > 1 {
+            height += 1;
+        }
+        let root = traversal.traverse(height, 0)?;
+
+        // Every hash and every flag byte must be used
+        if traversal.hashes_used != self.hashes.len()
+            || traversal.bits_used.div_ceil(8) != self.flags.len()
+        {
+            return None;
+        }
+        let matches = traversal.matches.iter().map(|h| display_hex(h)).collect();
+        Some((display_hex(&root), matches))
+    }
+
+    // Txids proven to be in the block: the tree must be well-formed, commit
+    // to the header's merkle root, and the header must meet its own target
+    pub fn verify(&self) -> Option<Vec<String>> {
+        let (root, matches) = self.extract_matches()?;
+        (root == self.header.merkle_root && self.header.check_pow()).then_some(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_for(txids: &[Vec<u8>]) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 0x2000_0000,
+            prev_blockhash: "00".repeat(32),
+            merkle_root: display_hex(&crypto::merkle_root(txids)),
+            time: 1_700_000_000,
+            bits: 0x207fffff,
+            nonce: 0,
+        };
+        while !header.check_pow() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    // Sibling hashes of leaf `index`, from the leaf up
+    fn branch(txids: &[Vec<u8>], mut index: usize) -> Vec<String> {
+        let mut level = txids.to_vec();
+        let mut branch = Vec::new();
+        while level.len() > 1 {
+            if level.len() % 2 == 1 {
+                level.push(level[level.len() - 1].clone());
+            }
+            branch.push(display_hex(&level[index ^ 1]));
+            level = level.chunks(2).map(|p| hash_pair(&p[0], &p[1])).collect();
+            index /= 2;
+        }
+        branch
+    }
+
+    // Core's CPartialMerkleTree::TraverseAndBuild
+    fn build(
+        txids: &[Vec<u8>],
+        matched: &[bool],
+        height: u32,
+        pos: usize,
+        tree: &mut (Vec<Vec<u8>>, Vec<bool>),
+    ) {
+        let width = |h: u32| (txids.len() + (1 << h) - 1) >> h;
+        let node = |h: u32, p: usize| {
+            let mut level = txids.to_vec();
+            for _ in 0..h {
+                if level.len() % 2 == 1 {
+                    level.push(level[level.len() - 1].clone());
+                }
+                level = level.chunks(2).map(|c| hash_pair(&c[0], &c[1])).collect();
+            }
+            level[p].clone()
+        };
+        let start = pos << height;
+        let end = ((pos + 1) << height).min(txids.len());
+        let parent_of_match = matched[start..end].iter().any(|m| *m);
+        tree.1.push(parent_of_match);
+        if height == 0 || !parent_of_match {
+            tree.0.push(node(height, pos));
+        } else {
+            build(txids, matched, height - 1, pos * 2, tree);
+            if pos * 2 + 1 < width(height - 1) {
+                build(txids, matched, height - 1, pos * 2 + 1, tree);
+            }
+        }
+    }
+
+    fn proof(txids: &[Vec<u8>], matched: &[bool]) -> Vec<u8> {
+        let mut height = 0;
+        while (txids.len() + (1 << height) - 1) >> height > 1 {
+            height += 1;
+        }
+        let mut tree = (Vec::new(), Vec::new());
+        build(txids, matched, height, 0, &mut tree);
+
+        let mut flags = vec![0u8; tree.1.len().div_ceil(8)];
+        for (i, bit) in tree.1.iter().enumerate() {
+            flags[i / 8] |= (*bit as u8) << (i % 8);
+        }
+        let mut data = header_for(txids).serialize();
+        data.extend(utils::u32_le(txids.len() as u32));
+        data.extend(utils::varint(tree.0.len()));
+        for hash in &tree.0 {
+            data.extend(hash);
+        }
+        data.extend(utils::varint(flags.len()));
+        data.extend(flags);
+        data
+    }
+
+    #[test]
+    fn test_electrum_branch() {
+        let txids: Vec<Vec<u8>> = (1..=6u8).map(|i| vec![i; 32]).collect();
+        let header = header_for(&txids);
+
+        for (pos, txid) in txids.iter().enumerate() {
+            let branch = branch(&txids, pos);
+            assert!(verify_merkle_branch(
+                &display_hex(txid),
+                &branch,
+                pos as u32,
+                &header
+            ));
+            // Wrong position or wrong txid
+            assert!(!verify_merkle_branch(
+                &display_hex(txid),
+                &branch,
+                pos as u32 ^ 1,
+                &header
+            ));
+            assert!(!verify_merkle_branch(
+                &"aa".repeat(32),
+                &branch,
+                pos as u32,
+                &header
+            ));
+        }
+        // A position beyond the branch depth is rejected
+        assert!(!verify_merkle_branch(
+            &display_hex(&txids[0]),
+            &branch(&txids, 0),
+            8,
+            &header
+        ));
+    }
+
+    #[test]
+    fn test_oversized_branch() {
+        let txids: Vec<Vec<u8>> = (1..=2u8).map(|i| vec![i; 32]).collect();
+        let header = header_for(&txids);
+
+        // Deeper than any tree with 32-bit positions: rejected, not a shift
+        // overflow
+        let branch = vec!["00".repeat(32); 33];
+        assert!(!verify_merkle_branch(
+            &display_hex(&txids[0]),
+            &branch,
+            0,
+            &header
+        ));
+        assert!(!verify_merkle_branch(
+            &display_hex(&txids[0]),
+            &branch,
+            u32::MAX,
+            &header
+        ));
+    }
+
+    #[test]
+    fn test_partial_merkle_tree() {
+        let txids: Vec<Vec<u8>> = (1..=7u8).map(|i| vec![i; 32]).collect();
+        let matched = [false, true, false, false, false, false, true];
+
+        let raw = proof(&txids, &matched);
+        let merkle_block = MerkleBlock::parse(&raw).unwrap();
+        assert_eq!(
+            merkle_block.verify().unwrap(),
+            vec![display_hex(&txids[1]), display_hex(&txids[6])]
+        );
+
+        // A single-transaction block proves its only txid
+        let single = MerkleBlock::parse(&proof(&txids[..1], &[true])).unwrap();
+        assert_eq!(single.verify().unwrap(), vec![display_hex(&txids[0])]);
+
+        // Tampered hash, surplus flag byte or wrong transaction count
+        let mut bad = merkle_block.clone();
+        bad.hashes[0][0] ^= 1;
+        assert_eq!(bad.verify(), None);
+        let mut bad = merkle_block.clone();
+        bad.flags.push(0);
+        assert_eq!(bad.extract_matches(), None);
+        let mut bad = merkle_block.clone();
+        bad.total_transactions = 8;
+        assert_eq!(bad.verify(), None);
+        assert_eq!(MerkleBlock::parse(&raw[..raw.len() - 1]), None);
+    }
+}
@@ -0,0 +1,492 @@
+use crate::{
+    block::{self, Block, BlockHeader},
+    coinbase, crypto,
+    tx::{Transaction, TxOut},
+    utils,
+};
+use serde::{Deserialize, Serialize};
+
+// BIP310 version-rolling mask miners may change
+const VERSION_ROLLING_MASK: u32 = 0x1fff_e000;
+
+// How far past the template time a share's ntime may run
+const MAX_NTIME_DRIFT: u32 = 7_200;
+
+// Difficulty 1 target (nBits 0x1d00ffff)
+const DIFF1_BITS: u32 = 0x1d00_ffff;
+
+// --------------------
+// getblocktemplate
+// --------------------
+
+#[derive(Serialize, Deserialize)]
+pub struct TemplateTx {
+    pub data: String,
+    pub txid: String,
+    // wtxid; equal to txid for transactions without witness
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+// The getblocktemplate fields a job needs
+#[derive(Serialize, Deserialize)]
+pub struct BlockTemplate {
+    pub version: u32,
+    pub previousblockhash: String,
+    pub transactions: Vec<TemplateTx>,
+    pub coinbasevalue: u64,
+    pub bits: String,
+    pub curtime: u32,
+    pub height: u32,
+}
+
+// --------------------
+// Encodings
+// --------------------
+
+// Stratum's prevhash: the internal-order hash with every 4-byte word reversed
+fn stratum_prevhash(display_hex: &str) -> String {
+    let mut hash = utils::hex_to_bytes(display_hex);
+    hash.reverse();
+    for word in hash.chunks_mut(4) {
+        word.reverse();
+    }
+    utils::bytes_to_hex(&hash)
+}
+
+fn display_prevhash(stratum_hex: &str) -> String {
+    let mut hash = utils::hex_to_bytes(stratum_hex);
+    for word in hash.chunks_mut(4) {
+        word.reverse();
+    }
+    hash.reverse();
+    utils::bytes_to_hex(&hash)
+}
+
+fn hex_u32(s: &str) -> Option<u32> {
+    (s.len() == 8).then_some(())?;
+    u32::from_str_radix(s, 16).ok()
+}
+
+// Merkle branch (internal order) linking a block's first transaction to the
+// root: the sibling at each level of the tree
+pub fn merkle_branch(txids: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    // Index 0 stands in for the coinbase, whose hash is not known yet
+    let mut level: Vec<Vec<u8>> = std::iter::once(Vec::new())
+        .chain(txids.iter().cloned())
+        .collect();
+    let mut branch = Vec::new();
+    while level.len() > 1 {
+        branch.push(level[1].clone());
+        if level.len() % 2 == 1 {
+            level.push(level[level.len() - 1].clone());
+        }
+        let mut next = vec![Vec::new()];
+        for pair in level[2..].chunks(2) {
+            next.push(crypto::double_sha256(
+                &[pair[0].as_slice(), &pair[1]].concat(),
+            ));
+        }
+        level = next;
+    }
+    branch
+}
+
+// Merkle root from the coinbase txid (internal order) and its branch
+pub fn merkle_root_from_branch(coinbase_txid: &[u8], branch: &[Vec<u8>]) -> Vec<u8> {
+    branch.iter().fold(coinbase_txid.to_vec(), |hash, sibling| {
+        crypto::double_sha256(&[hash.as_slice(), sibling].concat())
+    })
+}
+
+// --------------------
+// mining.notify
+// --------------------
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub job_id: String,
+    pub prevhash: String,
+    pub coinb1: String,
+    pub coinb2: String,
+    pub merkle_branch: Vec<String>,
+    pub version: String,
+    pub nbits: String,
+    pub ntime: String,
+    pub clean_jobs: bool,
+    // Kept to validate shares and assemble the block
+    pub extranonce1_size: usize,
+    pub extranonce2_size: usize,
+    pub transactions: Vec<String>,
+}
+
+// Build a job from a block template. `payouts` may not exceed the template's
+// coinbase value; the coinbase always carries a witness commitment.
+pub fn build_job(
+    template: &BlockTemplate,
+    job_id: &str,
+    payouts: &[TxOut],
+    extranonce1_size: usize,
+    extranonce2_size: usize,
+    tag: &[u8],
+    clean_jobs: bool,
+) -> Job {
+    let paid: u64 = payouts.iter().map(|p| p.value).sum();
+    assert!(
+        paid <= template.coinbasevalue,
+        "payouts exceed the coinbase value"
+    );
+
+    let wtxids: Vec<String> = template
+        .transactions
+        .iter()
+        .map(|t| t.hash.clone().unwrap_or_else(|| t.txid.clone()))
+        .collect();
+    let commitment =
+        coinbase::witness_commitment(&coinbase::witness_merkle_root(&wtxids), &[0; 32]);
+
+    let cb = coinbase::build_coinbase(
+        template.height,
+        extranonce1_size + extranonce2_size,
+        tag,
+        payouts,
+        Some(&commitment),
+    );
+
+    let txids: Vec<Vec<u8>> = template
+        .transactions
+        .iter()
+        .map(|t| {
+            let mut txid = utils::hex_to_bytes(&t.txid);
+            txid.reverse();
+            txid
+        })
+        .collect();
+
+    Job {
+        job_id: job_id.to_string(),
+        prevhash: stratum_prevhash(&template.previousblockhash),
+        coinb1: utils::bytes_to_hex(&cb.coinb1),
+        coinb2: utils::bytes_to_hex(&cb.coinb2),
+        merkle_branch: merkle_branch(&txids)
+            .iter()
+            .map(|h| utils::bytes_to_hex(h))
+            .collect(),
+        version: format!("{:08x}", template.version),
+        nbits: template.bits.clone(),
+        ntime: format!("{:08x}", template.curtime),
+        clean_jobs,
+        extranonce1_size,
+        extranonce2_size,
+        transactions: template
+            .transactions
+            .iter()
+            .map(|t| t.data.clone())
+            .collect(),
+    }
+}
+
+impl Job {
+    // mining.notify params
+    pub fn notify_params(&self) -> serde_json::Value {
+        serde_json::json!([
+            self.job_id,
+            self.prevhash,
+            self.coinb1,
+            self.coinb2,
+            self.merkle_branch,
+            self.version,
+            self.nbits,
+            self.ntime,
+            self.clean_jobs,
+        ])
+    }
+}
+
+// --------------------
+// mining.submit
+// --------------------
+
+// mining.submit params: worker, job_id, extranonce2, ntime, nonce and the
+// optional BIP310 version bits
+pub struct Submit {
+    pub job_id: String,
+    pub extranonce2: Vec<u8>,
+    pub ntime: u32,
+    pub nonce: u32,
+    pub version_bits: Option<u32>,
+}
+
+impl Submit {
+    pub fn from_params(params: &[String]) -> Option<Submit> {
+        if !(5..=6).contains(&params.len()) {
+            return None;
+        }
+        Some(Submit {
+            job_id: params[1].clone(),
+            extranonce2: hex::decode(&params[2]).ok()?,
+            ntime: hex_u32(&params[3])?,
+            nonce: hex_u32(&params[4])?,
+            version_bits: match params.get(5) {
+                Some(bits) => Some(hex_u32(bits)?),
+                None => None,
+            },
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShareError {
+    JobMismatch,
+    Extranonce1Size,
+    Extranonce2Size,
+    NtimeOutOfRange,
+    VersionBits,
+    LowDifficulty,
+}
+
+impl ShareError {
+    pub fn message(self) -> &'static str {
+        match self {
+            ShareError::JobMismatch => "share is for a different job",
+            ShareError::Extranonce1Size => "extranonce1 has the wrong size",
+            ShareError::Extranonce2Size => "extranonce2 has the wrong size",
+            ShareError::NtimeOutOfRange => "ntime out of range",
+            ShareError::VersionBits => "version bits outside the rolling mask",
+            ShareError::LowDifficulty => "share difficulty too low",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Share {
+    pub hash: String,
+    pub difficulty: f64,
+    // Meets the network target; `block` is the raw block to submit
+    pub is_block: bool,
+    pub block: Option<String>,
+}
+
+// Big-endian 256-bit number as a float, for difficulty ratios
+fn to_f64(be: &[u8]) -> f64 {
+    be.iter().fold(0.0, |acc, b| acc * 256.0 + *b as f64)
+}
+
+// Rebuild the header a miner hashed and check it against the pool's share
+// difficulty and the network target
+pub fn validate_share(
+    job: &Job,
+    extranonce1: &[u8],
+    submit: &Submit,
+    share_difficulty: f64,
+) -> Result<Share, ShareError> {
+    if submit.job_id != job.job_id {
+        return Err(ShareError::JobMismatch);
+    }
+    if extranonce1.len() != job.extranonce1_size {
+        return Err(ShareError::Extranonce1Size);
+    }
+    if submit.extranonce2.len() != job.extranonce2_size {
+        return Err(ShareError::Extranonce2Size);
+    }
+
+    let job_time = hex_u32(&job.ntime).expect("job ntime");
+    if submit.ntime < job_time || submit.ntime > job_time + MAX_NTIME_DRIFT {
+        return Err(ShareError::NtimeOutOfRange);
+    }
+
+    let mut version = hex_u32(&job.version).expect("job version");
+    if let Some(bits) = submit.version_bits {
+        if bits & !VERSION_ROLLING_MASK != 0 {
+            return Err(ShareError::VersionBits);
+        }
+        version = (version & !VERSION_ROLLING_MASK) | bits;
+    }
+
+    let raw_coinbase = [
+        utils::hex_to_bytes(&job.coinb1),
+        extranonce1.to_vec(),
+        submit.extranonce2.clone(),
+        utils::hex_to_bytes(&job.coinb2),
+    ]
+    .concat();
+    let coinbase_txid = crypto::double_sha256(&raw_coinbase);
+    let branch: Vec<Vec<u8>> = job
+        .merkle_branch
+        .iter()
+        .map(|h| utils::hex_to_bytes(h))
+        .collect();
+    let mut merkle_root = merkle_root_from_branch(&coinbase_txid, &branch);
+    merkle_root.reverse();
+
+    let header = BlockHeader {
+        version,
+        prev_blockhash: display_prevhash(&job.prevhash),
+        merkle_root: utils::bytes_to_hex(&merkle_root),
+        time: submit.ntime,
+        bits: hex_u32(&job.nbits).expect("job nbits"),
+        nonce: submit.nonce,
+    };
+
+    let hash = header.block_hash();
+    let diff1 = block::bits_to_target(DIFF1_BITS).expect("difficulty 1 target");
+    let difficulty = to_f64(&diff1) / to_f64(&utils::hex_to_bytes(&hash));
+    if difficulty < share_difficulty {
+        return Err(ShareError::LowDifficulty);
+    }
+
+    let is_block = header.check_pow();
+    let block = is_block.then(|| {
+        let mut coinbase = Transaction::parse(&raw_coinbase).expect("coinbase reassembles");
+        coinbase.inputs[0].witness = vec![vec![0; 32]]; // witness reserved value
+
+        let mut txdata = vec![coinbase];
+        for raw in &job.transactions {
+            txdata.push(Transaction::parse(&utils::hex_to_bytes(raw)).expect("template tx"));
+        }
+        utils::bytes_to_hex(&Block { header, txdata }.serialize())
+    });
+
+    Ok(Share {
+        hash,
+        difficulty,
+        is_block,
+        block,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{address, tx::TxIn};
+
+    fn template_tx(seed: u8, segwit: bool) -> Transaction {
+        Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                txid: hex::encode([seed; 32]),
+                vout: 0,
+                script_sig: if segwit { Vec::new() } else { vec![0x51] },
+                sequence: 0xffffffff,
+                witness: if segwit {
+                    vec![vec![seed; 71], vec![2; 33]]
+                } else {
+                    Vec::new()
+                },
+            }],
+            outputs: vec![TxOut {
+                value: 10_000,
+                script_pubkey: address::p2wpkh_script(&[seed; 20]),
+            }],
+            locktime: 0,
+        }
+    }
+
+    #[test]
+    fn test_merkle_branch_matches_root() {
+        for n in 0..7u8 {
+            let txids: Vec<Vec<u8>> = (1..=n).map(|i| vec![i; 32]).collect();
+            let coinbase = vec![0xcb; 32];
+            let all: Vec<Vec<u8>> = std::iter::once(coinbase.clone())
+                .chain(txids.clone())
+                .collect();
+
+            let branch = merkle_branch(&txids);
+            assert_eq!(
+                merkle_root_from_branch(&coinbase, &branch),
+                crypto::merkle_root(&all)
+            );
+        }
+
+        // Internal order with each 4-byte word reversed
+        let display = format!("{}{}", "00".repeat(28), "01020304");
+        assert_eq!(
+            stratum_prevhash(&display),
+            format!("01020304{}", "00".repeat(28))
+        );
+        assert_eq!(display_prevhash(&stratum_prevhash(&display)), display);
+    }
+
+    #[test]
+    fn test_job_and_shares() {
+        let txs = [template_tx(1, true), template_tx(2, false)];
+        let transactions: Vec<serde_json::Value> = txs
+            .iter()
+            .map(|tx| {
+                serde_json::json!({
+                    "data": hex::encode(tx.serialize()),
+                    "txid": tx.txid(),
+                    "hash": tx.wtxid(),
+                })
+            })
+            .collect();
+        let gbt = serde_json::json!({
+            "version": 0x2000_0000,
+            "previousblockhash": "00000000000000000001a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d5e6f7",
+            "transactions": transactions,
+            "coinbasevalue": 312_500_000,
+            "bits": "207fffff",
+            "curtime": 1_700_000_000,
+            "height": 850_000,
+        });
+        let template: BlockTemplate = serde_json::from_value(gbt).unwrap();
+
+        let payouts = [TxOut {
+            value: 312_500_000,
+            script_pubkey: address::p2wpkh_script(&[9; 20]),
+        }];
+        let job = build_job(&template, "1f", &payouts, 4, 4, b"/pool/", true);
+        let params = job.notify_params();
+        assert_eq!(params[0], "1f");
+        assert_eq!(params[5], "20000000");
+        assert_eq!(params[4].as_array().unwrap().len(), 2);
+
+        let extranonce1 = [0xaa; 4];
+        let submit = |nonce: u32, version_bits: Option<&str>| {
+            let mut params: Vec<String> = ["worker.1", "1f", "00000001", "6553f100"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            params.push(format!("{:08x}", nonce));
+            params.extend(version_bits.map(String::from));
+            Submit::from_params(&params).unwrap()
+        };
+
+        // The regtest target accepts roughly every other hash
+        let (share, nonce) = (0..64)
+            .find_map(|nonce| {
+                let share = validate_share(&job, &extranonce1, &submit(nonce, None), 1e-12).ok()?;
+                share.is_block.then_some((share, nonce))
+            })
+            .unwrap();
+
+        let block = Block::parse(&utils::hex_to_bytes(share.block.as_ref().unwrap())).unwrap();
+        assert_eq!(block.header.block_hash(), share.hash);
+        assert!(block.header.check_pow());
+        assert!(block.check_merkle_root() && block.check_witness_commitment());
+        assert_eq!(block.txdata[1..], txs);
+        assert_eq!(block.header.prev_blockhash, template.previousblockhash);
+
+        // Pool-side rejections
+        let err = |s: &Submit, diff| validate_share(&job, &extranonce1, s, diff).unwrap_err();
+        assert_eq!(err(&submit(nonce, None), 1e6), ShareError::LowDifficulty);
+        assert_eq!(
+            err(&submit(nonce, Some("00000001")), 1e-12),
+            ShareError::VersionBits
+        );
+        let mut late = submit(nonce, None);
+        late.ntime += MAX_NTIME_DRIFT + 1;
+        assert_eq!(err(&late, 1e-12), ShareError::NtimeOutOfRange);
+        let mut short = submit(nonce, None);
+        short.extranonce2.pop();
+        assert_eq!(err(&short, 1e-12), ShareError::Extranonce2Size);
+        assert_eq!(
+            validate_share(&job, &extranonce1[1..], &submit(nonce, None), 1e-12).unwrap_err(),
+            ShareError::Extranonce1Size
+        );
+
+        // Rolled version bits change the header
+        let rolled = validate_share(&job, &extranonce1, &submit(nonce, Some("00002000")), 1e-12);
+        assert_ne!(rolled.unwrap().hash, share.hash);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{
+    address, crypto,
+    keys::{self, Keyring},
+    network::Network,
+    tx::{self, Recipient, TxResult, UTXO},
+};
+use serde::Serialize;
+
+// --------------------------------------------------
+// Address forms of an imported key
+// --------------------------------------------------
+
+// Every address a paper-wallet key may have received funds on
+#[derive(Serialize)]
+pub struct SweepAddresses {
+    pub p2pkh_uncompressed: String,
+    pub p2pkh: String,
+    pub p2wpkh: String,
+    pub p2sh_p2wpkh: String,
+}
+
+pub fn sweep_addresses(wif: &str, network: Network) -> SweepAddresses {
+    let (secret, _) = keys::decode_wif(wif, network);
+    let pubkey = keys::privkey_to_pubkey(&secret);
+
+    SweepAddresses {
+        p2pkh_uncompressed: address::pubkey_hash_to_address(
+            &crypto::hash160(&pubkey.serialize_uncompressed()),
+            network,
+        ),
+        p2pkh: address::pubkey_to_address(&pubkey, network),
+        p2wpkh: address::pubkey_to_bech32(&pubkey, network.bech32_hrp()),
+        p2sh_p2wpkh: address::pubkey_to_p2sh_p2wpkh(&pubkey, network),
+    }
+}
+
+// --------------------------------------------------
+// Sweep
+// --------------------------------------------------
+
+// Spend every UTXO of `wif` (any address form) to `to_address` at
+// `sat_per_vbyte`, with no change output
+pub fn sweep(
+    utxos: &[UTXO],
+    wif: &str,
+    to_address: &str,
+    sat_per_vbyte: u64,
+    network: Network,
+) -> TxResult {
+    assert!(!utxos.is_empty(), "nothing to sweep");
+
+    // The same secret controls both the compressed and uncompressed forms
+    let (secret, _) = keys::decode_wif(wif, network);
+    let mut keyring = Keyring::default();
+    keyring.add_key(secret, true);
+    keyring.add_key(secret, false);
+
+    let recipient = Recipient {
+        address: to_address.to_string(),
+        amount: 0,
+        max: true,
+        subtract_fee: false,
+    };
+    tx::create_payment(utxos, &[recipient], sat_per_vbyte, &keyring, network, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tx::Transaction, utils};
+    use secp256k1::SecretKey;
+
+    #[test]
+    fn test_sweeps_all_address_forms() {
+        let network = Network::Testnet;
+        let secret = SecretKey::from_slice(&[7; 32]).unwrap();
+
+        // Uncompressed WIF: prefix + key + checksum
+        let mut payload = vec![network.wif_prefix()];
+        payload.extend(secret.secret_bytes());
+        payload.extend(crypto::checksum(&payload));
+        let wif = bs58::encode(payload).into_string();
+
+        let forms = sweep_addresses(&wif, network);
+        // The keyring keeps the uncompressed encoding the WIF is flagged with
+        let key = Keyring::from_wif(&wif, network);
+        let uncompressed_hash =
+            crypto::hash160(&keys::privkey_to_pubkey(&secret).serialize_uncompressed());
+        assert!(key.find_by_hash160(&uncompressed_hash).is_some());
+        assert_eq!(
+            address::pubkey_hash_to_address(&uncompressed_hash, network),
+            forms.p2pkh_uncompressed
+        );
+        let utxos: Vec<UTXO> = [
+            &forms.p2pkh_uncompressed,
+            &forms.p2pkh,
+            &forms.p2wpkh,
+            &forms.p2sh_p2wpkh,
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, addr)| UTXO {
+            txid: hex::encode([i as u8 + 1; 32]),
+            vout: 0,
+            scriptPubKey: hex::encode(address::address_to_scriptpubkey(addr, network)),
+            amount: 25_000,
+            ..Default::default()
+        })
+        .collect();
+
+        let result = sweep(&utxos, &wif, &forms.p2wpkh, 5, network);
+        let tx = Transaction::parse(&utils::hex_to_bytes(&result.raw_tx)).unwrap();
+
+        assert_eq!(tx.outputs.len(), 1);
+        assert_eq!(tx.outputs[0].value + result.effective_fee, 100_000);
+        assert!(result.effective_fee >= result.vbytes * 5);
+        assert!(tx.inputs[3].script_sig.len() == 23 && tx.inputs[3].witness.len() == 2);
+    }
+}
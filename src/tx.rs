@@ -1,11 +1,19 @@
-use crate::{address, crypto, keys, network::Network, utils};
+use crate::{
+    address, crypto,
+    keys::{Keyring, SigningKey},
+    network::Network,
+    policy,
+    psbt::Psbt,
+    script, utils,
+};
+use rand::rngs::OsRng;
 use secp256k1::{Message, Secp256k1};
-use serde::Deserialize; // for div_ceil
+use serde::{Deserialize, Serialize}; // for div_ceil
 
 // --------------------
 // UTXO struct
 // --------------------
-#[derive(Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 #[allow(clippy::upper_case_acronyms)]
 #[allow(non_snake_case)]
 pub struct UTXO {
@@ -13,28 +21,322 @@ pub struct UTXO {
     pub vout: u32,
     pub scriptPubKey: String,
     pub amount: u64,
+    // Sighash flags for this input (ALL, or DEFAULT for P2TR, when absent)
+    #[serde(default)]
+    pub sighash: Option<u32>,
+    // Confirming block height; absent or 0 while in the mempool
+    #[serde(default)]
+    pub height: Option<u32>,
+    // Confirmation count, used when no height is known
+    #[serde(default)]
+    pub confirmations: Option<u32>,
+    #[serde(default)]
+    pub is_coinbase: bool,
+}
+
+// Blocks a coinbase output must be buried under before it can be spent
+pub const COINBASE_MATURITY: u32 = 100;
+
+impl UTXO {
+    // Confirmations with the chain tip at `tip_height`; 0 while unconfirmed
+    pub fn confirmations_at(&self, tip_height: u32) -> u32 {
+        match self.height {
+            Some(height) if height > 0 && height <= tip_height => tip_height - height + 1,
+            Some(_) => 0,
+            None => self.confirmations.unwrap_or(0),
+        }
+    }
+
+    // Coinbase outputs are immature until COINBASE_MATURITY confirmations
+    pub fn is_mature(&self, tip_height: u32) -> bool {
+        !self.is_coinbase || self.confirmations_at(tip_height) >= COINBASE_MATURITY
+    }
+
+    pub fn is_spendable(&self, tip_height: u32, min_confirmations: u32) -> bool {
+        self.is_mature(tip_height) && self.confirmations_at(tip_height) >= min_confirmations
+    }
+}
+
+// UTXOs that can be spent at `tip_height`: mature and with at least
+// `min_confirmations` confirmations
+pub fn spendable_utxos(utxos: &[UTXO], tip_height: u32, min_confirmations: u32) -> Vec<UTXO> {
+    utxos
+        .iter()
+        .filter(|u| u.is_spendable(tip_height, min_confirmations))
+        .cloned()
+        .collect()
+}
+
+// Flag UTXOs created by a coinbase among `funding_txs`. Backends that list
+// outputs without saying where they came from (Electrum listunspent, Core's
+// listunspent) leave `is_coinbase` unset; looking up each UTXO's funding
+// transaction and passing it here lets the maturity rule apply.
+pub fn mark_coinbase(utxos: &mut [UTXO], funding_txs: &[Transaction]) {
+    for utxo in utxos {
+        if funding_txs
+            .iter()
+            .any(|tx| tx.is_coinbase() && tx.txid() == utxo.txid)
+        {
+            utxo.is_coinbase = true;
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Balance {
+    pub spendable: u64,
+    pub immature: u64,    // coinbase outputs awaiting maturity
+    pub unconfirmed: u64, // in the mempool
+}
+
+// Split the value of `utxos` by what can be spent at `tip_height`
+pub fn balance(utxos: &[UTXO], tip_height: u32) -> Balance {
+    let mut balance = Balance::default();
+    for utxo in utxos {
+        if !utxo.is_mature(tip_height) {
+            balance.immature += utxo.amount;
+        } else if utxo.confirmations_at(tip_height) == 0 {
+            balance.unconfirmed += utxo.amount;
+        } else {
+            balance.spendable += utxo.amount;
+        }
+    }
+    balance
 }
 
 // --------------------
 // Input type detection
 // --------------------
 #[derive(Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
 enum InputType {
     P2PKH,
+    P2SH_P2WPKH, // wrapped segwit
     P2WPKH,
+    P2TR, // key path only
 }
 
-fn detect_input_type(script: &[u8]) -> InputType {
+fn detect_input_type(script: &[u8]) -> Option<InputType> {
     match script {
-        [0x00, 0x14, ..] => InputType::P2WPKH,
-        [0x76, 0xa9, 0x14, .., 0x88, 0xac] => InputType::P2PKH,
-        _ => InputType::P2PKH,
+        [0x00, 0x14, ..] if script.len() == 22 => Some(InputType::P2WPKH),
+        [0x51, 0x20, ..] if script.len() == 34 => Some(InputType::P2TR),
+        [0xa9, 0x14, .., 0x87] if script.len() == 23 => Some(InputType::P2SH_P2WPKH),
+        [0x76, 0xa9, 0x14, .., 0x88, 0xac] if script.len() == 25 => Some(InputType::P2PKH),
+        _ => None,
+    }
+}
+
+// Keyring key an input script pays to. SegWit forms require a compressed key;
+// P2SH is assumed to wrap P2WPKH and checked against each key's redeem script.
+fn find_input_key<'a>(
+    keyring: &'a Keyring,
+    input_type: InputType,
+    script: &[u8],
+) -> Option<&'a SigningKey> {
+    match input_type {
+        InputType::P2PKH => keyring.find_by_hash160(&script[3..23]),
+        InputType::P2WPKH => keyring.find(|k| k.compressed && k.hash160() == script[2..22]),
+        InputType::P2SH_P2WPKH => keyring.find(|k| {
+            k.compressed && crypto::hash160(&address::p2wpkh_script(&k.hash160())) == script[2..22]
+        }),
+        InputType::P2TR => keyring.find(|k| k.taproot_output_key().serialize() == script[2..34]),
+    }
+}
+
+// --------------------
+// Transaction model
+// --------------------
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxIn {
+    pub txid: String, // display (big-endian) hex, as in UTXO
+    pub vout: u32,
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+    pub witness: Vec<Vec<u8>>,
+}
+
+impl TxIn {
+    // Unsigned, final-sequence input spending `utxo`
+    pub fn spending(utxo: &UTXO) -> TxIn {
+        TxIn {
+            txid: utxo.txid.clone(),
+            vout: utxo.vout,
+            script_sig: Vec::new(),
+            sequence: 0xffffffff,
+            witness: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxOut {
+    pub value: u64,
+    pub script_pubkey: Vec<u8>,
+}
+
+impl TxOut {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(9 + self.script_pubkey.len());
+        out.extend(utils::u64_le(self.value));
+        out.extend(utils::varint(self.script_pubkey.len()));
+        out.extend(&self.script_pubkey);
+        out
+    }
+
+    pub fn parse(data: &[u8], pos: &mut usize) -> Option<TxOut> {
+        let value = utils::read_u64_le(data, pos)?;
+        let script_pubkey = utils::read_var_bytes(data, pos)?.to_vec();
+        Some(TxOut {
+            value,
+            script_pubkey,
+        })
     }
 }
 
-// Dust threshold
-fn dust_threshold() -> u64 {
-    546
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transaction {
+    pub version: u32,
+    pub inputs: Vec<TxIn>,
+    pub outputs: Vec<TxOut>,
+    pub locktime: u32,
+}
+
+impl Transaction {
+    pub fn is_coinbase(&self) -> bool {
+        matches!(self.inputs.as_slice(), [input] if input.vout == 0xffffffff && input.txid == "00".repeat(32))
+    }
+
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|i| !i.witness.is_empty())
+    }
+
+    // Network serialization (BIP-144 when any input carries a witness)
+    pub fn serialize(&self) -> Vec<u8> {
+        self.encode(self.has_witness())
+    }
+
+    // Serialization used for the txid
+    pub fn serialize_no_witness(&self) -> Vec<u8> {
+        self.encode(false)
+    }
+
+    fn encode(&self, with_witness: bool) -> Vec<u8> {
+        let mut tx = Vec::new();
+        tx.extend(utils::u32_le(self.version));
+
+        if with_witness {
+            tx.extend([0x00, 0x01]);
+        }
+
+        tx.extend(utils::varint(self.inputs.len()));
+        for input in &self.inputs {
+            tx.extend(utils::hex_to_bytes(&input.txid).into_iter().rev());
+            tx.extend(utils::u32_le(input.vout));
+            tx.extend(utils::varint(input.script_sig.len()));
+            tx.extend(&input.script_sig);
+            tx.extend(utils::u32_le(input.sequence));
+        }
+
+        tx.extend(utils::varint(self.outputs.len()));
+        for output in &self.outputs {
+            tx.extend(output.serialize());
+        }
+
+        if with_witness {
+            for input in &self.inputs {
+                tx.extend(utils::varint(input.witness.len()));
+                for item in &input.witness {
+                    tx.extend(utils::varint(item.len()));
+                    tx.extend(item);
+                }
+            }
+        }
+
+        tx.extend(utils::u32_le(self.locktime));
+        tx
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Transaction> {
+        let mut pos = 0;
+        let tx = Self::parse_from(data, &mut pos)?;
+        (pos == data.len()).then_some(tx)
+    }
+
+    // Parse one transaction starting at `pos` (used for blocks and PSBT fields)
+    pub fn parse_from(data: &[u8], pos: &mut usize) -> Option<Transaction> {
+        let version = utils::read_u32_le(data, pos)?;
+
+        let segwit = data.get(*pos..*pos + 2) == Some(&[0x00, 0x01]);
+        if segwit {
+            *pos += 2;
+        }
+
+        let input_count = utils::read_varint(data, pos)?;
+        let mut inputs = Vec::with_capacity(input_count.min(data.len()));
+        for _ in 0..input_count {
+            let mut txid = utils::read_bytes(data, pos, 32)?.to_vec();
+            txid.reverse();
+            let vout = utils::read_u32_le(data, pos)?;
+            let script_sig = utils::read_var_bytes(data, pos)?.to_vec();
+            let sequence = utils::read_u32_le(data, pos)?;
+
+            inputs.push(TxIn {
+                txid: utils::bytes_to_hex(&txid),
+                vout,
+                script_sig,
+                sequence,
+                witness: Vec::new(),
+            });
+        }
+
+        let output_count = utils::read_varint(data, pos)?;
+        let mut outputs = Vec::with_capacity(output_count.min(data.len()));
+        for _ in 0..output_count {
+            outputs.push(TxOut::parse(data, pos)?);
+        }
+
+        if segwit {
+            for input in inputs.iter_mut() {
+                let items = utils::read_varint(data, pos)?;
+                for _ in 0..items {
+                    input
+                        .witness
+                        .push(utils::read_var_bytes(data, pos)?.to_vec());
+                }
+            }
+        }
+
+        let locktime = utils::read_u32_le(data, pos)?;
+
+        Some(Transaction {
+            version,
+            inputs,
+            outputs,
+            locktime,
+        })
+    }
+
+    pub fn txid(&self) -> String {
+        let mut hash = crypto::double_sha256(&self.serialize_no_witness());
+        hash.reverse();
+        utils::bytes_to_hex(&hash)
+    }
+
+    // BIP-141 witness txid; equals the txid for transactions without witness
+    pub fn wtxid(&self) -> String {
+        let mut hash = crypto::double_sha256(&self.serialize());
+        hash.reverse();
+        utils::bytes_to_hex(&hash)
+    }
+
+    // BIP-141 weight: base size * 3 + total size
+    pub fn weight(&self) -> usize {
+        self.serialize_no_witness().len() * 3 + self.serialize().len()
+    }
+
+    pub fn vsize(&self) -> u64 {
+        self.weight().div_ceil(4) as u64
+    }
 }
 
 // --------------------
@@ -47,29 +349,19 @@ pub struct TxResult {
     pub effective_fee: u64,
 }
 
-// --------------------
-// PSBT helper
-// --------------------
-fn psbt_kv(psbt: &mut Vec<u8>, key_type: u8, key_data: &[u8], value: &[u8]) {
-    let mut key = vec![key_type];
-    key.extend(key_data);
-
-    psbt.extend(utils::varint(key.len()));
-    psbt.extend(key);
-    psbt.extend(utils::varint(value.len()));
-    psbt.extend(value);
-}
-
 // --------------------
 // Main TX + PSBT builder
 // --------------------
+
+// Each input is signed by the keyring key its scriptPubKey pays to
 pub fn create_and_sign(
-    utxos_json: &str,
+    utxos: &[UTXO],
     to_address: &str,
     amount: u64,
     fee: u64,
-    wif: &str,
+    keyring: &Keyring,
     mainnet: bool,
+    op_return: Option<&[u8]>,
 ) -> TxResult {
     let network = if mainnet {
         Network::Mainnet
@@ -77,182 +369,525 @@ pub fn create_and_sign(
         Network::Testnet
     };
 
-    let secp = Secp256k1::new();
-    let utxos: Vec<UTXO> = serde_json::from_str(utxos_json).expect("invalid UTXO JSON");
-
     let total_in: u64 = utxos.iter().map(|u| u.amount).sum();
     assert!(total_in >= amount + fee, "insufficient funds");
 
     let mut change = total_in - amount - fee;
 
-    let privkey = keys::wif_to_privkey(wif, network);
-    let pubkey = keys::privkey_to_pubkey(&privkey);
-    let pubkey_bytes = pubkey.serialize().to_vec();
-
-    let has_segwit = utxos
-        .iter()
-        .any(|u| detect_input_type(&utils::hex_to_bytes(&u.scriptPubKey)) == InputType::P2WPKH);
-
     // -------------------- outputs --------------------
-    let mut outputs = Vec::new();
-    let mut output_count = 1;
+    let mut outputs = vec![TxOut {
+        value: amount,
+        script_pubkey: address::address_to_scriptpubkey(to_address, network),
+    }];
+
+    if let Some(data) = op_return {
+        outputs.push(TxOut {
+            value: 0,
+            script_pubkey: address::op_return_script(data),
+        });
+    }
 
-    let to_script = address::address_to_scriptpubkey(to_address, network);
-    outputs.extend(utils::u64_le(amount));
-    outputs.extend(utils::varint(to_script.len()));
-    outputs.extend(to_script);
+    let change_addr = address::pubkey_to_address(&keyring.change_pubkey(), network);
+    let change_script = address::address_to_scriptpubkey(&change_addr, network);
 
     let mut effective_fee = fee;
-    if change < dust_threshold() {
+    if change < policy::dust_threshold(&change_script, policy::DUST_RELAY_FEE) {
         effective_fee += change;
         change = 0;
     }
 
-    if change >= dust_threshold() {
-        let change_addr = address::pubkey_to_address(&pubkey, network);
-        let change_script = address::address_to_scriptpubkey(&change_addr, network);
+    if change > 0 {
+        outputs.push(TxOut {
+            value: change,
+            script_pubkey: change_script,
+        });
+    }
+
+    // -------------------- build + sign --------------------
+    let mut tx = Transaction {
+        version: 1,
+        inputs: utxos.iter().map(TxIn::spending).collect(),
+        outputs,
+        locktime: 0,
+    };
 
-        outputs.extend(utils::u64_le(change));
-        outputs.extend(utils::varint(change_script.len()));
-        outputs.extend(change_script);
+    let psbt = sign_inputs(&mut tx, utxos, keyring);
 
-        output_count += 1;
+    TxResult {
+        raw_tx: utils::bytes_to_hex(&tx.serialize()),
+        psbt: utils::bytes_to_hex(&psbt.serialize()),
+        vbytes: tx.vsize(),
+        effective_fee,
     }
+}
 
-    // -------------------- build raw TX --------------------
-    let mut tx = Vec::new();
-    tx.extend(utils::u32_le(1));
+// Sign every input of `tx` (spending `utxos`, in order) with the keyring key
+// its scriptPubKey pays to. Returns a PSBT carrying the spent outputs and our
+// signatures for external finalizers.
+pub fn sign_inputs(tx: &mut Transaction, utxos: &[UTXO], keyring: &Keyring) -> Psbt {
+    let secp = Secp256k1::new();
 
-    if has_segwit {
-        tx.extend([0x00, 0x01]);
+    for input in tx.inputs.iter_mut() {
+        input.script_sig.clear();
+        input.witness.clear();
     }
+    let mut psbt = Psbt::from_unsigned_tx(tx.clone());
 
-    tx.extend(utils::varint(utxos.len()));
+    // Taproot signatures commit to every spent output
+    let spent: Vec<TxOut> = utxos
+        .iter()
+        .map(|u| TxOut {
+            value: u.amount,
+            script_pubkey: utils::hex_to_bytes(&u.scriptPubKey),
+        })
+        .collect();
 
-    let mut witnesses: Vec<Vec<Vec<u8>>> = vec![vec![]; utxos.len()];
+    // Shared hashes are computed once for all inputs; signatures only touch
+    // witnesses and scriptSigs, which no sighash commits to
+    let cache = crypto::SighashCache::new(tx, Some(&spent));
 
     for (i, utxo) in utxos.iter().enumerate() {
         let script = utils::hex_to_bytes(&utxo.scriptPubKey);
-        let input_type = detect_input_type(&script);
+        let input_type = detect_input_type(&script).unwrap_or_else(|| {
+            panic!(
+                "unsupported scriptPubKey for input {}:{}",
+                utxo.txid, utxo.vout
+            )
+        });
+
+        let key = find_input_key(keyring, input_type, &script)
+            .unwrap_or_else(|| panic!("no key in keyring for input {}:{}", utxo.txid, utxo.vout));
+        let pubkey_bytes = key.pubkey_bytes();
+
+        if input_type == InputType::P2TR {
+            let sighash_type = utxo.sighash.unwrap_or(crypto::SIGHASH_DEFAULT);
+            let sighash = cache
+                .bip341(i, sighash_type, None, None)
+                .expect("invalid taproot sighash type");
+
+            let keypair = crate::keys::taproot_keypair(&key.secret, None);
+            let sig = secp.sign_schnorr_with_rng(
+                &Message::from_digest_slice(&sighash).unwrap(),
+                &keypair,
+                &mut OsRng,
+            );
+
+            // DEFAULT signatures omit the trailing sighash byte
+            let mut sig_bytes = sig.as_ref().to_vec();
+            if sighash_type != crypto::SIGHASH_DEFAULT {
+                sig_bytes.push(sighash_type as u8);
+            }
+
+            tx.inputs[i].witness = vec![sig_bytes.clone()];
+            psbt.inputs[i].tap_key_sig = Some(sig_bytes);
+            psbt.inputs[i].tap_internal_key = Some(pubkey_bytes[1..].to_vec());
+            psbt.inputs[i].witness_utxo = Some(spent[i].clone());
+            continue;
+        }
+
+        let sighash_type = utxo.sighash.unwrap_or(crypto::SIGHASH_ALL);
+        let sighash = match input_type {
+            InputType::P2WPKH | InputType::P2SH_P2WPKH => {
+                let script_code = address::p2pkh_script(&key.hash160());
+                cache.bip143(i, &script_code, utxo.amount, sighash_type)
+            }
+            _ => cache.legacy(i, &script, sighash_type),
+        };
 
-        tx.extend(utils::hex_to_bytes(&utxo.txid).into_iter().rev());
-        tx.extend(utils::u32_le(utxo.vout));
+        let sig = secp.sign_ecdsa(&Message::from_digest_slice(&sighash).unwrap(), &key.secret);
+
+        let mut sig_der = sig.serialize_der().to_vec();
+        sig_der.push(sighash_type as u8);
 
         match input_type {
             InputType::P2WPKH => {
-                tx.push(0x00);
-                tx.extend(utils::u32_le(0xffffffff));
+                tx.inputs[i].witness = vec![sig_der.clone(), pubkey_bytes.clone()];
+            }
+            InputType::P2SH_P2WPKH => {
+                let redeem_script = address::p2wpkh_script(&key.hash160());
+                let mut script_sig = Vec::new();
+                script::push_data(&mut script_sig, &redeem_script);
+                tx.inputs[i].script_sig = script_sig;
+                tx.inputs[i].witness = vec![sig_der.clone(), pubkey_bytes.clone()];
 
-                let pubkey_hash = &script[2..22];
-                let script_code = address::p2pkh_script(pubkey_hash);
+                psbt.inputs[i].redeem_script = Some(redeem_script);
+            }
+            _ => {
+                let mut script_sig = Vec::new();
+                script::push_data(&mut script_sig, &sig_der);
+                script::push_data(&mut script_sig, &pubkey_bytes);
+                tx.inputs[i].script_sig = script_sig;
+            }
+        }
 
-                let sighash =
-                    crypto::bip143_sighash(&utxos, i, &script_code, utxo.amount, &outputs);
+        if sighash_type != crypto::SIGHASH_ALL {
+            psbt.inputs[i].sighash_type = Some(sighash_type);
+        }
+        psbt.inputs[i].witness_utxo = Some(TxOut {
+            value: utxo.amount,
+            script_pubkey: script,
+        });
+        psbt.inputs[i].partial_sigs.insert(pubkey_bytes, sig_der);
+    }
 
-                let sig = secp.sign_ecdsa(&Message::from_digest_slice(&sighash).unwrap(), &privkey);
+    psbt
+}
 
-                let mut sig_der = sig.serialize_der().to_vec();
-                sig_der.push(0x01);
+// --------------------
+// Fee-rate payments
+// --------------------
 
-                witnesses[i] = vec![sig_der, pubkey_bytes.clone()];
-            }
+// Payment target. A `max` recipient receives everything left after the other
+// recipients and the fee; `subtract_fee` recipients pay the fee out of their
+// own amounts (split evenly, the first one covering any remainder).
+#[derive(Clone, Deserialize)]
+pub struct Recipient {
+    pub address: String,
+    #[serde(default)]
+    pub amount: u64,
+    #[serde(default)]
+    pub max: bool,
+    #[serde(default)]
+    pub subtract_fee: bool,
+}
 
-            InputType::P2PKH => {
-                let sighash = crypto::legacy_sighash(&utxos, i, &outputs);
+// Build and sign a payment whose fee is `sat_per_vbyte` times the vsize of
+// the final signed transaction
+pub fn create_payment(
+    utxos: &[UTXO],
+    recipients: &[Recipient],
+    sat_per_vbyte: u64,
+    keyring: &Keyring,
+    network: Network,
+    op_return: Option<&[u8]>,
+) -> TxResult {
+    assert!(!utxos.is_empty(), "no UTXOs to spend");
+    assert!(!recipients.is_empty(), "no recipients");
+
+    let max_recipients = recipients.iter().filter(|r| r.max).count();
+    let fee_payers = recipients.iter().filter(|r| r.subtract_fee).count() as u64;
+    assert!(
+        max_recipients <= 1,
+        "only one recipient can receive the maximum"
+    );
+    assert!(
+        max_recipients == 0 || fee_payers == 0,
+        "send max cannot be combined with subtract_fee"
+    );
 
-                let sig = secp.sign_ecdsa(&Message::from_digest_slice(&sighash).unwrap(), &privkey);
+    let total_in: u64 = utxos.iter().map(|u| u.amount).sum();
+    let fixed: u64 = recipients.iter().filter(|r| !r.max).map(|r| r.amount).sum();
+    assert!(total_in >= fixed, "insufficient funds");
 
-                let mut sig_der = sig.serialize_der().to_vec();
-                sig_der.push(0x01);
+    let scripts: Vec<Vec<u8>> = recipients
+        .iter()
+        .map(|r| {
+            address::checked_scriptpubkey(&r.address, network)
+                .unwrap_or_else(|| panic!("invalid recipient address: {}", r.address))
+        })
+        .collect();
+    let change_script =
+        address::p2pkh_script(&crypto::hash160(&keyring.change_pubkey().serialize()));
+
+    let mut tx = Transaction {
+        version: 1,
+        inputs: utxos.iter().map(TxIn::spending).collect(),
+        outputs: Vec::new(),
+        locktime: 0,
+    };
 
-                let mut script_sig = Vec::new();
-                script_sig.extend(utils::varint(sig_der.len()));
-                script_sig.extend(&sig_der);
-                script_sig.extend(utils::varint(pubkey_bytes.len()));
-                script_sig.extend(&pubkey_bytes);
+    // Signature lengths vary by a byte, so re-sign until the fee covers the
+    // size of the transaction actually produced
+    let mut fee = 0;
+    loop {
+        let mut outputs = Vec::with_capacity(recipients.len() + 2);
+        let mut payer_index = 0;
+
+        for (r, script) in recipients.iter().zip(&scripts) {
+            let value = if r.max {
+                total_in
+                    .checked_sub(fixed + fee)
+                    .filter(|v| *v >= policy::dust_threshold(script, policy::DUST_RELAY_FEE))
+                    .expect("insufficient funds")
+            } else if r.subtract_fee {
+                let mut share = fee / fee_payers;
+                if payer_index == 0 {
+                    share += fee % fee_payers;
+                }
+                payer_index += 1;
+
+                r.amount
+                    .checked_sub(share)
+                    .filter(|v| *v >= policy::dust_threshold(script, policy::DUST_RELAY_FEE))
+                    .expect("amount too small to pay its share of the fee")
+            } else {
+                r.amount
+            };
+
+            outputs.push(TxOut {
+                value,
+                script_pubkey: script.clone(),
+            });
+        }
 
-                tx.extend(utils::varint(script_sig.len()));
-                tx.extend(script_sig);
-                tx.extend(utils::u32_le(0xffffffff));
+        if let Some(data) = op_return {
+            outputs.push(TxOut {
+                value: 0,
+                script_pubkey: address::op_return_script(data),
+            });
+        }
 
-                witnesses[i] = vec![sig_der, pubkey_bytes.clone()];
+        if max_recipients == 0 {
+            let fee_from_change = if fee_payers == 0 { fee } else { 0 };
+            let change = (total_in - fixed)
+                .checked_sub(fee_from_change)
+                .expect("insufficient funds");
+
+            // Sub-dust change is left to the fee
+            if change >= policy::dust_threshold(&change_script, policy::DUST_RELAY_FEE) {
+                outputs.push(TxOut {
+                    value: change,
+                    script_pubkey: change_script.clone(),
+                });
             }
         }
+
+        tx.outputs = outputs;
+        let psbt = sign_inputs(&mut tx, utxos, keyring);
+        let required = tx.vsize() * sat_per_vbyte;
+
+        if fee >= required {
+            let total_out: u64 = tx.outputs.iter().map(|o| o.value).sum();
+            return TxResult {
+                raw_tx: utils::bytes_to_hex(&tx.serialize()),
+                psbt: utils::bytes_to_hex(&psbt.serialize()),
+                vbytes: tx.vsize(),
+                effective_fee: total_in - total_out,
+            };
+        }
+        fee = required;
     }
+}
 
-    tx.extend(utils::varint(output_count));
-    tx.extend(&outputs);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::SecretKey;
 
-    if has_segwit {
-        for w in &witnesses {
-            tx.extend(utils::varint(w.len()));
-            for item in w {
-                tx.extend(utils::varint(item.len()));
-                tx.extend(item);
-            }
+    fn keyring(seeds: &[u8]) -> Keyring {
+        let mut keyring = Keyring::default();
+        for &seed in seeds {
+            keyring.add(SecretKey::from_slice(&[seed; 32]).unwrap());
         }
+        keyring
     }
 
-    tx.extend(utils::u32_le(0)); // locktime
-
-    // -------------------- weight / vbytes --------------------
-    let witness_size: usize = if has_segwit {
-        witnesses
-            .iter()
-            .map(|w| {
-                utils::varint(w.len()).len()
-                    + w.iter()
-                        .map(|i| utils::varint(i.len()).len() + i.len())
-                        .sum::<usize>()
-            })
-            .sum()
-    } else {
-        0
-    };
+    fn utxo_json(seed: u8, segwit: bool) -> String {
+        let secret = SecretKey::from_slice(&[seed; 32]).unwrap();
+        let hash = crypto::hash160(&crate::keys::privkey_to_pubkey(&secret).serialize());
+        let script = if segwit {
+            [vec![0x00, 0x14], hash].concat()
+        } else {
+            address::p2pkh_script(&hash)
+        };
+        format!(
+            r#"{{"txid":"{}","vout":0,"scriptPubKey":"{}","amount":100000}}"#,
+            hex::encode([seed; 32]),
+            hex::encode(script)
+        )
+    }
 
-    let base_size = tx.len() - witness_size;
-    let weight = base_size * 4 + witness_size;
-    let vbytes = weight.div_ceil(4) as u64;
+    #[test]
+    fn test_signs_each_input_with_matching_key() {
+        let utxos: Vec<UTXO> =
+            serde_json::from_str(&format!("[{},{}]", utxo_json(1, false), utxo_json(2, true)))
+                .unwrap();
+        let to = address::pubkey_to_address(&keyring(&[9]).change_pubkey(), Network::Testnet);
 
-    let raw_tx_hex = utils::bytes_to_hex(&tx);
+        let result = create_and_sign(&utxos, &to, 150_000, 1_000, &keyring(&[2, 1]), false, None);
+        let tx = Transaction::parse(&utils::hex_to_bytes(&result.raw_tx)).unwrap();
 
-    // -------------------- PSBT --------------------
-    let mut psbt = Vec::new();
-    psbt.extend(b"psbt\xff");
+        // Legacy input signs in the scriptSig, segwit input in the witness
+        assert!(!tx.inputs[0].script_sig.is_empty() && tx.inputs[0].witness.is_empty());
+        assert!(tx.inputs[1].script_sig.is_empty() && tx.inputs[1].witness.len() == 2);
 
-    psbt_kv(&mut psbt, 0x00, &[], &tx);
-    psbt.push(0x00);
+        let psbt = Psbt::parse(&utils::hex_to_bytes(&result.psbt)).unwrap();
+        assert!(psbt.inputs.iter().all(|i| i.partial_sigs.len() == 1));
+    }
 
-    for (i, utxo) in utxos.iter().enumerate() {
-        let script = utils::hex_to_bytes(&utxo.scriptPubKey);
-        let input_type = detect_input_type(&script);
+    #[test]
+    fn test_send_max_and_subtract_fee() {
+        let utxos: Vec<UTXO> =
+            serde_json::from_str(&format!("[{},{}]", utxo_json(1, true), utxo_json(2, false)))
+                .unwrap();
+        let keys = keyring(&[1, 2]);
+        let to =
+            |seed| address::pubkey_to_address(&keyring(&[seed]).change_pubkey(), Network::Testnet);
+
+        let send_max = [Recipient {
+            address: to(9),
+            amount: 0,
+            max: true,
+            subtract_fee: false,
+        }];
+        let result = create_payment(&utxos, &send_max, 3, &keys, Network::Testnet, None);
+        let tx = Transaction::parse(&utils::hex_to_bytes(&result.raw_tx)).unwrap();
+        assert_eq!(tx.outputs.len(), 1);
+        assert_eq!(tx.outputs[0].value + result.effective_fee, 200_000);
+        assert!(result.effective_fee >= result.vbytes * 3);
+
+        let payout: Vec<Recipient> = [8, 9]
+            .into_iter()
+            .map(|seed| Recipient {
+                address: to(seed),
+                amount: 50_000,
+                max: false,
+                subtract_fee: true,
+            })
+            .collect();
+        let result = create_payment(&utxos, &payout, 3, &keys, Network::Testnet, None);
+        let tx = Transaction::parse(&utils::hex_to_bytes(&result.raw_tx)).unwrap();
+
+        // Recipients share the fee; change is untouched
+        assert_eq!(tx.outputs.len(), 3);
+        assert_eq!(tx.outputs[2].value, 100_000);
+        assert_eq!(
+            100_000 - tx.outputs[0].value - tx.outputs[1].value,
+            result.effective_fee
+        );
+    }
 
-        match input_type {
-            InputType::P2PKH => {
-                psbt_kv(&mut psbt, 0x00, &[], &tx);
-            }
-            InputType::P2WPKH => {
-                let mut wit = Vec::new();
-                wit.extend(utils::u64_le(utxo.amount));
-                wit.extend(utils::varint(script.len()));
-                wit.extend(&script);
-                psbt_kv(&mut psbt, 0x01, &[], &wit);
+    #[test]
+    fn test_coinbase_maturity_and_balance() {
+        let utxo = |amount, height, is_coinbase| UTXO {
+            txid: "11".repeat(32),
+            amount,
+            height,
+            is_coinbase,
+            ..Default::default()
+        };
+        let utxos = [
+            utxo(50_000, Some(1_000), true), // 100 confirmations at 1099
+            utxo(60_000, Some(1_001), true),
+            utxo(7_000, Some(1_050), false),
+            utxo(3_000, None, false),
+            UTXO {
+                confirmations: Some(6),
+                ..utxo(1_000, None, false)
+            },
+        ];
+
+        assert_eq!(utxos[0].confirmations_at(1_099), 100);
+        assert!(utxos[0].is_mature(1_099) && !utxos[1].is_mature(1_099));
+        assert_eq!(
+            balance(&utxos, 1_099),
+            Balance {
+                spendable: 58_000,
+                immature: 60_000,
+                unconfirmed: 3_000,
             }
-        }
+        );
 
-        let mut sig = witnesses[i][0].clone();
-        sig.push(0x01);
-        psbt_kv(&mut psbt, 0x02, &pubkey_bytes, &sig);
+        let spendable = spendable_utxos(&utxos, 1_099, 1);
+        let amounts: Vec<u64> = spendable.iter().map(|u| u.amount).collect();
+        assert_eq!(amounts, [50_000, 7_000, 1_000]);
+        assert_eq!(spendable_utxos(&utxos, 1_099, 6).len(), 3);
+        assert_eq!(spendable_utxos(&utxos, 1_099, 7).len(), 2);
+    }
 
-        psbt.push(0x00);
+    #[test]
+    fn test_taproot_anyonecanpay_input() {
+        let secp = Secp256k1::new();
+        let keys = keyring(&[4]);
+        let output_key = keys.find(|_| true).unwrap().taproot_output_key();
+
+        let utxo = UTXO {
+            txid: hex::encode([4; 32]),
+            vout: 1,
+            scriptPubKey: format!("5120{}", hex::encode(output_key.serialize())),
+            amount: 80_000,
+            sighash: Some(crypto::SIGHASH_ALL | crypto::SIGHASH_ANYONECANPAY),
+            ..Default::default()
+        };
+        let mut tx = Transaction {
+            version: 2,
+            inputs: vec![TxIn::spending(&utxo)],
+            outputs: vec![TxOut {
+                value: 79_000,
+                script_pubkey: address::p2pkh_script(&[0; 20]),
+            }],
+            locktime: 0,
+        };
+
+        sign_inputs(&mut tx, std::slice::from_ref(&utxo), &keys);
+
+        let witness = &tx.inputs[0].witness;
+        assert_eq!(witness.len(), 1);
+        assert_eq!(witness[0].len(), 65);
+        assert_eq!(witness[0][64], 0x81);
+
+        let spent = [TxOut {
+            value: utxo.amount,
+            script_pubkey: utils::hex_to_bytes(&utxo.scriptPubKey),
+        }];
+        let sighash = crypto::SighashCache::new(&tx, Some(&spent))
+            .bip341(0, 0x81, None, None)
+            .unwrap();
+        let sig = secp256k1::schnorr::Signature::from_slice(&witness[0][..64]).unwrap();
+        assert!(secp
+            .verify_schnorr(
+                &sig,
+                &Message::from_digest_slice(&sighash).unwrap(),
+                &output_key
+            )
+            .is_ok());
     }
 
-    psbt.extend(std::iter::repeat_n(0x00, output_count));
+    #[test]
+    #[should_panic(expected = "no key in keyring")]
+    fn test_rejects_input_without_key() {
+        let utxos: Vec<UTXO> = serde_json::from_str(&format!("[{}]", utxo_json(3, true))).unwrap();
+        let to = address::pubkey_to_address(&keyring(&[9]).change_pubkey(), Network::Testnet);
 
-    TxResult {
-        raw_tx: raw_tx_hex,
-        psbt: utils::bytes_to_hex(&psbt),
-        vbytes,
-        effective_fee,
+        create_and_sign(&utxos, &to, 50_000, 1_000, &keyring(&[1, 2]), false, None);
+    }
+
+    fn pay_to(address: &str) {
+        let utxos: Vec<UTXO> = serde_json::from_str(&format!("[{}]", utxo_json(1, true))).unwrap();
+        let recipient = Recipient {
+            address: address.to_string(),
+            amount: 50_000,
+            max: false,
+            subtract_fee: false,
+        };
+        create_payment(
+            &utxos,
+            &[recipient],
+            3,
+            &keyring(&[1]),
+            Network::Testnet,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid recipient address")]
+    fn test_rejects_wrong_network_recipient() {
+        pay_to(&address::pubkey_to_address(
+            &keyring(&[9]).change_pubkey(),
+            Network::Mainnet,
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid recipient address")]
+    fn test_rejects_malformed_recipient() {
+        // Testnet P2PKH address with its last character changed
+        let mut address =
+            address::pubkey_to_address(&keyring(&[9]).change_pubkey(), Network::Testnet);
+        let last = if address.ends_with('1') { '2' } else { '1' };
+        address.pop();
+        address.push(last);
+        pay_to(&address);
     }
 }
@@ -61,3 +61,41 @@ pub fn varint(n: usize) -> Vec<u8> {
         }
     }
 }
+
+// --------------------
+// Parsing helpers
+// --------------------
+
+pub fn read_bytes<'a>(data: &'a [u8], pos: &mut usize, n: usize) -> Option<&'a [u8]> {
+    let end = pos.checked_add(n)?;
+    let out = data.get(*pos..end)?;
+    *pos = end;
+    Some(out)
+}
+
+pub fn read_u32_le(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let b = read_bytes(data, pos, 4)?;
+    Some(u32::from_le_bytes(b.try_into().ok()?))
+}
+
+pub fn read_u64_le(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let b = read_bytes(data, pos, 8)?;
+    Some(u64::from_le_bytes(b.try_into().ok()?))
+}
+
+pub fn read_varint(data: &[u8], pos: &mut usize) -> Option<usize> {
+    let first = *read_bytes(data, pos, 1)?.first()?;
+    let n = match first {
+        0xfd => u16::from_le_bytes(read_bytes(data, pos, 2)?.try_into().ok()?) as u64,
+        0xfe => u32::from_le_bytes(read_bytes(data, pos, 4)?.try_into().ok()?) as u64,
+        0xff => u64::from_le_bytes(read_bytes(data, pos, 8)?.try_into().ok()?),
+        n => n as u64,
+    };
+    usize::try_from(n).ok()
+}
+
+// Length-prefixed byte string
+pub fn read_var_bytes<'a>(data: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let len = read_varint(data, pos)?;
+    read_bytes(data, pos, len)
+}
@@ -0,0 +1,507 @@
+use crate::{
+    block::Block,
+    descriptor::Descriptor,
+    tx::{self, Balance, Transaction, UTXO},
+    utils,
+};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// Unconfirmed transactions that arrived before the parent paying the wallet
+// are held back, up to this many (as Core's orphan pool)
+const MAX_ORPHANS: usize = 100;
+
+// --------------------
+// Wallet state
+// --------------------
+
+struct WalletTx {
+    tx: Transaction,
+    // Confirming block height; None while in the mempool
+    height: Option<u32>,
+    // Order first seen, breaking ties within a block and in the mempool
+    seen: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct HistoryEntry {
+    pub txid: String,
+    pub height: Option<u32>,
+    pub received: u64,
+    pub sent: u64,
+    pub net: i64,
+    // Known when every spent output is in the wallet
+    pub fee: Option<u64>,
+}
+
+// Transactions touching a set of watched scripts, with spent/unspent
+// tracking against a chain tip
+#[derive(Default)]
+pub struct Wallet {
+    scripts: HashSet<Vec<u8>>,
+    txs: HashMap<String, WalletTx>,
+    // Outpoints (txid, vout) spent by any wallet transaction
+    spent: HashSet<(String, u32)>,
+    tip_height: u32,
+    next_seen: usize,
+    // Transactions spending outputs of unknown transactions, re-checked as
+    // those arrive
+    orphans: VecDeque<(Transaction, Option<u32>)>,
+}
+
+impl Wallet {
+    pub fn new() -> Wallet {
+        Wallet::default()
+    }
+
+    pub fn watch_script(&mut self, script_pubkey: &[u8]) {
+        self.scripts.insert(script_pubkey.to_vec());
+    }
+
+    // Watch indexes 0..count of a descriptor (its only script when not
+    // ranged)
+    pub fn watch_descriptor(&mut self, descriptor: &Descriptor, count: u32) {
+        let count = if descriptor.is_ranged() { count } else { 1 };
+        for index in 0..count {
+            self.watch_script(&descriptor.script_pubkey(index));
+        }
+    }
+
+    pub fn tip_height(&self) -> u32 {
+        self.tip_height
+    }
+
+    pub fn set_tip(&mut self, height: u32) {
+        self.tip_height = height;
+    }
+
+    fn is_mine(&self, script_pubkey: &[u8]) -> bool {
+        self.scripts.contains(script_pubkey)
+    }
+
+    fn prevout(&self, txid: &str, vout: u32) -> Option<&tx::TxOut> {
+        self.txs.get(txid)?.tx.outputs.get(vout as usize)
+    }
+
+    fn is_relevant(&self, tx: &Transaction) -> bool {
+        tx.outputs.iter().any(|o| self.is_mine(&o.script_pubkey))
+            || tx.inputs.iter().any(|i| {
+                self.prevout(&i.txid, i.vout)
+                    .is_some_and(|o| self.is_mine(&o.script_pubkey))
+            })
+    }
+
+    // Whether a confirmed wallet transaction spends the outpoint of `input`
+    fn spent_in_block(&self, input: &tx::TxIn) -> bool {
+        self.txs.values().any(|wtx| {
+            wtx.height.is_some()
+                && wtx
+                    .tx
+                    .inputs
+                    .iter()
+                    .any(|i| i.txid == input.txid && i.vout == input.vout)
+        })
+    }
+
+    // Store a transaction that pays or spends a watched script, at `height`
+    // (None while unconfirmed); a known transaction only has its height
+    // updated. Returns whether the wallet holds it.
+    pub fn add_transaction(&mut self, tx: Transaction, height: Option<u32>) -> bool {
+        self.add(tx, height, true)
+    }
+
+    // With `keep_orphan`, a transaction not concerning the wallet yet is held
+    // until the transactions it spends from arrive
+    fn add(&mut self, tx: Transaction, height: Option<u32>, keep_orphan: bool) -> bool {
+        let txid = tx.txid();
+        if let Some(known) = self.txs.get_mut(&txid) {
+            if height.is_some() {
+                known.height = height;
+                self.evict_conflicts(&txid);
+            }
+            return true;
+        }
+        if !self.is_relevant(&tx) {
+            let orphan =
+                !tx.is_coinbase() && tx.inputs.iter().any(|i| !self.txs.contains_key(&i.txid));
+            if keep_orphan && orphan {
+                if self.orphans.len() == MAX_ORPHANS {
+                    self.orphans.pop_front();
+                }
+                self.orphans.push_back((tx, height));
+            }
+            return false;
+        }
+        // A double spend of a confirmed transaction can never confirm
+        if height.is_none() && tx.inputs.iter().any(|i| self.spent_in_block(i)) {
+            return false;
+        }
+
+        if !tx.is_coinbase() {
+            for input in &tx.inputs {
+                self.spent.insert((input.txid.clone(), input.vout));
+            }
+        }
+        let seen = self.next_seen;
+        self.next_seen += 1;
+        self.txs.insert(txid.clone(), WalletTx { tx, height, seen });
+        if height.is_some() {
+            self.evict_conflicts(&txid);
+        }
+
+        // Children that arrived first
+        let (children, orphans) = self
+            .orphans
+            .drain(..)
+            .partition(|(orphan, _)| orphan.inputs.iter().any(|i| i.txid == txid));
+        self.orphans = orphans;
+        for (child, height) in Vec::from(children) {
+            self.add(child, height, true);
+        }
+        true
+    }
+
+    // Drop unconfirmed transactions double-spending confirmed `txid`, with
+    // everything built on them
+    fn evict_conflicts(&mut self, txid: &str) {
+        let spends: HashSet<(&String, u32)> = self.txs[txid]
+            .tx
+            .inputs
+            .iter()
+            .map(|i| (&i.txid, i.vout))
+            .collect();
+        let conflicts: Vec<String> = self
+            .txs
+            .iter()
+            .filter(|(id, wtx)| {
+                *id != txid
+                    && wtx.height.is_none()
+                    && wtx
+                        .tx
+                        .inputs
+                        .iter()
+                        .any(|i| spends.contains(&(&i.txid, i.vout)))
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !conflicts.is_empty() {
+            self.remove_with_descendants(conflicts);
+        }
+    }
+
+    // Remove transactions and every wallet transaction spending from them,
+    // then recompute the spent outpoints from what is left
+    fn remove_with_descendants(&mut self, mut txids: Vec<String>) {
+        while let Some(txid) = txids.pop() {
+            if self.txs.remove(&txid).is_none() {
+                continue;
+            }
+            txids.extend(
+                self.txs
+                    .iter()
+                    .filter(|(_, wtx)| wtx.tx.inputs.iter().any(|i| i.txid == txid))
+                    .map(|(id, _)| id.clone()),
+            );
+        }
+        self.spent = self
+            .txs
+            .values()
+            .filter(|wtx| !wtx.tx.is_coinbase())
+            .flat_map(|wtx| wtx.tx.inputs.iter().map(|i| (i.txid.clone(), i.vout)))
+            .collect();
+    }
+
+    // Ingest a block at `height`: confirm its wallet transactions and move
+    // the tip up to it. Returns how many transactions concern the wallet.
+    pub fn add_block(&mut self, block: &Block, height: u32) -> usize {
+        let added = block
+            .txdata
+            .iter()
+            .filter(|tx| self.add((*tx).clone(), Some(height), false))
+            .count();
+        self.tip_height = self.tip_height.max(height);
+        added
+    }
+
+    // Undo blocks above `height` after a reorg: their transactions return
+    // to the mempool, except coinbases which no longer exist, along with
+    // everything spending from them
+    pub fn disconnect_above(&mut self, height: u32) {
+        let orphaned: Vec<String> = self
+            .txs
+            .iter()
+            .filter(|(_, wtx)| wtx.height.is_some_and(|h| h > height) && wtx.tx.is_coinbase())
+            .map(|(txid, _)| txid.clone())
+            .collect();
+        self.remove_with_descendants(orphaned);
+        for wtx in self.txs.values_mut() {
+            if wtx.height.is_some_and(|h| h > height) {
+                wtx.height = None;
+            }
+        }
+        self.tip_height = self.tip_height.min(height);
+    }
+
+    // Unspent wallet outputs, ready for the transaction builders
+    pub fn utxos(&self) -> Vec<UTXO> {
+        let mut utxos = Vec::new();
+        for (txid, wtx) in &self.txs {
+            for (vout, output) in wtx.tx.outputs.iter().enumerate() {
+                let vout = vout as u32;
+                if !self.is_mine(&output.script_pubkey)
+                    || self.spent.contains(&(txid.clone(), vout))
+                {
+                    continue;
+                }
+                utxos.push(UTXO {
+                    txid: txid.clone(),
+                    vout,
+                    scriptPubKey: utils::bytes_to_hex(&output.script_pubkey),
+                    amount: output.value,
+                    height: Some(wtx.height.unwrap_or(0)),
+                    is_coinbase: wtx.tx.is_coinbase(),
+                    ..Default::default()
+                });
+            }
+        }
+        utxos.sort_by(|a, b| (&a.txid, a.vout).cmp(&(&b.txid, b.vout)));
+        utxos
+    }
+
+    pub fn balance(&self) -> Balance {
+        tx::balance(&self.utxos(), self.tip_height)
+    }
+
+    // Wallet transactions oldest first, unconfirmed last, with what each
+    // moved in and out of the wallet
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        let mut txs: Vec<(&String, &WalletTx)> = self.txs.iter().collect();
+        txs.sort_by_key(|(_, wtx)| (wtx.height.unwrap_or(u32::MAX), wtx.seen));
+
+        txs.into_iter()
+            .map(|(txid, wtx)| {
+                let received: u64 = wtx
+                    .tx
+                    .outputs
+                    .iter()
+                    .filter(|o| self.is_mine(&o.script_pubkey))
+                    .map(|o| o.value)
+                    .sum();
+
+                let prevouts: Option<Vec<&tx::TxOut>> = wtx
+                    .tx
+                    .inputs
+                    .iter()
+                    .map(|i| self.prevout(&i.txid, i.vout))
+                    .collect();
+                let sent: u64 = wtx
+                    .tx
+                    .inputs
+                    .iter()
+                    .filter_map(|i| self.prevout(&i.txid, i.vout))
+                    .filter(|o| self.is_mine(&o.script_pubkey))
+                    .map(|o| o.value)
+                    .sum();
+                let fee = prevouts.filter(|_| !wtx.tx.is_coinbase()).map(|prevouts| {
+                    let spent: u64 = prevouts.iter().map(|o| o.value).sum();
+                    let paid: u64 = wtx.tx.outputs.iter().map(|o| o.value).sum();
+                    spent.saturating_sub(paid)
+                });
+
+                HistoryEntry {
+                    txid: txid.clone(),
+                    height: wtx.height,
+                    received,
+                    sent,
+                    net: received as i64 - sent as i64,
+                    fee,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        address, coinbase,
+        tx::{TxIn, TxOut},
+    };
+
+    // Segwit spend of `txid:vout` with a placeholder signature and key
+    fn spend_outpoint(txid: String, vout: u32, outputs: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                txid,
+                vout,
+                script_sig: Vec::new(),
+                sequence: 0xfffffffd,
+                witness: vec![vec![1; 71], vec![2; 33]],
+            }],
+            outputs,
+            locktime: 0,
+        }
+    }
+
+    fn spend(prev: &Transaction, vout: u32, outputs: Vec<TxOut>) -> Transaction {
+        spend_outpoint(prev.txid(), vout, outputs)
+    }
+
+    fn pay(script: &[u8], value: u64) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey: script.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_balances_and_history() {
+        let mine = address::p2wpkh_script(&[1; 20]);
+        let change = address::p2wpkh_script(&[2; 20]);
+        let theirs = address::p2wpkh_script(&[3; 20]);
+
+        let mut wallet = Wallet::new();
+        wallet.watch_script(&mine);
+        wallet.watch_script(&change);
+
+        // Mined reward at 100, a payment in at 150, then a spend with change
+        let reward = coinbase::build_coinbase(100, 4, b"", &[pay(&mine, 5_000_000)], None).tx;
+        let funding = spend(&reward, 0, vec![pay(&theirs, 80_000)]);
+        let incoming = spend(&funding, 0, vec![pay(&mine, 60_000), pay(&theirs, 19_000)]);
+        let outgoing = spend(
+            &incoming,
+            0,
+            vec![pay(&theirs, 40_000), pay(&change, 19_500)],
+        );
+        let unrelated = spend(&funding, 0, vec![pay(&theirs, 79_000)]);
+
+        assert!(wallet.add_transaction(reward.clone(), Some(100)));
+        assert!(!wallet.add_transaction(unrelated, Some(150)));
+        assert!(wallet.add_transaction(incoming.clone(), Some(150)));
+        assert!(wallet.add_transaction(outgoing.clone(), None));
+        wallet.set_tip(160);
+
+        // Only the change is left besides the immature reward
+        let utxos = wallet.utxos();
+        assert_eq!(utxos.len(), 2);
+        assert_eq!(
+            wallet.balance(),
+            Balance {
+                spendable: 0,
+                immature: 5_000_000,
+                unconfirmed: 19_500,
+            }
+        );
+
+        let history = wallet.history();
+        let txids: Vec<&str> = history.iter().map(|h| h.txid.as_str()).collect();
+        assert_eq!(txids, [reward.txid(), incoming.txid(), outgoing.txid()]);
+        assert_eq!((history[1].net, history[1].fee), (60_000, None));
+        assert_eq!((history[2].net, history[2].fee), (-40_500, Some(500)));
+        assert_eq!(history[0].fee, None);
+
+        // Confirming the spend and maturing the reward
+        let block = Block {
+            header: crate::block::BlockHeader {
+                version: 0x2000_0000,
+                prev_blockhash: "00".repeat(32),
+                merkle_root: "00".repeat(32),
+                time: 0,
+                bits: 0x207fffff,
+                nonce: 0,
+            },
+            txdata: vec![outgoing.clone()],
+        };
+        assert_eq!(wallet.add_block(&block, 200), 1);
+        assert_eq!(wallet.balance().spendable, 5_019_500);
+        assert_eq!(tx::spendable_utxos(&wallet.utxos(), 200, 1).len(), 2);
+
+        // A reorg below the reward drops it and returns the rest to the mempool
+        wallet.disconnect_above(99);
+        assert_eq!(
+            wallet.balance(),
+            Balance {
+                spendable: 0,
+                immature: 0,
+                unconfirmed: 19_500,
+            }
+        );
+        assert!(wallet.history().iter().all(|h| h.height.is_none()));
+    }
+
+    #[test]
+    fn test_reorg_drops_coinbase_descendants() {
+        let mine = address::p2wpkh_script(&[1; 20]);
+        let theirs = address::p2wpkh_script(&[3; 20]);
+        let mut wallet = Wallet::new();
+        wallet.watch_script(&mine);
+
+        let payment = spend(
+            &coinbase::build_coinbase(1, 4, b"", &[pay(&theirs, 10_000)], None).tx,
+            0,
+            vec![pay(&mine, 8_000)],
+        );
+        let reward = coinbase::build_coinbase(100, 4, b"", &[pay(&mine, 5_000_000)], None).tx;
+        // Spends the reward and the payment, then a child of that
+        let mut child = spend(&reward, 0, vec![pay(&mine, 5_007_000)]);
+        child
+            .inputs
+            .push(spend(&payment, 0, Vec::new()).inputs[0].clone());
+        let grandchild = spend(&child, 0, vec![pay(&theirs, 5_006_000)]);
+
+        assert!(wallet.add_transaction(payment.clone(), Some(50)));
+        assert!(wallet.add_transaction(reward, Some(100)));
+        assert!(wallet.add_transaction(child, Some(201)));
+        assert!(wallet.add_transaction(grandchild, None));
+        wallet.set_tip(300);
+        assert!(wallet.utxos().is_empty());
+
+        // Both descendants go with the reward and the payment is unspent again
+        wallet.disconnect_above(99);
+        let txids: Vec<String> = wallet.history().into_iter().map(|h| h.txid).collect();
+        assert_eq!(txids, [payment.txid()]);
+        assert_eq!(wallet.balance().spendable, 8_000);
+    }
+
+    #[test]
+    fn test_out_of_order_and_conflicting_transactions() {
+        let mine = address::p2wpkh_script(&[1; 20]);
+        let theirs = address::p2wpkh_script(&[3; 20]);
+        let mut wallet = Wallet::new();
+        wallet.watch_script(&mine);
+
+        let funding = spend_outpoint(
+            "44".repeat(32),
+            0,
+            vec![pay(&mine, 50_000), pay(&mine, 20_000)],
+        );
+        let child = spend(&funding, 0, vec![pay(&theirs, 49_000)]);
+
+        // The child only concerns the wallet once its parent is known
+        assert!(!wallet.add_transaction(child.clone(), None));
+        assert!(wallet.add_transaction(funding.clone(), Some(10)));
+        assert_eq!(wallet.history().len(), 2);
+        assert_eq!(wallet.utxos().len(), 1);
+
+        // Seeing a confirmed transaction in the mempool keeps its height
+        assert!(wallet.add_transaction(funding.clone(), None));
+        assert_eq!(wallet.history()[0].height, Some(10));
+
+        // A confirmed double spend evicts the mempool one and its child
+        let replaced = spend(&funding, 1, vec![pay(&theirs, 19_000)]);
+        let replaced_child = spend(&replaced, 0, vec![pay(&mine, 18_000)]);
+        let confirmed = spend(&funding, 1, vec![pay(&mine, 19_500)]);
+        assert!(wallet.add_transaction(replaced.clone(), None));
+        assert!(wallet.add_transaction(replaced_child, None));
+        assert!(wallet.add_transaction(confirmed.clone(), Some(11)));
+        let txids: Vec<String> = wallet.history().into_iter().map(|h| h.txid).collect();
+        assert_eq!(txids, [funding.txid(), confirmed.txid(), child.txid()]);
+        assert_eq!(wallet.utxos().len(), 1);
+        assert_eq!(wallet.utxos()[0].amount, 19_500);
+
+        // and is refused from then on
+        assert!(!wallet.add_transaction(replaced, None));
+    }
+}